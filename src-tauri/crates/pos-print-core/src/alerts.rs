@@ -0,0 +1,103 @@
+//! OS notifications for jobs that failed for good, so a cashier who isn't
+//! watching the app still learns a receipt didn't print.
+//!
+//! This decides whether a failure deserves a notification and words it;
+//! the host shows it. Destinations below `failure_notify_min_severity`
+//! never notify. The rest notify at most once per
+//! `failure_notify_interval_ms`, and failures in between are counted into
+//! the next notification instead of raising their own.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PrintConfig;
+use crate::error::PrintError;
+use crate::events::JobEvent;
+
+/// How much a destination's failures matter, e.g. `high` for the kitchen
+/// and `low` for a label printer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+  Low,
+  #[default]
+  Normal,
+  High,
+}
+
+#[derive(Clone, Debug)]
+pub struct Alert {
+  pub title: String,
+  pub body: String,
+}
+
+struct Slot {
+  notified_at: Instant,
+  suppressed: u32,
+}
+
+#[derive(Clone, Default)]
+pub struct FailureAlerts {
+  slots: Arc<Mutex<HashMap<String, Slot>>>,
+}
+
+impl FailureAlerts {
+  /// The notification for a job that failed with `event.error`, or `None`
+  /// when notifications are off, the destination is below the threshold,
+  /// or it notified too recently.
+  pub fn on_failure(&self, event: &JobEvent, cfg: &PrintConfig) -> Option<Alert> {
+    let error = event.error.as_ref()?;
+    // Aborted jobs were cancelled by the operator.
+    if !cfg.failure_notifications
+      || matches!(error, PrintError::Aborted(_))
+      || cfg.severity(&event.destination) < cfg.failure_notify_min_severity
+    {
+      return None;
+    }
+
+    let interval = Duration::from_millis(cfg.failure_notify_interval_ms);
+    let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+    let earlier = match slots.get_mut(&event.destination) {
+      Some(slot) if slot.notified_at.elapsed() < interval => {
+        slot.suppressed += 1;
+        return None;
+      }
+      Some(slot) => std::mem::take(&mut slot.suppressed),
+      None => 0,
+    };
+    slots.insert(
+      event.destination.clone(),
+      Slot {
+        notified_at: Instant::now(),
+        suppressed: 0,
+      },
+    );
+
+    let mut body = short_reason(error);
+    if earlier > 0 {
+      body.push_str(&format!(" {earlier} earlier failures were not notified."));
+    }
+    Some(Alert {
+      title: format!("Print failed on {}", event.destination),
+      body,
+    })
+  }
+}
+
+/// One sentence on why the job failed, without the remedy that follows in
+/// the full message.
+fn short_reason(error: &PrintError) -> String {
+  match error {
+    PrintError::PrinterFault(fault) => format!("The printer {}.", fault.kind.describe()),
+    _ => {
+      let message = error.message();
+      match message.find(". ") {
+        Some(end) => message[..=end].to_string(),
+        None => message.to_string(),
+      }
+    }
+  }
+}
@@ -0,0 +1,269 @@
+//! Opt-in archive of printed receipts for dispute handling.
+//!
+//! Each successful job's payload is redrawn with the preview renderer and
+//! stored as `<dir>/<YYYY-MM-DD>/<job_id>.png` next to a `<job_id>.json`
+//! metadata file. Rendering and pruning happen on a background thread;
+//! archive failures are logged and never affect the print.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::PrintError;
+use crate::preview;
+use crate::rtc::ClockTime;
+
+const DEFAULT_FIND_LIMIT: usize = 50;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveSettings {
+  pub enabled: bool,
+  /// Head width used to redraw payloads.
+  pub dot_width: u32,
+  /// Days of receipts to keep; 0 keeps them regardless of age.
+  pub max_age_days: u32,
+  /// Total archive size cap; the oldest receipts go first. 0 disables the cap.
+  pub max_total_mb: u64,
+}
+
+impl Default for ArchiveSettings {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      dot_width: crate::raster::DEFAULT_MAX_WIDTH,
+      max_age_days: 90,
+      max_total_mb: 500,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArchivedReceipt {
+  pub job_id: String,
+  pub destination: String,
+  pub bytes: usize,
+  pub printed_at_ms: u64,
+  #[serde(default)]
+  pub metadata: Option<Value>,
+  /// Absolute path of the PNG; filled in by `find`.
+  #[serde(default, skip_deserializing)]
+  pub image_path: Option<String>,
+}
+
+/// Lookup criteria for `find`; every given field must match.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ArchiveFilter {
+  pub job_id: Option<String>,
+  /// Substring of the destination label.
+  pub destination: Option<String>,
+  /// Substring searched in the job metadata, e.g. a receipt number.
+  pub text: Option<String>,
+  pub from_ms: Option<u64>,
+  pub to_ms: Option<u64>,
+  pub limit: Option<usize>,
+}
+
+struct Pending {
+  receipt: ArchivedReceipt,
+  data: Vec<u8>,
+  settings: ArchiveSettings,
+}
+
+#[derive(Clone, Default)]
+pub struct ReceiptArchive {
+  dir: Option<PathBuf>,
+  settings: Arc<RwLock<ArchiveSettings>>,
+  tx: Option<Sender<Pending>>,
+}
+
+impl ReceiptArchive {
+  pub fn open(dir: PathBuf) -> Self {
+    let (tx, rx) = channel::<Pending>();
+    let worker_dir = dir.clone();
+    let spawned = thread::Builder::new().name("receipt-archive".to_string()).spawn(move || {
+      for pending in rx {
+        let job_id = pending.receipt.job_id.clone();
+        if let Err(e) = write_entry(&worker_dir, &pending) {
+          log::warn!("unable to archive receipt for job {job_id}: {e}");
+        }
+        prune(&worker_dir, &pending.settings);
+      }
+    });
+    if let Err(e) = spawned {
+      log::warn!("receipt archive disabled, unable to start its thread: {e}");
+      return Self::default();
+    }
+    Self {
+      dir: Some(dir),
+      settings: Arc::default(),
+      tx: Some(tx),
+    }
+  }
+
+  pub fn settings(&self) -> ArchiveSettings {
+    self.settings.read().unwrap_or_else(|e| e.into_inner()).clone()
+  }
+
+  pub fn set_settings(&self, settings: ArchiveSettings) -> Result<ArchiveSettings, PrintError> {
+    if !(192..=1152).contains(&settings.dot_width) {
+      return Err(PrintError::InvalidRequest(format!(
+        "dot_width must be between 192 and 1152, got {}.",
+        settings.dot_width
+      )));
+    }
+    *self.settings.write().unwrap_or_else(|e| e.into_inner()) = settings.clone();
+    Ok(settings)
+  }
+
+  /// Whether payloads should be kept for archiving.
+  pub fn enabled(&self) -> bool {
+    self.tx.is_some() && self.settings.read().unwrap_or_else(|e| e.into_inner()).enabled
+  }
+
+  /// Queues a printed payload for archiving. Never blocks.
+  pub fn store(&self, receipt: ArchivedReceipt, data: Vec<u8>) {
+    let Some(tx) = &self.tx else {
+      return;
+    };
+    let settings = self.settings();
+    if !settings.enabled {
+      return;
+    }
+    let _ = tx.send(Pending { receipt, data, settings });
+  }
+
+  /// Newest matching receipts first.
+  pub fn find(&self, filter: &ArchiveFilter) -> Result<Vec<ArchivedReceipt>, PrintError> {
+    let Some(dir) = &self.dir else {
+      return Ok(Vec::new());
+    };
+    let from_day = filter.from_ms.and_then(day_folder);
+    let to_day = filter.to_ms.and_then(day_folder);
+
+    let mut found = Vec::new();
+    for day in day_dirs(dir) {
+      let name = day.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+      if from_day.as_deref().is_some_and(|from| name < from) || to_day.as_deref().is_some_and(|to| name > to) {
+        continue;
+      }
+      let Ok(entries) = fs::read_dir(&day) else {
+        continue;
+      };
+      for path in entries.flatten().map(|e| e.path()) {
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+          continue;
+        }
+        let Some(mut receipt) = fs::read(&path)
+          .ok()
+          .and_then(|raw| serde_json::from_slice::<ArchivedReceipt>(&raw).ok())
+        else {
+          continue;
+        };
+        if matches(&receipt, filter) {
+          receipt.image_path = Some(path.with_extension("png").to_string_lossy().into_owned());
+          found.push(receipt);
+        }
+      }
+    }
+    found.sort_by_key(|r| std::cmp::Reverse(r.printed_at_ms));
+    found.truncate(filter.limit.unwrap_or(DEFAULT_FIND_LIMIT));
+    Ok(found)
+  }
+}
+
+fn matches(receipt: &ArchivedReceipt, filter: &ArchiveFilter) -> bool {
+  filter.job_id.as_ref().map_or(true, |id| &receipt.job_id == id)
+    && filter
+      .destination
+      .as_ref()
+      .map_or(true, |d| receipt.destination.contains(d.as_str()))
+    && filter.text.as_ref().map_or(true, |t| {
+      receipt
+        .metadata
+        .as_ref()
+        .is_some_and(|m| m.to_string().contains(t.as_str()))
+    })
+    && filter.from_ms.map_or(true, |from| receipt.printed_at_ms >= from)
+    && filter.to_ms.map_or(true, |to| receipt.printed_at_ms <= to)
+}
+
+/// `YYYY-MM-DD` (UTC) for a millisecond timestamp.
+fn day_folder(ts_ms: u64) -> Option<String> {
+  let t = ClockTime::from_unix((ts_ms / 1000) as i64).ok()?;
+  Some(format!("{:04}-{:02}-{:02}", t.year, t.month, t.day))
+}
+
+/// Day folders, oldest first.
+fn day_dirs(dir: &Path) -> Vec<PathBuf> {
+  let mut days = fs::read_dir(dir)
+    .map(|entries| {
+      entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_dir())
+        .collect::<Vec<_>>()
+    })
+    .unwrap_or_default();
+  days.sort();
+  days
+}
+
+fn write_entry(dir: &Path, pending: &Pending) -> Result<(), String> {
+  let day = day_folder(pending.receipt.printed_at_ms).ok_or("timestamp out of range")?;
+  let day_dir = dir.join(day);
+  fs::create_dir_all(&day_dir).map_err(|e| format!("create {}: {e}", day_dir.display()))?;
+
+  let png = preview::escpos_to_png(&pending.data, pending.settings.dot_width).map_err(|e| e.to_string())?;
+  let base = day_dir.join(&pending.receipt.job_id);
+  fs::write(base.with_extension("png"), png).map_err(|e| e.to_string())?;
+  let json = serde_json::to_vec_pretty(&pending.receipt).map_err(|e| e.to_string())?;
+  fs::write(base.with_extension("json"), json).map_err(|e| e.to_string())
+}
+
+/// Applies the age and size limits.
+fn prune(dir: &Path, settings: &ArchiveSettings) {
+  let days = day_dirs(dir);
+  let cutoff = (settings.max_age_days > 0)
+    .then(|| crate::audit::now_ms().saturating_sub(settings.max_age_days as u64 * 86_400_000))
+    .and_then(day_folder);
+
+  let mut files = Vec::new();
+  for day in days {
+    let name = day.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+    if cutoff.as_deref().is_some_and(|cutoff| name.as_str() < cutoff) {
+      if let Err(e) = fs::remove_dir_all(&day) {
+        log::warn!("unable to prune receipt archive {}: {e}", day.display());
+      }
+      continue;
+    }
+    if let Ok(entries) = fs::read_dir(&day) {
+      let mut day_files = entries
+        .flatten()
+        .filter_map(|e| Some((e.path(), e.metadata().ok()?.len())))
+        .collect::<Vec<_>>();
+      day_files.sort();
+      files.extend(day_files);
+    }
+  }
+
+  if settings.max_total_mb == 0 {
+    return;
+  }
+  let cap = settings.max_total_mb * 1024 * 1024;
+  let mut total = files.iter().map(|(_, len)| len).sum::<u64>();
+  for (path, len) in files {
+    if total <= cap {
+      break;
+    }
+    if fs::remove_file(&path).is_ok() {
+      total -= len;
+    }
+  }
+}
@@ -0,0 +1,186 @@
+//! Append-only JSONL audit trail of print activity.
+
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::stats::{PrintStatistics, StatsIndex, StatsRange};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AuditEntry {
+  pub ts_ms: u64,
+  /// Where the request came from, e.g. `app` or `http`.
+  pub source: String,
+  pub action: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub destination: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub bytes: Option<usize>,
+  pub ok: bool,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub detail: Option<String>,
+  /// Time from submission to the result, for jobs.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub duration_ms: Option<u64>,
+  /// Till or station that sent the job; see `PrintOptions::register`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub register: Option<String>,
+}
+
+impl AuditEntry {
+  pub fn new(source: &str, action: &str) -> Self {
+    Self {
+      ts_ms: now_ms(),
+      source: source.to_string(),
+      action: action.to_string(),
+      ..Self::default()
+    }
+  }
+
+  pub fn destination(mut self, destination: impl Into<String>) -> Self {
+    self.destination = Some(destination.into());
+    self
+  }
+
+  pub fn bytes(mut self, bytes: usize) -> Self {
+    self.bytes = Some(bytes);
+    self
+  }
+
+  pub fn detail(mut self, detail: impl Into<String>) -> Self {
+    self.detail = Some(detail.into());
+    self
+  }
+
+  pub fn duration_ms(mut self, duration_ms: u64) -> Self {
+    self.duration_ms = Some(duration_ms);
+    self
+  }
+
+  pub fn register(mut self, register: Option<String>) -> Self {
+    self.register = register;
+    self
+  }
+
+  /// Marks the entry with the outcome of `result`.
+  pub fn outcome<T, E: std::fmt::Display>(mut self, result: &Result<T, E>) -> Self {
+    self.ok = result.is_ok();
+    self.error = result.as_ref().err().map(|e| e.to_string());
+    self
+  }
+}
+
+pub fn now_ms() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_millis() as u64)
+    .unwrap_or(0)
+}
+
+/// Writes entries to `<dir>/print-audit.jsonl`. Write failures are logged
+/// and otherwise ignored so auditing can never fail a print.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+  path: Option<PathBuf>,
+  lock: Arc<Mutex<()>>,
+  stats: Arc<Mutex<StatsIndex>>,
+}
+
+impl AuditLog {
+  pub fn open(dir: PathBuf) -> Self {
+    if let Err(e) = fs::create_dir_all(&dir) {
+      log::warn!("audit log disabled, unable to create {}: {e}", dir.display());
+      return Self::default();
+    }
+    Self {
+      path: Some(dir.join("print-audit.jsonl")),
+      lock: Arc::default(),
+      stats: Arc::default(),
+    }
+  }
+
+  pub fn record(&self, entry: AuditEntry) {
+    let Some(path) = &self.path else {
+      return;
+    };
+    let line = match serde_json::to_string(&entry) {
+      Ok(line) => line,
+      Err(e) => {
+        log::warn!("unable to serialize audit entry: {e}");
+        return;
+      }
+    };
+
+    let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+    let written = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+      .and_then(|mut f| writeln!(f, "{line}"));
+    if let Err(e) = written {
+      log::warn!("unable to write audit log {}: {e}", path.display());
+    }
+  }
+
+  /// Print statistics for `range`; only entries added since the last call
+  /// are read from disk.
+  pub fn statistics(&self, range: &StatsRange) -> PrintStatistics {
+    let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(path) = &self.path {
+      let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+      stats.catch_up(path);
+    }
+    stats.summarize(range)
+  }
+
+  /// The last `n` entries, oldest first.
+  pub fn recent(&self, n: usize) -> Vec<AuditEntry> {
+    let Some(path) = &self.path else {
+      return Vec::new();
+    };
+    let file = {
+      let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+      match fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Vec::new(),
+      }
+    };
+    let mut tail = VecDeque::with_capacity(n + 1);
+    for entry in BufReader::new(file)
+      .lines()
+      .map_while(Result::ok)
+      .filter_map(|line| serde_json::from_str::<AuditEntry>(&line).ok())
+    {
+      tail.push_back(entry);
+      if tail.len() > n {
+        tail.pop_front();
+      }
+    }
+    tail.into()
+  }
+
+  /// The successful `print` entry for `job_id`, if the log has one.
+  pub fn find_print(&self, job_id: &str) -> Option<AuditEntry> {
+    let path = self.path.as_ref()?;
+    let file = {
+      let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+      fs::File::open(path).ok()?
+    };
+    let needle = format!("job_id={job_id}");
+    BufReader::new(file)
+      .lines()
+      .map_while(Result::ok)
+      .filter(|line| line.contains(&needle))
+      .filter_map(|line| serde_json::from_str::<AuditEntry>(&line).ok())
+      .find(|e| {
+        e.action == "print" && e.ok && e.detail.as_deref().is_some_and(|d| d.split(' ').any(|kv| kv == needle))
+      })
+  }
+}
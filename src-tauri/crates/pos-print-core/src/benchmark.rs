@@ -0,0 +1,100 @@
+//! Throughput measurement for evaluating printer hardware.
+
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::error::PrintError;
+use crate::escpos::{Cut, EscPosBuilder};
+
+pub const MAX_ITERATIONS: u32 = 50;
+pub const PAYLOAD_RANGE: std::ops::RangeInclusive<usize> = 256..=1024 * 1024;
+
+/// Characters per generated line, narrow enough for 58 mm paper.
+const LINE_CHARS: usize = 32;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BenchmarkReport {
+  pub destination: String,
+  pub iterations: u32,
+  pub payload_bytes: usize,
+  /// Printed lines per payload.
+  pub lines: usize,
+  pub min_ms: f64,
+  pub max_ms: f64,
+  pub mean_ms: f64,
+  pub stddev_ms: f64,
+  pub p50_ms: f64,
+  pub p90_ms: f64,
+  pub p99_ms: f64,
+  /// Payload bytes over the mean iteration time.
+  pub bytes_per_sec: f64,
+  pub lines_per_sec: f64,
+}
+
+pub fn check(payload_bytes: usize, iterations: u32) -> Result<(), PrintError> {
+  if !PAYLOAD_RANGE.contains(&payload_bytes) {
+    return Err(PrintError::InvalidRequest(format!(
+      "payload_bytes must be between {} and {}, got {payload_bytes}.",
+      PAYLOAD_RANGE.start(),
+      PAYLOAD_RANGE.end()
+    )));
+  }
+  if !(1..=MAX_ITERATIONS).contains(&iterations) {
+    return Err(PrintError::InvalidRequest(format!(
+      "iterations must be between 1 and {MAX_ITERATIONS}, got {iterations}."
+    )));
+  }
+  Ok(())
+}
+
+/// Plain text lines padding the payload to about `bytes`, ending in a cut.
+/// Returns the payload and its line count.
+pub fn payload(bytes: usize) -> (Vec<u8>, usize) {
+  let mut b = EscPosBuilder::new();
+  b.init();
+  let trailer = 3 + 4;
+  let lines = (bytes.saturating_sub(2 + trailer) / (LINE_CHARS + 1)).max(1);
+  for n in 0..lines {
+    let label = format!("BENCH {n:06} ");
+    let fill = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ".chars().cycle().skip(n % 36);
+    let line = label.chars().chain(fill).take(LINE_CHARS).collect::<String>();
+    b.line(&line);
+  }
+  b.feed(3).cut(Cut::Partial);
+  (b.into_bytes(), lines)
+}
+
+pub fn summarize(destination: String, samples: &[Duration], payload_bytes: usize, lines: usize) -> BenchmarkReport {
+  let mut ms = samples.iter().map(|d| d.as_secs_f64() * 1000.0).collect::<Vec<_>>();
+  ms.sort_by(f64::total_cmp);
+  let n = ms.len().max(1) as f64;
+  let mean = ms.iter().sum::<f64>() / n;
+  let variance = ms.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+  let per_sec = |count: usize| if mean > 0.0 { count as f64 * 1000.0 / mean } else { 0.0 };
+
+  BenchmarkReport {
+    destination,
+    iterations: samples.len() as u32,
+    payload_bytes,
+    lines,
+    min_ms: ms.first().copied().unwrap_or(0.0),
+    max_ms: ms.last().copied().unwrap_or(0.0),
+    mean_ms: mean,
+    stddev_ms: variance.sqrt(),
+    p50_ms: percentile(&ms, 50.0),
+    p90_ms: percentile(&ms, 90.0),
+    p99_ms: percentile(&ms, 99.0),
+    bytes_per_sec: per_sec(payload_bytes),
+    lines_per_sec: per_sec(lines),
+  }
+}
+
+/// Nearest-rank percentile of ascending `sorted`.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+  if sorted.is_empty() {
+    return 0.0;
+  }
+  let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+  sorted[rank.clamp(1, sorted.len()) - 1]
+}
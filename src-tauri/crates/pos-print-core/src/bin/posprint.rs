@@ -0,0 +1,262 @@
+//! Headless printer test tool for support technicians.
+//!
+//! Exit codes:
+//! - 0 success
+//! - 1 other failure
+//! - 2 usage error
+//! - 3 input file could not be read
+//! - 4 printer unreachable (resolve/connect/open failed)
+//! - 5 I/O with the printer failed (write/read/timeout)
+//! - 6 transport not supported on this platform
+
+use std::io::Read;
+use std::process::ExitCode;
+
+use pos_print_core::config::PrintConfig;
+use pos_print_core::error::PrintError;
+use pos_print_core::{escpos, serial, spooler, transport};
+
+const USAGE: &str = "\
+usage: posprint <command> [options]
+
+commands:
+  list-ports                              list serial ports
+  list-printers                           list Windows spooler printers
+  print --tcp HOST:PORT FILE              send FILE (or - for stdin) over TCP
+  print --serial PORT [--baud N] FILE     send FILE (or - for stdin) over serial
+  status --tcp HOST:PORT                  query printer and paper status
+  status --serial PORT [--baud N]
+  testpage --tcp HOST:PORT [--width N]    print a test page
+  testpage --serial PORT [--baud N] [--width N]
+  grid --tcp HOST:PORT [--width N] [--lines N]
+                                          print an alignment grid for paper and cutter setup
+  grid --serial PORT [--baud N] [--width N] [--lines N]";
+
+const DEFAULT_BAUD: u32 = 9600;
+const DEFAULT_WIDTH: usize = 48;
+const DEFAULT_GRID_LINES: usize = 10;
+
+enum Target {
+  Tcp { host: String, port: u16 },
+  Serial { port: String, baud: u32 },
+}
+
+enum CliError {
+  Usage(String),
+  Input(String),
+  Print(PrintError),
+}
+
+impl CliError {
+  fn exit_code(&self) -> u8 {
+    match self {
+      CliError::Usage(_) => 2,
+      CliError::Input(_) => 3,
+      CliError::Print(e) => match e {
+        PrintError::Resolve(_) | PrintError::Connect(_) | PrintError::SerialOpen(_) => 4,
+        PrintError::Write(_) | PrintError::Read(_) | PrintError::Timeout(_) | PrintError::Spooler(_) => 5,
+        PrintError::Unsupported(_) => 6,
+        _ => 1,
+      },
+    }
+  }
+}
+
+impl From<PrintError> for CliError {
+  fn from(e: PrintError) -> Self {
+    CliError::Print(e)
+  }
+}
+
+fn main() -> ExitCode {
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  match run(&args) {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(err) => {
+      match &err {
+        CliError::Usage(msg) => eprintln!("{msg}\n\n{USAGE}"),
+        CliError::Input(msg) => eprintln!("error: {msg}"),
+        CliError::Print(e) => eprintln!("error [{}]: {e}", e.code()),
+      }
+      ExitCode::from(err.exit_code())
+    }
+  }
+}
+
+fn run(args: &[String]) -> Result<(), CliError> {
+  let Some((command, rest)) = args.split_first() else {
+    return Err(CliError::Usage("missing command".to_string()));
+  };
+  let cfg = PrintConfig::default();
+
+  match command.as_str() {
+    "list-ports" => {
+      for p in serial::list_ports()? {
+        let ids = match (p.vid, p.pid) {
+          (Some(vid), Some(pid)) => format!(" {vid:04x}:{pid:04x}"),
+          _ => String::new(),
+        };
+        let product = p.product.map(|s| format!(" {s}")).unwrap_or_default();
+        println!("{}\t{}{ids}{product}", p.port_name, p.kind.as_str());
+      }
+      Ok(())
+    }
+    "list-printers" => {
+      for name in spooler::list_printers()? {
+        println!("{name}");
+      }
+      Ok(())
+    }
+    "print" => {
+      let (target, positional) = parse_target(rest)?;
+      let [file] = positional.as_slice() else {
+        return Err(CliError::Usage("print expects exactly one FILE argument".to_string()));
+      };
+      let data = read_input(file)?;
+      send(&target, &data, &cfg)?;
+      println!("sent {} bytes", data.len());
+      Ok(())
+    }
+    "status" => {
+      let (target, positional) = parse_target(rest)?;
+      if !positional.is_empty() {
+        return Err(CliError::Usage("status takes no positional arguments".to_string()));
+      }
+      let report = match &target {
+        Target::Tcp { host, port } => transport::tcp_status(host, *port, &cfg)?,
+        Target::Serial { port, baud } => transport::serial_status(port, *baud, &cfg)?,
+      };
+      println!("online: {}", report.printer.online);
+      println!("drawer_open: {}", report.printer.drawer_open);
+      println!("waiting_for_recovery: {}", report.printer.waiting_for_recovery);
+      println!("paper_near_end: {}", report.paper.near_end);
+      println!("paper_out: {}", report.paper.out);
+      Ok(())
+    }
+    "testpage" => {
+      let (target, positional) = parse_target(rest)?;
+      if !positional.is_empty() {
+        return Err(CliError::Usage("testpage takes no positional arguments".to_string()));
+      }
+      let width = option_value(rest, "--width")?
+        .map(|v| parse_num::<usize>("--width", v))
+        .transpose()?
+        .unwrap_or(DEFAULT_WIDTH);
+      if width == 0 {
+        return Err(CliError::Usage("--width must be greater than 0".to_string()));
+      }
+      send(&target, &escpos::test_page(width), &cfg)?;
+      println!("test page sent");
+      Ok(())
+    }
+    "grid" => {
+      let (target, positional) = parse_target(rest)?;
+      if !positional.is_empty() {
+        return Err(CliError::Usage("grid takes no positional arguments".to_string()));
+      }
+      let width = option_value(rest, "--width")?
+        .map(|v| parse_num::<usize>("--width", v))
+        .transpose()?
+        .unwrap_or(DEFAULT_WIDTH);
+      let lines = option_value(rest, "--lines")?
+        .map(|v| parse_num::<usize>("--lines", v))
+        .transpose()?
+        .unwrap_or(DEFAULT_GRID_LINES);
+      if width < 8 {
+        return Err(CliError::Usage("--width must be at least 8".to_string()));
+      }
+      send(&target, &escpos::build_alignment_grid(width, lines), &cfg)?;
+      println!("alignment grid sent");
+      Ok(())
+    }
+    "-h" | "--help" | "help" => {
+      println!("{USAGE}");
+      Ok(())
+    }
+    other => Err(CliError::Usage(format!("unknown command '{other}'"))),
+  }
+}
+
+fn send(target: &Target, data: &[u8], cfg: &PrintConfig) -> Result<(), PrintError> {
+  match target {
+    Target::Tcp { host, port } => transport::tcp_send(host, *port, data, cfg),
+    Target::Serial { port, baud } => transport::serial_send(port, *baud, data, cfg),
+  }
+}
+
+/// Splits `--tcp`/`--serial`/`--baud`/`--width`/`--lines` options from positional arguments.
+fn parse_target(args: &[String]) -> Result<(Target, Vec<&String>), CliError> {
+  let mut tcp = None;
+  let mut serial = None;
+  let mut baud = DEFAULT_BAUD;
+  let mut positional = Vec::new();
+
+  let mut it = args.iter();
+  while let Some(arg) = it.next() {
+    match arg.as_str() {
+      "--tcp" => tcp = Some(next_value(&mut it, "--tcp")?),
+      "--serial" => serial = Some(next_value(&mut it, "--serial")?),
+      "--baud" => baud = parse_num("--baud", next_value(&mut it, "--baud")?)?,
+      "--width" | "--lines" => {
+        next_value(&mut it, arg)?;
+      }
+      s if s.starts_with("--") => return Err(CliError::Usage(format!("unknown option '{s}'"))),
+      _ => positional.push(arg),
+    }
+  }
+
+  let target = match (tcp, serial) {
+    (Some(addr), None) => {
+      let (host, port) = addr
+        .rsplit_once(':')
+        .ok_or_else(|| CliError::Usage(format!("--tcp expects HOST:PORT, got '{addr}'")))?;
+      Target::Tcp {
+        host: host.trim_matches(|c| c == '[' || c == ']').to_string(),
+        port: parse_num("--tcp port", port)?,
+      }
+    }
+    (None, Some(port)) => Target::Serial {
+      port: port.to_string(),
+      baud,
+    },
+    _ => return Err(CliError::Usage("specify exactly one of --tcp or --serial".to_string())),
+  };
+  Ok((target, positional))
+}
+
+fn next_value<'a>(it: &mut std::slice::Iter<'a, String>, flag: &str) -> Result<&'a str, CliError> {
+  it.next()
+    .map(String::as_str)
+    .ok_or_else(|| CliError::Usage(format!("{flag} requires a value")))
+}
+
+fn option_value<'a>(args: &'a [String], flag: &str) -> Result<Option<&'a str>, CliError> {
+  match args.iter().position(|a| a == flag) {
+    Some(i) => args
+      .get(i + 1)
+      .map(|v| Some(v.as_str()))
+      .ok_or_else(|| CliError::Usage(format!("{flag} requires a value"))),
+    None => Ok(None),
+  }
+}
+
+fn parse_num<T: std::str::FromStr>(what: &str, value: &str) -> Result<T, CliError> {
+  value
+    .parse()
+    .map_err(|_| CliError::Usage(format!("invalid value for {what}: '{value}'")))
+}
+
+fn read_input(path: &str) -> Result<Vec<u8>, CliError> {
+  let mut data = Vec::new();
+  if path == "-" {
+    std::io::stdin()
+      .read_to_end(&mut data)
+      .map_err(|e| CliError::Input(format!("unable to read stdin: {e}")))?;
+  } else {
+    data = std::fs::read(path).map_err(|e| CliError::Input(format!("unable to read '{path}': {e}")))?;
+  }
+  if data.is_empty() {
+    return Err(CliError::Input(format!("'{path}' is empty; nothing to print")));
+  }
+  Ok(data)
+}
@@ -0,0 +1,447 @@
+//! Classic Bluetooth discovery, to show which printers are paired and which
+//! are only in range before setting one up. Paired printers print through
+//! the serial (SPP) port the OS creates for them.
+//!
+//! Scans run on their own thread in short rounds, checking for cancellation
+//! between rounds, so a cancelled scan stops within a round and the
+//! platform's inquiry is always stopped. One scan runs at a time. Windows
+//! uses the classic Bluetooth API, Linux BlueZ over D-Bus and macOS
+//! IOBluetooth. Other builds, Android included, fail with `Unsupported`.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::cancel::CancelToken;
+use crate::error::PrintError;
+
+/// Major device class "imaging", in bits 8..13 of the class of device.
+#[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos", test))]
+const MAJOR_CLASS_IMAGING: u32 = 0x06;
+/// Minor class bit of an imaging device that prints.
+#[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos", test))]
+const MINOR_PRINTER: u32 = 0x80;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BluetoothDevice {
+  pub name: String,
+  /// `AA:BB:CC:DD:EE:FF`.
+  pub address: String,
+  pub class_of_device: u32,
+  /// The class of device says imaging/printer. Many receipt printers report
+  /// a generic class, so false doesn't rule one out.
+  pub likely_printer: bool,
+  /// Paired (bonded) with this computer.
+  pub paired: bool,
+  pub connected: bool,
+  /// Signal strength in dBm, for devices seen during this scan; Windows'
+  /// classic Bluetooth API doesn't report it.
+  pub rssi: Option<i16>,
+}
+
+#[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos", test))]
+fn likely_printer(class_of_device: u32) -> bool {
+  (class_of_device >> 8) & 0x1f == MAJOR_CLASS_IMAGING && class_of_device & MINOR_PRINTER != 0
+}
+
+#[cfg(any(target_os = "windows", test))]
+fn format_address(address: u64) -> String {
+  (0..6)
+    .rev()
+    .map(|i| format!("{:02X}", (address >> (i * 8)) & 0xff))
+    .collect::<Vec<_>>()
+    .join(":")
+}
+
+/// IOBluetooth's `00-11-22-aa-bb-cc` as `00:11:22:AA:BB:CC`.
+#[cfg(any(target_os = "macos", test))]
+fn normalize_address(address: &str) -> String {
+  address.replace('-', ":").to_uppercase()
+}
+
+#[derive(Clone, Default)]
+pub struct BluetoothScanner {
+  /// The running scan's token, until its thread has finished.
+  running: Arc<Mutex<Option<CancelToken>>>,
+}
+
+impl BluetoothScanner {
+  /// Scans for about `duration` and returns paired devices and those in
+  /// range, sorted by name. A cancelled scan returns what it found so far.
+  pub async fn scan(&self, duration: Duration) -> Result<Vec<BluetoothDevice>, PrintError> {
+    let token = {
+      let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+      if running.is_some() {
+        return Err(PrintError::InvalidRequest(
+          "A Bluetooth scan is already running; cancel it or wait for it to finish.".to_string(),
+        ));
+      }
+      let token = CancelToken::default();
+      *running = Some(token.clone());
+      token
+    };
+
+    let (done, rx) = oneshot::channel();
+    let running = self.running.clone();
+    let spawned = thread::Builder::new().name("bluetooth-scan".to_string()).spawn(move || {
+      let result = imp::discover(duration, &token).map(|mut devices| {
+        devices.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()).then(a.address.cmp(&b.address)));
+        devices
+      });
+      *running.lock().unwrap_or_else(|e| e.into_inner()) = None;
+      let _ = done.send(result);
+    });
+    if let Err(e) = spawned {
+      *self.running.lock().unwrap_or_else(|e| e.into_inner()) = None;
+      return Err(PrintError::Task(format!("Unable to start Bluetooth scan: {e}.")));
+    }
+    rx.await
+      .map_err(|_| PrintError::Task("The Bluetooth scan stopped without a result.".to_string()))?
+  }
+
+  /// Stops the running scan after its current round; false when none is
+  /// running.
+  pub fn cancel(&self) -> bool {
+    match &*self.running.lock().unwrap_or_else(|e| e.into_inner()) {
+      Some(token) => {
+        token.cancel();
+        true
+      }
+      None => false,
+    }
+  }
+}
+
+#[cfg(target_os = "windows")]
+mod imp {
+  use std::collections::BTreeMap;
+  use std::mem::size_of;
+  use std::time::{Duration, Instant};
+
+  use windows_sys::Win32::Devices::Bluetooth::{
+    BluetoothFindDeviceClose, BluetoothFindFirstDevice, BluetoothFindNextDevice, BLUETOOTH_DEVICE_INFO,
+    BLUETOOTH_DEVICE_SEARCH_PARAMS,
+  };
+  use windows_sys::Win32::Foundation::GetLastError;
+
+  use super::{format_address, likely_printer, BluetoothDevice};
+  use crate::cancel::CancelToken;
+  use crate::error::PrintError;
+
+  const ERROR_NO_MORE_ITEMS: u32 = 259;
+  /// Inquiry length per round, in units of 1.28 s.
+  const ROUND_UNITS: u8 = 1;
+
+  pub fn discover(duration: Duration, cancel: &CancelToken) -> Result<Vec<BluetoothDevice>, PrintError> {
+    let deadline = Instant::now() + duration;
+    let mut found = BTreeMap::new();
+    loop {
+      for device in unsafe { round()? } {
+        found.insert(device.address.clone(), device);
+      }
+      if cancel.is_cancelled() || Instant::now() >= deadline {
+        break;
+      }
+    }
+    Ok(found.into_values().collect())
+  }
+
+  /// One inquiry, plus the devices Windows remembers.
+  unsafe fn round() -> Result<Vec<BluetoothDevice>, PrintError> {
+    let mut params: BLUETOOTH_DEVICE_SEARCH_PARAMS = std::mem::zeroed();
+    params.dwSize = size_of::<BLUETOOTH_DEVICE_SEARCH_PARAMS>() as u32;
+    params.fReturnAuthenticated = 1;
+    params.fReturnRemembered = 1;
+    params.fReturnUnknown = 1;
+    params.fReturnConnected = 1;
+    params.fIssueInquiry = 1;
+    params.cTimeoutMultiplier = ROUND_UNITS;
+
+    let mut info: BLUETOOTH_DEVICE_INFO = std::mem::zeroed();
+    info.dwSize = size_of::<BLUETOOTH_DEVICE_INFO>() as u32;
+    let find = BluetoothFindFirstDevice(&params, &mut info);
+    if find as isize == 0 {
+      return match GetLastError() {
+        ERROR_NO_MORE_ITEMS => Ok(Vec::new()),
+        code => Err(PrintError::Enumerate(format!(
+          "Bluetooth discovery failed (Windows error {code}). Check that Bluetooth is turned on."
+        ))),
+      };
+    }
+    let mut out = vec![device(&info)];
+    loop {
+      info = std::mem::zeroed();
+      info.dwSize = size_of::<BLUETOOTH_DEVICE_INFO>() as u32;
+      if BluetoothFindNextDevice(find, &mut info) == 0 {
+        break;
+      }
+      out.push(device(&info));
+    }
+    BluetoothFindDeviceClose(find);
+    Ok(out)
+  }
+
+  unsafe fn device(info: &BLUETOOTH_DEVICE_INFO) -> BluetoothDevice {
+    let len = info.szName.iter().position(|&c| c == 0).unwrap_or(info.szName.len());
+    BluetoothDevice {
+      name: String::from_utf16_lossy(&info.szName[..len]),
+      address: format_address(info.Address.Anonymous.ullLong),
+      class_of_device: info.ulClassofDevice,
+      likely_printer: likely_printer(info.ulClassofDevice),
+      paired: info.fAuthenticated != 0 || info.fRemembered != 0,
+      connected: info.fConnected != 0,
+      rssi: None,
+    }
+  }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+  use std::collections::{BTreeMap, HashMap};
+  use std::fmt::Display;
+  use std::thread;
+  use std::time::{Duration, Instant};
+
+  use zbus::blocking::fdo::ObjectManagerProxy;
+  use zbus::blocking::{Connection, Proxy};
+  use zbus::zvariant::OwnedValue;
+
+  use super::{likely_printer, BluetoothDevice};
+  use crate::cancel::CancelToken;
+  use crate::error::PrintError;
+
+  const BLUEZ: &str = "org.bluez";
+  const ADAPTER: &str = "org.bluez.Adapter1";
+  const DEVICE: &str = "org.bluez.Device1";
+  /// How long to sleep between cancellation checks while BlueZ scans.
+  const ROUND: Duration = Duration::from_millis(500);
+
+  pub(super) type Properties = HashMap<String, OwnedValue>;
+
+  /// Stops the adapter's discovery when dropped, so an error or a cancel
+  /// never leaves the radio scanning.
+  struct Discovery<'a>(Proxy<'a>);
+
+  impl Drop for Discovery<'_> {
+    fn drop(&mut self) {
+      let _ = self.0.call_method("StopDiscovery", &());
+    }
+  }
+
+  pub fn discover(duration: Duration, cancel: &CancelToken) -> Result<Vec<BluetoothDevice>, PrintError> {
+    let deadline = Instant::now() + duration;
+    let bus = Connection::system().map_err(bus_error)?;
+    let objects = ObjectManagerProxy::builder(&bus)
+      .destination(BLUEZ)
+      .and_then(|b| b.path("/"))
+      .and_then(|b| b.build())
+      .map_err(bus_error)?;
+    let adapter = objects
+      .get_managed_objects()
+      .map_err(bus_error)?
+      .into_iter()
+      .filter(|(_, interfaces)| interfaces.keys().any(|i| i.as_str() == ADAPTER))
+      .map(|(path, _)| path)
+      .min_by(|a, b| a.as_str().cmp(b.as_str()))
+      .ok_or_else(|| PrintError::Enumerate("No Bluetooth adapter was found. Check that Bluetooth is turned on.".to_string()))?;
+
+    let proxy = Proxy::new(&bus, BLUEZ, adapter, ADAPTER).map_err(bus_error)?;
+    proxy.call_method("StartDiscovery", &()).map_err(bus_error)?;
+    let discovery = Discovery(proxy);
+    while !cancel.is_cancelled() && Instant::now() < deadline {
+      thread::sleep(ROUND.min(deadline.saturating_duration_since(Instant::now())));
+    }
+    drop(discovery);
+
+    // BlueZ keeps a Device1 object for every paired device and for each one
+    // seen by a recent discovery.
+    let mut found = BTreeMap::new();
+    for (_, mut interfaces) in objects.get_managed_objects().map_err(bus_error)? {
+      let Some(properties) = interfaces.iter_mut().find(|(i, _)| i.as_str() == DEVICE).map(|(_, p)| std::mem::take(p))
+      else {
+        continue;
+      };
+      if let Some(device) = device(&properties) {
+        found.insert(device.address.clone(), device);
+      }
+    }
+    Ok(found.into_values().collect())
+  }
+
+  pub(super) fn device(properties: &Properties) -> Option<BluetoothDevice> {
+    let address: String = get(properties, "Address")?;
+    let class_of_device = get(properties, "Class").unwrap_or(0);
+    Some(BluetoothDevice {
+      name: get(properties, "Name").or_else(|| get(properties, "Alias")).unwrap_or_else(|| address.clone()),
+      address,
+      class_of_device,
+      likely_printer: likely_printer(class_of_device),
+      paired: get(properties, "Paired").unwrap_or(false) || get(properties, "Bonded").unwrap_or(false),
+      connected: get(properties, "Connected").unwrap_or(false),
+      // BlueZ only reports RSSI for devices seen by the running discovery.
+      rssi: get(properties, "RSSI"),
+    })
+  }
+
+  fn get<T: TryFrom<OwnedValue>>(properties: &Properties, key: &str) -> Option<T> {
+    properties.get(key)?.try_clone().ok()?.try_into().ok()
+  }
+
+  fn bus_error(e: impl Display) -> PrintError {
+    PrintError::Enumerate(format!(
+      "Bluetooth discovery failed: {e}. Check that Bluetooth is turned on and the bluetooth service (BlueZ) is running."
+    ))
+  }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+  use std::collections::BTreeMap;
+  use std::thread;
+  use std::time::{Duration, Instant};
+
+  use objc2_foundation::{NSArray, NSDate, NSDefaultRunLoopMode, NSRunLoop};
+  use objc2_io_bluetooth::{IOBluetoothDevice, IOBluetoothDeviceInquiry};
+
+  use super::{likely_printer, normalize_address, BluetoothDevice};
+  use crate::cancel::CancelToken;
+  use crate::error::PrintError;
+
+  /// How long the run loop runs between cancellation checks. The inquiry
+  /// reports to the run loop of the thread that started it.
+  const ROUND: Duration = Duration::from_millis(500);
+  /// What IOBluetooth reports when it has no signal reading.
+  const NO_RSSI: i8 = 127;
+
+  pub fn discover(duration: Duration, cancel: &CancelToken) -> Result<Vec<BluetoothDevice>, PrintError> {
+    let deadline = Instant::now() + duration;
+    let mut found = BTreeMap::new();
+    unsafe {
+      if let Some(paired) = IOBluetoothDevice::pairedDevices() {
+        collect(&paired, &mut found);
+      }
+      let inquiry = IOBluetoothDeviceInquiry::inquiryWithDelegate(None).ok_or_else(|| {
+        PrintError::Enumerate("Unable to start Bluetooth discovery. Check that Bluetooth is turned on.".to_string())
+      })?;
+      inquiry.setUpdateNewDeviceNames(true);
+      inquiry.setInquiryLength(duration.as_secs().clamp(1, u8::MAX as u64) as u8);
+      let status = inquiry.start();
+      if status != 0 {
+        return Err(PrintError::Enumerate(format!(
+          "Bluetooth discovery failed (IOReturn {status:#x}). Check that Bluetooth is turned on and the app is allowed to use it."
+        )));
+      }
+      let run_loop = NSRunLoop::currentRunLoop();
+      while !cancel.is_cancelled() && Instant::now() < deadline {
+        let round = ROUND.min(deadline.saturating_duration_since(Instant::now()));
+        let until = NSDate::dateWithTimeIntervalSinceNow(round.as_secs_f64());
+        if !run_loop.runMode_beforeDate(NSDefaultRunLoopMode, &until) {
+          // Nothing attached to the run loop: the inquiry has finished.
+          thread::sleep(round);
+        }
+      }
+      inquiry.stop();
+      if let Some(devices) = inquiry.foundDevices() {
+        collect(&devices, &mut found);
+      }
+    }
+    Ok(found.into_values().collect())
+  }
+
+  unsafe fn collect(devices: &NSArray, found: &mut BTreeMap<String, BluetoothDevice>) {
+    for object in devices.iter() {
+      let Ok(device) = object.downcast::<IOBluetoothDevice>() else {
+        continue;
+      };
+      let Some(address) = device.addressString() else {
+        continue;
+      };
+      let address = normalize_address(&address.to_string());
+      let class_of_device = device.classOfDevice();
+      let rssi = device.RSSI();
+      found.insert(
+        address.clone(),
+        BluetoothDevice {
+          name: device.nameOrAddress().map(|n| n.to_string()).unwrap_or_else(|| address.clone()),
+          address,
+          class_of_device,
+          likely_printer: likely_printer(class_of_device),
+          paired: device.isPaired(),
+          connected: device.isConnected(),
+          rssi: (rssi != NO_RSSI).then_some(rssi as i16),
+        },
+      );
+    }
+  }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+mod imp {
+  use std::time::Duration;
+
+  use super::BluetoothDevice;
+  use crate::cancel::CancelToken;
+  use crate::error::PrintError;
+
+  pub fn discover(_duration: Duration, _cancel: &CancelToken) -> Result<Vec<BluetoothDevice>, PrintError> {
+    Err(PrintError::Unsupported(
+      "Bluetooth discovery isn't available on this platform yet. Pair the printer in the system settings and print to it over the network or its serial port.".to_string(),
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn address_is_big_endian_hex() {
+    assert_eq!(format_address(0x0011_22AA_BBCC), "00:11:22:AA:BB:CC");
+    assert_eq!(format_address(0), "00:00:00:00:00:00");
+  }
+
+  #[test]
+  fn printer_class_needs_imaging_major_and_printer_minor() {
+    // Imaging, printer.
+    assert!(likely_printer(0x000680));
+    // Service bits set too.
+    assert!(likely_printer(0x040680));
+    // Imaging, camera only.
+    assert!(!likely_printer(0x000620));
+    // Audio/video with the same minor bit.
+    assert!(!likely_printer(0x000480));
+    assert!(!likely_printer(0));
+  }
+
+  #[test]
+  fn iobluetooth_address_is_normalized() {
+    assert_eq!(normalize_address("00-11-22-aa-bb-cc"), "00:11:22:AA:BB:CC");
+  }
+
+  #[cfg(target_os = "linux")]
+  #[test]
+  fn bluez_device_properties_are_read() {
+    use zbus::zvariant::{OwnedValue, Str};
+
+    let mut properties = imp::Properties::new();
+    properties.insert("Address".to_string(), OwnedValue::from(Str::from("00:11:22:AA:BB:CC")));
+    properties.insert("Alias".to_string(), OwnedValue::from(Str::from("RPP02N")));
+    properties.insert("Class".to_string(), OwnedValue::from(0x040680u32));
+    properties.insert("Paired".to_string(), OwnedValue::from(true));
+    properties.insert("RSSI".to_string(), OwnedValue::from(-61i16));
+    let device = imp::device(&properties).unwrap();
+    assert_eq!(device.name, "RPP02N");
+    assert_eq!(device.address, "00:11:22:AA:BB:CC");
+    assert!(device.likely_printer);
+    assert!(device.paired);
+    assert!(!device.connected);
+    assert_eq!(device.rssi, Some(-61));
+
+    // No address, nothing to connect to.
+    properties.remove("Address");
+    assert!(imp::device(&properties).is_none());
+  }
+}
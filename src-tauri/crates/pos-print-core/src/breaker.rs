@@ -0,0 +1,177 @@
+//! Per-destination circuit breakers, so a printer that keeps failing fails
+//! new jobs at once instead of each one waiting out its connect timeouts
+//! and retries.
+//!
+//! After `breaker_threshold` consecutive failures the circuit opens and
+//! jobs fail with `circuit_open`. Once `breaker_cooldown_ms` has passed it
+//! is half-open: the next job goes through as a probe, closing the circuit
+//! if it prints and reopening it for another cooldown if it doesn't.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::config::PrintConfig;
+use crate::error::{ErrorDetail, PrintError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+  Closed,
+  Open,
+  /// Cooled down; the next job is a probe, or one is in flight.
+  HalfOpen,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CircuitStatus {
+  pub destination: String,
+  pub state: CircuitState,
+  pub consecutive_failures: u32,
+  /// Until the circuit half-opens, when open.
+  pub retry_after_ms: Option<u64>,
+}
+
+#[derive(Default)]
+struct Circuit {
+  failures: u32,
+  opened_at: Option<Instant>,
+  /// A half-open probe job is in flight.
+  probing: bool,
+}
+
+impl Circuit {
+  fn state(&self, cooldown: Duration) -> CircuitState {
+    match self.opened_at {
+      None => CircuitState::Closed,
+      Some(at) if at.elapsed() < cooldown => CircuitState::Open,
+      Some(_) => CircuitState::HalfOpen,
+    }
+  }
+}
+
+#[derive(Clone, Default)]
+pub struct CircuitBreakers {
+  circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+}
+
+impl CircuitBreakers {
+  /// Lets a job for `destination` through, or fails it with `CircuitOpen`
+  /// while the circuit is open or a half-open probe is still running.
+  pub fn admit(&self, destination: &str, cfg: &PrintConfig) -> Result<(), PrintError> {
+    if cfg.breaker_threshold == 0 {
+      return Ok(());
+    }
+    let cooldown = Duration::from_millis(cfg.breaker_cooldown_ms);
+    let mut circuits = self.lock();
+    let Some(circuit) = circuits.get_mut(destination) else {
+      return Ok(());
+    };
+    let detail = match circuit.state(cooldown) {
+      CircuitState::Closed => return Ok(()),
+      CircuitState::HalfOpen if !circuit.probing => {
+        circuit.probing = true;
+        log::info!("{destination} circuit half-open, sending a probe job");
+        return Ok(());
+      }
+      CircuitState::HalfOpen => ErrorDetail::new(format!(
+        "{destination} failed its last {} jobs and a test job is still finding out whether it is back. Try again shortly.",
+        circuit.failures
+      )),
+      CircuitState::Open => {
+        let retry_after = cooldown.saturating_sub(circuit.opened_at.map_or(cooldown, |at| at.elapsed()));
+        ErrorDetail::new(format!(
+          "{destination} failed its last {} jobs, so new jobs are refused for {} s. Check the printer is on and connected.",
+          circuit.failures,
+          retry_after.as_secs().max(1)
+        ))
+        .param("retry_after_ms", retry_after.as_millis())
+      }
+    };
+    Err(PrintError::CircuitOpen(
+      detail.param("destination", destination).param("failures", circuit.failures),
+    ))
+  }
+
+  /// Counts a finished or refused job's result against `destination`.
+  pub fn record(&self, destination: &str, result: &Result<(), PrintError>, cfg: &PrintConfig) {
+    if cfg.breaker_threshold == 0 {
+      return;
+    }
+    let mut circuits = self.lock();
+    match result {
+      Ok(()) => {
+        if circuits.remove(destination).is_some_and(|c| c.opened_at.is_some()) {
+          log::info!("{destination} circuit closed, the printer is back");
+        }
+      }
+      Err(e) if counts(e) => {
+        let circuit = circuits.entry(destination.to_string()).or_default();
+        circuit.failures += 1;
+        if circuit.probing || (circuit.opened_at.is_none() && circuit.failures >= cfg.breaker_threshold) {
+          log::warn!("{destination} circuit open after {} consecutive failures", circuit.failures);
+          circuit.opened_at = Some(Instant::now());
+          circuit.probing = false;
+        }
+      }
+      Err(PrintError::CircuitOpen(_)) => {}
+      // Says nothing about the printer; a probe that ended this way frees
+      // the slot for another.
+      Err(_) => {
+        if let Some(circuit) = circuits.get_mut(destination) {
+          circuit.probing = false;
+        }
+      }
+    }
+  }
+
+  /// Destinations with failures on record; unlisted ones are closed.
+  pub fn status(&self, cfg: &PrintConfig) -> Vec<CircuitStatus> {
+    let cooldown = Duration::from_millis(cfg.breaker_cooldown_ms);
+    let mut out = self
+      .lock()
+      .iter()
+      .map(|(destination, circuit)| {
+        let state = circuit.state(cooldown);
+        CircuitStatus {
+          destination: destination.clone(),
+          state,
+          consecutive_failures: circuit.failures,
+          retry_after_ms: match (state, circuit.opened_at) {
+            (CircuitState::Open, Some(at)) => Some(cooldown.saturating_sub(at.elapsed()).as_millis() as u64),
+            _ => None,
+          },
+        }
+      })
+      .collect::<Vec<_>>();
+    out.sort_by(|a, b| a.destination.cmp(&b.destination));
+    out
+  }
+
+  /// Closes `destination`'s circuit, e.g. once someone has fixed the
+  /// printer. Returns whether it had failures on record.
+  pub fn reset(&self, destination: &str) -> bool {
+    self.lock().remove(destination).is_some()
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Circuit>> {
+    self.circuits.lock().unwrap_or_else(|e| e.into_inner())
+  }
+}
+
+/// Failures meaning the printer can't be reached or isn't taking data, as
+/// opposed to a bad request, a paper fault or a cancelled job.
+fn counts(e: &PrintError) -> bool {
+  matches!(
+    e,
+    PrintError::Resolve(_)
+      | PrintError::Connect(_)
+      | PrintError::Write(_)
+      | PrintError::Read(_)
+      | PrintError::Timeout(_)
+      | PrintError::SerialOpen(_)
+      | PrintError::Spooler(_)
+  )
+}
@@ -0,0 +1,393 @@
+//! Local HTTP print bridge for browser-based POS clients.
+//!
+//! Endpoints (all require `Authorization: Bearer <token>`):
+//! - `POST /print/tcp?host=H&port=P`
+//! - `POST /print/serial?port=COM3&baud=9600`
+//! - `POST /print/spooler?printer=NAME`
+//! - `GET /printers`
+//! - `GET /status`
+//!
+//! Print bodies are either raw bytes (`application/octet-stream`) or JSON
+//! `{ "data_base64": "..." }`; in the JSON form the destination fields may
+//! also be given in the body instead of the query string. Each print is a
+//! job like the app's own: checked for duplicates, archived, kept for
+//! reprints and settled under the `http` source.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::audit::{AuditEntry, AuditLog};
+use crate::config::ConfigStore;
+use crate::error::PrintError;
+use crate::job::{JobRecord, JobSinks, PrintOptions};
+use crate::target::Target;
+use crate::workers::WorkerPool;
+use crate::{serial, spooler};
+
+pub const DEFAULT_PORT: u16 = 9120;
+const HANDLER_THREADS: usize = 4;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BridgeConfig {
+  #[serde(default = "default_port")]
+  pub port: u16,
+  pub token: String,
+  /// Accept connections from other machines. Off by default: the bridge
+  /// binds to loopback and rejects any non-loopback peer.
+  #[serde(default)]
+  pub allow_remote: bool,
+}
+
+fn default_port() -> u16 {
+  DEFAULT_PORT
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BridgeInfo {
+  pub running: bool,
+  pub address: Option<String>,
+  pub allow_remote: bool,
+}
+
+struct Running {
+  server: Arc<Server>,
+  threads: Vec<JoinHandle<()>>,
+  address: SocketAddr,
+  allow_remote: bool,
+}
+
+#[derive(Clone)]
+struct Ctx {
+  workers: WorkerPool,
+  config: ConfigStore,
+  audit: AuditLog,
+  sinks: JobSinks,
+  token: Arc<str>,
+  allow_remote: bool,
+}
+
+/// Owns the bridge server while it runs.
+#[derive(Default)]
+pub struct PrintBridge {
+  running: Mutex<Option<Running>>,
+}
+
+impl PrintBridge {
+  pub fn start(
+    &self,
+    cfg: BridgeConfig,
+    workers: WorkerPool,
+    config: ConfigStore,
+    audit: AuditLog,
+    sinks: JobSinks,
+  ) -> Result<BridgeInfo, PrintError> {
+    if cfg.token.trim().len() < 16 {
+      return Err(PrintError::InvalidRequest(
+        "Bridge token must be at least 16 characters.".to_string(),
+      ));
+    }
+
+    let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+    if running.is_some() {
+      return Err(PrintError::InvalidRequest("Print bridge is already running.".to_string()));
+    }
+
+    let ip = if cfg.allow_remote {
+      IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+      IpAddr::V4(Ipv4Addr::LOCALHOST)
+    };
+    let address = SocketAddr::new(ip, cfg.port);
+    let server = Arc::new(Server::http(address).map_err(|e| {
+      PrintError::Connect(format!("Unable to start print bridge on {address}: {e}. Is the port in use?").into())
+    })?);
+
+    let ctx = Ctx {
+      workers,
+      config,
+      audit,
+      sinks,
+      token: Arc::from(cfg.token.trim()),
+      allow_remote: cfg.allow_remote,
+    };
+    let threads = (0..HANDLER_THREADS)
+      .filter_map(|i| {
+        let server = server.clone();
+        let ctx = ctx.clone();
+        thread::Builder::new()
+          .name(format!("print-bridge-{i}"))
+          .spawn(move || {
+            while let Ok(request) = server.recv() {
+              handle(&ctx, request);
+            }
+          })
+          .map_err(|e| log::error!("failed to spawn print bridge thread: {e}"))
+          .ok()
+      })
+      .collect::<Vec<_>>();
+
+    log::info!("print bridge listening on {address}");
+    *running = Some(Running {
+      server,
+      threads,
+      address,
+      allow_remote: cfg.allow_remote,
+    });
+    Ok(info(running.as_ref()))
+  }
+
+  pub fn stop(&self) -> BridgeInfo {
+    let taken = self.running.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(running) = taken {
+      for _ in &running.threads {
+        running.server.unblock();
+      }
+      for t in running.threads {
+        let _ = t.join();
+      }
+      log::info!("print bridge on {} stopped", running.address);
+    }
+    info(None)
+  }
+
+  pub fn info(&self) -> BridgeInfo {
+    info(self.running.lock().unwrap_or_else(|e| e.into_inner()).as_ref())
+  }
+}
+
+fn info(running: Option<&Running>) -> BridgeInfo {
+  BridgeInfo {
+    running: running.is_some(),
+    address: running.map(|r| r.address.to_string()),
+    allow_remote: running.is_some_and(|r| r.allow_remote),
+  }
+}
+
+fn handle(ctx: &Ctx, mut request: Request) {
+  let peer = request.remote_addr().copied();
+  let method = request.method().clone();
+  let (path, query) = split_url(request.url());
+
+  let mut entry = AuditEntry::new("http", &format!("{method} {path}"));
+  if let Some(peer) = peer {
+    entry = entry.detail(format!("peer={peer}"));
+  }
+
+  let result = route(ctx, &mut request, &mut entry, peer, &method, &path, &query);
+  let (status, body) = match &result {
+    Ok(v) => (200, json!({ "ok": true, "result": v })),
+    Err((status, e)) => (*status, json!({ "ok": false, "error": e })),
+  };
+  ctx.audit.record(entry.outcome(&result.as_ref().map_err(|(_, e)| e)));
+
+  let response = Response::from_string(body.to_string())
+    .with_status_code(status)
+    .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("static header is valid"));
+  let _ = request.respond(response);
+}
+
+type Routed = Result<Value, (u16, PrintError)>;
+
+fn route(
+  ctx: &Ctx,
+  request: &mut Request,
+  entry: &mut AuditEntry,
+  peer: Option<SocketAddr>,
+  method: &Method,
+  path: &str,
+  query: &HashMap<String, String>,
+) -> Routed {
+  if !ctx.allow_remote && !peer.is_some_and(|p| p.ip().is_loopback()) {
+    return Err((
+      403,
+      PrintError::InvalidRequest("Print bridge only accepts local connections.".to_string()),
+    ));
+  }
+  if !authorized(request, &ctx.token) {
+    return Err((401, PrintError::InvalidRequest("Missing or invalid bearer token.".to_string())));
+  }
+
+  match (method, path) {
+    (Method::Post, "/print/tcp" | "/print/serial" | "/print/spooler") => {
+      let (body, fields) = read_body(ctx, request)?;
+      let param = |name: &str| -> Result<String, (u16, PrintError)> {
+        fields
+          .get(name)
+          .cloned()
+          .or_else(|| query.get(name).cloned())
+          .ok_or_else(|| bad_request(format!("Missing '{name}' parameter.")))
+      };
+      let target = match path {
+        "/print/tcp" => Target::Tcp {
+          host: param("host")?,
+          port: param("port")?.parse().map_err(|_| bad_request("Invalid 'port'.".to_string()))?,
+        },
+        "/print/serial" => Target::Serial {
+          port: param("port")?,
+          baud: param("baud")?.parse().map_err(|_| bad_request("Invalid 'baud'.".to_string()))?,
+        },
+        _ => Target::Spooler {
+          printer_name: param("printer")?,
+          devmode: None,
+        },
+      };
+
+      let bytes = body.len();
+      let destination = target.label();
+      entry.destination = Some(destination.clone());
+      entry.bytes = Some(bytes);
+      let record = JobRecord::new(&target, &body, PrintOptions::default());
+      record
+        .print_blocking(&ctx.workers, &ctx.sinks, &ctx.config.snapshot(), "http", body)
+        .map(|outcome| {
+          json!({ "destination": destination, "bytes": bytes, "job_id": outcome.job_id, "status": outcome.status })
+        })
+        .map_err(|e| (502, e))
+    }
+    (Method::Get, "/printers") => {
+      let serial_ports = serial::list_ports()
+        .map_err(|e| (500, e))?
+        .into_iter()
+        .map(|p| {
+          json!({
+            "port_name": p.port_name,
+            "port_type": p.kind.as_str(),
+            "manufacturer": p.manufacturer,
+            "product": p.product,
+            "serial_number": p.serial_number,
+            "vid": p.vid,
+            "pid": p.pid,
+          })
+        })
+        .collect::<Vec<_>>();
+      let spooler_printers = spooler::list_printers().map_err(|e| (500, e))?;
+      Ok(json!({ "serial_ports": serial_ports, "spooler_printers": spooler_printers }))
+    }
+    (Method::Get, "/status") => Ok(json!({ "workers": ctx.workers.stats() })),
+    _ => Err((404, PrintError::InvalidRequest(format!("No route for {method} {path}.")))),
+  }
+}
+
+fn bad_request(message: String) -> (u16, PrintError) {
+  (400, PrintError::InvalidRequest(message))
+}
+
+fn authorized(request: &Request, token: &str) -> bool {
+  request
+    .headers()
+    .iter()
+    .find(|h| h.field.equiv("Authorization"))
+    .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+    .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  if a.len() != b.len() {
+    return false;
+  }
+  a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Deserialize)]
+struct JsonPrintBody {
+  data_base64: String,
+  #[serde(flatten)]
+  fields: HashMap<String, Value>,
+}
+
+/// Payload bytes and the JSON body's other fields, or the HTTP status and
+/// error to answer with.
+type Body = Result<(Vec<u8>, HashMap<String, String>), (u16, PrintError)>;
+
+/// Reads the payload, capped at the configured maximum, decoding the JSON
+/// base64 form when the content type says so.
+fn read_body(ctx: &Ctx, request: &mut Request) -> Body {
+  let limit = ctx.config.snapshot().max_payload_bytes;
+  let is_json = request
+    .headers()
+    .iter()
+    .any(|h| h.field.equiv("Content-Type") && h.value.as_str().starts_with("application/json"));
+
+  let mut raw = Vec::new();
+  request
+    .as_reader()
+    .take(limit as u64 * 2 + 1)
+    .read_to_end(&mut raw)
+    .map_err(|e| bad_request(format!("Unable to read request body: {e}.")))?;
+
+  let (data, fields) = if is_json {
+    let parsed: JsonPrintBody =
+      serde_json::from_slice(&raw).map_err(|e| bad_request(format!("Invalid JSON body: {e}.")))?;
+    let data = base64::engine::general_purpose::STANDARD
+      .decode(parsed.data_base64.trim())
+      .map_err(|e| bad_request(format!("Invalid base64 in 'data_base64': {e}.")))?;
+    let fields = parsed
+      .fields
+      .into_iter()
+      .map(|(k, v)| {
+        let v = match v {
+          Value::String(s) => s,
+          other => other.to_string(),
+        };
+        (k, v)
+      })
+      .collect();
+    (data, fields)
+  } else {
+    (raw, HashMap::new())
+  };
+
+  if data.is_empty() {
+    return Err(bad_request("Print body is empty.".to_string()));
+  }
+  if data.len() > limit {
+    return Err((
+      413,
+      PrintError::InvalidRequest(format!("Print payload exceeds the {limit} byte limit.")),
+    ));
+  }
+  Ok((data, fields))
+}
+
+fn split_url(url: &str) -> (String, HashMap<String, String>) {
+  let (path, query) = url.split_once('?').unwrap_or((url, ""));
+  let params = query
+    .split('&')
+    .filter(|p| !p.is_empty())
+    .map(|p| {
+      let (k, v) = p.split_once('=').unwrap_or((p, ""));
+      (percent_decode(k), percent_decode(v))
+    })
+    .collect();
+  (path.to_string(), params)
+}
+
+fn percent_decode(s: &str) -> String {
+  let bytes = s.as_bytes();
+  let mut out = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    match bytes[i] {
+      b'+' => out.push(b' '),
+      b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+        (Some(hi), Some(lo)) => {
+          out.push(hi << 4 | lo);
+          i += 2;
+        }
+        _ => out.push(b'%'),
+      },
+      b => out.push(b),
+    }
+    i += 1;
+  }
+  String::from_utf8_lossy(&out).into_owned()
+}
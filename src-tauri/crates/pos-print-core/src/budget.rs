@@ -0,0 +1,104 @@
+//! Per-destination retry budgets, so a flapping printer with a busy queue
+//! can't turn every job's retries into a retry storm.
+//!
+//! Each destination has a token bucket of `retry_budget` tokens, refilled
+//! one per `retry_budget_refill_ms`. A connect retry or offline reconnect
+//! spends a token; once the bucket is empty, jobs fail on their first error
+//! instead of retrying until it refills. First attempts are never limited.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::config::PrintConfig;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RetryBudgetStatus {
+  pub destination: String,
+  /// Retries left; `capacity` when fully refilled.
+  pub tokens: u32,
+  pub capacity: u32,
+  /// Retries refused since the budget ran out.
+  pub refused: u64,
+}
+
+struct Bucket {
+  tokens: f64,
+  refilled_at: Instant,
+  refused: u64,
+}
+
+impl Bucket {
+  fn refill(&mut self, capacity: u32, every: Duration) {
+    let now = Instant::now();
+    let earned = now.duration_since(self.refilled_at).as_secs_f64() / every.as_secs_f64();
+    self.tokens = (self.tokens + earned).min(capacity as f64);
+    self.refilled_at = now;
+  }
+}
+
+#[derive(Clone, Default)]
+pub struct RetryBudgets {
+  buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RetryBudgets {
+  /// Takes a token for one more attempt at `destination`; false when its
+  /// budget is spent and the job should fail instead.
+  pub fn spend(&self, destination: &str, cfg: &PrintConfig) -> bool {
+    if cfg.retry_budget == 0 {
+      return true;
+    }
+    let mut buckets = self.lock();
+    let bucket = buckets.entry(destination.to_string()).or_insert_with(|| Bucket {
+      tokens: cfg.retry_budget as f64,
+      refilled_at: Instant::now(),
+      refused: 0,
+    });
+    bucket.refill(cfg.retry_budget, refill_every(cfg));
+    if bucket.tokens < 1.0 {
+      if bucket.refused == 0 {
+        log::warn!("{destination} used up its retry budget; jobs fail without retrying until it refills");
+      }
+      bucket.refused += 1;
+      return false;
+    }
+    if bucket.refused > 0 {
+      log::info!("{destination} retry budget refilled after refusing {} retries", bucket.refused);
+      bucket.refused = 0;
+    }
+    bucket.tokens -= 1.0;
+    true
+  }
+
+  /// Destinations that have spent some of their budget; unlisted ones are
+  /// full.
+  pub fn status(&self, cfg: &PrintConfig) -> Vec<RetryBudgetStatus> {
+    let mut buckets = self.lock();
+    for bucket in buckets.values_mut() {
+      bucket.refill(cfg.retry_budget, refill_every(cfg));
+    }
+    buckets.retain(|_, b| b.tokens < cfg.retry_budget as f64);
+    let mut out = buckets
+      .iter()
+      .map(|(destination, bucket)| RetryBudgetStatus {
+        destination: destination.clone(),
+        tokens: bucket.tokens as u32,
+        capacity: cfg.retry_budget,
+        refused: bucket.refused,
+      })
+      .collect::<Vec<_>>();
+    out.sort_by(|a, b| a.destination.cmp(&b.destination));
+    out
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Bucket>> {
+    self.buckets.lock().unwrap_or_else(|e| e.into_inner())
+  }
+}
+
+fn refill_every(cfg: &PrintConfig) -> Duration {
+  Duration::from_millis(cfg.retry_budget_refill_ms.max(1))
+}
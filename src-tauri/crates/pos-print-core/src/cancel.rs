@@ -0,0 +1,58 @@
+//! Cancellation of queued and running print jobs.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use crate::error::PrintError;
+
+#[derive(Default)]
+struct State {
+  cancelled: AtomicBool,
+  started: AtomicBool,
+  /// Windows spooler job id once the document is open, otherwise 0.
+  spooler_job: AtomicU32,
+}
+
+/// Shared between a job and whoever may abort it. Writers check it between
+/// chunks, so a running job stops at the next chunk boundary.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<State>);
+
+impl CancelToken {
+  pub fn cancel(&self) {
+    self.0.cancelled.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.0.cancelled.load(Ordering::SeqCst)
+  }
+
+  /// Fails with `Aborted` once the job has been cancelled, unless all
+  /// `total` bytes have already gone out.
+  pub fn check(&self, destination: &str, sent: usize, total: usize) -> Result<(), PrintError> {
+    if !self.is_cancelled() || (sent > 0 && sent >= total) {
+      return Ok(());
+    }
+    Err(PrintError::Aborted(if sent == 0 {
+      format!("Job for {destination} was aborted before anything was sent.")
+    } else {
+      format!("Job for {destination} was aborted after {sent} of {total} bytes; the printout is incomplete.")
+    }))
+  }
+
+  pub fn mark_started(&self) {
+    self.0.started.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_started(&self) -> bool {
+    self.0.started.load(Ordering::SeqCst)
+  }
+
+  pub fn set_spooler_job(&self, job_id: u32) {
+    self.0.spooler_job.store(job_id, Ordering::SeqCst);
+  }
+
+  pub fn spooler_job(&self) -> Option<u32> {
+    Some(self.0.spooler_job.load(Ordering::SeqCst)).filter(|&id| id != 0)
+  }
+}
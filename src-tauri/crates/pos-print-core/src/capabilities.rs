@@ -0,0 +1,54 @@
+//! What this build can do, so the UI can hide options the deployed binary
+//! doesn't support and support can confirm which build is installed.
+
+use serde::Serialize;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Transports {
+  pub tcp: bool,
+  pub serial: bool,
+  /// Raw printing through the Windows print spooler.
+  pub windows_spooler: bool,
+  /// Raw writes straight to Windows COM/LPT port devices.
+  pub windows_port: bool,
+  /// Printing through CUPS queues. Not implemented yet on any platform.
+  pub cups: bool,
+  /// Direct USB printer-class access. Not implemented yet on any platform;
+  /// USB printers are reached through their serial or spooler interface.
+  pub usb: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Capabilities {
+  /// `pos-print-core` version.
+  pub version: &'static str,
+  /// `std::env::consts::OS` and `ARCH` of the build, e.g. `windows`/`x86_64`.
+  pub os: &'static str,
+  pub arch: &'static str,
+  pub transports: Transports,
+  /// `get_printer_driver_info` and DEVMODE overrides for spooler jobs.
+  pub spooler_driver_info: bool,
+  pub serial_enumeration: bool,
+  /// `discover_bluetooth_devices`.
+  pub bluetooth_discovery: bool,
+}
+
+pub fn capabilities() -> Capabilities {
+  let windows = cfg!(target_os = "windows");
+  Capabilities {
+    version: env!("CARGO_PKG_VERSION"),
+    os: std::env::consts::OS,
+    arch: std::env::consts::ARCH,
+    transports: Transports {
+      tcp: true,
+      serial: true,
+      windows_spooler: windows,
+      windows_port: windows,
+      cups: false,
+      usb: false,
+    },
+    spooler_driver_info: windows,
+    serial_enumeration: true,
+    bluetooth_discovery: cfg!(any(target_os = "windows", target_os = "linux", target_os = "macos")),
+  }
+}
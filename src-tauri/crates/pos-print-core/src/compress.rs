@@ -0,0 +1,62 @@
+//! Gzip for payloads: large raster jobs shrink several-fold, which cuts the
+//! IPC transfer from the frontend and the reprint copies kept on disk.
+
+use std::io::{Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+
+use crate::error::PrintError;
+
+/// How the frontend sent a payload's bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadEncoding {
+  #[default]
+  Raw,
+  Gzip,
+}
+
+impl PayloadEncoding {
+  /// Parses the `x-print-encoding` header value.
+  pub fn parse(value: &str) -> Result<Self, PrintError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+      "" | "raw" | "identity" => Ok(PayloadEncoding::Raw),
+      "gzip" => Ok(PayloadEncoding::Gzip),
+      other => Err(PrintError::InvalidRequest(format!(
+        "Unsupported payload encoding '{other}'. Expected 'raw' or 'gzip'."
+      ))),
+    }
+  }
+}
+
+/// `data` as the printer should get it. A gzip payload that expands past
+/// `max_bytes` is refused without decompressing the rest.
+pub fn decode(data: Vec<u8>, encoding: PayloadEncoding, max_bytes: usize) -> Result<Vec<u8>, PrintError> {
+  match encoding {
+    PayloadEncoding::Raw => Ok(data),
+    PayloadEncoding::Gzip => gunzip(&data, max_bytes),
+  }
+}
+
+pub fn gunzip(data: &[u8], max_bytes: usize) -> Result<Vec<u8>, PrintError> {
+  let mut out = Vec::with_capacity(data.len().saturating_mul(4).min(max_bytes));
+  GzDecoder::new(data)
+    .take(max_bytes as u64 + 1)
+    .read_to_end(&mut out)
+    .map_err(|e| PrintError::InvalidRequest(format!("The gzip payload could not be decompressed: {e}. Resend it.")))?;
+  if out.len() > max_bytes {
+    return Err(PrintError::InvalidRequest(format!(
+      "The gzip payload expands past the {max_bytes}-byte limit (max_payload_bytes); it was not printed."
+    )));
+  }
+  Ok(out)
+}
+
+pub fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+  let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 4), Compression::fast());
+  encoder.write_all(data)?;
+  encoder.finish()
+}
@@ -0,0 +1,823 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::alerts::Severity;
+use crate::error::PrintError;
+use crate::escpos::BuzzerModel;
+use crate::quiet::QuietHours;
+
+/// Shortest `nudge_interval_ms`, so nudges can't crowd out print jobs.
+pub const MIN_NUDGE_INTERVAL_MS: u64 = 1000;
+
+/// Longest wait between retries, however far exponential backoff has grown.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How a job retries reaching its printer. Only failures before any byte
+/// is sent are retried, so a retry never prints a receipt twice.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+  /// Total attempts per job, including the first one.
+  pub max_attempts: u32,
+  /// Wait before the first retry.
+  pub backoff_ms: u64,
+  pub backoff: Backoff,
+  /// Failures worth retrying; the rest fail the job at once.
+  pub retry_on: BTreeSet<RetryClass>,
+  /// Stop retrying once this long has passed since the first attempt;
+  /// 0 leaves it to `max_attempts`.
+  pub deadline_ms: u64,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self {
+      max_attempts: 1,
+      backoff_ms: 250,
+      backoff: Backoff::Fixed,
+      retry_on: [RetryClass::Resolve, RetryClass::Connect, RetryClass::SerialOpen].into(),
+      deadline_ms: 0,
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// Wait before retry number `retry` (1 for the first).
+  pub fn delay(&self, retry: u32) -> Duration {
+    let base = Duration::from_millis(self.backoff_ms);
+    match self.backoff {
+      Backoff::Fixed => base,
+      Backoff::Exponential => base.saturating_mul(1 << retry.saturating_sub(1).min(16)).min(MAX_BACKOFF),
+    }
+  }
+
+  /// Whether `error` is one of the classes in `retry_on`.
+  pub fn retries(&self, error: &PrintError) -> bool {
+    RetryClass::of(error).is_some_and(|class| self.retry_on.contains(&class))
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backoff {
+  /// `backoff_ms` before every retry.
+  #[default]
+  Fixed,
+  /// `backoff_ms`, doubling each retry, up to 30 s.
+  Exponential,
+}
+
+/// Kinds of failure a retry policy can retry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryClass {
+  /// The printer's host name didn't resolve.
+  Resolve,
+  /// The TCP connection was refused or timed out.
+  Connect,
+  /// The serial port was missing or busy.
+  SerialOpen,
+}
+
+impl RetryClass {
+  pub fn of(error: &PrintError) -> Option<Self> {
+    match error {
+      PrintError::Resolve(_) => Some(RetryClass::Resolve),
+      PrintError::Connect(_) => Some(RetryClass::Connect),
+      PrintError::SerialOpen(_) => Some(RetryClass::SerialOpen),
+      _ => None,
+    }
+  }
+}
+
+/// Which retry policy a job ran under.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetrySource {
+  /// `PrintConfig::retry`.
+  Default,
+  /// The destination's entry in `PrintConfig::retry_overrides`.
+  Destination,
+  /// The job's own `PrintOptions::retry`.
+  Call,
+}
+
+impl RetrySource {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      RetrySource::Default => "default",
+      RetrySource::Destination => "destination",
+      RetrySource::Call => "call",
+    }
+  }
+}
+
+/// Bursting for TCP printers whose receive buffer is too small for a large
+/// job sent at full speed, typically cheap Wi-Fi modules that drop the
+/// overflow and print receipts with missing bands.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TcpPacing {
+  /// Bytes sent before pausing; a little under the printer's buffer size.
+  pub max_burst_bytes: usize,
+  pub inter_burst_delay_ms: u64,
+  /// After each burst, also wait for the printer to answer a DLE EOT status
+  /// request as online before sending more. Only for printers that answer
+  /// DLE EOT; the answer comes once the burst ahead of the request has
+  /// been taken in.
+  #[serde(default)]
+  pub wait_for_ready: bool,
+}
+
+/// Default pacing by GS I model name prefix, for destinations with a
+/// `printer_models` entry but no `tcp_pacing`. These Wi-Fi modules have
+/// 8-16 KB buffers and don't reliably answer DLE EOT mid-job. Add models
+/// here as they're confirmed.
+const MODEL_PACING: &[(&str, TcpPacing)] = &[
+  ("POS-58", TcpPacing { max_burst_bytes: 4096, inter_burst_delay_ms: 60, wait_for_ready: false }),
+  ("POS-80", TcpPacing { max_burst_bytes: 8192, inter_burst_delay_ms: 60, wait_for_ready: false }),
+  ("ZJ-58", TcpPacing { max_burst_bytes: 4096, inter_burst_delay_ms: 60, wait_for_ready: false }),
+  ("XP-58", TcpPacing { max_burst_bytes: 4096, inter_burst_delay_ms: 50, wait_for_ready: false }),
+  ("XP-80", TcpPacing { max_burst_bytes: 8192, inter_burst_delay_ms: 50, wait_for_ready: false }),
+];
+
+/// How many jobs may print at once. A destination always runs one job at a
+/// time since its worker owns the connection.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConcurrencyLimits {
+  /// Jobs in flight across all destinations.
+  pub max_in_flight: usize,
+  /// Per-transport caps; 0 leaves the transport bound only by `max_in_flight`.
+  pub tcp: usize,
+  pub serial: usize,
+  pub spooler: usize,
+}
+
+impl Default for ConcurrencyLimits {
+  fn default() -> Self {
+    Self {
+      max_in_flight: 4,
+      tcp: 0,
+      serial: 0,
+      spooler: 0,
+    }
+  }
+}
+
+impl ConcurrencyLimits {
+  /// The cap for a transport kind as returned by `Target::kind`, or `None`.
+  pub fn transport_limit(&self, kind: &str) -> Option<usize> {
+    let limit = match kind {
+      "tcp" => self.tcp,
+      "serial" => self.serial,
+      "spooler" => self.spooler,
+      _ => 0,
+    };
+    (limit > 0).then_some(limit)
+  }
+}
+
+/// Transport tuning shared by every print command.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PrintConfig {
+  pub connect_timeout_ms: u64,
+  /// How long a hostname lookup may take before falling back to the cache.
+  pub dns_timeout_ms: u64,
+  /// Base write timeout per chunk; see [`PrintConfig::chunk_write_timeout`].
+  pub write_timeout_ms: u64,
+  /// Slowest expected link speed in bytes per second, used to stretch the
+  /// write timeout for larger chunks.
+  pub write_throughput_bps: u64,
+  /// Per-destination `write_timeout_ms`, keyed by `Target::label`.
+  pub write_timeout_overrides: BTreeMap<String, u64>,
+  /// Per-destination `write_throughput_bps`, keyed by `Target::label`.
+  pub write_throughput_overrides: BTreeMap<String, u64>,
+  /// How long to wait for a reply to a real-time status request.
+  pub status_timeout_ms: u64,
+  /// How long to keep retrying a serial port that reports busy or access
+  /// denied on open; 0 fails on the first attempt.
+  pub serial_busy_retry_ms: u64,
+  pub chunk_size: usize,
+  pub chunk_delay_ms: u64,
+  /// Pause after a job's last byte is flushed, before the connection may
+  /// close. Some printers drop the last buffered line if the socket or port
+  /// closes right away.
+  pub post_write_delay_ms: u64,
+  /// Per-destination `post_write_delay_ms`, keyed by `Target::label`.
+  pub post_write_delay_overrides: BTreeMap<String, u64>,
+  pub max_payload_bytes: usize,
+  pub retry: RetryPolicy,
+  /// Per-destination `retry`, keyed by `Target::label`, for printers
+  /// whose link is better or worse than most.
+  pub retry_overrides: BTreeMap<String, RetryPolicy>,
+  /// How long a job is held when its printer can't be reached, reconnecting
+  /// periodically, before it fails. Rides out brief Wi-Fi drops that the
+  /// quick retries in `retry` don't cover. 0 fails right away.
+  pub offline_grace_ms: u64,
+  /// Per-destination `offline_grace_ms`, keyed by `Target::label`.
+  pub offline_grace_overrides: BTreeMap<String, u64>,
+  /// How long a job may wait in its queue before it's moved to the dead
+  /// letters instead of printing, checked when a worker picks it up. Keeps
+  /// a printer that comes back from a long outage from flushing stale
+  /// kitchen tickets. 0 never expires.
+  pub queued_ttl_ms: u64,
+  /// Per-destination `queued_ttl_ms`, keyed by `Target::label`.
+  pub queued_ttl_overrides: BTreeMap<String, u64>,
+  pub concurrency: ConcurrencyLimits,
+  /// Answer a job whose bytes already printed to the same destination
+  /// within `duplicate_window_ms` with a suppressed success instead of
+  /// printing it again. Reprints are never suppressed.
+  pub suppress_duplicates: bool,
+  pub duplicate_window_ms: u64,
+  /// Poll full printer status while TCP and serial jobs print, so paper
+  /// running out mid-ticket fails the job instead of vanishing into the
+  /// printer's buffer. Checks happen at line ends, at least
+  /// `confirm_interval_bytes` apart, and once after the last byte.
+  pub confirm_status: bool,
+  pub confirm_interval_bytes: usize,
+  /// How long a TCP or serial job waits for the printer to confirm each
+  /// GS ( H process ID marker in its payload before failing.
+  pub marker_timeout_ms: u64,
+  /// How long printed payloads are kept on disk for `reprint_job`. 0 keeps
+  /// only the last few jobs of the current session, in memory.
+  pub reprint_retention_hours: u64,
+  /// Quiet hours by destination (`Target::label`); see [`QuietHours`].
+  pub quiet_hours: BTreeMap<String, QuietHours>,
+  /// Buzzer command family by destination; unlisted ones use ESC B.
+  pub buzzer_models: BTreeMap<String, BuzzerModel>,
+  /// Management-page reboot URLs by destination, for `reboot_printer`'s
+  /// `http` method. Each must be on the printer's own host.
+  pub reboot_urls: BTreeMap<String, String>,
+  /// Consecutive failed jobs after which a destination's circuit breaker
+  /// opens and refuses jobs for `breaker_cooldown_ms`; 0 turns it off.
+  pub breaker_threshold: u32,
+  pub breaker_cooldown_ms: u64,
+  /// Retries a destination may make in a burst, shared by all its jobs:
+  /// each connect retry, and each job held for the offline grace period,
+  /// spends one. Once spent, jobs fail on their first error until it
+  /// refills; 0 turns the budget off.
+  pub retry_budget: u32,
+  /// One retry is added back to each destination's budget this often.
+  pub retry_budget_refill_ms: u64,
+  /// Send idle pooled TCP connections a DLE EOT status request this often,
+  /// closing any the printer doesn't answer; 0 turns it off. Keeps sockets
+  /// warm through routers that drop quiet ones. Connections idle for 30 s
+  /// are closed regardless.
+  pub nudge_interval_ms: u64,
+  /// Bursting by TCP destination; see [`PrintConfig::pacing`].
+  pub tcp_pacing: BTreeMap<String, TcpPacing>,
+  /// GS I model name by destination, from `query_printer_info`; picks
+  /// model defaults where a destination has no setting of its own.
+  pub printer_models: BTreeMap<String, String>,
+  /// Serial destinations (`Target::label`) whose printers use XON/XOFF
+  /// flow control: writes pause on XOFF until XON, failing after
+  /// `xoff_stall_ms`.
+  pub xon_xoff: BTreeSet<String>,
+  pub xoff_stall_ms: u64,
+  /// Windows queues listed as virtual (PDF, XPS, OneNote, fax): those whose
+  /// driver name contains one of `virtual_drivers` or whose port starts
+  /// with one of `virtual_ports`, ignoring case.
+  pub virtual_drivers: Vec<String>,
+  pub virtual_ports: Vec<String>,
+  /// Raise an OS notification when a job fails after its retries.
+  pub failure_notifications: bool,
+  /// Destinations below this severity fail without a notification.
+  pub failure_notify_min_severity: Severity,
+  /// Severity by destination; unlisted ones are `normal`.
+  pub destination_severity: BTreeMap<String, Severity>,
+  /// Each destination notifies at most once this often; failures in
+  /// between are counted into its next notification.
+  pub failure_notify_interval_ms: u64,
+}
+
+impl Default for PrintConfig {
+  fn default() -> Self {
+    Self {
+      connect_timeout_ms: 3000,
+      dns_timeout_ms: 2000,
+      write_timeout_ms: 3000,
+      write_throughput_bps: 4096,
+      write_timeout_overrides: BTreeMap::new(),
+      write_throughput_overrides: BTreeMap::new(),
+      status_timeout_ms: 1500,
+      serial_busy_retry_ms: 2000,
+      chunk_size: 512,
+      chunk_delay_ms: 20,
+      post_write_delay_ms: 0,
+      post_write_delay_overrides: BTreeMap::new(),
+      max_payload_bytes: 16 * 1024 * 1024,
+      retry: RetryPolicy::default(),
+      retry_overrides: BTreeMap::new(),
+      offline_grace_ms: 0,
+      offline_grace_overrides: BTreeMap::new(),
+      queued_ttl_ms: 0,
+      queued_ttl_overrides: BTreeMap::new(),
+      concurrency: ConcurrencyLimits::default(),
+      suppress_duplicates: false,
+      duplicate_window_ms: 10_000,
+      confirm_status: false,
+      confirm_interval_bytes: 2048,
+      marker_timeout_ms: 30_000,
+      reprint_retention_hours: 24,
+      quiet_hours: BTreeMap::new(),
+      buzzer_models: BTreeMap::new(),
+      reboot_urls: BTreeMap::new(),
+      breaker_threshold: 5,
+      breaker_cooldown_ms: 30_000,
+      retry_budget: 20,
+      retry_budget_refill_ms: 3_000,
+      nudge_interval_ms: 0,
+      tcp_pacing: BTreeMap::new(),
+      printer_models: BTreeMap::new(),
+      xon_xoff: BTreeSet::new(),
+      xoff_stall_ms: 10_000,
+      virtual_drivers: [
+        "Microsoft Print To PDF",
+        "Microsoft XPS Document Writer",
+        "OneNote",
+        "Fax",
+        "Adobe PDF",
+        "PDFCreator",
+        "PDF24",
+        "CutePDF",
+        "Bullzip",
+        "doPDF",
+        "Foxit",
+        "Nitro PDF",
+      ]
+      .map(String::from)
+      .to_vec(),
+      virtual_ports: ["PORTPROMPT:", "FILE:", "nul:", "XPSPort:", "SHRFAX:", "PDF24:"]
+        .map(String::from)
+        .to_vec(),
+      failure_notifications: false,
+      failure_notify_min_severity: Severity::Normal,
+      destination_severity: BTreeMap::new(),
+      failure_notify_interval_ms: 60_000,
+    }
+  }
+}
+
+impl PrintConfig {
+  /// Whether a queue with this driver and port prints to a file or a
+  /// virtual device rather than paper.
+  pub fn is_virtual_printer(&self, driver_name: &str, port_name: &str) -> bool {
+    let driver = driver_name.to_ascii_lowercase();
+    let port = port_name.trim().to_ascii_lowercase();
+    self.virtual_drivers.iter().any(|d| !d.is_empty() && driver.contains(&d.to_ascii_lowercase()))
+      || self.virtual_ports.iter().any(|p| !p.is_empty() && port.starts_with(&p.to_ascii_lowercase()))
+  }
+
+  pub fn offline_grace(&self, destination: &str) -> Duration {
+    Duration::from_millis(
+      self
+        .offline_grace_overrides
+        .get(destination)
+        .copied()
+        .unwrap_or(self.offline_grace_ms),
+    )
+  }
+
+  /// The retry policy for `destination` and where it came from.
+  pub fn retry_policy(&self, destination: &str) -> (&RetryPolicy, RetrySource) {
+    match self.retry_overrides.get(destination) {
+      Some(policy) => (policy, RetrySource::Destination),
+      None => (&self.retry, RetrySource::Default),
+    }
+  }
+
+  pub fn post_write_delay(&self, destination: &str) -> Duration {
+    Duration::from_millis(
+      self
+        .post_write_delay_overrides
+        .get(destination)
+        .copied()
+        .unwrap_or(self.post_write_delay_ms),
+    )
+  }
+
+  /// How long jobs for `destination` may stay queued, or `None` for ever.
+  pub fn queued_ttl(&self, destination: &str) -> Option<Duration> {
+    let ms = self
+      .queued_ttl_overrides
+      .get(destination)
+      .copied()
+      .unwrap_or(self.queued_ttl_ms);
+    (ms > 0).then(|| Duration::from_millis(ms))
+  }
+
+  /// Whether a job of `priority` for `destination` is held at `unix_ms`.
+  pub fn holds(&self, destination: &str, priority: u8, unix_ms: u64) -> bool {
+    self.quiet_hours.get(destination).is_some_and(|q| q.holds(priority, unix_ms))
+  }
+
+  pub fn severity(&self, destination: &str) -> Severity {
+    self.destination_severity.get(destination).copied().unwrap_or_default()
+  }
+
+  pub fn buzzer_model(&self, destination: &str) -> BuzzerModel {
+    self.buzzer_models.get(destination).copied().unwrap_or_default()
+  }
+
+  /// Pacing for `destination`: its `tcp_pacing` entry, else the default for
+  /// its printer model, else `None` to send at full speed.
+  pub fn pacing(&self, destination: &str) -> Option<&TcpPacing> {
+    self.tcp_pacing.get(destination).or_else(|| {
+      let model = self.printer_models.get(destination)?.trim();
+      MODEL_PACING.iter().find(|(prefix, _)| model.starts_with(prefix)).map(|(_, pacing)| pacing)
+    })
+  }
+
+  /// Base write timeout for `destination`, in ms.
+  pub fn write_timeout(&self, destination: &str) -> u64 {
+    self
+      .write_timeout_overrides
+      .get(destination)
+      .copied()
+      .unwrap_or(self.write_timeout_ms)
+  }
+
+  /// Expected throughput to `destination` in bytes per second: its
+  /// configured value, or `line_bps` if slower.
+  pub fn write_throughput(&self, destination: &str, line_bps: Option<u64>) -> u64 {
+    let configured = self
+      .write_throughput_overrides
+      .get(destination)
+      .copied()
+      .unwrap_or(self.write_throughput_bps);
+    line_bps.map_or(configured, |line| line.min(configured)).max(1)
+  }
+
+  /// Write timeout for one chunk of `bytes` to `destination`: its base
+  /// timeout plus the time the chunk takes at its throughput.
+  pub fn chunk_write_timeout(&self, destination: &str, bytes: usize, line_bps: Option<u64>) -> Duration {
+    let bps = self.write_throughput(destination, line_bps);
+    Duration::from_millis(self.write_timeout(destination) + bytes as u64 * 1000 / bps)
+  }
+}
+
+/// Partial update for `set_print_config`; omitted fields keep their value.
+#[derive(Debug, Default, Deserialize)]
+pub struct PrintConfigPatch {
+  pub connect_timeout_ms: Option<u64>,
+  pub dns_timeout_ms: Option<u64>,
+  pub write_timeout_ms: Option<u64>,
+  pub write_throughput_bps: Option<u64>,
+  /// Replaces all per-destination write timeouts.
+  pub write_timeout_overrides: Option<BTreeMap<String, u64>>,
+  /// Replaces all per-destination write throughputs.
+  pub write_throughput_overrides: Option<BTreeMap<String, u64>>,
+  pub status_timeout_ms: Option<u64>,
+  pub serial_busy_retry_ms: Option<u64>,
+  pub chunk_size: Option<usize>,
+  pub chunk_delay_ms: Option<u64>,
+  pub post_write_delay_ms: Option<u64>,
+  /// Replaces all per-destination post-write delays.
+  pub post_write_delay_overrides: Option<BTreeMap<String, u64>>,
+  pub max_payload_bytes: Option<usize>,
+  pub retry_max_attempts: Option<u32>,
+  pub retry_backoff_ms: Option<u64>,
+  pub retry_backoff: Option<Backoff>,
+  pub retry_on: Option<BTreeSet<RetryClass>>,
+  pub retry_deadline_ms: Option<u64>,
+  /// Replaces all per-destination retry policies.
+  pub retry_overrides: Option<BTreeMap<String, RetryPolicy>>,
+  pub offline_grace_ms: Option<u64>,
+  /// Replaces all per-destination grace periods.
+  pub offline_grace_overrides: Option<BTreeMap<String, u64>>,
+  pub queued_ttl_ms: Option<u64>,
+  /// Replaces all per-destination TTLs.
+  pub queued_ttl_overrides: Option<BTreeMap<String, u64>>,
+  pub max_in_flight: Option<usize>,
+  pub tcp_max_in_flight: Option<usize>,
+  pub serial_max_in_flight: Option<usize>,
+  pub spooler_max_in_flight: Option<usize>,
+  pub suppress_duplicates: Option<bool>,
+  pub duplicate_window_ms: Option<u64>,
+  pub confirm_status: Option<bool>,
+  pub confirm_interval_bytes: Option<usize>,
+  pub marker_timeout_ms: Option<u64>,
+  pub reprint_retention_hours: Option<u64>,
+  /// Replaces quiet hours for all destinations.
+  pub quiet_hours: Option<BTreeMap<String, QuietHours>>,
+  /// Replaces the buzzer models for all destinations.
+  pub buzzer_models: Option<BTreeMap<String, BuzzerModel>>,
+  /// Replaces the reboot URLs for all destinations.
+  pub reboot_urls: Option<BTreeMap<String, String>>,
+  pub breaker_threshold: Option<u32>,
+  pub breaker_cooldown_ms: Option<u64>,
+  pub retry_budget: Option<u32>,
+  pub retry_budget_refill_ms: Option<u64>,
+  pub nudge_interval_ms: Option<u64>,
+  /// Replaces the TCP pacing for all destinations.
+  pub tcp_pacing: Option<BTreeMap<String, TcpPacing>>,
+  /// Replaces the printer models for all destinations.
+  pub printer_models: Option<BTreeMap<String, String>>,
+  /// Replaces the set of XON/XOFF destinations.
+  pub xon_xoff: Option<BTreeSet<String>>,
+  pub xoff_stall_ms: Option<u64>,
+  /// Replaces the virtual driver names.
+  pub virtual_drivers: Option<Vec<String>>,
+  /// Replaces the virtual port prefixes.
+  pub virtual_ports: Option<Vec<String>>,
+  pub failure_notifications: Option<bool>,
+  pub failure_notify_min_severity: Option<Severity>,
+  /// Replaces the severity for all destinations.
+  pub destination_severity: Option<BTreeMap<String, Severity>>,
+  pub failure_notify_interval_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct PrintConfigView {
+  pub config: PrintConfig,
+  pub defaults: PrintConfig,
+  /// Names of fields set explicitly via `set_print_config`; everything else is a default.
+  pub overridden: Vec<String>,
+}
+
+#[derive(Default)]
+struct Inner {
+  config: PrintConfig,
+  overridden: BTreeSet<&'static str>,
+}
+
+#[derive(Clone, Default)]
+pub struct ConfigStore {
+  inner: Arc<RwLock<Inner>>,
+}
+
+impl ConfigStore {
+  pub fn snapshot(&self) -> PrintConfig {
+    self.inner.read().unwrap_or_else(|e| e.into_inner()).config.clone()
+  }
+
+  pub fn view(&self) -> PrintConfigView {
+    let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+    PrintConfigView {
+      config: inner.config.clone(),
+      defaults: PrintConfig::default(),
+      overridden: inner.overridden.iter().map(|s| s.to_string()).collect(),
+    }
+  }
+
+  pub fn apply(&self, patch: PrintConfigPatch) -> Result<PrintConfigView, PrintError> {
+    if patch.chunk_size == Some(0) {
+      return Err(PrintError::InvalidRequest("chunk_size must be greater than 0.".to_string()));
+    }
+    if patch.connect_timeout_ms == Some(0)
+      || patch.dns_timeout_ms == Some(0)
+      || patch.write_timeout_ms == Some(0)
+      || patch.status_timeout_ms == Some(0)
+    {
+      return Err(PrintError::InvalidRequest("Timeouts must be greater than 0 ms.".to_string()));
+    }
+    if patch.write_throughput_bps == Some(0) {
+      return Err(PrintError::InvalidRequest("write_throughput_bps must be greater than 0.".to_string()));
+    }
+    for (destination, ms) in patch.write_timeout_overrides.iter().flatten() {
+      if *ms == 0 {
+        return Err(PrintError::InvalidRequest(format!(
+          "The write timeout for {destination} must be greater than 0 ms."
+        )));
+      }
+    }
+    for (destination, bps) in patch.write_throughput_overrides.iter().flatten() {
+      if *bps == 0 {
+        return Err(PrintError::InvalidRequest(format!(
+          "The write throughput for {destination} must be greater than 0."
+        )));
+      }
+    }
+    if patch.max_payload_bytes == Some(0) {
+      return Err(PrintError::InvalidRequest("max_payload_bytes must be greater than 0.".to_string()));
+    }
+    if patch.retry_max_attempts == Some(0) {
+      return Err(PrintError::InvalidRequest("retry_max_attempts must be at least 1.".to_string()));
+    }
+    for (destination, policy) in patch.retry_overrides.iter().flatten() {
+      if policy.max_attempts == 0 {
+        return Err(PrintError::InvalidRequest(format!(
+          "The retry policy for {destination} needs max_attempts of at least 1."
+        )));
+      }
+    }
+    if patch.duplicate_window_ms == Some(0) {
+      return Err(PrintError::InvalidRequest(
+        "duplicate_window_ms must be greater than 0; turn suppress_duplicates off instead.".to_string(),
+      ));
+    }
+    if patch.confirm_interval_bytes == Some(0) {
+      return Err(PrintError::InvalidRequest("confirm_interval_bytes must be greater than 0.".to_string()));
+    }
+    if patch.marker_timeout_ms == Some(0) {
+      return Err(PrintError::InvalidRequest("marker_timeout_ms must be greater than 0.".to_string()));
+    }
+    for quiet in patch.quiet_hours.iter().flat_map(|q| q.values()) {
+      quiet.validate()?;
+    }
+    if patch.breaker_cooldown_ms == Some(0) {
+      return Err(PrintError::InvalidRequest(
+        "breaker_cooldown_ms must be greater than 0; set breaker_threshold to 0 to turn the breaker off.".to_string(),
+      ));
+    }
+    if patch.retry_budget_refill_ms == Some(0) {
+      return Err(PrintError::InvalidRequest(
+        "retry_budget_refill_ms must be greater than 0; set retry_budget to 0 to turn the budget off.".to_string(),
+      ));
+    }
+    if patch.nudge_interval_ms.is_some_and(|ms| ms > 0 && ms < MIN_NUDGE_INTERVAL_MS) {
+      return Err(PrintError::InvalidRequest(format!(
+        "nudge_interval_ms must be 0 (off) or at least {MIN_NUDGE_INTERVAL_MS}."
+      )));
+    }
+    for (destination, pacing) in patch.tcp_pacing.iter().flatten() {
+      if pacing.max_burst_bytes < 256 {
+        return Err(PrintError::InvalidRequest(format!(
+          "tcp_pacing for {destination}: max_burst_bytes must be at least 256."
+        )));
+      }
+    }
+    if patch.xoff_stall_ms == Some(0) {
+      return Err(PrintError::InvalidRequest("xoff_stall_ms must be greater than 0.".to_string()));
+    }
+    if patch.max_in_flight == Some(0) {
+      return Err(PrintError::InvalidRequest("max_in_flight must be at least 1.".to_string()));
+    }
+    if patch.failure_notify_interval_ms == Some(0) {
+      return Err(PrintError::InvalidRequest(
+        "failure_notify_interval_ms must be greater than 0; turn failure_notifications off instead.".to_string(),
+      ));
+    }
+
+    {
+      let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+      let Inner { config, overridden } = &mut *inner;
+      macro_rules! set {
+        ($field:ident, $target:expr) => {
+          if let Some(v) = patch.$field {
+            $target = v;
+            overridden.insert(stringify!($field));
+          }
+        };
+      }
+      set!(connect_timeout_ms, config.connect_timeout_ms);
+      set!(dns_timeout_ms, config.dns_timeout_ms);
+      set!(write_timeout_ms, config.write_timeout_ms);
+      set!(write_throughput_bps, config.write_throughput_bps);
+      set!(write_timeout_overrides, config.write_timeout_overrides);
+      set!(write_throughput_overrides, config.write_throughput_overrides);
+      set!(status_timeout_ms, config.status_timeout_ms);
+      set!(serial_busy_retry_ms, config.serial_busy_retry_ms);
+      set!(chunk_size, config.chunk_size);
+      set!(chunk_delay_ms, config.chunk_delay_ms);
+      set!(post_write_delay_ms, config.post_write_delay_ms);
+      set!(post_write_delay_overrides, config.post_write_delay_overrides);
+      set!(max_payload_bytes, config.max_payload_bytes);
+      set!(retry_max_attempts, config.retry.max_attempts);
+      set!(retry_backoff_ms, config.retry.backoff_ms);
+      set!(retry_backoff, config.retry.backoff);
+      set!(retry_on, config.retry.retry_on);
+      set!(retry_deadline_ms, config.retry.deadline_ms);
+      set!(retry_overrides, config.retry_overrides);
+      set!(offline_grace_ms, config.offline_grace_ms);
+      set!(offline_grace_overrides, config.offline_grace_overrides);
+      set!(queued_ttl_ms, config.queued_ttl_ms);
+      set!(queued_ttl_overrides, config.queued_ttl_overrides);
+      set!(max_in_flight, config.concurrency.max_in_flight);
+      set!(tcp_max_in_flight, config.concurrency.tcp);
+      set!(serial_max_in_flight, config.concurrency.serial);
+      set!(spooler_max_in_flight, config.concurrency.spooler);
+      set!(suppress_duplicates, config.suppress_duplicates);
+      set!(duplicate_window_ms, config.duplicate_window_ms);
+      set!(confirm_status, config.confirm_status);
+      set!(confirm_interval_bytes, config.confirm_interval_bytes);
+      set!(marker_timeout_ms, config.marker_timeout_ms);
+      set!(reprint_retention_hours, config.reprint_retention_hours);
+      set!(quiet_hours, config.quiet_hours);
+      set!(buzzer_models, config.buzzer_models);
+      set!(reboot_urls, config.reboot_urls);
+      set!(breaker_threshold, config.breaker_threshold);
+      set!(breaker_cooldown_ms, config.breaker_cooldown_ms);
+      set!(retry_budget, config.retry_budget);
+      set!(retry_budget_refill_ms, config.retry_budget_refill_ms);
+      set!(nudge_interval_ms, config.nudge_interval_ms);
+      set!(tcp_pacing, config.tcp_pacing);
+      set!(printer_models, config.printer_models);
+      set!(xon_xoff, config.xon_xoff);
+      set!(xoff_stall_ms, config.xoff_stall_ms);
+      set!(virtual_drivers, config.virtual_drivers);
+      set!(virtual_ports, config.virtual_ports);
+      set!(failure_notifications, config.failure_notifications);
+      set!(failure_notify_min_severity, config.failure_notify_min_severity);
+      set!(destination_severity, config.destination_severity);
+      set!(failure_notify_interval_ms, config.failure_notify_interval_ms);
+    }
+    Ok(self.view())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn chunk_write_timeout_uses_destination_overrides() {
+    let mut cfg = PrintConfig {
+      write_timeout_ms: 1000,
+      write_throughput_bps: 1000,
+      ..PrintConfig::default()
+    };
+    cfg.write_timeout_overrides.insert("tcp://slow:9100".to_string(), 5000);
+    cfg.write_throughput_overrides.insert("tcp://slow:9100".to_string(), 100);
+
+    assert_eq!(cfg.chunk_write_timeout("tcp://fast:9100", 500, None), Duration::from_millis(1500));
+    assert_eq!(cfg.chunk_write_timeout("tcp://slow:9100", 500, None), Duration::from_millis(10_000));
+    // A slower line wins over the configured throughput.
+    assert_eq!(cfg.chunk_write_timeout("tcp://fast:9100", 500, Some(250)), Duration::from_millis(3000));
+    assert_eq!(cfg.write_throughput("tcp://slow:9100", Some(960)), 100);
+  }
+
+  #[test]
+  fn pacing_falls_back_to_the_printer_model() {
+    let mut cfg = PrintConfig::default();
+    cfg.printer_models.insert("tcp://bar:9100".to_string(), " XP-58IIH".to_string());
+    cfg.printer_models.insert("tcp://office:9100".to_string(), "TM-T88V".to_string());
+    assert_eq!(cfg.pacing("tcp://bar:9100").map(|p| p.max_burst_bytes), Some(4096));
+    assert!(cfg.pacing("tcp://office:9100").is_none());
+    assert!(cfg.pacing("tcp://unknown:9100").is_none());
+
+    let own = TcpPacing { max_burst_bytes: 1024, inter_burst_delay_ms: 10, wait_for_ready: true };
+    cfg.tcp_pacing.insert("tcp://bar:9100".to_string(), own);
+    assert_eq!(cfg.pacing("tcp://bar:9100").map(|p| p.max_burst_bytes), Some(1024));
+  }
+
+  #[test]
+  fn destination_overrides_fall_back_to_the_defaults() {
+    let mut cfg = PrintConfig {
+      offline_grace_ms: 2000,
+      post_write_delay_ms: 100,
+      queued_ttl_ms: 60_000,
+      ..PrintConfig::default()
+    };
+    let kitchen = "tcp://kitchen:9100";
+    cfg.offline_grace_overrides.insert(kitchen.to_string(), 0);
+    cfg.post_write_delay_overrides.insert(kitchen.to_string(), 500);
+    cfg.queued_ttl_overrides.insert(kitchen.to_string(), 0);
+    cfg.buzzer_models.insert(kitchen.to_string(), BuzzerModel::Star);
+    let policy = RetryPolicy { max_attempts: 5, ..RetryPolicy::default() };
+    cfg.retry_overrides.insert(kitchen.to_string(), policy);
+
+    assert_eq!(cfg.offline_grace(kitchen), Duration::ZERO);
+    assert_eq!(cfg.offline_grace("serial://COM3@9600"), Duration::from_millis(2000));
+    assert_eq!(cfg.post_write_delay(kitchen), Duration::from_millis(500));
+    assert_eq!(cfg.post_write_delay("serial://COM3@9600"), Duration::from_millis(100));
+    // 0 keeps jobs queued for ever.
+    assert_eq!(cfg.queued_ttl(kitchen), None);
+    assert_eq!(cfg.queued_ttl("serial://COM3@9600"), Some(Duration::from_secs(60)));
+    assert_eq!(cfg.buzzer_model(kitchen), BuzzerModel::Star);
+    assert_eq!(cfg.buzzer_model("serial://COM3@9600"), BuzzerModel::EscB);
+    assert_eq!(cfg.severity(kitchen), Severity::Normal);
+
+    let (policy, source) = cfg.retry_policy(kitchen);
+    assert_eq!((policy.max_attempts, source), (5, RetrySource::Destination));
+    let (policy, source) = cfg.retry_policy("spooler://Office");
+    assert_eq!((policy.max_attempts, source), (1, RetrySource::Default));
+  }
+
+  #[test]
+  fn exponential_backoff_doubles_up_to_the_cap() {
+    let mut policy = RetryPolicy { backoff_ms: 1000, ..RetryPolicy::default() };
+    assert_eq!(policy.delay(3), Duration::from_secs(1));
+    policy.backoff = Backoff::Exponential;
+    let delays: Vec<_> = (1..=6).map(|retry| policy.delay(retry).as_secs()).collect();
+    assert_eq!(delays, [1, 2, 4, 8, 16, 30]);
+    assert_eq!(policy.delay(u32::MAX), MAX_BACKOFF);
+  }
+
+  #[test]
+  fn only_listed_failures_are_retried() {
+    let policy = RetryPolicy::default();
+    assert!(policy.retries(&PrintError::Connect("refused".to_string().into())));
+    assert!(!policy.retries(&PrintError::Write("reset".to_string().into())));
+    let policy = RetryPolicy { retry_on: [RetryClass::Resolve].into(), ..RetryPolicy::default() };
+    assert!(!policy.retries(&PrintError::Connect("refused".to_string().into())));
+  }
+
+  #[test]
+  fn zero_write_overrides_are_rejected() {
+    let store = ConfigStore::default();
+    let patch = PrintConfigPatch {
+      write_timeout_overrides: Some(BTreeMap::from([("tcp://a:9100".to_string(), 0)])),
+      ..PrintConfigPatch::default()
+    };
+    assert!(matches!(store.apply(patch), Err(PrintError::InvalidRequest(_))));
+    let patch = PrintConfigPatch {
+      write_throughput_overrides: Some(BTreeMap::from([("tcp://a:9100".to_string(), 0)])),
+      ..PrintConfigPatch::default()
+    };
+    assert!(matches!(store.apply(patch), Err(PrintError::InvalidRequest(_))));
+    assert!(store.view().overridden.is_empty());
+  }
+}
@@ -0,0 +1,74 @@
+//! Jobs that sat queued past their destination's TTL and were dropped
+//! instead of printed, kept for a manual reprint or discard.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use crate::target::Target;
+
+const MAX_ENTRIES: usize = 500;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DeadLetter {
+  pub job_id: String,
+  pub target: Target,
+  pub destination: String,
+  pub bytes: usize,
+  pub submitted_at_ms: u64,
+  pub expired_at_ms: u64,
+  #[serde(skip)]
+  pub payload: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Inner {
+  entries: VecDeque<DeadLetter>,
+  /// Expired since the last [`DeadLetters::take_unreported`].
+  unreported: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct DeadLetters {
+  inner: Arc<Mutex<Inner>>,
+}
+
+impl DeadLetters {
+  pub fn push(&self, letter: DeadLetter) {
+    log::warn!(
+      "job {} to {} expired after queueing for {} ms; moved to dead letters",
+      letter.job_id,
+      letter.destination,
+      letter.expired_at_ms.saturating_sub(letter.submitted_at_ms)
+    );
+    let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+    inner.entries.push_back(letter);
+    inner.unreported += 1;
+    while inner.entries.len() > MAX_ENTRIES {
+      inner.entries.pop_front();
+    }
+  }
+
+  /// Oldest first.
+  pub fn list(&self) -> Vec<DeadLetter> {
+    self.inner.lock().unwrap_or_else(|e| e.into_inner()).entries.iter().cloned().collect()
+  }
+
+  /// Removes and returns the entries in `job_ids`, or all of them.
+  pub fn take(&self, job_ids: Option<&[String]>) -> Vec<DeadLetter> {
+    let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+    let (out, keep) = std::mem::take(&mut inner.entries)
+      .into_iter()
+      .partition(|l| job_ids.map_or(true, |ids| ids.contains(&l.job_id)));
+    inner.entries = keep;
+    out.into()
+  }
+
+  /// How many jobs expired since the last call, and how many entries the
+  /// list holds now.
+  pub fn take_unreported(&self) -> (usize, usize) {
+    let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+    (std::mem::take(&mut inner.unreported), inner.entries.len())
+  }
+}
@@ -0,0 +1,316 @@
+//! Best-effort interpretation of ESC/POS bytes back into layout blocks, so a
+//! printed payload can be redrawn by the preview renderer.
+//!
+//! Covers what our own renderers and common receipt software emit: text
+//! with alignment, emphasis and size, feeds, cuts, drawer kicks, CODE128,
+//! QR codes and GS v 0 raster images. Other commands are skipped by their
+//! parameter length; anything unrecognized is dropped.
+
+use crate::escpos::{Align, Cut, DrawerPin, HriFont, HriPosition, DLE, ESC, GS, LF};
+use crate::receipt::{Block, Line};
+
+const FS: u8 = 0x1c;
+
+/// A point where the stream can be paused without splitting a command.
+enum Stop {
+  LineEnd(usize),
+  /// Just past a GS ( H process ID request, with its ID.
+  ProcessId(usize, [u8; 4]),
+}
+
+struct State {
+  align: Align,
+  bold: bool,
+  width: u8,
+  height: u8,
+  text: String,
+  qr: Option<String>,
+  hri: HriPosition,
+  hri_font: HriFont,
+}
+
+impl Default for State {
+  fn default() -> Self {
+    Self {
+      align: Align::Left,
+      bold: false,
+      width: 1,
+      height: 1,
+      text: String::new(),
+      qr: None,
+      hri: HriPosition::default(),
+      hri_font: HriFont::default(),
+    }
+  }
+}
+
+impl State {
+  /// Emits the pending text, hard-wrapped the way the printer wraps it.
+  fn flush(&mut self, columns: usize, out: &mut Vec<Block>) {
+    let fit = (columns / self.width as usize).max(1);
+    let text = std::mem::take(&mut self.text);
+    let chars = text.chars().collect::<Vec<_>>();
+    let pieces = if chars.is_empty() { vec![&chars[..]] } else { chars.chunks(fit).collect() };
+    for piece in pieces {
+      out.push(Block::Line(Line {
+        text: piece.iter().collect(),
+        align: self.align,
+        bold: self.bold,
+        width: self.width,
+        height: self.height,
+      }));
+    }
+  }
+}
+
+/// Decodes `data` for a printer with `columns` Font A characters per line.
+pub fn decode(data: &[u8], columns: usize) -> Vec<Block> {
+  walk(data, columns, &mut |_| {})
+}
+
+/// Offsets just past each line feed that ends a printed line, i.e. points
+/// where the stream can be paused without splitting a command or its data.
+pub fn line_ends(data: &[u8]) -> Vec<usize> {
+  let mut ends = Vec::new();
+  walk(data, 48, &mut |stop| {
+    if let Stop::LineEnd(at) = stop {
+      ends.push(at);
+    }
+  });
+  ends
+}
+
+/// Each GS ( H process ID request in `data`, as the offset just past it and
+/// its 4-byte ID, in payload order. Requests with IDs outside `0x30..=0x7e`
+/// are left out; printers ignore them.
+pub fn process_ids(data: &[u8]) -> Vec<(usize, [u8; 4])> {
+  let mut ids = Vec::new();
+  walk(data, 48, &mut |stop| {
+    if let Stop::ProcessId(at, id) = stop {
+      ids.push((at, id));
+    }
+  });
+  ids
+}
+
+/// How many ESC p drawer kicks `data` contains.
+pub fn drawer_kicks(data: &[u8]) -> usize {
+  walk(data, 48, &mut |_| {})
+    .iter()
+    .filter(|b| matches!(b, Block::DrawerKick { .. }))
+    .count()
+}
+
+fn walk(data: &[u8], columns: usize, stop: &mut dyn FnMut(Stop)) -> Vec<Block> {
+  let mut out = Vec::new();
+  let mut st = State::default();
+  let mut i = 0;
+  let byte = |at: usize| data.get(at).copied().unwrap_or(0);
+
+  while i < data.len() {
+    let b = data[i];
+    match b {
+      LF => {
+        st.flush(columns, &mut out);
+        i += 1;
+        stop(Stop::LineEnd(i));
+      }
+      b'\t' => {
+        st.text.push(' ');
+        i += 1;
+      }
+      0x20..=0x7e => {
+        st.text.push(b as char);
+        i += 1;
+      }
+      0x80..=0xff => {
+        st.text.push('?');
+        i += 1;
+      }
+      ESC => {
+        let cmd = byte(i + 1);
+        let n = byte(i + 2);
+        i += match cmd {
+          b'@' => {
+            let pending = std::mem::take(&mut st.text);
+            st = State {
+              text: pending,
+              ..State::default()
+            };
+            2
+          }
+          b'a' => {
+            st.align = match n {
+              1 | b'1' => Align::Center,
+              2 | b'2' => Align::Right,
+              _ => Align::Left,
+            };
+            3
+          }
+          b'E' | b'G' => {
+            st.bold = n & 1 == 1;
+            3
+          }
+          b'!' => {
+            st.bold = n & 0x08 != 0;
+            st.height = if n & 0x10 != 0 { 2 } else { 1 };
+            st.width = if n & 0x20 != 0 { 2 } else { 1 };
+            3
+          }
+          b'd' => {
+            if !st.text.is_empty() {
+              st.flush(columns, &mut out);
+            }
+            out.push(Block::Feed(n));
+            3
+          }
+          b'J' => {
+            if !st.text.is_empty() {
+              st.flush(columns, &mut out);
+            }
+            out.push(Block::Feed(n / 30));
+            3
+          }
+          b'p' => {
+            out.push(Block::DrawerKick {
+              pin: if n & 1 == 1 { DrawerPin::Pin5 } else { DrawerPin::Pin2 },
+              pulse_ms: byte(i + 3) as u16 * 2,
+            });
+            5
+          }
+          b'2' => 2,
+          b'*' => 5 + (byte(i + 3) as usize | (byte(i + 4) as usize) << 8) * if n >= 32 { 3 } else { 1 },
+          b'$' | b'\\' => 4,
+          b'-' | b'3' | b'M' | b'R' | b't' | b'{' | b'V' | b' ' | b'c' | b'U' => 3,
+          _ => 2,
+        };
+      }
+      GS => {
+        let cmd = byte(i + 1);
+        let n = byte(i + 2);
+        i += match cmd {
+          b'!' => {
+            st.width = (n >> 4 & 0x07) + 1;
+            st.height = (n & 0x07) + 1;
+            3
+          }
+          b'V' => {
+            if !st.text.is_empty() {
+              st.flush(columns, &mut out);
+            }
+            out.push(Block::Cut(if matches!(n, 0 | 48 | 65 | 97 | 103) { Cut::Full } else { Cut::Partial }));
+            if matches!(n, 65 | 66 | 97 | 98 | 103 | 104) {
+              4
+            } else {
+              3
+            }
+          }
+          b'k' => {
+            if !st.text.is_empty() {
+              st.flush(columns, &mut out);
+            }
+            if n <= 6 {
+              let start = i + 3;
+              let end = data[start.min(data.len())..]
+                .iter()
+                .position(|&c| c == 0)
+                .map_or(data.len(), |p| start + p);
+              out.push(Block::Barcode {
+                data: String::from_utf8_lossy(&data[start.min(end)..end]).into_owned(),
+                hri: st.hri,
+                hri_font: st.hri_font,
+              });
+              end + 1 - i
+            } else {
+              let len = byte(i + 3) as usize;
+              let start = (i + 4).min(data.len());
+              let payload = &data[start..(start + len).min(data.len())];
+              // CODE128 data starts with a code set selector such as `{B`.
+              let payload = if n == 73 && payload.first() == Some(&b'{') { &payload[2.min(payload.len())..] } else { payload };
+              out.push(Block::Barcode {
+                data: String::from_utf8_lossy(payload).into_owned(),
+                hri: st.hri,
+                hri_font: st.hri_font,
+              });
+              4 + len
+            }
+          }
+          b'(' => {
+            let sub = n;
+            let len = byte(i + 3) as usize | (byte(i + 4) as usize) << 8;
+            let params = &data[(i + 5).min(data.len())..(i + 5 + len).min(data.len())];
+            if sub == b'k' && params.len() >= 2 && params[0] == 49 {
+              match params[1] {
+                80 if params.len() >= 3 => st.qr = Some(String::from_utf8_lossy(&params[3..]).into_owned()),
+                81 => {
+                  if let Some(qr) = st.qr.take() {
+                    if !st.text.is_empty() {
+                      st.flush(columns, &mut out);
+                    }
+                    out.push(Block::Qr(qr));
+                  }
+                }
+                _ => {}
+              }
+            }
+            if let (b'H', &[48, 48, d1, d2, d3, d4]) = (sub, params) {
+              let id = [d1, d2, d3, d4];
+              if id.iter().all(|d| (0x30..=0x7e).contains(d)) {
+                stop(Stop::ProcessId(i + 5 + len, id));
+              }
+            }
+            5 + len
+          }
+          b'v' if n == b'0' => {
+            let width_bytes = byte(i + 4) as usize | (byte(i + 5) as usize) << 8;
+            let height = byte(i + 6) as usize | (byte(i + 7) as usize) << 8;
+            let start = (i + 8).min(data.len());
+            let bits = data[start..(start + width_bytes * height).min(data.len())].to_vec();
+            if !st.text.is_empty() {
+              st.flush(columns, &mut out);
+            }
+            if width_bytes > 0 && bits.len() == width_bytes * height {
+              out.push(Block::Raster {
+                align: st.align,
+                width_bytes,
+                height,
+                bits,
+              });
+            }
+            8 + width_bytes * height
+          }
+          b'H' => {
+            st.hri = HriPosition::from_n(n).unwrap_or(st.hri);
+            3
+          }
+          b'f' => {
+            st.hri_font = HriFont::from_n(n).unwrap_or(st.hri_font);
+            3
+          }
+          b'L' | b'W' => 4,
+          b'B' | b'h' | b'w' | b'a' | b'r' | b'I' => 3,
+          _ => 2,
+        };
+      }
+      DLE => {
+        i += match byte(i + 1) {
+          0x04 | 0x05 => 3,
+          0x14 => 5,
+          _ => 2,
+        };
+      }
+      FS => {
+        i += match byte(i + 1) {
+          b'p' => 4,
+          _ => 2,
+        };
+      }
+      _ => i += 1,
+    }
+  }
+
+  if !st.text.is_empty() {
+    st.flush(columns, &mut out);
+  }
+  out
+}
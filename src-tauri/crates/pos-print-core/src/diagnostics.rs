@@ -0,0 +1,261 @@
+//! One-shot environment report for support tickets: versions, ports and
+//! queues, the effective config, recent audit entries, queue depths, and a
+//! quick reachability check of each printer profile.
+//!
+//! A section that fails is reported in `errors` rather than failing the
+//! report. Profiles are checked concurrently with short timeouts, so the
+//! report comes back in a few seconds even when printers are unreachable.
+
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::audit::{now_ms, AuditEntry, AuditLog};
+use crate::config::PrintConfig;
+use crate::error::PrintError;
+use crate::serial::{self, SerialPortInfo};
+use crate::spooler::{self, SpoolerPrinter};
+use crate::target::Target;
+use crate::transport;
+use crate::winport;
+use crate::workers::WorkerStats;
+
+/// Audit entries included in the report.
+const AUDIT_TAIL: usize = 20;
+/// Connect and DNS limit for each TCP check; well under the print
+/// timeouts, since this only asks whether the port answers.
+const PING_TIMEOUT: Duration = Duration::from_millis(1500);
+/// Extra time allowed for the checks' threads to report back.
+const PING_SLACK: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OsInfo {
+  /// `windows`, `linux` or `macos`.
+  pub name: &'static str,
+  pub arch: &'static str,
+  /// Release as the OS describes itself, when it can be read.
+  pub version: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PingResult {
+  pub destination: String,
+  pub ok: bool,
+  pub duration_ms: u64,
+  pub detail: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SectionError {
+  pub section: &'static str,
+  pub error: PrintError,
+}
+
+#[derive(Serialize)]
+pub struct Diagnostics {
+  pub generated_at_ms: u64,
+  pub app_version: String,
+  pub os: OsInfo,
+  pub serial_ports: Vec<SerialPortInfo>,
+  pub printers: Vec<SpoolerPrinter>,
+  pub profiles: Vec<Target>,
+  /// The effective config with credentials in reboot URLs removed.
+  pub config: PrintConfig,
+  /// Newest last.
+  pub recent_audit: Vec<AuditEntry>,
+  pub queues: Vec<WorkerStats>,
+  pub pings: Vec<PingResult>,
+  pub errors: Vec<SectionError>,
+}
+
+/// Builds the report. `profiles` are the printer setups the frontend has
+/// saved; each is checked without printing anything.
+pub fn collect(
+  app_version: &str,
+  profiles: Vec<Target>,
+  cfg: &PrintConfig,
+  audit: &AuditLog,
+  queues: Vec<WorkerStats>,
+) -> Diagnostics {
+  let mut errors = Vec::new();
+  let pings = ping_all(&profiles, cfg);
+
+  let serial_ports = serial::list_ports().unwrap_or_else(|error| {
+    errors.push(SectionError { section: "serial_ports", error });
+    Vec::new()
+  });
+  let printers = spooler::list_printers_detailed(cfg).unwrap_or_else(|error| {
+    errors.push(SectionError { section: "printers", error });
+    Vec::new()
+  });
+  let pings = pings.finish(&serial_ports, &printers);
+
+  let mut config = cfg.clone();
+  for url in config.reboot_urls.values_mut() {
+    *url = redact_url(url);
+  }
+
+  Diagnostics {
+    generated_at_ms: now_ms(),
+    app_version: app_version.to_string(),
+    os: OsInfo {
+      name: std::env::consts::OS,
+      arch: std::env::consts::ARCH,
+      version: os_version(),
+    },
+    serial_ports,
+    printers,
+    profiles,
+    config,
+    recent_audit: audit.recent(AUDIT_TAIL),
+    queues,
+    pings,
+    errors,
+  }
+}
+
+/// TCP checks already running in the background, plus the profiles that
+/// are checked against the port and queue lists once those are read.
+struct Pings<'a> {
+  profiles: &'a [Target],
+  rx: mpsc::Receiver<(usize, PingResult)>,
+  pending: usize,
+  started: Instant,
+}
+
+fn ping_all<'a>(profiles: &'a [Target], cfg: &PrintConfig) -> Pings<'a> {
+  let mut quick = cfg.clone();
+  quick.connect_timeout_ms = cfg.connect_timeout_ms.min(PING_TIMEOUT.as_millis() as u64);
+  quick.dns_timeout_ms = cfg.dns_timeout_ms.min(PING_TIMEOUT.as_millis() as u64);
+
+  let (tx, rx) = mpsc::channel();
+  let mut pending = 0;
+  for (i, target) in profiles.iter().enumerate() {
+    let Target::Tcp { host, port } = target else {
+      continue;
+    };
+    let (host, port, tx, quick, destination) = (host.clone(), *port, tx.clone(), quick.clone(), target.label());
+    pending += 1;
+    // Detached: a check stuck past the deadline is reported as timed out
+    // and its thread ends on its own at the connect timeout.
+    thread::spawn(move || {
+      let started = Instant::now();
+      let connected = transport::tcp_connect(&host, port, &quick);
+      let _ = tx.send((i, ping_result(destination, started, connected.map(|_| "Port is open.".to_string()))));
+    });
+  }
+  Pings {
+    profiles,
+    rx,
+    pending,
+    started: Instant::now(),
+  }
+}
+
+impl Pings<'_> {
+  fn finish(self, ports: &[SerialPortInfo], printers: &[SpoolerPrinter]) -> Vec<PingResult> {
+    let mut results = BTreeMap::new();
+    for (i, target) in self.profiles.iter().enumerate() {
+      let started = Instant::now();
+      let checked = match target {
+        Target::Tcp { .. } => continue,
+        Target::Serial { port, .. } => {
+          if ports.iter().any(|p| p.matches(port)) {
+            Ok(format!("{port} is present."))
+          } else {
+            Err(PrintError::InvalidRequest(format!(
+              "{port} is not among this computer's serial ports. Check the cable or Bluetooth pairing."
+            )))
+          }
+        }
+        Target::Spooler { printer_name, .. } => match printers.iter().find(|p| p.name.eq_ignore_ascii_case(printer_name)) {
+          Some(p) if p.status.is_empty() => Ok(format!("'{printer_name}' is installed; {} jobs queued.", p.jobs)),
+          Some(p) => Ok(format!(
+            "'{printer_name}' is installed and reports {}; {} jobs queued.",
+            p.status.join(", "),
+            p.jobs
+          )),
+          None => Err(PrintError::InvalidRequest(format!(
+            "'{printer_name}' is not an installed printer. Check the name in Windows printer settings."
+          ))),
+        },
+        Target::Port { port_name } => {
+          winport::device_path(port_name).map(|path| format!("{path} is opened when printing."))
+        }
+      };
+      results.insert(i, ping_result(target.label(), started, checked));
+    }
+
+    // DNS and connect each get up to PING_TIMEOUT.
+    let deadline = self.started + PING_TIMEOUT * 2 + PING_SLACK;
+    let mut pending = self.pending;
+    while pending > 0 {
+      let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+        break;
+      };
+      match self.rx.recv_timeout(left) {
+        Ok((i, result)) => {
+          results.insert(i, result);
+          pending -= 1;
+        }
+        Err(_) => break,
+      }
+    }
+    for (i, target) in self.profiles.iter().enumerate() {
+      results.entry(i).or_insert_with(|| PingResult {
+        destination: target.label(),
+        ok: false,
+        duration_ms: self.started.elapsed().as_millis() as u64,
+        detail: "No answer in time. Verify the printer is on and on this network.".to_string(),
+      });
+    }
+    results.into_values().collect()
+  }
+}
+
+fn ping_result(destination: String, started: Instant, checked: Result<String, PrintError>) -> PingResult {
+  PingResult {
+    destination,
+    ok: checked.is_ok(),
+    duration_ms: started.elapsed().as_millis() as u64,
+    detail: checked.unwrap_or_else(|e| e.to_string()),
+  }
+}
+
+/// `url` without a `user:password@` part or query string, either of which
+/// may carry the printer's admin credentials.
+fn redact_url(url: &str) -> String {
+  let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+  let (rest, query) = rest.split_once('?').map_or((rest, false), |(r, _)| (r, true));
+  let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+  let (host, userinfo) = authority.rsplit_once('@').map_or((authority, false), |(_, h)| (h, true));
+  let prefix = if scheme.is_empty() { String::new() } else { format!("{scheme}://") };
+  let note = if query || userinfo { " (credentials removed)" } else { "" };
+  format!("{prefix}{host}{path}{note}")
+}
+
+#[cfg(target_os = "windows")]
+fn os_version() -> Option<String> {
+  let out = std::process::Command::new("cmd").args(["/C", "ver"]).output().ok()?;
+  let text = String::from_utf8_lossy(&out.stdout).trim().to_string();
+  (!text.is_empty()).then_some(text)
+}
+
+#[cfg(target_os = "macos")]
+fn os_version() -> Option<String> {
+  let out = std::process::Command::new("sw_vers").arg("-productVersion").output().ok()?;
+  let text = String::from_utf8_lossy(&out.stdout).trim().to_string();
+  (!text.is_empty()).then(|| format!("macOS {text}"))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn os_version() -> Option<String> {
+  let release = std::fs::read_to_string("/etc/os-release").ok()?;
+  release
+    .lines()
+    .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+    .map(|name| name.trim_matches('"').to_string())
+}
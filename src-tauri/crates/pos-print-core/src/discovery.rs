@@ -0,0 +1,149 @@
+//! Combined printer enumeration with change tracking between refreshes.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::config::PrintConfig;
+use crate::error::PrintError;
+use crate::{serial, spooler};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrinterSource {
+  Serial,
+  Spooler,
+}
+
+/// One printer as seen by any enumeration source. `id` is stable across
+/// refreshes (`serial:COM3`, `spooler:Receipt`), the rest may change.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PrinterEntry {
+  pub id: String,
+  pub source: PrinterSource,
+  pub name: String,
+  pub port_type: Option<String>,
+  pub manufacturer: Option<String>,
+  pub product: Option<String>,
+  pub serial_number: Option<String>,
+  pub vid: Option<u16>,
+  pub pid: Option<u16>,
+  /// A spooler queue that prints to a file or virtual device; see
+  /// [`spooler::SpoolerPrinter::is_virtual`].
+  pub is_virtual: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PrinterDiff {
+  pub added: Vec<PrinterEntry>,
+  pub removed: Vec<PrinterEntry>,
+  pub changed: Vec<PrinterEntry>,
+  pub total: usize,
+  /// Sources that failed this round; their previous entries are kept as-is.
+  pub errors: Vec<PrintError>,
+}
+
+/// Runs every enumeration source. A failing source is reported rather than
+/// failing the whole scan.
+pub fn enumerate(cfg: &PrintConfig) -> (Vec<PrinterEntry>, Vec<(PrinterSource, PrintError)>) {
+  let mut entries = Vec::new();
+  let mut errors = Vec::new();
+
+  match serial::list_ports() {
+    Ok(ports) => entries.extend(ports.into_iter().map(|p| PrinterEntry {
+      id: format!("serial:{}", p.port_name),
+      source: PrinterSource::Serial,
+      name: p.port_name,
+      port_type: Some(p.kind.as_str().to_string()),
+      manufacturer: p.manufacturer,
+      product: p.product,
+      serial_number: p.serial_number,
+      vid: p.vid,
+      pid: p.pid,
+      is_virtual: false,
+    })),
+    Err(e) => errors.push((PrinterSource::Serial, e)),
+  }
+
+  match spooler::list_printers_detailed(cfg) {
+    Ok(printers) => entries.extend(printers.into_iter().map(|p| PrinterEntry {
+      id: format!("spooler:{}", p.name),
+      source: PrinterSource::Spooler,
+      name: p.name,
+      port_type: None,
+      manufacturer: None,
+      product: None,
+      serial_number: None,
+      vid: None,
+      pid: None,
+      is_virtual: p.is_virtual,
+    })),
+    Err(e) => errors.push((PrinterSource::Spooler, e)),
+  }
+
+  (entries, errors)
+}
+
+/// The last enumeration result, used to compute incremental changes.
+#[derive(Default)]
+pub struct PrinterSnapshot {
+  entries: Mutex<BTreeMap<String, PrinterEntry>>,
+}
+
+impl PrinterSnapshot {
+  /// Re-enumerates and returns the changes. With `exclude_virtual`,
+  /// virtual queues are tracked but left out of the diff and the total.
+  pub fn refresh(&self, cfg: &PrintConfig, exclude_virtual: bool) -> PrinterDiff {
+    let (current, errors) = enumerate(cfg);
+    let failed = errors.iter().map(|(source, _)| *source).collect::<Vec<_>>();
+    let mut diff = self.apply(current, &failed);
+    diff.errors = errors.into_iter().map(|(_, e)| e).collect();
+    if exclude_virtual {
+      for entries in [&mut diff.added, &mut diff.removed, &mut diff.changed] {
+        entries.retain(|e| !e.is_virtual);
+      }
+      diff.total = self
+        .entries
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .values()
+        .filter(|e| !e.is_virtual)
+        .count();
+    }
+    diff
+  }
+
+  /// Replaces the snapshot with `current`, except entries from `failed`
+  /// sources, which are carried over so a transient error isn't reported as
+  /// every printer disappearing.
+  pub fn apply(&self, current: Vec<PrinterEntry>, failed: &[PrinterSource]) -> PrinterDiff {
+    let mut previous = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+    let mut next = current
+      .into_iter()
+      .map(|e| (e.id.clone(), e))
+      .collect::<BTreeMap<_, _>>();
+    for (id, entry) in previous.iter() {
+      if failed.contains(&entry.source) {
+        next.insert(id.clone(), entry.clone());
+      }
+    }
+
+    let mut diff = PrinterDiff::default();
+    for (id, entry) in &next {
+      match previous.get(id) {
+        None => diff.added.push(entry.clone()),
+        Some(old) if old != entry => diff.changed.push(entry.clone()),
+        Some(_) => {}
+      }
+    }
+    for (id, entry) in previous.iter() {
+      if !next.contains_key(id) {
+        diff.removed.push(entry.clone());
+      }
+    }
+    diff.total = next.len();
+    *previous = next;
+    diff
+  }
+}
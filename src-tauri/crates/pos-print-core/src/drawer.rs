@@ -0,0 +1,148 @@
+//! Cash drawer openings, kept apart from print activity for loss
+//! prevention reports.
+
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audit::now_ms;
+use crate::error::PrintError;
+
+/// Why the drawer was opened. `Other` carries a free-text note.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DrawerReason {
+  Sale,
+  Refund,
+  NoSale,
+  TillCount,
+  Other(String),
+}
+
+impl DrawerReason {
+  pub fn validate(&self) -> Result<(), PrintError> {
+    match self {
+      DrawerReason::Other(note) if note.trim().is_empty() => Err(PrintError::InvalidRequest(
+        "A drawer opened for another reason needs a note saying why.".to_string(),
+      )),
+      _ => Ok(()),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DrawerEvent {
+  pub ts_ms: u64,
+  pub destination: String,
+  pub job_id: String,
+  /// `None` for kicks found inside a print payload.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub reason: Option<DrawerReason>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub operator: Option<String>,
+  /// The kick was part of a print job's bytes rather than `open_drawer`.
+  pub embedded: bool,
+  pub ok: bool,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+impl DrawerEvent {
+  pub fn new(destination: &str, job_id: &str) -> Self {
+    Self {
+      ts_ms: now_ms(),
+      destination: destination.to_string(),
+      job_id: job_id.to_string(),
+      reason: None,
+      operator: None,
+      embedded: false,
+      ok: true,
+      error: None,
+    }
+  }
+}
+
+/// Window for `query_drawer_events`; open ends are unbounded.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DrawerRange {
+  pub from_ms: Option<u64>,
+  pub to_ms: Option<u64>,
+}
+
+/// Told about every recorded opening; called on the recording thread.
+pub trait DrawerObserver: Send + Sync {
+  fn drawer_opened(&self, event: &DrawerEvent);
+}
+
+/// Writes events to `<dir>/drawer-events.jsonl`. Like the audit log, write
+/// failures are logged and never fail the kick.
+#[derive(Clone, Default)]
+pub struct DrawerLog {
+  path: Option<PathBuf>,
+  lock: Arc<Mutex<()>>,
+  observer: Arc<RwLock<Option<Arc<dyn DrawerObserver>>>>,
+}
+
+impl DrawerLog {
+  pub fn open(dir: PathBuf) -> Self {
+    if let Err(e) = fs::create_dir_all(&dir) {
+      log::warn!("drawer log disabled, unable to create {}: {e}", dir.display());
+      return Self::default();
+    }
+    Self {
+      path: Some(dir.join("drawer-events.jsonl")),
+      ..Self::default()
+    }
+  }
+
+  pub fn set_observer(&self, observer: Arc<dyn DrawerObserver>) {
+    *self.observer.write().unwrap_or_else(|e| e.into_inner()) = Some(observer);
+  }
+
+  pub fn record(&self, event: DrawerEvent) {
+    if let Some(path) = &self.path {
+      match serde_json::to_string(&event) {
+        Ok(line) => {
+          let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+          let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut f| writeln!(f, "{line}"));
+          if let Err(e) = written {
+            log::warn!("unable to write drawer log {}: {e}", path.display());
+          }
+        }
+        Err(e) => log::warn!("unable to serialize drawer event: {e}"),
+      }
+    }
+    let observer = self.observer.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(observer) = observer {
+      observer.drawer_opened(&event);
+    }
+  }
+
+  /// Events within `range`, oldest first.
+  pub fn query(&self, range: &DrawerRange) -> Vec<DrawerEvent> {
+    let Some(path) = &self.path else {
+      return Vec::new();
+    };
+    let file = {
+      let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+      match fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Vec::new(),
+      }
+    };
+    BufReader::new(file)
+      .lines()
+      .map_while(Result::ok)
+      .filter_map(|line| serde_json::from_str::<DrawerEvent>(&line).ok())
+      .filter(|e| range.from_ms.map_or(true, |from| e.ts_ms >= from) && range.to_ms.map_or(true, |to| e.ts_ms < to))
+      .collect()
+  }
+}
@@ -0,0 +1,47 @@
+//! Detection of byte-identical payloads sent to the same printer shortly
+//! after each other, typically a frontend retrying a job that already
+//! printed.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+
+const MAX_PER_DESTINATION: usize = 64;
+
+pub type PayloadHash = [u8; 32];
+
+/// Hash and print time of each recent payload, oldest first, per destination.
+type Printed = Arc<Mutex<HashMap<String, VecDeque<(PayloadHash, u64)>>>>;
+
+pub fn hash(data: &[u8]) -> PayloadHash {
+  Sha256::digest(data).into()
+}
+
+/// Hashes of recently printed payloads, per destination label.
+#[derive(Clone, Default)]
+pub struct RecentPayloads {
+  printed: Printed,
+}
+
+impl RecentPayloads {
+  /// When `hash` last printed to `destination`, if within `window_ms` of `now_ms`.
+  pub fn printed_within(&self, destination: &str, hash: &PayloadHash, window_ms: u64, now_ms: u64) -> Option<u64> {
+    let printed = self.printed.lock().unwrap_or_else(|e| e.into_inner());
+    printed
+      .get(destination)?
+      .iter()
+      .rev()
+      .find(|(h, at)| h == hash && now_ms.saturating_sub(*at) <= window_ms)
+      .map(|(_, at)| *at)
+  }
+
+  pub fn record(&self, destination: &str, hash: PayloadHash, now_ms: u64) {
+    let mut printed = self.printed.lock().unwrap_or_else(|e| e.into_inner());
+    let recent = printed.entry(destination.to_string()).or_default();
+    recent.push_back((hash, now_ms));
+    while recent.len() > MAX_PER_DESTINATION {
+      recent.pop_front();
+    }
+  }
+}
@@ -0,0 +1,450 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::status::FaultKind;
+
+/// A printer error raised while a job was printing, found by status checks
+/// between chunks.
+#[derive(Clone, Debug, Serialize)]
+pub struct PrinterFault {
+  pub kind: FaultKind,
+  /// Bytes sent before the last status check that found the printer healthy;
+  /// everything up to here is known to have reached the printer cleanly.
+  pub confirmed_bytes: usize,
+  pub sent_bytes: usize,
+  pub total_bytes: usize,
+  #[serde(skip)]
+  pub message: String,
+}
+
+/// Stable error codes. The frontend keys localized messages on these, so an
+/// existing code must never change meaning or be renamed; add a new one
+/// instead.
+///
+/// `code` is one per [`PrintError`] variant. `reason` narrows it down where
+/// the cause is known (e.g. `tcp_connect_timeout` within `connect_failed`)
+/// and otherwise equals `code`.
+pub mod codes {
+  pub const INVALID_REQUEST: &str = "invalid_request";
+  pub const RESOLVE_FAILED: &str = "resolve_failed";
+  pub const CONNECT_FAILED: &str = "connect_failed";
+  pub const WRITE_FAILED: &str = "write_failed";
+  pub const READ_FAILED: &str = "read_failed";
+  pub const TIMEOUT: &str = "timeout";
+  pub const SERIAL_OPEN_FAILED: &str = "serial_open_failed";
+  pub const SPOOLER_FAILED: &str = "spooler_failed";
+  pub const ENUMERATE_FAILED: &str = "enumerate_failed";
+  pub const QUEUE_FULL: &str = "queue_full";
+  pub const IMAGE_DECODE_FAILED: &str = "image_decode_failed";
+  pub const UNSUPPORTED: &str = "unsupported";
+  pub const TASK_FAILED: &str = "task_failed";
+  pub const ALL_TARGETS_FAILED: &str = "all_targets_failed";
+  pub const ABORTED: &str = "aborted";
+  pub const PAYLOAD_NOT_RETAINED: &str = "payload_not_retained";
+  pub const EXPIRED: &str = "expired";
+  pub const PRINTER_FAULT: &str = "printer_fault";
+  pub const PAYLOAD_LENGTH_MISMATCH: &str = "payload_length_mismatch"; // got, expected
+  pub const CIRCUIT_OPEN: &str = "circuit_open"; // destination, failures, retry_after_ms
+
+  /// Reasons, with the params they carry.
+  pub const DNS_LOOKUP_FAILED: &str = "dns_lookup_failed"; // host, port
+  pub const TCP_CONNECT_TIMEOUT: &str = "tcp_connect_timeout"; // host, port
+  pub const TCP_CONNECTION_REFUSED: &str = "tcp_connection_refused"; // host, port
+  pub const TCP_WRITE_TIMEOUT: &str = "tcp_write_timeout"; // host, port, timeout_ms
+  pub const SERIAL_PORT_BUSY: &str = "serial_port_busy"; // port, waited_ms
+  pub const SERIAL_PORT_NOT_FOUND: &str = "serial_port_not_found"; // port
+  pub const SERIAL_DEVICE_NOT_CONNECTED: &str = "serial_device_not_connected"; // port
+  pub const SERIAL_WRITE_TIMEOUT: &str = "serial_write_timeout"; // port, timeout_ms
+  pub const STATUS_TIMEOUT: &str = "status_timeout"; // printer, timeout_ms
+  pub const MARKER_TIMEOUT: &str = "marker_timeout"; // printer, marker, sent_bytes, timeout_ms
+  pub const SPOOLER_PRINTER_NOT_FOUND: &str = "spooler_printer_not_found";
+  pub const SPOOLER_PRINTER_OFFLINE: &str = "spooler_printer_offline";
+  pub const SPOOLER_ACCESS_DENIED: &str = "spooler_access_denied";
+  pub const SPOOLER_INVALID_DATATYPE: &str = "spooler_invalid_datatype";
+  pub const SPOOLER_CREDENTIALS_REQUIRED: &str = "spooler_credentials_required";
+
+  /// Every code and reason; checked for duplicates at compile time.
+  pub const ALL: &[&str] = &[
+    INVALID_REQUEST,
+    RESOLVE_FAILED,
+    CONNECT_FAILED,
+    WRITE_FAILED,
+    READ_FAILED,
+    TIMEOUT,
+    SERIAL_OPEN_FAILED,
+    SPOOLER_FAILED,
+    ENUMERATE_FAILED,
+    QUEUE_FULL,
+    IMAGE_DECODE_FAILED,
+    UNSUPPORTED,
+    TASK_FAILED,
+    ALL_TARGETS_FAILED,
+    ABORTED,
+    PAYLOAD_NOT_RETAINED,
+    EXPIRED,
+    PRINTER_FAULT,
+    PAYLOAD_LENGTH_MISMATCH,
+    CIRCUIT_OPEN,
+    DNS_LOOKUP_FAILED,
+    TCP_CONNECT_TIMEOUT,
+    TCP_CONNECTION_REFUSED,
+    TCP_WRITE_TIMEOUT,
+    SERIAL_PORT_BUSY,
+    SERIAL_PORT_NOT_FOUND,
+    SERIAL_DEVICE_NOT_CONNECTED,
+    SERIAL_WRITE_TIMEOUT,
+    STATUS_TIMEOUT,
+    MARKER_TIMEOUT,
+    SPOOLER_PRINTER_NOT_FOUND,
+    SPOOLER_PRINTER_OFFLINE,
+    SPOOLER_ACCESS_DENIED,
+    SPOOLER_INVALID_DATATYPE,
+    SPOOLER_CREDENTIALS_REQUIRED,
+  ];
+
+  const fn same(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+      return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+      if a[i] != b[i] {
+        return false;
+      }
+      i += 1;
+    }
+    true
+  }
+
+  const _: () = {
+    let mut i = 0;
+    while i < ALL.len() {
+      let mut j = i + 1;
+      while j < ALL.len() {
+        assert!(!same(ALL[i], ALL[j]), "duplicate error code");
+        j += 1;
+      }
+      i += 1;
+    }
+  };
+}
+
+/// Message of a transport error plus what the frontend needs to localize it.
+#[derive(Clone, Debug)]
+pub struct ErrorDetail {
+  pub message: String,
+  /// One of the reasons in [`codes`]; `None` falls back to the variant's code.
+  pub reason: Option<&'static str>,
+  /// Values to interpolate, e.g. `host`, `port`, `os_error`.
+  pub params: BTreeMap<&'static str, String>,
+}
+
+impl ErrorDetail {
+  pub fn new(message: String) -> Self {
+    Self {
+      message,
+      reason: None,
+      params: BTreeMap::new(),
+    }
+  }
+
+  pub fn reason(mut self, reason: &'static str) -> Self {
+    self.reason = Some(reason);
+    self
+  }
+
+  pub fn param(mut self, name: &'static str, value: impl ToString) -> Self {
+    self.params.insert(name, value.to_string());
+    self
+  }
+
+  /// Adds `os_error` when `err` carries an OS error number.
+  pub fn os_error(self, err: &std::io::Error) -> Self {
+    match err.raw_os_error() {
+      Some(code) => self.param("os_error", code),
+      None => self,
+    }
+  }
+}
+
+impl From<String> for ErrorDetail {
+  fn from(message: String) -> Self {
+    Self::new(message)
+  }
+}
+
+/// Failure returned by the print commands.
+///
+/// Serializes to `{ "code", "reason", "message", "params" }` so the
+/// frontend can branch on `code`, show a localized message for `reason`
+/// filled in from `params`, and fall back to the English `message`. See
+/// [`codes`].
+#[derive(Clone, Debug)]
+pub enum PrintError {
+  InvalidRequest(String),
+  Resolve(ErrorDetail),
+  Connect(ErrorDetail),
+  Write(ErrorDetail),
+  Read(ErrorDetail),
+  Timeout(ErrorDetail),
+  SerialOpen(ErrorDetail),
+  Spooler(ErrorDetail),
+  Enumerate(String),
+  QueueFull(String),
+  Image(String),
+  Unsupported(String),
+  Task(String),
+  AllTargetsFailed(String),
+  Aborted(String),
+  /// The job printed, but its payload is past the reprint retention period.
+  NotRetained(String),
+  /// The job waited in its queue past the destination's TTL.
+  Expired(String),
+  PrinterFault(PrinterFault),
+  /// The payload arrived with a different length than the caller declared;
+  /// `params` has `got` and `expected`.
+  PayloadLengthMismatch(ErrorDetail),
+  /// The destination's circuit breaker is refusing jobs after repeated
+  /// failures; `params` has `destination`, `failures` and, while the
+  /// circuit is open, `retry_after_ms`.
+  CircuitOpen(ErrorDetail),
+}
+
+impl PrintError {
+  pub fn code(&self) -> &'static str {
+    match self {
+      PrintError::InvalidRequest(_) => codes::INVALID_REQUEST,
+      PrintError::Resolve(_) => codes::RESOLVE_FAILED,
+      PrintError::Connect(_) => codes::CONNECT_FAILED,
+      PrintError::Write(_) => codes::WRITE_FAILED,
+      PrintError::Read(_) => codes::READ_FAILED,
+      PrintError::Timeout(_) => codes::TIMEOUT,
+      PrintError::SerialOpen(_) => codes::SERIAL_OPEN_FAILED,
+      PrintError::Spooler(_) => codes::SPOOLER_FAILED,
+      PrintError::Enumerate(_) => codes::ENUMERATE_FAILED,
+      PrintError::QueueFull(_) => codes::QUEUE_FULL,
+      PrintError::Image(_) => codes::IMAGE_DECODE_FAILED,
+      PrintError::Unsupported(_) => codes::UNSUPPORTED,
+      PrintError::Task(_) => codes::TASK_FAILED,
+      PrintError::AllTargetsFailed(_) => codes::ALL_TARGETS_FAILED,
+      PrintError::Aborted(_) => codes::ABORTED,
+      PrintError::NotRetained(_) => codes::PAYLOAD_NOT_RETAINED,
+      PrintError::Expired(_) => codes::EXPIRED,
+      PrintError::PrinterFault(_) => codes::PRINTER_FAULT,
+      PrintError::PayloadLengthMismatch(_) => codes::PAYLOAD_LENGTH_MISMATCH,
+      PrintError::CircuitOpen(_) => codes::CIRCUIT_OPEN,
+    }
+  }
+
+  pub fn message(&self) -> &str {
+    match self {
+      PrintError::Resolve(d)
+      | PrintError::Connect(d)
+      | PrintError::Write(d)
+      | PrintError::Read(d)
+      | PrintError::Timeout(d)
+      | PrintError::SerialOpen(d)
+      | PrintError::Spooler(d)
+      | PrintError::PayloadLengthMismatch(d)
+      | PrintError::CircuitOpen(d) => &d.message,
+      PrintError::InvalidRequest(m)
+      | PrintError::Enumerate(m)
+      | PrintError::QueueFull(m)
+      | PrintError::Image(m)
+      | PrintError::Unsupported(m)
+      | PrintError::Task(m)
+      | PrintError::AllTargetsFailed(m)
+      | PrintError::Aborted(m)
+      | PrintError::NotRetained(m)
+      | PrintError::Expired(m) => m,
+      PrintError::PrinterFault(f) => &f.message,
+    }
+  }
+
+  fn detail(&self) -> Option<&ErrorDetail> {
+    match self {
+      PrintError::Resolve(d)
+      | PrintError::Connect(d)
+      | PrintError::Write(d)
+      | PrintError::Read(d)
+      | PrintError::Timeout(d)
+      | PrintError::SerialOpen(d)
+      | PrintError::Spooler(d)
+      | PrintError::PayloadLengthMismatch(d)
+      | PrintError::CircuitOpen(d) => Some(d),
+      _ => None,
+    }
+  }
+
+  /// Whether the failure came after data was handed to the printer, so part
+  /// of the job may still print. Spooler write and finalize failures mark
+  /// this with a `written` param.
+  pub fn after_send(&self) -> bool {
+    self.detail().is_some_and(|d| d.params.contains_key("written"))
+  }
+
+  /// Fails with `PayloadLengthMismatch` when the caller declared a length
+  /// and `data` is not exactly that long.
+  pub fn check_len(data: &[u8], expected: Option<usize>) -> Result<(), PrintError> {
+    match expected {
+      Some(expected) if expected != data.len() => Err(PrintError::PayloadLengthMismatch(
+        ErrorDetail::new(format!(
+          "Print data arrived with {} bytes but {expected} were sent; it was not printed. Retry the print.",
+          data.len()
+        ))
+        .param("got", data.len())
+        .param("expected", expected),
+      )),
+      _ => Ok(()),
+    }
+  }
+
+  /// The most specific code known for this failure.
+  pub fn reason(&self) -> &'static str {
+    self.detail().and_then(|d| d.reason).unwrap_or_else(|| self.code())
+  }
+}
+
+impl fmt::Display for PrintError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.message())
+  }
+}
+
+impl std::error::Error for PrintError {}
+
+impl serde::Serialize for PrintError {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeStruct;
+    let fault = match self {
+      PrintError::PrinterFault(f) => Some(f),
+      _ => None,
+    };
+    let empty = BTreeMap::new();
+    let mut s = serializer.serialize_struct("PrintError", 4 + fault.is_some() as usize)?;
+    s.serialize_field("code", self.code())?;
+    s.serialize_field("reason", self.reason())?;
+    s.serialize_field("message", self.message())?;
+    s.serialize_field("params", self.detail().map_or(&empty, |d| &d.params))?;
+    if let Some(fault) = fault {
+      s.serialize_field("fault", fault)?;
+    }
+    s.end()
+  }
+}
+
+impl From<PrintError> for String {
+  fn from(err: PrintError) -> Self {
+    err.to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeSet;
+
+  use super::*;
+
+  /// One of each variant. The match below stops compiling when a variant
+  /// is added, as a reminder to list it here too.
+  fn every_variant() -> Vec<PrintError> {
+    let detail = || ErrorDetail::new("detail".to_string());
+    let text = || "text".to_string();
+    let all = vec![
+      PrintError::InvalidRequest(text()),
+      PrintError::Resolve(detail()),
+      PrintError::Connect(detail()),
+      PrintError::Write(detail()),
+      PrintError::Read(detail()),
+      PrintError::Timeout(detail()),
+      PrintError::SerialOpen(detail()),
+      PrintError::Spooler(detail()),
+      PrintError::Enumerate(text()),
+      PrintError::QueueFull(text()),
+      PrintError::Image(text()),
+      PrintError::Unsupported(text()),
+      PrintError::Task(text()),
+      PrintError::AllTargetsFailed(text()),
+      PrintError::Aborted(text()),
+      PrintError::NotRetained(text()),
+      PrintError::Expired(text()),
+      PrintError::PrinterFault(PrinterFault {
+        kind: FaultKind::PaperOut,
+        confirmed_bytes: 0,
+        sent_bytes: 0,
+        total_bytes: 0,
+        message: text(),
+      }),
+      PrintError::PayloadLengthMismatch(detail()),
+      PrintError::CircuitOpen(detail()),
+    ];
+    for e in &all {
+      match e {
+        PrintError::InvalidRequest(_)
+        | PrintError::Resolve(_)
+        | PrintError::Connect(_)
+        | PrintError::Write(_)
+        | PrintError::Read(_)
+        | PrintError::Timeout(_)
+        | PrintError::SerialOpen(_)
+        | PrintError::Spooler(_)
+        | PrintError::Enumerate(_)
+        | PrintError::QueueFull(_)
+        | PrintError::Image(_)
+        | PrintError::Unsupported(_)
+        | PrintError::Task(_)
+        | PrintError::AllTargetsFailed(_)
+        | PrintError::Aborted(_)
+        | PrintError::NotRetained(_)
+        | PrintError::Expired(_)
+        | PrintError::PrinterFault(_)
+        | PrintError::PayloadLengthMismatch(_)
+        | PrintError::CircuitOpen(_) => {}
+      }
+    }
+    all
+  }
+
+  #[test]
+  fn every_variant_has_its_own_code() {
+    let all = every_variant();
+    let variants: BTreeSet<_> = all.iter().map(|e| format!("{:?}", std::mem::discriminant(e))).collect();
+    assert_eq!(variants.len(), all.len(), "every_variant lists a variant twice");
+
+    let codes: BTreeSet<_> = all.iter().map(PrintError::code).collect();
+    assert_eq!(codes.len(), all.len(), "two variants share a code");
+    for code in &codes {
+      assert!(codes::ALL.contains(code), "{code} is missing from codes::ALL");
+    }
+  }
+
+  #[test]
+  fn reason_falls_back_to_code() {
+    let plain = PrintError::Connect(ErrorDetail::new("refused".to_string()));
+    assert_eq!(plain.reason(), codes::CONNECT_FAILED);
+    let narrowed = PrintError::Connect(ErrorDetail::new("refused".to_string()).reason(codes::TCP_CONNECTION_REFUSED));
+    assert_eq!(narrowed.reason(), codes::TCP_CONNECTION_REFUSED);
+    assert_eq!(PrintError::QueueFull("full".to_string()).reason(), codes::QUEUE_FULL);
+  }
+
+  #[test]
+  fn after_send_needs_the_written_param() {
+    assert!(!PrintError::Spooler(ErrorDetail::new("open".to_string())).after_send());
+    assert!(PrintError::Spooler(ErrorDetail::new("write".to_string()).param("written", 512)).after_send());
+    assert!(!PrintError::Task("written".to_string()).after_send());
+  }
+
+  #[test]
+  fn check_len_reports_both_lengths() {
+    assert!(PrintError::check_len(b"abc", None).is_ok());
+    assert!(PrintError::check_len(b"abc", Some(3)).is_ok());
+    let err = PrintError::check_len(b"abc", Some(4)).unwrap_err();
+    let json = serde_json::to_value(&err).unwrap();
+    assert_eq!(json["code"], codes::PAYLOAD_LENGTH_MISMATCH);
+    assert_eq!(json["params"]["got"], "3");
+    assert_eq!(json["params"]["expected"], "4");
+  }
+}
@@ -0,0 +1,872 @@
+//! ESC/POS command assembly.
+
+pub const ESC: u8 = 0x1b;
+pub const GS: u8 = 0x1d;
+pub const DLE: u8 = 0x10;
+pub const FS: u8 = 0x1c;
+pub const LF: u8 = 0x0a;
+pub const BEL: u8 = 0x07;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Align {
+  Left,
+  Center,
+  Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cut {
+  Full,
+  Partial,
+}
+
+/// Where a barcode's human-readable digits print (GS H).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HriPosition {
+  None,
+  Above,
+  #[default]
+  Below,
+  Both,
+}
+
+impl HriPosition {
+  /// From GS H's `n`, which printers accept as 0-3 or ASCII '0'-'3'.
+  pub fn from_n(n: u8) -> Option<Self> {
+    match n {
+      0 | b'0' => Some(Self::None),
+      1 | b'1' => Some(Self::Above),
+      2 | b'2' => Some(Self::Below),
+      3 | b'3' => Some(Self::Both),
+      _ => None,
+    }
+  }
+
+  pub fn above(self) -> bool {
+    matches!(self, Self::Above | Self::Both)
+  }
+
+  pub fn below(self) -> bool {
+    matches!(self, Self::Below | Self::Both)
+  }
+}
+
+/// The font of a barcode's human-readable digits (GS f).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HriFont {
+  #[default]
+  A,
+  /// Smaller; fits more digits under a narrow barcode.
+  B,
+}
+
+impl HriFont {
+  /// From GS f's `n`: 0 or '0' is font A, 1 or '1' font B.
+  pub fn from_n(n: u8) -> Option<Self> {
+    match n {
+      0 | b'0' => Some(Self::A),
+      1 | b'1' => Some(Self::B),
+      _ => None,
+    }
+  }
+}
+
+pub const DENSITY_RANGE: std::ops::RangeInclusive<i8> = -6..=6;
+
+/// Font A characters per line on an 80 mm head, the builder's default width.
+pub const DEFAULT_COLUMNS: usize = 48;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeparatorStyle {
+  #[default]
+  Dashed,
+  Solid,
+  Double,
+}
+
+/// CP437 box-drawing bytes, or ASCII stand-ins when the code page is unknown.
+struct BoxChars {
+  horizontal: u8,
+  double: u8,
+  vertical: u8,
+  corners: [u8; 4],
+}
+
+const CP437_BOX: BoxChars = BoxChars {
+  horizontal: 0xc4,
+  double: 0xcd,
+  vertical: 0xb3,
+  corners: [0xda, 0xbf, 0xc0, 0xd9],
+};
+
+const ASCII_BOX: BoxChars = BoxChars {
+  horizontal: b'-',
+  double: b'=',
+  vertical: b'|',
+  corners: [b'+'; 4],
+};
+
+/// Cash drawer connector pin driven by a kick pulse.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DrawerPin {
+  #[default]
+  Pin2,
+  Pin5,
+}
+
+/// Buzzer command family. Models disagree on the sequence, so the one to use
+/// is configured per destination (`PrintConfig::buzzer_models`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuzzerModel {
+  /// ESC B n t — most generic kitchen printers (Xprinter, Rongta, HPRT and
+  /// other Epson-compatible clones). Up to 9 beeps of 50–450 ms; the gap is
+  /// fixed by the printer.
+  #[default]
+  EscB,
+  /// ESC ( A — Epson TM models with the optional buzzer. Up to 63 beeps in
+  /// 100 ms steps; the gap is fixed by the printer.
+  Epson,
+  /// ESC BEL n1 n2 then BEL per beep — Star line mode, driving the buzzer on
+  /// the external device port. On and off times are in 10 ms steps.
+  Star,
+  /// A bare BEL per beep, for printers and add-on buzzers that ignore timing.
+  Bel,
+}
+
+/// Most beeps `EscPosBuilder::buzzer` sends in one command, the ESC B limit.
+pub const MAX_BUZZER_TIMES: u8 = 9;
+
+/// Multibyte character system for CJK text. The printer must have the
+/// matching font; which systems a model supports depends on its region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultibyteSystem {
+  /// Japanese Kanji, Shift-JIS.
+  ShiftJis,
+  /// Simplified Chinese, GBK (a superset of GB2312).
+  Gbk,
+  /// Traditional Chinese, Big5.
+  Big5,
+  /// Korean Hangul, EUC-KR.
+  EucKr,
+}
+
+impl MultibyteSystem {
+  /// The system for text in `lang` (`ja`, `zh-TW`, ...), if it needs one.
+  /// Chinese is Simplified unless the tag names Traditional or a region
+  /// that writes it.
+  pub fn for_language(lang: &str) -> Option<Self> {
+    let lang = lang.trim().to_ascii_lowercase();
+    let mut parts = lang.split(['-', '_']);
+    Some(match parts.next().unwrap_or_default() {
+      "ja" => MultibyteSystem::ShiftJis,
+      "ko" => MultibyteSystem::EucKr,
+      "zh" => match parts.next() {
+        Some("hant" | "tw" | "hk" | "mo") => MultibyteSystem::Big5,
+        _ => MultibyteSystem::Gbk,
+      },
+      _ => return None,
+    })
+  }
+
+  /// Bytes `ch` takes in this system, which is also the Font A cells it
+  /// prints in: two for full-width characters. `None` if it lacks `ch`.
+  pub(crate) fn char_bytes(self, ch: char) -> Option<usize> {
+    if (' '..='~').contains(&ch) {
+      return Some(1);
+    }
+    if ch.is_control() {
+      return None;
+    }
+    let mut buf = [0u8; 4];
+    let (bytes, _, unmappable) = self.encoding().encode(ch.encode_utf8(&mut buf));
+    (!unmappable).then_some(bytes.len())
+  }
+
+  fn encoding(self) -> &'static encoding_rs::Encoding {
+    match self {
+      MultibyteSystem::ShiftJis => encoding_rs::SHIFT_JIS,
+      MultibyteSystem::Gbk => encoding_rs::GBK,
+      MultibyteSystem::Big5 => encoding_rs::BIG5,
+      MultibyteSystem::EucKr => encoding_rs::EUC_KR,
+    }
+  }
+}
+
+/// Single-byte code page for text outside ASCII, by its Epson ESC t table.
+/// Clones often number their tables differently; check the self-test
+/// page of the model in use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodePage {
+  /// Cyrillic, DOS.
+  Pc866,
+  /// Western European.
+  Wpc1252,
+  /// Central European.
+  Wpc1250,
+  /// Cyrillic.
+  Wpc1251,
+  /// Greek.
+  Wpc1253,
+  /// Turkish.
+  Wpc1254,
+  /// Hebrew.
+  Wpc1255,
+  /// Arabic.
+  Wpc1256,
+  /// Baltic.
+  Wpc1257,
+}
+
+impl CodePage {
+  /// The table to use for text in `lang` (`ru`, `pt-BR`, ...), if any.
+  pub fn for_language(lang: &str) -> Option<Self> {
+    let lang = lang.trim().to_ascii_lowercase();
+    Some(match lang.split(['-', '_']).next().unwrap_or_default() {
+      "en" | "fr" | "de" | "es" | "it" | "pt" | "nl" | "da" | "sv" | "nb" | "no" | "fi" | "is" | "ga" | "sw" | "af" => {
+        CodePage::Wpc1252
+      }
+      "pl" | "cs" | "sk" | "hu" | "sl" | "hr" | "ro" => CodePage::Wpc1250,
+      "ru" | "uk" | "be" | "bg" | "sr" | "mk" => CodePage::Wpc1251,
+      "el" => CodePage::Wpc1253,
+      "tr" | "az" => CodePage::Wpc1254,
+      "he" | "yi" => CodePage::Wpc1255,
+      "ar" | "fa" | "ur" => CodePage::Wpc1256,
+      "lt" | "lv" | "et" => CodePage::Wpc1257,
+      _ => return None,
+    })
+  }
+
+  /// The ESC t table number.
+  pub fn table(self) -> u8 {
+    match self {
+      CodePage::Pc866 => 17,
+      CodePage::Wpc1252 => 16,
+      CodePage::Wpc1250 => 45,
+      CodePage::Wpc1251 => 46,
+      CodePage::Wpc1253 => 47,
+      CodePage::Wpc1254 => 48,
+      CodePage::Wpc1255 => 49,
+      CodePage::Wpc1256 => 50,
+      CodePage::Wpc1257 => 51,
+    }
+  }
+
+  /// Hebrew and Arabic, written right to left.
+  pub fn is_rtl(self) -> bool {
+    matches!(self, CodePage::Wpc1255 | CodePage::Wpc1256)
+  }
+
+  fn encoding(self) -> &'static encoding_rs::Encoding {
+    match self {
+      CodePage::Pc866 => encoding_rs::IBM866,
+      CodePage::Wpc1252 => encoding_rs::WINDOWS_1252,
+      CodePage::Wpc1250 => encoding_rs::WINDOWS_1250,
+      CodePage::Wpc1251 => encoding_rs::WINDOWS_1251,
+      CodePage::Wpc1253 => encoding_rs::WINDOWS_1253,
+      CodePage::Wpc1254 => encoding_rs::WINDOWS_1254,
+      CodePage::Wpc1255 => encoding_rs::WINDOWS_1255,
+      CodePage::Wpc1256 => encoding_rs::WINDOWS_1256,
+      CodePage::Wpc1257 => encoding_rs::WINDOWS_1257,
+    }
+  }
+
+  /// The table's single byte for `ch`, if it has one.
+  fn byte(self, ch: char) -> Option<u8> {
+    if (' '..='~').contains(&ch) {
+      return Some(ch as u8);
+    }
+    if ch.is_control() {
+      return None;
+    }
+    let mut buf = [0u8; 4];
+    let (bytes, _, unmappable) = self.encoding().encode(ch.encode_utf8(&mut buf));
+    match (&*bytes, unmappable) {
+      (&[b], false) if b >= 0x80 => Some(b),
+      _ => None,
+    }
+  }
+}
+
+/// Like [`printable`], but keeps what `page` can show; with no page,
+/// exactly [`printable`].
+pub fn printable_in(text: &str, page: Option<CodePage>, transliterate: bool) -> String {
+  let Some(page) = page else {
+    return printable(text, transliterate);
+  };
+  let mut out = String::with_capacity(text.len());
+  for ch in text.chars() {
+    if page.byte(ch).is_some() {
+      out.push(ch);
+    } else {
+      out.push_str(&printable(&ch.to_string(), transliterate));
+    }
+  }
+  out
+}
+
+/// Like [`printable`], but keeps what `system` can encode.
+pub fn printable_multibyte(text: &str, system: MultibyteSystem, transliterate: bool) -> String {
+  let mut out = String::with_capacity(text.len());
+  for ch in text.chars() {
+    if system.char_bytes(ch).is_some() {
+      out.push(ch);
+    } else {
+      out.push_str(&printable(&ch.to_string(), transliterate));
+    }
+  }
+  out
+}
+
+/// Encodes `text` for a printer in `system`'s multibyte mode. ASCII passes
+/// through; characters the encoding lacks become `?`.
+pub fn encode_multibyte(text: &str, system: MultibyteSystem) -> Vec<u8> {
+  use encoding_rs::EncoderResult;
+
+  let mut encoder = system.encoding().new_encoder();
+  let mut out = Vec::with_capacity(text.len() * 2);
+  let mut buf = [0u8; 256];
+  let mut rest = text;
+  loop {
+    let (result, read, written) = encoder.encode_from_utf8_without_replacement(rest, &mut buf, true);
+    out.extend_from_slice(&buf[..written]);
+    rest = &rest[read..];
+    match result {
+      EncoderResult::InputEmpty => break,
+      EncoderResult::OutputFull => {}
+      EncoderResult::Unmappable(_) => out.push(b'?'),
+    }
+  }
+  out.retain(|b| *b >= b' ' || *b == b'\t');
+  out
+}
+
+/// Maps `text` onto printable ASCII, which is all the default code page is
+/// trusted to show. With `transliterate`, other characters become their
+/// closest ASCII spelling (é → e, ß → ss, Ж → Zh); otherwise, or when there
+/// is none, they become `?`.
+pub fn printable(text: &str, transliterate: bool) -> String {
+  let mut out = String::with_capacity(text.len());
+  for ch in text.chars() {
+    if (' '..='~').contains(&ch) {
+      out.push(ch);
+      continue;
+    }
+    let approx = transliterate
+      .then(|| deunicode::deunicode_char(ch))
+      .flatten()
+      .map(|s| s.trim_end().chars().filter(|c| (' '..='~').contains(c)).collect::<String>())
+      .filter(|s| !s.is_empty());
+    match approx {
+      Some(approx) => out.push_str(&approx),
+      None => out.push('?'),
+    }
+  }
+  out
+}
+
+/// Appends ESC/POS commands to a byte buffer.
+#[derive(Clone, Debug, Default)]
+pub struct EscPosBuilder {
+  buf: Vec<u8>,
+  transliterate: bool,
+  multibyte: Option<MultibyteSystem>,
+  /// Paper width in Font A characters; 0 means [`DEFAULT_COLUMNS`].
+  columns: usize,
+  /// Current GS ! width multiplier; 0 means 1.
+  char_width: u8,
+  /// Whether ESC t selected PC437, so box-drawing bytes are safe to send.
+  pc437: bool,
+  /// Table text is encoded for, set by [`select_code_page`](Self::select_code_page).
+  single_byte: Option<CodePage>,
+}
+
+impl EscPosBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// ESC @ — reset the printer to its power-on modes.
+  pub fn init(&mut self) -> &mut Self {
+    self.char_width = 1;
+    self.pc437 = false;
+    self.single_byte = None;
+    self.raw(&[ESC, b'@'])
+  }
+
+  /// Sets the paper width in Font A characters that separators, boxes and
+  /// signature lines fill. Sends nothing.
+  pub fn columns(&mut self, columns: usize) -> &mut Self {
+    self.columns = columns;
+    self
+  }
+
+  /// ESC t — select character code table `n`. Table 0 (PC437) also enables
+  /// box-drawing characters in [`boxed`](Self::boxed) and [`separator`](Self::separator).
+  pub fn code_page(&mut self, n: u8) -> &mut Self {
+    self.pc437 = n == 0;
+    self.single_byte = None;
+    self.raw(&[ESC, b't', n])
+  }
+
+  /// ESC t — select `page` and encode text for it until the next code page
+  /// change; characters it lacks are handled as in [`printable`].
+  pub fn select_code_page(&mut self, page: CodePage) -> &mut Self {
+    self.code_page(page.table());
+    self.single_byte = Some(page);
+    self
+  }
+
+  /// Characters that fit on a line at the current width multiplier.
+  fn line_chars(&self) -> usize {
+    let columns = if self.columns == 0 { DEFAULT_COLUMNS } else { self.columns };
+    (columns / self.char_width.max(1) as usize).max(1)
+  }
+
+  fn box_chars(&self) -> &'static BoxChars {
+    if self.pc437 {
+      &CP437_BOX
+    } else {
+      &ASCII_BOX
+    }
+  }
+
+  pub fn align(&mut self, align: Align) -> &mut Self {
+    let n = match align {
+      Align::Left => 0,
+      Align::Center => 1,
+      Align::Right => 2,
+    };
+    self.raw(&[ESC, b'a', n])
+  }
+
+  pub fn bold(&mut self, on: bool) -> &mut Self {
+    self.raw(&[ESC, b'E', on as u8])
+  }
+
+  pub fn underline(&mut self, on: bool) -> &mut Self {
+    self.raw(&[ESC, b'-', on as u8])
+  }
+
+  /// GS ! — character size multipliers, each 1..=8.
+  pub fn size(&mut self, width: u8, height: u8) -> &mut Self {
+    let w = width.clamp(1, 8) - 1;
+    let h = height.clamp(1, 8) - 1;
+    self.char_width = w + 1;
+    self.raw(&[GS, b'!', (w << 4) | h])
+  }
+
+  pub fn inverse(&mut self, on: bool) -> &mut Self {
+    self.raw(&[GS, b'B', on as u8])
+  }
+
+  /// Approximate characters the code page lacks in ASCII from now on,
+  /// instead of printing `?`; see [`printable`].
+  pub fn transliterate(&mut self, on: bool) -> &mut Self {
+    self.transliterate = on;
+    self
+  }
+
+  /// FS C / FS & — switch to `system`'s multibyte mode; text is encoded
+  /// for it until [`disable_multibyte`](Self::disable_multibyte).
+  pub fn enable_multibyte(&mut self, system: MultibyteSystem) -> &mut Self {
+    if system == MultibyteSystem::ShiftJis {
+      self.raw(&[FS, b'C', 1]);
+    }
+    self.multibyte = Some(system);
+    self.raw(&[FS, b'&'])
+  }
+
+  /// FS . — back to single-byte text.
+  pub fn disable_multibyte(&mut self) -> &mut Self {
+    self.multibyte = None;
+    self.raw(&[FS, b'.'])
+  }
+
+  /// Writes text, encoded per [`encode_multibyte`] in multibyte mode, for
+  /// the selected [`CodePage`] if there is one, and otherwise replacing
+  /// anything outside printable ASCII per [`printable`].
+  pub fn text(&mut self, text: &str) -> &mut Self {
+    for (i, part) in text.split('\n').enumerate() {
+      if i > 0 {
+        self.buf.push(LF);
+      }
+      match (self.multibyte, self.single_byte) {
+        (Some(system), _) => self.buf.extend_from_slice(&encode_multibyte(part, system)),
+        (None, Some(page)) => {
+          let text = printable_in(part, Some(page), self.transliterate);
+          self.buf.extend(text.chars().map(|ch| page.byte(ch).unwrap_or(b'?')));
+        }
+        (None, None) => self.buf.extend_from_slice(printable(part, self.transliterate).as_bytes()),
+      }
+    }
+    self
+  }
+
+  pub fn line(&mut self, text: &str) -> &mut Self {
+    self.text(text).newline()
+  }
+
+  pub fn newline(&mut self) -> &mut Self {
+    self.raw(&[LF])
+  }
+
+  /// A full-width rule in `style`, or of `ch` when it is a visible ASCII
+  /// character.
+  /// Solid and double rules need PC437 ([`code_page`](Self::code_page)`(0)`)
+  /// and fall back to `-` and `=`.
+  pub fn separator(&mut self, style: SeparatorStyle, ch: Option<char>) -> &mut Self {
+    let chars = self.box_chars();
+    let byte = match (ch, style) {
+      (Some(ch), _) if ('!'..='~').contains(&ch) => ch as u8,
+      (_, SeparatorStyle::Dashed) => b'-',
+      (_, SeparatorStyle::Solid) => chars.horizontal,
+      (_, SeparatorStyle::Double) => chars.double,
+    };
+    let rule = vec![byte; self.line_chars()];
+    self.raw(&rule).newline()
+  }
+
+  /// `text` word-wrapped inside a full-width box with `padding` spaces
+  /// between the border and the text. Uses CP437 box-drawing after
+  /// [`code_page`](Self::code_page)`(0)`, `+-|` otherwise.
+  pub fn boxed(&mut self, text: &str, padding: usize) -> &mut Self {
+    let chars = self.box_chars();
+    let width = self.line_chars().max(3);
+    let padding = padding.min((width - 3) / 2);
+    let inner = width - 2 - 2 * padding;
+    let [top_left, top_right, bottom_left, bottom_right] = chars.corners;
+    let mut edge = vec![chars.horizontal; width];
+    (edge[0], edge[width - 1]) = (top_left, top_right);
+    self.raw(&edge).newline();
+    for line in crate::receipt::wrap(&printable(text, self.transliterate), inner) {
+      let pad = inner - line.len();
+      let row = format!(
+        "{}{}{line}{}{}",
+        " ".repeat(padding),
+        " ".repeat(pad / 2),
+        " ".repeat(pad - pad / 2),
+        " ".repeat(padding)
+      );
+      self.raw(&[chars.vertical]).raw(row.as_bytes()).raw(&[chars.vertical]).newline();
+    }
+    (edge[0], edge[width - 1]) = (bottom_left, bottom_right);
+    self.raw(&edge).newline()
+  }
+
+  /// The signing space on a merchant copy: two blank lines, an `X____`
+  /// line the full width, and `label` centered beneath it.
+  pub fn signature_line(&mut self, label: &str) -> &mut Self {
+    let width = self.line_chars();
+    let label = printable(label, self.transliterate);
+    let label = &label[..label.len().min(width)];
+    let indent = (width - label.len()) / 2;
+    self
+      .newline()
+      .newline()
+      .line(&format!("X{}", "_".repeat(width - 1)))
+      .line(&format!("{}{label}", " ".repeat(indent)))
+  }
+
+  /// ESC d — print and feed `lines` lines.
+  pub fn feed(&mut self, lines: u8) -> &mut Self {
+    self.raw(&[ESC, b'd', lines])
+  }
+
+  /// GS V — cut, feeding to the cutter first.
+  pub fn cut(&mut self, cut: Cut) -> &mut Self {
+    let m = match cut {
+      Cut::Full => 65,
+      Cut::Partial => 66,
+    };
+    self.raw(&[GS, b'V', m, 0])
+  }
+
+  /// GS ( H fn 48 — a marker the printer answers with `id` once it has
+  /// processed everything before it, so a job can confirm the receipt
+  /// printed up to here; see [`status::await_process_id`](crate::status::await_process_id).
+  /// Each byte of `id` must be in `0x30..=0x7e` (e.g. `*b"A001"`).
+  pub fn process_id(&mut self, id: [u8; 4]) -> &mut Self {
+    self.raw(&[GS, b'(', b'H', 6, 0, 48, 48]).raw(&id)
+  }
+
+  /// ESC p — pulse the drawer kick connector for `on_ms` (rounded to the
+  /// printer's 2 ms units, capped at 510 ms).
+  pub fn drawer_kick(&mut self, pin: DrawerPin, on_ms: u16) -> &mut Self {
+    let m = match pin {
+      DrawerPin::Pin2 => 0,
+      DrawerPin::Pin5 => 1,
+    };
+    let on = (on_ms / 2).clamp(1, 255) as u8;
+    self.raw(&[ESC, b'p', m, on, on])
+  }
+
+  /// Sounds the buzzer `times` times (at most [`MAX_BUZZER_TIMES`]) using
+  /// `model`'s command. `on_ms` and `off_ms` are rounded to the model's steps
+  /// and ignored where the printer fixes them.
+  pub fn buzzer(&mut self, model: BuzzerModel, times: u8, on_ms: u16, off_ms: u16) -> &mut Self {
+    let times = times.clamp(1, MAX_BUZZER_TIMES);
+    match model {
+      BuzzerModel::EscB => self.raw(&[ESC, b'B', times, (on_ms / 50).clamp(1, 9) as u8]),
+      BuzzerModel::Epson => self.raw(&[ESC, b'(', b'A', 4, 0, 48, 49, times, (on_ms / 100).clamp(1, 50) as u8]),
+      BuzzerModel::Star => {
+        let on = (on_ms / 10).clamp(1, 255) as u8;
+        let off = (off_ms / 10).clamp(1, 255) as u8;
+        self.raw(&[ESC, BEL, on, off]);
+        self.raw(&vec![BEL; times as usize])
+      }
+      BuzzerModel::Bel => self.raw(&vec![BEL; times as usize]),
+    }
+  }
+
+  /// GS ( K fn 49 — print density, -6 (lightest) to +6 (darkest), 0 being
+  /// the model's standard. Some models persist this to NV memory, which
+  /// wears out with repeated writes, so apply it once per job rather than
+  /// per line and only when it changes.
+  pub fn density(&mut self, level: i8) -> &mut Self {
+    self.raw(&[GS, b'(', b'K', 2, 0, 49, level.clamp(-6, 6) as u8])
+  }
+
+  /// GS k — CODE128 (code set B), with the human-readable text placed by
+  /// `hri` (GS H) in `hri_font` (GS f).
+  pub fn barcode_code128(&mut self, data: &str, height: u8, hri: HriPosition, hri_font: HriFont) -> &mut Self {
+    let data = data.as_bytes();
+    let len = (data.len() + 2).min(255) as u8;
+    self.raw(&[GS, b'H', hri as u8, GS, b'f', hri_font as u8]);
+    self.raw(&[GS, b'h', height.max(1), GS, b'w', 2]);
+    self.raw(&[GS, b'k', 73, len, b'{', b'B']);
+    self.raw(&data[..len as usize - 2])
+  }
+
+  /// GS ( k — store and print a model 2 QR code, error level M.
+  pub fn qr(&mut self, data: &str, module_size: u8) -> &mut Self {
+    let data = data.as_bytes();
+    let store_len = (data.len() + 3).min(7092);
+    let (pl, ph) = ((store_len % 256) as u8, (store_len / 256) as u8);
+    self.raw(&[GS, b'(', b'k', 4, 0, 49, 65, 50, 0]);
+    self.raw(&[GS, b'(', b'k', 3, 0, 49, 67, module_size.clamp(1, 16)]);
+    self.raw(&[GS, b'(', b'k', 3, 0, 49, 69, 49]);
+    self.raw(&[GS, b'(', b'k', pl, ph, 49, 80, 48]);
+    self.raw(&data[..store_len - 3]);
+    self.raw(&[GS, b'(', b'k', 3, 0, 49, 81, 48])
+  }
+
+  pub fn raw(&mut self, bytes: &[u8]) -> &mut Self {
+    self.buf.extend_from_slice(bytes);
+    self
+  }
+
+  pub fn build(&self) -> Vec<u8> {
+    self.buf.clone()
+  }
+
+  pub fn into_bytes(self) -> Vec<u8> {
+    self.buf
+  }
+}
+
+/// A standalone buzzer job; see [`EscPosBuilder::buzzer`].
+pub fn build_buzzer(model: BuzzerModel, times: u8, on_ms: u16, off_ms: u16) -> Vec<u8> {
+  let mut builder = EscPosBuilder::new();
+  builder.buzzer(model, times, on_ms, off_ms);
+  builder.into_bytes()
+}
+
+/// A short receipt exercising alignment, emphasis, sizes, and the cutter.
+pub fn test_page(width_chars: usize) -> Vec<u8> {
+  let rule = "-".repeat(width_chars);
+  let mut b = EscPosBuilder::new();
+  b.init()
+    .align(Align::Center)
+    .size(2, 2)
+    .line("TEST PAGE")
+    .size(1, 1)
+    .line(&rule)
+    .align(Align::Left)
+    .line("Left aligned")
+    .align(Align::Center)
+    .line("Centered")
+    .align(Align::Right)
+    .line("Right aligned")
+    .align(Align::Left)
+    .bold(true)
+    .line("Bold")
+    .bold(false)
+    .underline(true)
+    .line("Underline")
+    .underline(false)
+    .inverse(true)
+    .line(" Inverse ")
+    .inverse(false)
+    .size(2, 1)
+    .line("Double width")
+    .size(1, 1)
+    .line(&rule)
+    .line(&"0123456789".repeat(width_chars.div_ceil(10))[..width_chars])
+    .feed(3)
+    .cut(Cut::Partial);
+  b.into_bytes()
+}
+
+/// An installer's grid for centering the paper and checking the cutter:
+/// a column ruler numbered every 8 characters over a tick line with `|`
+/// edge markers,
+/// `lines` rows numbered at both edges as a vertical ruler, and a solid
+/// rule right before a cut with no extra feed, so the gap below it is the
+/// head-to-cutter distance.
+pub fn build_alignment_grid(width_chars: usize, lines: usize) -> Vec<u8> {
+  let width = width_chars.max(8);
+  let mut numbers = vec![b'.'; width];
+  let mut ticks = vec![b'-'; width];
+  for col in (0..width).step_by(8) {
+    let label = (col + 1).to_string();
+    for (i, digit) in label.bytes().enumerate().take(width - col) {
+      numbers[col + i] = digit;
+    }
+    ticks[col] = b'+';
+  }
+  ticks[0] = b'|';
+  ticks[width - 1] = b'|';
+  let numbers = String::from_utf8_lossy(&numbers).into_owned();
+  let ticks = String::from_utf8_lossy(&ticks).into_owned();
+
+  let mut b = EscPosBuilder::new();
+  b.init().align(Align::Left).line(&numbers).line(&ticks);
+  for n in 1..=lines {
+    let label = format!("{:02}", n % 100);
+    let mut row = vec![b' '; width];
+    for col in (8..width).step_by(8) {
+      row[col] = b':';
+    }
+    row[..2].copy_from_slice(label.as_bytes());
+    row[width - 2..].copy_from_slice(label.as_bytes());
+    b.line(&String::from_utf8_lossy(&row));
+  }
+  b.line(&ticks).line(&numbers).line(&"=".repeat(width)).cut(Cut::Partial);
+  b.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn builder_emits_commands_in_order() {
+    let mut b = EscPosBuilder::new();
+    b.init()
+      .align(Align::Center)
+      .bold(true)
+      .size(2, 3)
+      .line("Hi")
+      .feed(2)
+      .cut(Cut::Full)
+      .drawer_kick(DrawerPin::Pin5, 100);
+    assert_eq!(
+      b.into_bytes(),
+      [
+        ESC, b'@', ESC, b'a', 1, ESC, b'E', 1, GS, b'!', 0x12, b'H', b'i', LF, ESC, b'd', 2, GS, b'V', 65, 0, ESC, b'p',
+        1, 50, 50,
+      ]
+    );
+  }
+
+  #[test]
+  fn sizes_and_pulses_are_clamped() {
+    let mut b = EscPosBuilder::new();
+    b.size(0, 9).drawer_kick(DrawerPin::Pin2, 2000).density(-9);
+    assert_eq!(b.into_bytes(), [GS, b'!', 0x07, ESC, b'p', 0, 255, 255, GS, b'(', b'K', 2, 0, 49, (-6i8) as u8]);
+  }
+
+  #[test]
+  fn separator_fills_the_line_at_the_current_width() {
+    let mut b = EscPosBuilder::new();
+    b.columns(32).separator(SeparatorStyle::Dashed, None);
+    assert_eq!(b.build(), [vec![b'-'; 32], vec![LF]].concat());
+
+    let mut b = EscPosBuilder::new();
+    b.columns(32).size(2, 1).separator(SeparatorStyle::Solid, None);
+    // Without PC437 selected, solid falls back to ASCII.
+    assert_eq!(&b.build()[3..], [vec![b'-'; 16], vec![LF]].concat());
+
+    let mut b = EscPosBuilder::new();
+    b.columns(32).code_page(0).separator(SeparatorStyle::Double, Some('*'));
+    assert_eq!(&b.build()[3..], [vec![b'*'; 32], vec![LF]].concat());
+  }
+
+  #[test]
+  fn code128_and_qr_carry_their_lengths() {
+    let mut b = EscPosBuilder::new();
+    b.barcode_code128("A1", 80, HriPosition::Below, HriFont::A);
+    let bytes = b.into_bytes();
+    assert_eq!(&bytes[bytes.len() - 8..], [GS, b'k', 73, 4, b'{', b'B', b'A', b'1']);
+
+    let mut b = EscPosBuilder::new();
+    b.qr("hello", 6);
+    let bytes = b.into_bytes();
+    let store = [GS, b'(', b'k', 8, 0, 49, 80, 48];
+    let at = bytes.windows(store.len()).position(|w| w == store).unwrap();
+    assert_eq!(&bytes[at + store.len()..at + store.len() + 5], b"hello");
+    assert_eq!(&bytes[bytes.len() - 8..], [GS, b'(', b'k', 3, 0, 49, 81, 48]);
+  }
+
+  #[test]
+  fn buzzer_commands_by_model() {
+    assert_eq!(build_buzzer(BuzzerModel::EscB, 3, 200, 0), [ESC, b'B', 3, 4]);
+    assert_eq!(build_buzzer(BuzzerModel::EscB, 20, 10_000, 0), [ESC, b'B', MAX_BUZZER_TIMES, 9]);
+    assert_eq!(build_buzzer(BuzzerModel::Epson, 2, 300, 0), [ESC, b'(', b'A', 4, 0, 48, 49, 2, 3]);
+    assert_eq!(build_buzzer(BuzzerModel::Star, 2, 100, 50), [ESC, BEL, 10, 5, BEL, BEL]);
+    assert_eq!(build_buzzer(BuzzerModel::Bel, 2, 100, 50), [BEL, BEL]);
+  }
+
+  #[test]
+  fn printable_replaces_or_transliterates() {
+    assert_eq!(printable("Café ß", false), "Caf? ?");
+    assert_eq!(printable("Café ß Жар", true), "Cafe ss Zhar");
+    assert_eq!(printable("tab\there", false), "tab?here");
+  }
+
+  #[test]
+  fn code_page_text_is_encoded_for_the_table() {
+    let mut b = EscPosBuilder::new();
+    b.select_code_page(CodePage::Wpc1251).text("Чай €");
+    assert_eq!(b.into_bytes(), [ESC, b't', 46, 0xd7, 0xe0, 0xe9, b' ', 0x88]);
+
+    let mut b = EscPosBuilder::new();
+    b.select_code_page(CodePage::Wpc1252).transliterate(true).text("Ж");
+    assert_eq!(b.into_bytes(), [ESC, b't', 16, b'Z', b'h']);
+    assert_eq!(printable_in("naïve Ω", Some(CodePage::Wpc1252), false), "naïve ?");
+  }
+
+  #[test]
+  fn multibyte_text_is_encoded_for_the_system() {
+    assert_eq!(encode_multibyte("A日", MultibyteSystem::ShiftJis), [b'A', 0x93, 0xfa]);
+    assert_eq!(encode_multibyte("中", MultibyteSystem::Gbk), [0xd6, 0xd0]);
+    assert_eq!(encode_multibyte("Ж\u{1}", MultibyteSystem::EucKr), [0xac, 0xa8]);
+    assert_eq!(encode_multibyte("🙂", MultibyteSystem::Big5), [b'?']);
+    assert_eq!(printable_multibyte("日本 é", MultibyteSystem::ShiftJis, false), "日本 ?");
+
+    let mut b = EscPosBuilder::new();
+    b.enable_multibyte(MultibyteSystem::ShiftJis).text("日").disable_multibyte();
+    assert_eq!(b.into_bytes(), [FS, b'C', 1, FS, b'&', 0x93, 0xfa, FS, b'.']);
+  }
+
+  #[test]
+  fn languages_pick_tables_and_systems() {
+    assert_eq!(CodePage::for_language("pt-BR"), Some(CodePage::Wpc1252));
+    assert_eq!(CodePage::for_language(" RU "), Some(CodePage::Wpc1251));
+    assert_eq!(CodePage::for_language("ja"), None);
+    assert!(CodePage::for_language("ar").unwrap().is_rtl());
+    assert_eq!(MultibyteSystem::for_language("zh-TW"), Some(MultibyteSystem::Big5));
+    assert_eq!(MultibyteSystem::for_language("zh_Hans"), Some(MultibyteSystem::Gbk));
+    assert_eq!(MultibyteSystem::for_language("ko-KR"), Some(MultibyteSystem::EucKr));
+    assert_eq!(MultibyteSystem::for_language("en"), None);
+  }
+}
@@ -0,0 +1,104 @@
+//! Print job lifecycle notifications.
+
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+
+use crate::audit::now_ms;
+use crate::config::RetrySource;
+use crate::error::PrintError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobPhase {
+  Queued,
+  Started,
+  Progress,
+  Finished,
+  Failed,
+}
+
+impl JobPhase {
+  /// Event name used by the app, e.g. `print-job-queued`.
+  pub fn event_name(self) -> &'static str {
+    match self {
+      JobPhase::Queued => "print-job-queued",
+      JobPhase::Started => "print-job-started",
+      JobPhase::Progress => "print-job-progress",
+      JobPhase::Finished => "print-job-finished",
+      JobPhase::Failed => "print-job-failed",
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct JobEvent {
+  pub job_id: String,
+  pub destination: String,
+  pub bytes_total: usize,
+  pub bytes_sent: usize,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<PrintError>,
+  /// Set on `Finished` and `Failed`; the same numbers the trace spans log.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub timings: Option<PhaseTimings>,
+  pub ts_ms: u64,
+}
+
+/// Time a job spent in each pipeline phase, in milliseconds. `connect_ms`
+/// includes DNS and is zero when a pooled connection was reused.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PhaseTimings {
+  pub queue_ms: u64,
+  pub connect_ms: u64,
+  pub write_ms: u64,
+  pub confirm_ms: u64,
+  /// Whether the job went out on the worker's pooled connection rather
+  /// than a fresh one; `None` for spooler jobs and jobs that never got as
+  /// far as connecting.
+  pub connection_reused: Option<bool>,
+  /// Times a fresh connection was tried, retries and offline polls
+  /// included; 0 when the pooled one was reused.
+  pub connect_attempts: u32,
+  /// The retry policy those attempts followed, when a connection was made.
+  pub retry_policy: Option<RetrySource>,
+}
+
+impl JobEvent {
+  pub fn new(job_id: &str, destination: &str, bytes_total: usize) -> Self {
+    Self {
+      job_id: job_id.to_string(),
+      destination: destination.to_string(),
+      bytes_total,
+      bytes_sent: 0,
+      error: None,
+      timings: None,
+      ts_ms: now_ms(),
+    }
+  }
+}
+
+/// Receives lifecycle events; called from worker threads, so it must not block.
+pub trait JobObserver: Send + Sync {
+  fn notify(&self, phase: JobPhase, event: &JobEvent);
+}
+
+/// Shared slot for the observer, installed once the host is ready.
+#[derive(Clone, Default)]
+pub struct JobEvents {
+  observer: Arc<RwLock<Option<Arc<dyn JobObserver>>>>,
+}
+
+impl JobEvents {
+  pub fn set_observer(&self, observer: Arc<dyn JobObserver>) {
+    *self.observer.write().unwrap_or_else(|e| e.into_inner()) = Some(observer);
+  }
+
+  pub fn emit(&self, phase: JobPhase, event: &JobEvent) {
+    let observer = self.observer.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(observer) = observer {
+      let mut event = event.clone();
+      event.ts_ms = now_ms();
+      observer.notify(phase, &event);
+    }
+  }
+}
@@ -0,0 +1,256 @@
+//! Locale-aware money and dates for receipt templates.
+//!
+//! Template text fields take either a plain string or a value to format,
+//! e.g. `{ "amount": -1234.5, "currency": "EUR", "locale": "de-DE" }` →
+//! `-1.234,50 €`, or `{ "timestamp_ms": 1760000000000, "format": "%d.%m.%Y
+//! %H:%M", "utc_offset_minutes": 120 }`. Values are formatted before layout,
+//! so rows and columns measure the final text.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::rtc::civil_from_days;
+
+/// A template text field.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Field {
+  Text(String),
+  Money(Money),
+  Date(DateValue),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Money {
+  /// A JSON number, or a string in plain `-1234.5` form.
+  pub amount: Value,
+  /// ISO 4217 code, e.g. `USD`.
+  pub currency: String,
+  /// e.g. `de-DE`; `en-US` conventions when absent or unknown.
+  #[serde(default)]
+  pub locale: Option<String>,
+  #[serde(default)]
+  pub negative: NegativeStyle,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NegativeStyle {
+  /// A minus sign where the locale puts it.
+  #[default]
+  Minus,
+  /// Accounting style, `($12.00)`.
+  Parentheses,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DateValue {
+  /// Milliseconds since the Unix epoch.
+  pub timestamp_ms: Value,
+  /// strftime-style: `%Y %y %m %d %e %H %I %M %S %p %a %A %b %B %%`.
+  pub format: String,
+  /// Offset of the shown time from UTC.
+  #[serde(default)]
+  pub utc_offset_minutes: i32,
+}
+
+impl Field {
+  /// The text to print. With `ascii`, currency symbols outside ASCII are
+  /// replaced by the currency code, since printers' default code pages
+  /// can't be trusted with them.
+  pub fn render(&self, ascii: bool) -> Result<String, String> {
+    match self {
+      Field::Text(text) => Ok(text.clone()),
+      Field::Money(money) => money.render(ascii),
+      Field::Date(date) => date.render(),
+    }
+  }
+
+  /// [`render`](Self::render) for fields already checked by
+  /// [`check_fields`](crate::receipt::check_fields); `?` if one fails anyway.
+  pub fn display(&self, ascii: bool) -> String {
+    self.render(ascii).unwrap_or_else(|_| "?".to_string())
+  }
+}
+
+struct Locale {
+  group: &'static str,
+  decimal: char,
+  symbol_after: bool,
+  space: bool,
+  /// The minus goes between the symbol and the number (`€ -1,00`).
+  minus_after_symbol: bool,
+}
+
+const fn locale(group: &'static str, decimal: char, symbol_after: bool, space: bool) -> Locale {
+  Locale {
+    group,
+    decimal,
+    symbol_after,
+    space,
+    minus_after_symbol: false,
+  }
+}
+
+fn locale_rules(tag: Option<&str>) -> Locale {
+  let tag = tag.unwrap_or("en-US").trim().replace('_', "-").to_ascii_lowercase();
+  let language = tag.split('-').next().unwrap_or_default();
+  match tag.as_str() {
+    "de-ch" | "fr-ch" | "it-ch" => locale("'", '.', false, true),
+    "en-za" => locale(" ", '.', false, true),
+    "pt-br" => locale(".", ',', false, true),
+    "nl-nl" | "nl-be" => Locale {
+      minus_after_symbol: true,
+      ..locale(".", ',', false, true)
+    },
+    _ => match language {
+      "de" | "es" | "it" | "pt" | "id" | "tr" => locale(".", ',', true, true),
+      "fr" | "ru" | "pl" | "cs" | "sv" | "nb" | "fi" | "uk" => locale(" ", ',', true, true),
+      _ => locale(",", '.', false, false),
+    },
+  }
+}
+
+/// Symbol and minor-unit digits for `code`; unknown codes print as the code.
+fn currency(code: &str) -> (&str, u32) {
+  match code {
+    "USD" => ("$", 2),
+    "EUR" => ("€", 2),
+    "GBP" => ("£", 2),
+    "JPY" => ("¥", 0),
+    "CNY" => ("¥", 2),
+    "KRW" => ("₩", 0),
+    "INR" => ("₹", 2),
+    "BRL" => ("R$", 2),
+    "ZAR" => ("R", 2),
+    "NGN" => ("₦", 2),
+    "KES" => ("KSh", 2),
+    "BWP" => ("P", 2),
+    "ZWG" => ("ZiG", 2),
+    _ => (code, 2),
+  }
+}
+
+impl Money {
+  fn render(&self, ascii: bool) -> Result<String, String> {
+    let code = self.currency.trim().to_ascii_uppercase();
+    if code.len() != 3 || !code.bytes().all(|b| b.is_ascii_alphabetic()) {
+      return Err(format!("currency '{}' is not a three-letter ISO 4217 code", self.currency));
+    }
+    let amount = match &self.amount {
+      Value::Number(n) => n.as_f64(),
+      Value::String(s) => s.trim().parse::<f64>().ok(),
+      _ => None,
+    }
+    .filter(|a| a.is_finite() && a.abs() < 1e15)
+    .ok_or_else(|| format!("amount {} is not a number", self.amount))?;
+
+    let rules = locale_rules(self.locale.as_deref());
+    let (symbol, digits) = currency(&code);
+    let symbol = if ascii && !symbol.is_ascii() { code.as_str() } else { symbol };
+    let scale = 10u64.pow(digits);
+    let minor = (amount.abs() * scale as f64).round() as u64;
+    let negative = amount < 0.0 && minor > 0;
+
+    let whole = (minor / scale).to_string();
+    let mut number = String::new();
+    for (i, digit) in whole.chars().enumerate() {
+      if i > 0 && (whole.len() - i) % 3 == 0 {
+        number.push_str(rules.group);
+      }
+      number.push(digit);
+    }
+    if digits > 0 {
+      number.push(rules.decimal);
+      number.push_str(&format!("{:0width$}", minor % scale, width = digits as usize));
+    }
+
+    // Codes used as symbols (CHF, KSh) always get a space.
+    let space = if rules.space || symbol.len() > 1 && symbol.chars().all(|c| c.is_ascii_alphabetic()) {
+      " "
+    } else {
+      ""
+    };
+    let minus_inside = negative && self.negative == NegativeStyle::Minus && rules.minus_after_symbol;
+    let number = if minus_inside { format!("-{number}") } else { number };
+    let text = if rules.symbol_after {
+      format!("{number}{space}{symbol}")
+    } else {
+      format!("{symbol}{space}{number}")
+    };
+    Ok(match (negative, self.negative) {
+      (false, _) => text,
+      (true, NegativeStyle::Parentheses) => format!("({text})"),
+      (true, NegativeStyle::Minus) if minus_inside => text,
+      (true, NegativeStyle::Minus) => format!("-{text}"),
+    })
+  }
+}
+
+const MONTHS: [&str; 12] = [
+  "January",
+  "February",
+  "March",
+  "April",
+  "May",
+  "June",
+  "July",
+  "August",
+  "September",
+  "October",
+  "November",
+  "December",
+];
+const WEEKDAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+impl DateValue {
+  fn render(&self) -> Result<String, String> {
+    let ms = match &self.timestamp_ms {
+      Value::Number(n) => n.as_i64(),
+      Value::String(s) => s.trim().parse::<i64>().ok(),
+      _ => None,
+    }
+    .ok_or_else(|| format!("timestamp_ms {} is not a whole number of milliseconds", self.timestamp_ms))?;
+    if !(-1440..=1440).contains(&self.utc_offset_minutes) {
+      return Err(format!("utc_offset_minutes {} is outside -1440..=1440", self.utc_offset_minutes));
+    }
+
+    let ts = ms.div_euclid(1000) + self.utc_offset_minutes as i64 * 60;
+    let days = ts.div_euclid(86_400);
+    let secs = ts.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    let (hour, minute, second) = (secs / 3600, secs / 60 % 60, secs % 60);
+    // 1970-01-01 was a Thursday.
+    let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+    let month_name = MONTHS[month as usize - 1];
+
+    let mut out = String::new();
+    let mut chars = self.format.chars();
+    while let Some(ch) = chars.next() {
+      if ch != '%' {
+        out.push(ch);
+        continue;
+      }
+      match chars.next() {
+        Some('Y') => out.push_str(&year.to_string()),
+        Some('y') => out.push_str(&format!("{:02}", year.rem_euclid(100))),
+        Some('m') => out.push_str(&format!("{month:02}")),
+        Some('d') => out.push_str(&format!("{day:02}")),
+        Some('e') => out.push_str(&day.to_string()),
+        Some('H') => out.push_str(&format!("{hour:02}")),
+        Some('I') => out.push_str(&format!("{:02}", (hour + 11) % 12 + 1)),
+        Some('M') => out.push_str(&format!("{minute:02}")),
+        Some('S') => out.push_str(&format!("{second:02}")),
+        Some('p') => out.push_str(if hour < 12 { "AM" } else { "PM" }),
+        Some('a') => out.push_str(&weekday[..3]),
+        Some('A') => out.push_str(weekday),
+        Some('b') => out.push_str(&month_name[..3]),
+        Some('B') => out.push_str(month_name),
+        Some('%') => out.push('%'),
+        Some(other) => return Err(format!("date format '{}' has unknown token %{other}", self.format)),
+        None => return Err(format!("date format '{}' ends with a lone %", self.format)),
+      }
+    }
+    Ok(out)
+  }
+}
@@ -0,0 +1,462 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::archive::{ArchivedReceipt, ReceiptArchive};
+use crate::audit::{now_ms, AuditEntry, AuditLog};
+use crate::config::{PrintConfig, RetryPolicy};
+use crate::deadletter::{DeadLetter, DeadLetters};
+use crate::decode;
+use crate::drawer::{DrawerEvent, DrawerLog};
+use crate::duplicates::{self, PayloadHash, RecentPayloads};
+use crate::error::PrintError;
+use crate::events::PhaseTimings;
+use crate::workers::{JobReport, MarkerConfirmation, Submitted, WorkerPool};
+use crate::quiet::HeldJobs;
+use crate::reprint::ReprintStore;
+use crate::sidecar::{ReceiptMeta, SidecarLog};
+use crate::target::Target;
+use crate::webhook::{JobResult, WebhookConfig, WebhookDispatcher, WebhookPayload};
+
+/// Returns a new job identifier, unique for this process and sortable by
+/// submission time.
+pub fn next_job_id() -> String {
+  static SEQ: AtomicU64 = AtomicU64::new(0);
+  format!("{:011x}-{:04x}", now_ms(), SEQ.fetch_add(1, Ordering::Relaxed) & 0xffff)
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrintOptions {
+  /// Report `skipped` instead of failing when the transport isn't available
+  /// on this platform (e.g. the Windows spooler on a Linux dev machine).
+  pub tolerate_unsupported: bool,
+  /// Notified once the job has printed, been skipped, or failed.
+  pub webhook: Option<WebhookConfig>,
+  /// Stored with the archived copy, e.g. `{ "receipt_number": "A-1042" }`.
+  pub metadata: Option<Value>,
+  /// Compared against the destination's quiet hours `min_priority`; queued
+  /// jobs below it are held while quiet hours are on.
+  pub priority: u8,
+  /// Appended to the day's receipt sidecar file once the job prints.
+  pub record_sidecar: Option<ReceiptMeta>,
+  /// Retries this job under its own policy instead of the destination's,
+  /// e.g. a single attempt for a receipt the customer is waiting on.
+  pub retry: Option<RetryPolicy>,
+  /// Till or station that sent the job, e.g. `front-1`; recorded in the
+  /// audit log so statistics can be filtered per register.
+  pub register: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrintStatus {
+  Printed,
+  Skipped,
+  /// The same bytes printed to this destination moments ago, so the job
+  /// was answered without printing; see `PrintConfig::suppress_duplicates`.
+  DuplicateSuppressed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PrintOutcome {
+  pub job_id: String,
+  pub status: PrintStatus,
+  pub destination: String,
+  pub bytes: usize,
+  /// Why the job was skipped or suppressed; `None` when it printed.
+  pub reason: Option<String>,
+  /// Whether a TCP or serial job used the worker's pooled connection;
+  /// see [`PhaseTimings::connection_reused`](crate::events::PhaseTimings).
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub connection_reused: Option<bool>,
+  /// GS ( H markers in the payload that the printer confirmed reaching.
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub markers: Vec<MarkerConfirmation>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FailoverAttempt {
+  pub destination: String,
+  pub error: PrintError,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FailoverOutcome {
+  /// The target that printed the job.
+  pub target: Target,
+  pub destination: String,
+  /// Targets tried before it, in order, with why each was skipped.
+  pub failed: Vec<FailoverAttempt>,
+}
+
+/// One destination's result from a fan-out print.
+#[derive(Clone, Debug, Serialize)]
+pub struct FanoutResult {
+  pub destination: String,
+  /// Set when the job printed, was skipped or was suppressed.
+  pub outcome: Option<PrintOutcome>,
+  /// Set when it failed.
+  pub error: Option<PrintError>,
+}
+
+impl FanoutResult {
+  pub fn new(destination: String, result: Result<PrintOutcome, PrintError>) -> Self {
+    let (outcome, error) = match result {
+      Ok(outcome) => (Some(outcome), None),
+      Err(e) => (None, Some(e)),
+    };
+    Self {
+      destination,
+      outcome,
+      error,
+    }
+  }
+}
+
+/// Where finished jobs are reported: the audit log, webhooks, the receipt
+/// archive, the reprint store, the drawer log and the receipt sidecars,
+/// plus jobs held by quiet hours and jobs that expired in their queue.
+#[derive(Clone)]
+pub struct JobSinks {
+  pub audit: AuditLog,
+  pub webhooks: WebhookDispatcher,
+  pub archive: ReceiptArchive,
+  pub reprints: ReprintStore,
+  pub recent: RecentPayloads,
+  pub held: HeldJobs,
+  pub dead_letters: DeadLetters,
+  pub drawer: DrawerLog,
+  pub sidecars: SidecarLog,
+}
+
+/// Bookkeeping for one submitted print, turned into an outcome once the
+/// worker reports back.
+pub struct JobRecord {
+  pub job_id: String,
+  pub target: Target,
+  pub destination: String,
+  pub bytes: usize,
+  pub submitted_at_ms: u64,
+  pub options: PrintOptions,
+  /// Copy of the payload for reprints and the receipt archive.
+  pub payload: Vec<u8>,
+  pub hash: PayloadHash,
+  /// When an identical payload last printed here, if this job repeats it.
+  pub duplicate_of_ms: Option<u64>,
+  /// Reported by the worker; set before settling.
+  pub timings: Option<PhaseTimings>,
+  pub markers: Vec<MarkerConfirmation>,
+}
+
+impl JobRecord {
+  pub fn new(target: &Target, data: &[u8], options: PrintOptions) -> Self {
+    Self {
+      job_id: next_job_id(),
+      target: target.clone(),
+      destination: target.label(),
+      bytes: data.len(),
+      submitted_at_ms: now_ms(),
+      options,
+      payload: data.to_vec(),
+      hash: duplicates::hash(data),
+      duplicate_of_ms: None,
+      timings: None,
+      markers: Vec::new(),
+    }
+  }
+
+  /// Takes in what the worker reported about the job, before settling.
+  pub fn set_report(&mut self, report: Option<JobReport>) {
+    if let Some(report) = report {
+      self.timings = Some(report.timings);
+      self.markers = report.markers;
+    }
+  }
+
+  /// Marks the job as a duplicate when duplicate suppression is on and the
+  /// same bytes printed to this destination within the window. A duplicate
+  /// must be settled with `Ok(())` without being submitted.
+  pub fn check_duplicate(&mut self, cfg: &PrintConfig, sinks: &JobSinks) -> bool {
+    if cfg.suppress_duplicates {
+      self.duplicate_of_ms = sinks
+        .recent
+        .printed_within(&self.destination, &self.hash, cfg.duplicate_window_ms, now_ms());
+    }
+    self.duplicate_of_ms.is_some()
+  }
+
+  /// Prints the job with `data` and settles it under `source` once it
+  /// finishes. A job repeating a payload that just printed here is settled
+  /// without printing; see [`check_duplicate`](Self::check_duplicate).
+  pub async fn print(
+    mut self,
+    workers: &WorkerPool,
+    sinks: &JobSinks,
+    cfg: &PrintConfig,
+    source: &str,
+    data: Vec<u8>,
+  ) -> Result<PrintOutcome, PrintError> {
+    if self.check_duplicate(cfg, sinks) {
+      return self.settle(source, Ok(()), sinks);
+    }
+    self.submit(workers, sinks, source, data).await
+  }
+
+  /// Like [`print`](Self::print) for callers on plain threads, such as the
+  /// HTTP bridge. Must not be called from inside an async runtime.
+  pub fn print_blocking(
+    mut self,
+    workers: &WorkerPool,
+    sinks: &JobSinks,
+    cfg: &PrintConfig,
+    source: &str,
+    data: Vec<u8>,
+  ) -> Result<PrintOutcome, PrintError> {
+    if self.check_duplicate(cfg, sinks) {
+      return self.settle(source, Ok(()), sinks);
+    }
+    let result = self.queue(workers, data).and_then(|submitted| {
+      let (result, report) = submitted.wait_report_blocking();
+      self.set_report(report);
+      result
+    });
+    self.settle(source, result, sinks)
+  }
+
+  /// Prints the job with `data` and settles it, without the duplicate
+  /// check; for reprints and printer settings, which repeat on purpose.
+  pub async fn submit(
+    mut self,
+    workers: &WorkerPool,
+    sinks: &JobSinks,
+    source: &str,
+    data: Vec<u8>,
+  ) -> Result<PrintOutcome, PrintError> {
+    let result = match self.queue(workers, data) {
+      Ok(submitted) => {
+        let (result, report) = submitted.wait_report().await;
+        self.set_report(report);
+        result
+      }
+      Err(e) => Err(e),
+    };
+    self.settle(source, result, sinks)
+  }
+
+  /// Queues the job on its destination's worker under its own retry policy.
+  pub fn queue(&self, workers: &WorkerPool, data: Vec<u8>) -> Result<Submitted, PrintError> {
+    workers.submit_print_with(&self.job_id, self.target.clone(), data, self.options.retry.clone())
+  }
+
+  /// Audits the result under `source`, fires the webhook, keeps printed
+  /// payloads for reprints and the archive, logs drawer kicks embedded in
+  /// printed payloads, records `record_sidecar` for printed jobs, moves
+  /// expired jobs to the dead letters, and applies `tolerate_unsupported`.
+  pub fn settle(
+    mut self,
+    source: &str,
+    result: Result<(), PrintError>,
+    sinks: &JobSinks,
+  ) -> Result<PrintOutcome, PrintError> {
+    if let (Ok(()), Some(at)) = (&result, self.duplicate_of_ms) {
+      return Ok(self.settle_duplicate(source, at, sinks));
+    }
+    if result.is_ok() {
+      sinks.recent.record(&self.destination, self.hash, now_ms());
+      let data = std::mem::take(&mut self.payload);
+      for _ in 0..decode::drawer_kicks(&data) {
+        sinks.drawer.record(DrawerEvent {
+          embedded: true,
+          ..DrawerEvent::new(&self.destination, &self.job_id)
+        });
+      }
+      if sinks.archive.enabled() {
+        sinks.archive.store(
+          ArchivedReceipt {
+            job_id: self.job_id.clone(),
+            destination: self.destination.clone(),
+            bytes: self.bytes,
+            printed_at_ms: now_ms(),
+            metadata: self.options.metadata.clone(),
+            image_path: None,
+          },
+          data.clone(),
+        );
+      }
+      sinks.reprints.remember(&self.job_id, self.target.clone(), data);
+      if let Some(meta) = &self.options.record_sidecar {
+        sinks.sidecars.append(meta, &self.job_id, &self.destination);
+      }
+    }
+    if let Err(PrintError::Expired(_)) = &result {
+      sinks.dead_letters.push(DeadLetter {
+        job_id: self.job_id.clone(),
+        target: self.target.clone(),
+        destination: self.destination.clone(),
+        bytes: self.bytes,
+        submitted_at_ms: self.submitted_at_ms,
+        expired_at_ms: now_ms(),
+        payload: std::mem::take(&mut self.payload),
+      });
+    }
+    let connection_reused = self.timings.and_then(|t| t.connection_reused);
+    let mut detail = format!("job_id={}", self.job_id);
+    if let Some(reused) = connection_reused {
+      detail.push_str(&format!(" connection_reused={reused}"));
+    }
+    if let Some(timings) = self.timings.filter(|t| t.connect_attempts > 0) {
+      let policy = timings.retry_policy.map_or("default", |p| p.as_str());
+      detail.push_str(&format!(" connect_attempts={} retry_policy={policy}", timings.connect_attempts));
+    }
+    if !self.markers.is_empty() {
+      let ids = self.markers.iter().map(|m| m.id.as_str()).collect::<Vec<_>>();
+      detail.push_str(&format!(" markers_confirmed={}", ids.join(",")));
+    }
+    sinks.audit.record(
+      AuditEntry::new(source, "print")
+        .destination(self.destination.clone())
+        .bytes(self.bytes)
+        .detail(detail)
+        .duration_ms(now_ms().saturating_sub(self.submitted_at_ms))
+        .register(self.options.register.clone())
+        .outcome(&result),
+    );
+
+    let result = match result {
+      Ok(()) => Ok(PrintOutcome {
+        job_id: self.job_id.clone(),
+        status: PrintStatus::Printed,
+        destination: self.destination.clone(),
+        bytes: self.bytes,
+        reason: None,
+        connection_reused,
+        markers: std::mem::take(&mut self.markers),
+      }),
+      Err(PrintError::Unsupported(reason)) if self.options.tolerate_unsupported => {
+        log::info!("skipping print to {}: {reason}", self.destination);
+        Ok(PrintOutcome {
+          job_id: self.job_id.clone(),
+          status: PrintStatus::Skipped,
+          destination: self.destination.clone(),
+          bytes: self.bytes,
+          reason: Some(reason),
+          connection_reused: None,
+          markers: Vec::new(),
+        })
+      }
+      Err(e) => Err(e),
+    };
+    let job_result = match &result {
+      Ok(o) if o.status == PrintStatus::Printed => JobResult::Printed,
+      Ok(_) => JobResult::Skipped,
+      Err(_) => JobResult::Failed,
+    };
+    self.notify(job_result, result.as_ref().err().cloned(), sinks);
+    result
+  }
+
+  fn settle_duplicate(self, source: &str, printed_at_ms: u64, sinks: &JobSinks) -> PrintOutcome {
+    let ago_ms = now_ms().saturating_sub(printed_at_ms);
+    log::info!(
+      "suppressing duplicate job {} to {}: identical payload printed {ago_ms} ms ago",
+      self.job_id,
+      self.destination
+    );
+    sinks.audit.record(
+      AuditEntry::new(source, "print")
+        .destination(self.destination.clone())
+        .bytes(self.bytes)
+        .detail(format!("job_id={} duplicate_suppressed=true printed_ms_ago={ago_ms}", self.job_id))
+        .outcome(&Ok::<(), PrintError>(())),
+    );
+    self.notify(JobResult::Skipped, None, sinks);
+    PrintOutcome {
+      job_id: self.job_id,
+      status: PrintStatus::DuplicateSuppressed,
+      destination: self.destination,
+      bytes: self.bytes,
+      reason: Some(format!("An identical payload printed to this printer {ago_ms} ms ago.")),
+      connection_reused: None,
+      markers: Vec::new(),
+    }
+  }
+
+  fn notify(&self, outcome: JobResult, error: Option<PrintError>, sinks: &JobSinks) {
+    let Some(webhook) = &self.options.webhook else {
+      return;
+    };
+    sinks.webhooks.dispatch(
+      webhook,
+      WebhookPayload {
+        job_id: self.job_id.clone(),
+        destination: self.destination.clone(),
+        outcome,
+        bytes: self.bytes,
+        error,
+        metadata: None,
+        submitted_at_ms: self.submitted_at_ms,
+        finished_at_ms: now_ms(),
+      },
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Read;
+  use std::net::TcpListener;
+  use std::thread;
+
+  use super::*;
+  use crate::config::ConfigStore;
+
+  fn sinks() -> JobSinks {
+    JobSinks {
+      audit: AuditLog::default(),
+      webhooks: WebhookDispatcher::default(),
+      archive: ReceiptArchive::default(),
+      reprints: ReprintStore::default(),
+      recent: RecentPayloads::default(),
+      held: HeldJobs::default(),
+      dead_letters: DeadLetters::default(),
+      drawer: DrawerLog::default(),
+      sidecars: SidecarLog::default(),
+    }
+  }
+
+  #[test]
+  fn a_repeated_payload_is_suppressed_once_it_printed() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let target = Target::Tcp {
+      host: "127.0.0.1".to_string(),
+      port: listener.local_addr().unwrap().port(),
+    };
+    let printer = thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut received = Vec::new();
+      stream.read_to_end(&mut received).unwrap();
+      received
+    });
+    let workers = WorkerPool::new(ConfigStore::default());
+    let sinks = sinks();
+    let cfg = PrintConfig {
+      suppress_duplicates: true,
+      duplicate_window_ms: 60_000,
+      ..PrintConfig::default()
+    };
+    let print = || {
+      JobRecord::new(&target, b"receipt", PrintOptions::default()).print_blocking(&workers, &sinks, &cfg, "http", b"receipt".to_vec())
+    };
+
+    let first = print().unwrap();
+    assert_eq!(first.status, PrintStatus::Printed);
+    let second = print().unwrap();
+    assert_eq!(second.status, PrintStatus::DuplicateSuppressed);
+    assert_ne!(first.job_id, second.job_id);
+    // Closes the worker's connection so the printer sees the end.
+    workers.shutdown();
+    assert_eq!(printer.join().unwrap(), b"receipt");
+  }
+}
@@ -0,0 +1,158 @@
+//! Kitchen order chits.
+//!
+//! Kitchen staff read tickets at a glance from a distance, so the layout
+//! follows kitchen conventions rather than the receipt model: a huge order
+//! number, a dine-in/takeout banner, double-height items grouped by course,
+//! indented modifiers with "NO ..." negations highlighted, and voided items
+//! marked so they aren't cooked. Only ESC/POS is emitted, so Star printers
+//! must be in ESC/POS emulation mode.
+
+use serde::Deserialize;
+
+use crate::error::PrintError;
+use crate::escpos::{self, Align, Cut, EscPosBuilder, ESC};
+use crate::receipt::{check_columns, wrap};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct KitchenTicket {
+  pub order_number: String,
+  #[serde(default)]
+  pub table: Option<String>,
+  #[serde(default)]
+  pub takeout: bool,
+  pub courses: Vec<Course>,
+  /// Order-level note, e.g. "Allergy: peanuts".
+  #[serde(default)]
+  pub note: Option<String>,
+  #[serde(default)]
+  pub server: Option<String>,
+  /// Order time as shown to staff, already in local time, e.g. "18:42".
+  pub time: String,
+  /// The printer has a red ribbon or two-color paper; highlights print red
+  /// instead of white-on-black.
+  #[serde(default)]
+  pub two_color: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Course {
+  /// e.g. "Starters"; items are printed without a heading when absent.
+  #[serde(default)]
+  pub name: Option<String>,
+  pub items: Vec<KitchenItem>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct KitchenItem {
+  pub qty: u32,
+  pub name: String,
+  #[serde(default)]
+  pub modifiers: Vec<String>,
+  #[serde(default)]
+  pub voided: bool,
+}
+
+const INDENT: &str = "   ";
+
+/// A modifier that removes something, e.g. "no onions" or "without cheese".
+fn is_negation(modifier: &str) -> bool {
+  let m = modifier.trim_start().to_ascii_uppercase();
+  ["NO ", "NO-", "WITHOUT ", "HOLD "].iter().any(|p| m.starts_with(p))
+}
+
+fn highlight(b: &mut EscPosBuilder, two_color: bool, on: bool) {
+  if two_color {
+    // ESC r — select print color 2 (red).
+    b.raw(&[ESC, b'r', on as u8]);
+  } else {
+    b.inverse(on);
+  }
+}
+
+/// Renders the ticket for a printer with `columns` Font A characters per line.
+pub fn render_escpos(ticket: &KitchenTicket, columns: usize) -> Result<Vec<u8>, PrintError> {
+  check_columns(columns)?;
+  if ticket.courses.iter().all(|c| c.items.is_empty()) {
+    return Err(PrintError::InvalidRequest("Kitchen ticket has no items.".to_string()));
+  }
+  let text = |s: &str| escpos::printable(s, true);
+  let mut b = EscPosBuilder::new();
+  b.init().align(Align::Center);
+
+  // Quadruple size when the number fits, otherwise as large as it can be.
+  let number = text(&format!("#{}", ticket.order_number));
+  let scale = (1..=4u8).rev().find(|s| number.len() * *s as usize <= columns).unwrap_or(1);
+  b.bold(true).size(scale, scale).line(&number).size(1, 1);
+
+  let banner = match (&ticket.table, ticket.takeout) {
+    (_, true) => Some(" TAKEOUT ".to_string()),
+    (Some(table), false) => Some(format!(" TABLE {} ", text(table))),
+    (None, false) => None,
+  };
+  if let Some(banner) = banner {
+    b.size(2, 2);
+    highlight(&mut b, ticket.two_color, true);
+    b.line(&banner);
+    highlight(&mut b, ticket.two_color, false);
+    b.size(1, 1);
+  }
+  b.bold(false).align(Align::Left).line(&"=".repeat(columns));
+
+  for course in ticket.courses.iter().filter(|c| !c.items.is_empty()) {
+    if let Some(name) = &course.name {
+      b.align(Align::Center)
+        .bold(true)
+        .line(&format!("-- {} --", text(name).to_ascii_uppercase()))
+        .bold(false)
+        .align(Align::Left);
+    }
+    for item in &course.items {
+      let name = format!("{}x {}", item.qty, text(&item.name));
+      if item.voided {
+        highlight(&mut b, ticket.two_color, true);
+        for line in wrap(&format!("VOID {name}"), columns) {
+          b.line(&line);
+        }
+        highlight(&mut b, ticket.two_color, false);
+        continue;
+      }
+      b.bold(true).size(1, 2);
+      for line in wrap(&name, columns) {
+        b.line(&line);
+      }
+      b.size(1, 1).bold(false);
+      for modifier in &item.modifiers {
+        let negation = is_negation(modifier);
+        let modifier = if negation { text(modifier).to_ascii_uppercase() } else { text(modifier) };
+        for line in wrap(&modifier, columns - INDENT.len()) {
+          b.text(INDENT);
+          if negation {
+            b.bold(true);
+            highlight(&mut b, ticket.two_color, true);
+            b.text(&line);
+            highlight(&mut b, ticket.two_color, false);
+            b.bold(false).newline();
+          } else {
+            b.line(&line);
+          }
+        }
+      }
+    }
+  }
+
+  if let Some(note) = &ticket.note {
+    b.line(&"-".repeat(columns)).bold(true);
+    for line in wrap(&text(note), columns) {
+      b.line(&line);
+    }
+    b.bold(false);
+  }
+
+  b.line(&"=".repeat(columns));
+  let server = ticket.server.as_deref().map(|s| format!("Server: {}", text(s))).unwrap_or_default();
+  let time = text(&ticket.time);
+  let pad = columns.saturating_sub(time.len() + server.len()).max(1);
+  b.line(&format!("{time}{}{server}", " ".repeat(pad)));
+  b.feed(3).cut(Cut::Partial);
+  Ok(b.into_bytes())
+}
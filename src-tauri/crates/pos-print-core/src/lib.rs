@@ -0,0 +1,57 @@
+//! Printer transports, raster conversion, and job workers for BinanceXI POS.
+//!
+//! Nothing here depends on Tauri; the app crate wraps these in commands and
+//! other tools (daemons, CLIs) can link it directly.
+
+pub mod alerts;
+pub mod archive;
+pub mod audit;
+pub mod benchmark;
+pub mod bluetooth;
+pub mod breaker;
+pub mod bridge;
+pub mod budget;
+pub mod capabilities;
+pub mod cancel;
+pub mod compress;
+pub mod config;
+pub mod deadletter;
+pub mod decode;
+pub mod diagnostics;
+pub mod discovery;
+pub mod drawer;
+pub mod duplicates;
+pub mod error;
+pub mod escpos;
+pub mod events;
+pub mod format;
+pub mod job;
+pub mod kitchen;
+pub mod limiter;
+pub mod netif;
+pub mod nvlogo;
+pub mod partials;
+pub mod preview;
+pub mod printer_info;
+pub mod qr_payload;
+pub mod quiet;
+pub mod raster;
+pub mod receipt;
+pub mod recovery;
+pub mod reprint;
+pub mod resolve;
+pub mod rtc;
+pub mod serial;
+pub mod sidecar;
+pub mod snmp;
+pub mod spooler;
+pub mod stats;
+pub mod status;
+pub mod target;
+pub mod template;
+pub mod transport;
+pub mod typeset;
+pub mod validate;
+pub mod webhook;
+pub mod winport;
+pub mod workers;
@@ -0,0 +1,139 @@
+//! Global and per-transport caps on jobs printing at once.
+//!
+//! Workers take a permit before running a job. Waiters are served oldest
+//! first among those whose limits allow them to run, so one saturated
+//! transport doesn't hold up jobs for another.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::config::{ConcurrencyLimits, ConfigStore};
+
+/// Waiters re-check on this interval so limit changes apply without a release.
+const RECHECK: Duration = Duration::from_millis(250);
+
+#[derive(Default)]
+struct State {
+  next_ticket: u64,
+  waiting: VecDeque<(u64, &'static str)>,
+  in_flight: BTreeMap<&'static str, usize>,
+  total: usize,
+}
+
+impl State {
+  fn can_run(&self, limits: &ConcurrencyLimits, kind: &str) -> bool {
+    self.total < limits.max_in_flight
+      && limits
+        .transport_limit(kind)
+        .map_or(true, |cap| self.in_flight.get(kind).copied().unwrap_or(0) < cap)
+  }
+}
+
+pub struct Limiter {
+  state: Mutex<State>,
+  cond: Condvar,
+  config: ConfigStore,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TransportLoad {
+  pub transport: &'static str,
+  pub in_flight: usize,
+  pub waiting: usize,
+  pub limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ConcurrencyStats {
+  pub in_flight: usize,
+  /// Jobs picked up by a worker but waiting for a permit.
+  pub waiting: usize,
+  /// Jobs still sitting in worker queues.
+  pub queued: usize,
+  pub max_in_flight: usize,
+  pub by_transport: Vec<TransportLoad>,
+}
+
+/// Held while a job runs; releases its slot on drop.
+pub struct Permit {
+  limiter: Arc<Limiter>,
+  kind: &'static str,
+}
+
+impl Drop for Permit {
+  fn drop(&mut self) {
+    let mut state = self.limiter.state.lock().unwrap_or_else(|e| e.into_inner());
+    state.total -= 1;
+    if let Some(n) = state.in_flight.get_mut(self.kind) {
+      *n -= 1;
+    }
+    self.limiter.cond.notify_all();
+  }
+}
+
+impl Limiter {
+  pub fn new(config: ConfigStore) -> Self {
+    Self {
+      state: Mutex::default(),
+      cond: Condvar::new(),
+      config,
+    }
+  }
+
+  /// Blocks until a job of transport `kind` may run.
+  pub fn acquire(self: &Arc<Self>, kind: &'static str) -> Permit {
+    let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+    let ticket = state.next_ticket;
+    state.next_ticket += 1;
+    state.waiting.push_back((ticket, kind));
+
+    loop {
+      let limits = self.config.snapshot().concurrency;
+      let next = state
+        .waiting
+        .iter()
+        .find(|(_, k)| state.can_run(&limits, k))
+        .map(|(t, _)| *t);
+      if next == Some(ticket) {
+        state.waiting.retain(|(t, _)| *t != ticket);
+        state.total += 1;
+        *state.in_flight.entry(kind).or_default() += 1;
+        // Another waiter may also fit now.
+        self.cond.notify_all();
+        return Permit {
+          limiter: self.clone(),
+          kind,
+        };
+      }
+      state = self
+        .cond
+        .wait_timeout(state, RECHECK)
+        .unwrap_or_else(|e| e.into_inner())
+        .0;
+    }
+  }
+
+  pub fn stats(&self, queued: usize) -> ConcurrencyStats {
+    let limits = self.config.snapshot().concurrency;
+    let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+    let by_transport = ["tcp", "serial", "spooler", "port"]
+      .into_iter()
+      .map(|transport| TransportLoad {
+        transport,
+        in_flight: state.in_flight.get(transport).copied().unwrap_or(0),
+        waiting: state.waiting.iter().filter(|(_, k)| *k == transport).count(),
+        limit: limits.transport_limit(transport),
+      })
+      .collect();
+    ConcurrencyStats {
+      in_flight: state.total,
+      waiting: state.waiting.len(),
+      queued,
+      max_in_flight: limits.max_in_flight,
+      by_transport,
+    }
+  }
+}
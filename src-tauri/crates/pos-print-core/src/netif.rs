@@ -0,0 +1,57 @@
+//! This computer's network interfaces, so setup can pick which segment to
+//! look for printers on, e.g. the store LAN rather than the payment VLAN on
+//! a multi-homed POS box.
+
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use if_addrs::IfAddr;
+use serde::Serialize;
+
+use crate::error::PrintError;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Ipv4Network {
+  pub addr: Ipv4Addr,
+  pub prefix_len: u32,
+  /// Directed broadcast address, e.g. `192.168.1.255`; none on
+  /// point-to-point links.
+  pub broadcast: Option<Ipv4Addr>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct NetworkInterface {
+  /// OS name, e.g. `eth0` or `Ethernet 2`.
+  pub name: String,
+  pub loopback: bool,
+  pub ipv4: Vec<Ipv4Network>,
+  pub ipv6: Vec<Ipv6Addr>,
+}
+
+/// Interfaces with at least one address, sorted by name. Loopback is left
+/// out unless `include_loopback`.
+pub fn list_interfaces(include_loopback: bool) -> Result<Vec<NetworkInterface>, PrintError> {
+  let addrs = if_addrs::get_if_addrs()
+    .map_err(|e| PrintError::Enumerate(format!("Unable to list network interfaces: {e}.")))?;
+  let mut by_name = BTreeMap::<String, NetworkInterface>::new();
+  for iface in addrs {
+    if iface.is_loopback() && !include_loopback {
+      continue;
+    }
+    let entry = by_name.entry(iface.name.clone()).or_insert_with(|| NetworkInterface {
+      name: iface.name.clone(),
+      loopback: iface.is_loopback(),
+      ipv4: Vec::new(),
+      ipv6: Vec::new(),
+    });
+    match iface.addr {
+      IfAddr::V4(v4) => entry.ipv4.push(Ipv4Network {
+        addr: v4.ip,
+        prefix_len: u32::from(v4.netmask).count_ones(),
+        broadcast: v4.broadcast,
+      }),
+      IfAddr::V6(v6) => entry.ipv6.push(v6.ip),
+    }
+  }
+  Ok(by_name.into_values().collect())
+}
@@ -0,0 +1,204 @@
+//! Logos stored once in the printer's NV graphics memory and recalled by a
+//! two-character key, so each receipt sends 11 bytes instead of the whole
+//! raster (tens of KB, seconds at serial speeds).
+//!
+//! Uses the `GS ( L` NV graphics functions:
+//! - define (fn 67): `GS ( L pL pH 30 43 30 kc1 kc2 01 xL xH yL yH 31 d...`,
+//!   or `GS 8 L p1..p4 ...` when the data doesn't fit a 16-bit length
+//! - print (fn 69): `GS ( L 06 00 30 45 kc1 kc2 01 01`
+//! - key list (fn 64): `GS ( L 04 00 30 40 4B 43`, answered in blocks of
+//!   `37 72 status kc1 kc2 ... 00`; status `41` means another block follows
+//!   once the host sends ACK
+//!
+//! NV memory wears out after a limited number of writes, so provision at
+//! setup, not per receipt.
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::error::PrintError;
+use crate::escpos::GS;
+use crate::raster::Bitmap;
+use crate::workers::Duplex;
+
+const FN_KEY_LIST: u8 = 64;
+const FN_DEFINE: u8 = 67;
+const FN_PRINT: u8 = 69;
+const REPLY_HEADER: [u8; 2] = [0x37, 0x72];
+const STATUS_MORE: u8 = 0x41;
+const ACK: u8 = 0x06;
+/// Largest NV graphic the command set allows, in dots.
+const MAX_WIDTH: u32 = 8192;
+const MAX_HEIGHT: u32 = 2304;
+/// Key-list bytes accepted before the reply is treated as garbage.
+const MAX_REPLY: usize = 4096;
+/// Printers stay busy while writing NV memory, so the check after a define
+/// waits at least this long for the key list.
+pub const NV_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ProvisionReport {
+  pub key: String,
+  pub width: u32,
+  pub height: u32,
+  /// Raster bytes written to NV memory.
+  pub bytes: usize,
+  /// Every key the printer lists after the write, including `key`.
+  pub stored_keys: Vec<String>,
+}
+
+/// The key's two bytes; keys are two printable ASCII characters, e.g. `LG`.
+pub fn parse_key(key: &str) -> Result<[u8; 2], PrintError> {
+  match key.as_bytes() {
+    &[a, b] if (0x20..=0x7e).contains(&a) && (0x20..=0x7e).contains(&b) => Ok([a, b]),
+    _ => Err(PrintError::InvalidRequest(format!(
+      "Logo key '{key}' must be exactly two printable ASCII characters, e.g. 'LG'."
+    ))),
+  }
+}
+
+pub fn define_command(key: [u8; 2], bitmap: &Bitmap) -> Result<Vec<u8>, PrintError> {
+  if bitmap.width > MAX_WIDTH || bitmap.height > MAX_HEIGHT {
+    return Err(PrintError::InvalidRequest(format!(
+      "NV logos can be at most {MAX_WIDTH}x{MAX_HEIGHT} dots; this image is {}x{}. Pass a smaller max_width.",
+      bitmap.width, bitmap.height
+    )));
+  }
+  let mut params = vec![
+    0x30,
+    FN_DEFINE,
+    0x30,
+    key[0],
+    key[1],
+    1,
+    (bitmap.width & 0xff) as u8,
+    (bitmap.width >> 8) as u8,
+    (bitmap.height & 0xff) as u8,
+    (bitmap.height >> 8) as u8,
+    0x31,
+  ];
+  params.extend_from_slice(&bitmap.data);
+
+  let len = params.len();
+  let mut out = Vec::with_capacity(len + 7);
+  if len <= u16::MAX as usize {
+    out.extend_from_slice(&[GS, b'(', b'L']);
+    out.extend_from_slice(&(len as u16).to_le_bytes());
+  } else {
+    out.extend_from_slice(&[GS, b'8', b'L']);
+    out.extend_from_slice(&(len as u32).to_le_bytes());
+  }
+  out.extend_from_slice(&params);
+  Ok(out)
+}
+
+pub fn print_command(key: [u8; 2]) -> [u8; 11] {
+  [GS, b'(', b'L', 6, 0, 0x30, FN_PRINT, key[0], key[1], 1, 1]
+}
+
+pub fn key_list_command() -> [u8; 9] {
+  [GS, b'(', b'L', 4, 0, 0x30, FN_KEY_LIST, b'K', b'C']
+}
+
+/// Stores `bitmap` under `key`, replacing any logo with that key, then
+/// confirms the printer lists the key.
+pub fn provision(
+  io: &mut dyn Duplex,
+  label: &str,
+  key: &str,
+  bitmap: &Bitmap,
+  timeout: Duration,
+) -> Result<ProvisionReport, PrintError> {
+  let kc = parse_key(key)?;
+  let define = define_command(kc, bitmap)?;
+  io.write_all(&define)
+    .and_then(|_| io.flush())
+    .map_err(|e| PrintError::Write(format!("Storing logo '{key}' on {label} failed: {e}.").into()))?;
+
+  let stored_keys = list_keys(io, label, timeout.max(NV_WRITE_TIMEOUT))?;
+  if !stored_keys.iter().any(|k| k == key) {
+    return Err(PrintError::Write(
+      format!(
+        "{label} did not list logo '{key}' after storing it; its NV memory may be full. Remove unused logos or use a smaller image."
+      )
+      .into(),
+    ));
+  }
+  Ok(ProvisionReport {
+    key: key.to_string(),
+    width: bitmap.width,
+    height: bitmap.height,
+    bytes: bitmap.data.len(),
+    stored_keys,
+  })
+}
+
+/// Keys of the logos stored on the printer.
+pub fn list_keys(io: &mut dyn Duplex, label: &str, timeout: Duration) -> Result<Vec<String>, PrintError> {
+  io.write_all(&key_list_command())
+    .and_then(|_| io.flush())
+    .map_err(|e| PrintError::Write(format!("Logo key request to {label} failed: {e}.").into()))?;
+
+  let deadline = Instant::now() + timeout;
+  let mut data = Vec::new();
+  loop {
+    let more = read_block(io, label, deadline, timeout, &mut data)?;
+    if !more {
+      break;
+    }
+    io.write_all(&[ACK])
+      .and_then(|_| io.flush())
+      .map_err(|e| PrintError::Write(format!("Logo key request to {label} failed: {e}.").into()))?;
+  }
+  Ok(data.chunks_exact(2).map(|kc| String::from_utf8_lossy(kc).into_owned()).collect())
+}
+
+/// Appends one block's key bytes to `data`; true when another follows.
+fn read_block(
+  io: &mut dyn Duplex,
+  label: &str,
+  deadline: Instant,
+  timeout: Duration,
+  data: &mut Vec<u8>,
+) -> Result<bool, PrintError> {
+  let mut matched = 0;
+  let mut status = None;
+  let mut byte = [0u8; 1];
+  loop {
+    if Instant::now() >= deadline {
+      return Err(PrintError::Unsupported(format!(
+        "{label} did not answer the logo key request within {} ms; it likely doesn't support NV graphics (GS ( L).",
+        timeout.as_millis()
+      )));
+    }
+    match io.read(&mut byte) {
+      Ok(0) => return Err(PrintError::Read(format!("{label} closed the connection during the logo key request.").into())),
+      Ok(_) => {
+        let b = byte[0];
+        if matched < REPLY_HEADER.len() {
+          // Resynchronize on the header so stray status bytes are skipped.
+          matched = if b == REPLY_HEADER[matched] {
+            matched + 1
+          } else {
+            usize::from(b == REPLY_HEADER[0])
+          };
+        } else if status.is_none() {
+          status = Some(b);
+        } else if b == 0 {
+          return Ok(status == Some(STATUS_MORE));
+        } else if data.len() >= MAX_REPLY {
+          return Err(PrintError::Read(format!("{label} sent an overlong logo key list.").into()));
+        } else {
+          data.push(b);
+        }
+      }
+      Err(e)
+        if matches!(
+          e.kind(),
+          std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted
+        ) => {}
+      Err(e) => return Err(PrintError::Read(format!("Logo key read from {label} failed: {e}.").into())),
+    }
+  }
+}
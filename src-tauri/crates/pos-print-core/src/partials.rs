@@ -0,0 +1,163 @@
+//! Named receipt fragments shared between templates, e.g. a store header
+//! or a returns footer.
+//!
+//! A receipt's `elements` (or a section's `elements`/`narrow`) may hold
+//! `{ "partial": "store_header" }`, which is replaced by that partial's
+//! elements when the receipt is rendered, so changing a partial changes
+//! every receipt that uses it. Partials may use other partials.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use serde_json::Value;
+
+use crate::error::PrintError;
+use crate::receipt::Receipt;
+use crate::template;
+
+const MAX_NAME_LEN: usize = 64;
+
+/// Partials by name, saved to `<dir>/partials.json` on every change.
+#[derive(Clone, Default)]
+pub struct PartialStore {
+  path: Option<PathBuf>,
+  partials: Arc<RwLock<BTreeMap<String, Vec<Value>>>>,
+}
+
+impl PartialStore {
+  pub fn open(dir: PathBuf) -> Self {
+    if let Err(e) = fs::create_dir_all(&dir) {
+      log::warn!("template partials won't be saved, unable to create {}: {e}", dir.display());
+      return Self::default();
+    }
+    let path = dir.join("partials.json");
+    let partials = match fs::read(&path) {
+      Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+        log::warn!("ignoring unreadable template partials in {}: {e}", path.display());
+        BTreeMap::new()
+      }),
+      Err(_) => BTreeMap::new(),
+    };
+    Self {
+      path: Some(path),
+      partials: Arc::new(RwLock::new(partials)),
+    }
+  }
+
+  /// Adds or replaces the partial `name`. `fragment` is one element or an
+  /// array of them.
+  pub fn register(&self, name: &str, fragment: Value) -> Result<(), PrintError> {
+    if name.is_empty()
+      || name.len() > MAX_NAME_LEN
+      || !name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-')
+    {
+      return Err(PrintError::InvalidRequest(format!(
+        "Partial name '{name}' must be 1 to {MAX_NAME_LEN} lowercase letters, digits, '_' or '-'."
+      )));
+    }
+    let elements = match fragment {
+      Value::Array(elements) => elements,
+      element @ Value::Object(_) => vec![element],
+      _ => {
+        return Err(PrintError::InvalidRequest(format!(
+          "Partial '{name}' must be a receipt element or an array of them."
+        )))
+      }
+    };
+    if let Some(at) = elements.iter().position(|e| !e.is_object()) {
+      return Err(PrintError::InvalidRequest(format!(
+        "Partial '{name}' element {at} is not an object."
+      )));
+    }
+    self.update(|partials| {
+      partials.insert(name.to_string(), elements);
+    })
+  }
+
+  /// Returns whether `name` existed.
+  pub fn remove(&self, name: &str) -> Result<bool, PrintError> {
+    let mut existed = false;
+    self.update(|partials| existed = partials.remove(name).is_some())?;
+    Ok(existed)
+  }
+
+  pub fn names(&self) -> Vec<String> {
+    self.read().keys().cloned().collect()
+  }
+
+  /// Parses a receipt after replacing its partial references, failing
+  /// with the reference's path on a missing partial or a cycle, then
+  /// evaluating its conditions and loops against its `data`.
+  pub fn receipt(&self, mut receipt: Value) -> Result<Receipt, PrintError> {
+    if let Some(elements) = receipt.get_mut("elements") {
+      self.expand(elements, "elements", &mut Vec::new())?;
+    }
+    template::apply(&mut receipt)?;
+    serde_json::from_value(receipt).map_err(|e| PrintError::InvalidRequest(format!("Invalid receipt: {e}.")))
+  }
+
+  /// Splices partials into the `elements` array at `path`, recursing into
+  /// sections. `using` holds the partials being expanded, outermost first.
+  fn expand(&self, elements: &mut Value, path: &str, using: &mut Vec<String>) -> Result<(), PrintError> {
+    let Value::Array(items) = elements else {
+      return Ok(());
+    };
+    let mut out = Vec::with_capacity(items.len());
+    for (i, mut item) in std::mem::take(items).into_iter().enumerate() {
+      let at = format!("{path}[{i}]");
+      let partial = match (item.get("partial"), item.get("type")) {
+        (Some(Value::String(name)), None) => Some(name.clone()),
+        _ => None,
+      };
+      let Some(name) = partial else {
+        for key in ["elements", "narrow"] {
+          if let Some(inner) = item.get_mut(key) {
+            self.expand(inner, &format!("{at}.{key}"), using)?;
+          }
+        }
+        out.push(item);
+        continue;
+      };
+      if using.contains(&name) {
+        return Err(PrintError::InvalidRequest(format!(
+          "{at}: partial '{name}' includes itself ({} -> {name}).",
+          using.join(" -> ")
+        )));
+      }
+      let Some(fragment) = self.read().get(&name).cloned() else {
+        return Err(PrintError::InvalidRequest(format!(
+          "{at}: there is no template partial named '{name}'; register it with register_template_partial."
+        )));
+      };
+      let mut fragment = Value::Array(fragment);
+      using.push(name.clone());
+      self.expand(&mut fragment, &format!("{at}<{name}>"), using)?;
+      using.pop();
+      if let Value::Array(fragment) = fragment {
+        out.extend(fragment);
+      }
+    }
+    *items = out;
+    Ok(())
+  }
+
+  fn read(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<String, Vec<Value>>> {
+    self.partials.read().unwrap_or_else(|e| e.into_inner())
+  }
+
+  fn update(&self, change: impl FnOnce(&mut BTreeMap<String, Vec<Value>>)) -> Result<(), PrintError> {
+    let mut partials = self.partials.write().unwrap_or_else(|e| e.into_inner());
+    change(&mut partials);
+    let Some(path) = &self.path else {
+      return Ok(());
+    };
+    let json = serde_json::to_vec_pretty(&*partials)
+      .map_err(|e| PrintError::Task(format!("Unable to serialize template partials: {e}.")))?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json)
+      .and_then(|()| fs::rename(&tmp, path))
+      .map_err(|e| PrintError::Task(format!("Unable to save template partials to {}: {e}.", path.display())))
+  }
+}
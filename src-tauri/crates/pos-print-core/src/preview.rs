@@ -0,0 +1,223 @@
+//! Pixel preview of a receipt, drawn the way the printer would.
+//!
+//! Uses the same [`receipt::layout`] as the ESC/POS renderer, with Font A
+//! cells (12x24 dots) and 30-dot line pitch, so line breaks and alignment
+//! match the paper. Already-printed ESC/POS can be redrawn through
+//! [`decode`]. Glyphs come from an 8x8 bitmap font scaled into the
+//! cell, so letter shapes are approximate.
+
+use std::io::Cursor;
+
+use barcoders::sym::code128::Code128;
+use font8x8::{UnicodeFonts, BASIC_FONTS};
+use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+use qrcode::{Color, EcLevel, QrCode};
+
+use crate::decode;
+use crate::error::PrintError;
+use crate::escpos::{Align, Cut, HriFont, HriPosition};
+use crate::receipt::{self, Block, Line, Receipt};
+
+const CELL_W: u32 = 12;
+const CELL_H: u32 = 24;
+/// Font B cell, used for barcode digits in that font.
+const FONT_B_CELL_W: u32 = 9;
+const FONT_B_CELL_H: u32 = 17;
+const LINE_PITCH: u32 = 30;
+const BARCODE_HEIGHT: u32 = 80;
+const BARCODE_MODULE: u32 = 2;
+const QR_MODULE: u32 = 6;
+const MARGIN: u32 = 16;
+const WHITE: Luma<u8> = Luma([255]);
+const BLACK: Luma<u8> = Luma([0]);
+
+/// Renders `receipt` for a head `dot_width` dots wide and returns PNG bytes.
+pub fn render_png(receipt: &Receipt, dot_width: u32) -> Result<Vec<u8>, PrintError> {
+  let columns = columns_for(dot_width)?;
+  receipt::check_fields(receipt)?;
+  render_blocks(&receipt::layout(receipt, columns), dot_width)
+}
+
+/// Redraws raw ESC/POS bytes, e.g. a payload that was already printed.
+pub fn escpos_to_png(data: &[u8], dot_width: u32) -> Result<Vec<u8>, PrintError> {
+  let columns = columns_for(dot_width)?;
+  render_blocks(&decode::decode(data, columns), dot_width)
+}
+
+fn columns_for(dot_width: u32) -> Result<usize, PrintError> {
+  if !(192..=1152).contains(&dot_width) {
+    return Err(PrintError::InvalidRequest(format!(
+      "dot_width must be between 192 and 1152, got {dot_width}."
+    )));
+  }
+  let columns = (dot_width / CELL_W) as usize;
+  receipt::check_columns(columns)?;
+  Ok(columns)
+}
+
+fn render_blocks(blocks: &[Block], dot_width: u32) -> Result<Vec<u8>, PrintError> {
+  let height = MARGIN * 2 + blocks.iter().map(|b| block_height(b, dot_width)).sum::<u32>();
+  let mut img = GrayImage::from_pixel(dot_width, height, WHITE);
+
+  let mut y = MARGIN;
+  for block in blocks {
+    match block {
+      Block::Line(line) => draw_line(&mut img, line, y),
+      Block::Barcode { data, hri, hri_font } => draw_barcode(&mut img, data, *hri, *hri_font, y)?,
+      Block::Qr(data) => draw_qr(&mut img, data, y)?,
+      Block::Cut(cut) => {
+        // Solid for a full cut, dashed for a partial one.
+        let cut_y = y + LINE_PITCH * 3 + LINE_PITCH / 2;
+        for x in (0..dot_width).filter(|x| *cut == Cut::Full || x % 16 < 8) {
+          img.put_pixel(x, cut_y, BLACK);
+        }
+      }
+      Block::Raster {
+        align,
+        width_bytes,
+        height,
+        bits,
+      } => {
+        let width = (*width_bytes * 8) as u32;
+        let x0 = aligned_x(*align, width, dot_width);
+        for (row, line) in bits.chunks(*width_bytes).take(*height).enumerate() {
+          for x in 0..width {
+            let set = line[(x / 8) as usize] & (0x80 >> (x % 8)) != 0;
+            if set && x0 + x < dot_width {
+              img.put_pixel(x0 + x, y + row as u32, BLACK);
+            }
+          }
+        }
+      }
+      Block::Feed(_) | Block::DrawerKick { .. } | Block::Charset(_) => {}
+    }
+    y += block_height(block, dot_width);
+  }
+
+  let mut png = Vec::new();
+  DynamicImage::ImageLuma8(img)
+    .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+    .map_err(|e| PrintError::Image(format!("Unable to encode receipt preview: {e}.")))?;
+  Ok(png)
+}
+
+fn block_height(block: &Block, dot_width: u32) -> u32 {
+  match block {
+    Block::Line(line) => LINE_PITCH * line.height as u32,
+    Block::Barcode { hri, .. } => BARCODE_HEIGHT + LINE_PITCH * (1 + hri.above() as u32 + hri.below() as u32),
+    Block::Qr(data) => {
+      let modules = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M)
+        .map(|c| c.width() as u32)
+        .unwrap_or(0);
+      (modules * QR_MODULE).min(dot_width) + LINE_PITCH
+    }
+    Block::Feed(lines) => LINE_PITCH * *lines as u32,
+    Block::Cut(_) => LINE_PITCH * 4,
+    Block::DrawerKick { .. } | Block::Charset(_) => 0,
+    Block::Raster { height, .. } => *height as u32,
+  }
+}
+
+fn aligned_x(align: Align, content_width: u32, dot_width: u32) -> u32 {
+  match align {
+    Align::Left => 0,
+    Align::Center => dot_width.saturating_sub(content_width) / 2,
+    Align::Right => dot_width.saturating_sub(content_width),
+  }
+}
+
+fn draw_line(img: &mut GrayImage, line: &Line, top: u32) {
+  draw_text(img, line, (CELL_W, CELL_H), top);
+}
+
+/// Draws `line` in a font whose unscaled cell is `cell` dots.
+fn draw_text(img: &mut GrayImage, line: &Line, cell: (u32, u32), top: u32) {
+  let (w, h) = (line.width as u32, line.height as u32);
+  let (cell_w, cell_h) = (cell.0 * w, cell.1 * h);
+  let x0 = aligned_x(line.align, line.text.len() as u32 * cell_w, img.width());
+  for (i, ch) in line.text.chars().enumerate() {
+    let Some(glyph) = BASIC_FONTS.get(ch) else {
+      continue;
+    };
+    let cell_x = x0 + i as u32 * cell_w;
+    // The 8x8 glyph fills the cell minus a one-column, two-row margin.
+    let (inner_w, inner_h) = (cell_w - 2 * w, cell_h - 4 * h);
+    for cy in 0..inner_h {
+      let row = glyph[(cy * 8 / inner_h) as usize];
+      for cx in 0..inner_w {
+        if row >> (cx * 8 / inner_w) & 1 == 0 {
+          continue;
+        }
+        let (px, py) = (cell_x + w + cx, top + 2 * h + cy);
+        for dx in 0..=u32::from(line.bold) {
+          if px + dx < img.width() && py < img.height() {
+            img.put_pixel(px + dx, py, BLACK);
+          }
+        }
+      }
+    }
+  }
+}
+
+fn draw_barcode(
+  img: &mut GrayImage,
+  data: &str,
+  hri: HriPosition,
+  hri_font: HriFont,
+  top: u32,
+) -> Result<(), PrintError> {
+  let modules = Code128::new(format!("\u{0181}{data}"))
+    .map_err(|e| PrintError::InvalidRequest(format!("Barcode data '{data}' can't be encoded as CODE128: {e}.")))?
+    .encode();
+  let module = if modules.len() as u32 * BARCODE_MODULE <= img.width() { BARCODE_MODULE } else { 1 };
+  let x0 = aligned_x(Align::Center, modules.len() as u32 * module, img.width());
+  let bars_top = if hri.above() { top + LINE_PITCH } else { top };
+  for (i, _) in modules.iter().enumerate().filter(|(_, m)| **m == 1) {
+    for dx in 0..module {
+      let x = x0 + i as u32 * module + dx;
+      for y in bars_top..bars_top + BARCODE_HEIGHT {
+        if x < img.width() {
+          img.put_pixel(x, y, BLACK);
+        }
+      }
+    }
+  }
+  let text = Line {
+    text: data.to_string(),
+    align: Align::Center,
+    bold: false,
+    width: 1,
+    height: 1,
+  };
+  let cell = match hri_font {
+    HriFont::A => (CELL_W, CELL_H),
+    HriFont::B => (FONT_B_CELL_W, FONT_B_CELL_H),
+  };
+  if hri.above() {
+    draw_text(img, &text, cell, top);
+  }
+  if hri.below() {
+    draw_text(img, &text, cell, bars_top + BARCODE_HEIGHT);
+  }
+  Ok(())
+}
+
+fn draw_qr(img: &mut GrayImage, data: &str, top: u32) -> Result<(), PrintError> {
+  let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M)
+    .map_err(|e| PrintError::InvalidRequest(format!("QR data is too long to encode: {e}.")))?;
+  let width = code.width() as u32;
+  let module = (img.width() / width.max(1)).clamp(1, QR_MODULE);
+  let x0 = aligned_x(Align::Center, width * module, img.width());
+  for (i, color) in code.to_colors().into_iter().enumerate() {
+    if color != Color::Dark {
+      continue;
+    }
+    let (mx, my) = (i as u32 % width, i as u32 / width);
+    for dy in 0..module {
+      for dx in 0..module {
+        img.put_pixel(x0 + mx * module + dx, top + my * module + dy, BLACK);
+      }
+    }
+  }
+  Ok(())
+}
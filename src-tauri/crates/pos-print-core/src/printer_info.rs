@@ -0,0 +1,100 @@
+//! Printer identity (GS I) requests: maker, model, firmware and the unit
+//! serial number, for asset tracking of network and serial printers.
+
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::error::PrintError;
+use crate::escpos::{Cut, GS};
+
+/// Header and terminator of a GS I n=65..69 reply.
+const REPLY_HEADER: u8 = 0x5f;
+const REPLY_END: u8 = 0x00;
+const MAX_REPLY: usize = 80;
+
+/// GS I model names, by prefix, whose autocutter only partial-cuts; a
+/// full cut sent to them is rejected or ignored. Add models here as
+/// they're confirmed.
+const PARTIAL_CUT_ONLY: &[&str] = &["TM-T20", "TM-T82", "TM-T88", "TM-m10", "TM-m30", "TM-m50"];
+
+/// The cut to send for `requested` on `model` (a GS I model name): a full
+/// cut becomes partial on models that only partial-cut, unless `force`.
+/// Unknown models get what was requested.
+pub fn cut_for(requested: Cut, force: bool, model: Option<&str>) -> Cut {
+  let partial_only = model.is_some_and(|m| PARTIAL_CUT_ONLY.iter().any(|p| m.trim().starts_with(p)));
+  if requested == Cut::Full && partial_only && !force {
+    Cut::Partial
+  } else {
+    requested
+  }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PrinterInfo {
+  pub manufacturer: Option<String>,
+  pub model: Option<String>,
+  pub firmware: Option<String>,
+  pub serial_number: Option<String>,
+}
+
+/// Asks for each identity field in turn. A field the printer doesn't answer
+/// within `timeout` is `None`; when it doesn't answer the model request,
+/// the printer is taken not to support GS I at all and the rest are skipped.
+/// Only transport failures are errors.
+pub fn query_info<T: Read + Write + ?Sized>(io: &mut T, label: &str, timeout: Duration) -> Result<PrinterInfo, PrintError> {
+  let model = request_text(io, label, 67, timeout)?;
+  if model.is_none() {
+    log::info!("{label} did not answer GS I; assuming it has no identity queries");
+    return Ok(PrinterInfo::default());
+  }
+  Ok(PrinterInfo {
+    manufacturer: request_text(io, label, 66, timeout)?,
+    model,
+    firmware: request_text(io, label, 65, timeout)?,
+    serial_number: request_text(io, label, 68, timeout)?,
+  })
+}
+
+/// Sends GS I `n` and reads the `_ text NUL` reply, or `None` on timeout.
+fn request_text<T: Read + Write + ?Sized>(
+  io: &mut T,
+  label: &str,
+  n: u8,
+  timeout: Duration,
+) -> Result<Option<String>, PrintError> {
+  io.write_all(&[GS, b'I', n])
+    .and_then(|_| io.flush())
+    .map_err(|e| PrintError::Write(format!("Identity request to {label} failed: {e}.").into()))?;
+
+  let deadline = Instant::now() + timeout;
+  let mut reply: Option<Vec<u8>> = None;
+  let mut byte = [0u8; 1];
+  while Instant::now() < deadline {
+    match io.read(&mut byte) {
+      Ok(0) => {
+        return Err(PrintError::Read(format!(
+          "{label} closed the connection before answering the identity request."
+        ).into()))
+      }
+      Ok(_) => match (&mut reply, byte[0]) {
+        (None, REPLY_HEADER) => reply = Some(Vec::new()),
+        // Status bytes and other leftovers before the reply.
+        (None, _) => {}
+        (Some(text), REPLY_END) => {
+          let text = String::from_utf8_lossy(text).trim().to_string();
+          return Ok((!text.is_empty()).then_some(text));
+        }
+        (Some(text), b) if text.len() < MAX_REPLY => text.push(b),
+        (Some(_), _) => {
+          log::warn!("{label} sent an overlong GS I {n} reply; ignoring it");
+          return Ok(None);
+        }
+      },
+      Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => continue,
+      Err(e) => return Err(PrintError::Read(format!("Identity read from {label} failed: {e}.").into())),
+    }
+  }
+  Ok(None)
+}
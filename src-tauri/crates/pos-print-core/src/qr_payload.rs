@@ -0,0 +1,514 @@
+//! Correctly escaped QR payloads for common uses, to feed to the QR
+//! element or builder instead of strings assembled by hand: Wi-Fi join,
+//! vCard 3.0 contacts, URLs with query parameters and EPC (SEPA credit
+//! transfer) payments.
+
+use serde::Deserialize;
+
+use crate::error::PrintError;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum QrPayload {
+  Wifi(WifiNetwork),
+  Vcard(Contact),
+  Url(UrlWithQuery),
+  Epc(EpcPayment),
+}
+
+impl QrPayload {
+  /// The text to encode, or why the fields can't make a valid payload.
+  pub fn build(&self) -> Result<String, PrintError> {
+    match self {
+      QrPayload::Wifi(wifi) => wifi.build(),
+      QrPayload::Vcard(contact) => contact.build(),
+      QrPayload::Url(url) => url.build(),
+      QrPayload::Epc(payment) => payment.build(),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum WifiAuth {
+  #[default]
+  #[serde(rename = "WPA")]
+  Wpa,
+  #[serde(rename = "WEP")]
+  Wep,
+  #[serde(rename = "nopass")]
+  NoPass,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WifiNetwork {
+  pub ssid: String,
+  #[serde(default)]
+  pub auth: WifiAuth,
+  #[serde(default)]
+  pub password: Option<String>,
+  #[serde(default)]
+  pub hidden: bool,
+}
+
+impl WifiNetwork {
+  /// `WIFI:T:WPA;S:<ssid>;P:<password>;;`, with `\ ; , : "` escaped.
+  pub fn build(&self) -> Result<String, PrintError> {
+    if self.ssid.is_empty() || self.ssid.len() > 32 {
+      return Err(invalid(format!(
+        "Wi-Fi SSID must be 1 to 32 bytes; '{}' is {}.",
+        self.ssid,
+        self.ssid.len()
+      )));
+    }
+    let password = self.password.as_deref().unwrap_or_default();
+    let hex = |s: &str| s.bytes().all(|b| b.is_ascii_hexdigit());
+    match self.auth {
+      WifiAuth::NoPass if !password.is_empty() => {
+        return Err(invalid("An open (nopass) Wi-Fi network takes no password.".to_string()))
+      }
+      WifiAuth::Wpa if !((8..=63).contains(&password.len()) || (password.len() == 64 && hex(password))) => {
+        return Err(invalid(format!(
+          "A WPA password must be 8 to 63 characters (or 64 hex digits); this one is {}.",
+          password.len()
+        )))
+      }
+      WifiAuth::Wep if !(matches!(password.len(), 5 | 13) || (matches!(password.len(), 10 | 26) && hex(password))) => {
+        return Err(invalid(
+          "A WEP key must be 5 or 13 characters, or 10 or 26 hex digits.".to_string(),
+        ))
+      }
+      _ => {}
+    }
+    let auth = match self.auth {
+      WifiAuth::Wpa => "WPA",
+      WifiAuth::Wep => "WEP",
+      WifiAuth::NoPass => "nopass",
+    };
+    let mut out = format!("WIFI:T:{auth};S:{};", escape_wifi(&self.ssid));
+    if self.auth != WifiAuth::NoPass {
+      out.push_str(&format!("P:{};", escape_wifi(password)));
+    }
+    if self.hidden {
+      out.push_str("H:true;");
+    }
+    out.push(';');
+    Ok(out)
+  }
+}
+
+fn escape_wifi(value: &str) -> String {
+  let mut out = String::with_capacity(value.len());
+  for ch in value.chars() {
+    if matches!(ch, '\\' | ';' | ',' | ':' | '"') {
+      out.push('\\');
+    }
+    out.push(ch);
+  }
+  out
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Contact {
+  pub first_name: String,
+  pub last_name: String,
+  pub organization: Option<String>,
+  pub title: Option<String>,
+  pub phone: Option<String>,
+  pub email: Option<String>,
+  pub url: Option<String>,
+  pub address: Option<Address>,
+  pub note: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Address {
+  pub street: String,
+  pub city: String,
+  pub region: String,
+  pub postal_code: String,
+  pub country: String,
+}
+
+impl Contact {
+  /// A vCard 3.0 with CRLF line ends; text values have `\ , ;` and
+  /// newlines escaped.
+  pub fn build(&self) -> Result<String, PrintError> {
+    let full_name = [self.first_name.trim(), self.last_name.trim()]
+      .into_iter()
+      .filter(|s| !s.is_empty())
+      .collect::<Vec<_>>()
+      .join(" ");
+    let full_name = match (full_name.is_empty(), &self.organization) {
+      (false, _) => full_name,
+      (true, Some(org)) if !org.trim().is_empty() => org.trim().to_string(),
+      _ => return Err(invalid("A vCard needs a first name, last name or organization.".to_string())),
+    };
+    if let Some(email) = self.email.as_deref().filter(|e| !e.contains('@')) {
+      return Err(invalid(format!("'{email}' is not an email address.")));
+    }
+
+    let mut lines = vec![
+      "BEGIN:VCARD".to_string(),
+      "VERSION:3.0".to_string(),
+      format!("N:{};{};;;", escape_vcard(&self.last_name), escape_vcard(&self.first_name)),
+      format!("FN:{}", escape_vcard(&full_name)),
+    ];
+    let mut push = |property: &str, value: &Option<String>| {
+      if let Some(value) = value.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+        lines.push(format!("{property}:{}", escape_vcard(value)));
+      }
+    };
+    push("ORG", &self.organization);
+    push("TITLE", &self.title);
+    push("TEL;TYPE=CELL", &self.phone);
+    push("EMAIL", &self.email);
+    push("URL", &self.url);
+    push("NOTE", &self.note);
+    if let Some(a) = &self.address {
+      let parts = [&a.street, &a.city, &a.region, &a.postal_code, &a.country].map(|p| escape_vcard(p.trim()));
+      lines.push(format!("ADR;TYPE=WORK:;;{}", parts.join(";")));
+    }
+    lines.push("END:VCARD".to_string());
+    Ok(lines.join("\r\n"))
+  }
+}
+
+fn escape_vcard(value: &str) -> String {
+  let mut out = String::with_capacity(value.len());
+  let mut chars = value.chars().peekable();
+  while let Some(ch) = chars.next() {
+    match ch {
+      '\\' | ',' | ';' => {
+        out.push('\\');
+        out.push(ch);
+      }
+      '\r' => {
+        chars.next_if_eq(&'\n');
+        out.push_str("\\n");
+      }
+      '\n' => out.push_str("\\n"),
+      _ => out.push(ch),
+    }
+  }
+  out
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UrlWithQuery {
+  /// An `http://` or `https://` URL, possibly with a query already.
+  pub url: String,
+  /// Name/value pairs appended in order, percent-encoded.
+  #[serde(default)]
+  pub query: Vec<(String, String)>,
+}
+
+impl UrlWithQuery {
+  pub fn build(&self) -> Result<String, PrintError> {
+    let url = self.url.trim();
+    let lower = url.to_ascii_lowercase();
+    let rest = lower
+      .strip_prefix("https://")
+      .or_else(|| lower.strip_prefix("http://"))
+      .unwrap_or_default();
+    if rest.is_empty() || rest.starts_with(['/', '?', '#']) || url.chars().any(char::is_whitespace) {
+      return Err(invalid(format!("'{url}' is not an http:// or https:// URL.")));
+    }
+    let (base, fragment) = url.split_once('#').map_or((url, None), |(b, f)| (b, Some(f)));
+    let mut out = base.to_string();
+    for (name, value) in &self.query {
+      if name.is_empty() {
+        return Err(invalid("Query parameter names can't be empty.".to_string()));
+      }
+      out.push(if out.contains('?') { '&' } else { '?' });
+      out.push_str(&percent_encode(name));
+      out.push('=');
+      out.push_str(&percent_encode(value));
+    }
+    if let Some(fragment) = fragment {
+      out.push('#');
+      out.push_str(fragment);
+    }
+    Ok(out)
+  }
+}
+
+/// Percent-encodes all but RFC 3986 unreserved characters, as UTF-8.
+fn percent_encode(value: &str) -> String {
+  let mut out = String::with_capacity(value.len());
+  for b in value.bytes() {
+    if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+      out.push(b as char);
+    } else {
+      out.push_str(&format!("%{b:02X}"));
+    }
+  }
+  out
+}
+
+/// A SEPA credit transfer per EPC069-12 (the "GiroCode"), version 002.
+#[derive(Clone, Debug, Deserialize)]
+pub struct EpcPayment {
+  /// Beneficiary, up to 70 characters.
+  pub name: String,
+  pub iban: String,
+  /// Optional within the EEA.
+  #[serde(default)]
+  pub bic: Option<String>,
+  /// In euros, 0.01 to 999999999.99; left for the payer to fill in when absent.
+  #[serde(default)]
+  pub amount: Option<f64>,
+  /// Four-letter ISO 20022 purpose code, e.g. `GDDS`.
+  #[serde(default)]
+  pub purpose: Option<String>,
+  /// Structured creditor reference (e.g. `RF18 5390 0754 7034`), up to 35
+  /// characters. Not with `text`.
+  #[serde(default)]
+  pub reference: Option<String>,
+  /// Free-form remittance text, up to 140 characters. Not with `reference`.
+  #[serde(default)]
+  pub text: Option<String>,
+}
+
+/// EPC069-12's limit on the whole payload.
+const EPC_MAX_BYTES: usize = 331;
+
+impl EpcPayment {
+  pub fn build(&self) -> Result<String, PrintError> {
+    let name = self.name.trim();
+    if name.is_empty() || name.chars().count() > 70 {
+      return Err(invalid("The beneficiary name must be 1 to 70 characters.".to_string()));
+    }
+    let iban = normalize_iban(&self.iban)?;
+    let bic = match self.bic.as_deref().map(|b| b.replace(' ', "").to_ascii_uppercase()) {
+      Some(bic) if !bic.is_empty() => {
+        if !matches!(bic.len(), 8 | 11) || !bic.bytes().all(|b| b.is_ascii_alphanumeric()) {
+          return Err(invalid(format!("BIC '{bic}' must be 8 or 11 letters and digits.")));
+        }
+        bic
+      }
+      _ => String::new(),
+    };
+    let amount = match self.amount {
+      None => String::new(),
+      Some(amount) => {
+        let cents = (amount * 100.0).round();
+        if !amount.is_finite() || !(1.0..=99_999_999_999.0).contains(&cents) {
+          return Err(invalid(format!("EPC amounts must be EUR 0.01 to 999999999.99, not {amount}.")));
+        }
+        let cents = cents as u64;
+        format!("EUR{}.{:02}", cents / 100, cents % 100)
+      }
+    };
+    let purpose = self.purpose.as_deref().map(str::trim).unwrap_or_default();
+    if !purpose.is_empty() && (purpose.len() != 4 || !purpose.bytes().all(|b| b.is_ascii_alphabetic())) {
+      return Err(invalid(format!("Purpose code '{purpose}' must be four letters.")));
+    }
+    let reference = self.reference.as_deref().map(str::trim).unwrap_or_default();
+    let text = self.text.as_deref().map(str::trim).unwrap_or_default();
+    if !reference.is_empty() && !text.is_empty() {
+      return Err(invalid("An EPC payment takes a reference or a text, not both.".to_string()));
+    }
+    if reference.chars().count() > 35 {
+      return Err(invalid("The creditor reference must be at most 35 characters.".to_string()));
+    }
+    if text.chars().count() > 140 {
+      return Err(invalid("The remittance text must be at most 140 characters.".to_string()));
+    }
+    if [name, reference, text].iter().any(|s| s.contains(['\r', '\n'])) {
+      return Err(invalid("EPC fields can't contain line breaks.".to_string()));
+    }
+
+    let lines = [
+      "BCD", "002", "1", "SCT", &bic, name, &iban, &amount, &purpose.to_ascii_uppercase(), reference, text,
+    ];
+    let out = lines.join("\n").trim_end_matches('\n').to_string();
+    if out.len() > EPC_MAX_BYTES {
+      return Err(invalid(format!(
+        "The EPC payload is {} bytes; the standard allows {EPC_MAX_BYTES}. Shorten the name or text.",
+        out.len()
+      )));
+    }
+    Ok(out)
+  }
+}
+
+/// `iban` without spaces, upper-cased, once its ISO 13616 check digits
+/// verify.
+fn normalize_iban(iban: &str) -> Result<String, PrintError> {
+  let iban = iban.replace(' ', "").to_ascii_uppercase();
+  let shaped = (15..=34).contains(&iban.len())
+    && iban.bytes().all(|b| b.is_ascii_alphanumeric())
+    && iban.bytes().take(2).all(|b| b.is_ascii_alphabetic())
+    && iban.bytes().skip(2).take(2).all(|b| b.is_ascii_digit());
+  if !shaped {
+    return Err(invalid(format!("'{iban}' is not an IBAN.")));
+  }
+  // Country and check digits move to the end, letters become 10-35, and
+  // the number must be 1 mod 97.
+  let remainder = iban[4..].bytes().chain(iban[..4].bytes()).fold(0u32, |acc, b| {
+    let value = if b.is_ascii_digit() { (b - b'0') as u32 } else { (b - b'A') as u32 + 10 };
+    if value >= 10 {
+      (acc * 100 + value) % 97
+    } else {
+      (acc * 10 + value) % 97
+    }
+  });
+  if remainder != 1 {
+    return Err(invalid(format!("IBAN '{iban}' has wrong check digits; check it for typos.")));
+  }
+  Ok(iban)
+}
+
+fn invalid(message: String) -> PrintError {
+  PrintError::InvalidRequest(message)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn wifi(ssid: &str, auth: WifiAuth, password: Option<&str>) -> WifiNetwork {
+    WifiNetwork {
+      ssid: ssid.to_string(),
+      auth,
+      password: password.map(str::to_string),
+      hidden: false,
+    }
+  }
+
+  fn epc(iban: &str) -> EpcPayment {
+    EpcPayment {
+      name: "Cafe Rosa".to_string(),
+      iban: iban.to_string(),
+      bic: None,
+      amount: Some(12.5),
+      purpose: None,
+      reference: None,
+      text: Some("Table 4".to_string()),
+    }
+  }
+
+  #[test]
+  fn wifi_escapes_special_characters() {
+    let network = wifi(r#"Cafe;"Guest",1:a\b"#, WifiAuth::Wpa, Some("p;ss:w,rd\\"));
+    assert_eq!(
+      network.build().unwrap(),
+      r#"WIFI:T:WPA;S:Cafe\;\"Guest\"\,1\:a\\b;P:p\;ss\:w\,rd\\;;"#
+    );
+  }
+
+  #[test]
+  fn wifi_open_network_has_no_password_field() {
+    let mut network = wifi("Lobby", WifiAuth::NoPass, None);
+    network.hidden = true;
+    assert_eq!(network.build().unwrap(), "WIFI:T:nopass;S:Lobby;H:true;;");
+    assert!(wifi("Lobby", WifiAuth::NoPass, Some("secret12")).build().is_err());
+  }
+
+  #[test]
+  fn wifi_checks_key_lengths() {
+    assert!(wifi("Shop", WifiAuth::Wpa, Some("short")).build().is_err());
+    assert!(wifi("Shop", WifiAuth::Wpa, Some(&"a".repeat(64))).build().is_ok());
+    assert!(wifi("Shop", WifiAuth::Wpa, Some(&"g".repeat(64))).build().is_err());
+    assert!(wifi("Shop", WifiAuth::Wep, Some("abcde")).build().is_ok());
+    assert!(wifi("Shop", WifiAuth::Wep, Some("0123456789")).build().is_ok());
+    assert!(wifi("Shop", WifiAuth::Wep, Some("012345678z")).build().is_err());
+    assert!(wifi("", WifiAuth::NoPass, None).build().is_err());
+  }
+
+  #[test]
+  fn vcard_escapes_text_values() {
+    let contact = Contact {
+      first_name: "Ana".to_string(),
+      last_name: "Lopez; Jr".to_string(),
+      organization: Some("Rosa, Ltd\\Cafe".to_string()),
+      note: Some("Open 9-5\r\nClosed Sun\nHolidays".to_string()),
+      ..Contact::default()
+    };
+    let card = contact.build().unwrap();
+    let lines: Vec<_> = card.split("\r\n").collect();
+    assert_eq!(lines[0], "BEGIN:VCARD");
+    assert_eq!(lines[2], "N:Lopez\\; Jr;Ana;;;");
+    assert_eq!(lines[3], "FN:Ana Lopez\\; Jr");
+    assert!(lines.contains(&"ORG:Rosa\\, Ltd\\\\Cafe"));
+    assert!(lines.contains(&"NOTE:Open 9-5\\nClosed Sun\\nHolidays"));
+    assert_eq!(lines.last(), Some(&"END:VCARD"));
+  }
+
+  #[test]
+  fn vcard_falls_back_to_organization_and_checks_email() {
+    let contact = Contact {
+      organization: Some("Rosa Ltd".to_string()),
+      ..Contact::default()
+    };
+    assert!(contact.build().unwrap().contains("\r\nFN:Rosa Ltd\r\n"));
+    assert!(Contact::default().build().is_err());
+    let contact = Contact {
+      first_name: "Ana".to_string(),
+      email: Some("ana.example.com".to_string()),
+      ..Contact::default()
+    };
+    assert!(contact.build().is_err());
+  }
+
+  #[test]
+  fn url_query_is_percent_encoded() {
+    let url = UrlWithQuery {
+      url: "https://example.com/r?id=7#top".to_string(),
+      query: vec![
+        ("table no".to_string(), "4&5".to_string()),
+        ("name".to_string(), "Zoë/~x".to_string()),
+      ],
+    };
+    assert_eq!(
+      url.build().unwrap(),
+      "https://example.com/r?id=7&table%20no=4%265&name=Zo%C3%AB%2F~x#top"
+    );
+  }
+
+  #[test]
+  fn url_rejects_other_schemes_and_empty_names() {
+    let url = |url: &str, query: Vec<(String, String)>| UrlWithQuery {
+      url: url.to_string(),
+      query,
+    };
+    assert_eq!(url("HTTP://a.b", vec![]).build().unwrap(), "HTTP://a.b");
+    assert!(url("ftp://a.b", vec![]).build().is_err());
+    assert!(url("https://", vec![]).build().is_err());
+    assert!(url("https://a b", vec![]).build().is_err());
+    assert!(url("https://a.b", vec![(String::new(), "x".to_string())]).build().is_err());
+  }
+
+  #[test]
+  fn iban_is_normalized_and_checked() {
+    assert_eq!(normalize_iban("de89 3704 0044 0532 0130 00").unwrap(), "DE89370400440532013000");
+    assert_eq!(normalize_iban("GB82WEST12345698765432").unwrap(), "GB82WEST12345698765432");
+    assert!(normalize_iban("DE88 3704 0044 0532 0130 00").is_err());
+    assert!(normalize_iban("1234 5678 9012 3456").is_err());
+  }
+
+  #[test]
+  fn epc_payload_lines() {
+    let mut payment = epc("de89 3704 0044 0532 0130 00");
+    payment.bic = Some("cobade ff xxx".to_string());
+    assert_eq!(
+      payment.build().unwrap(),
+      "BCD\n002\n1\nSCT\nCOBADEFFXXX\nCafe Rosa\nDE89370400440532013000\nEUR12.50\n\n\nTable 4"
+    );
+  }
+
+  #[test]
+  fn epc_rejects_bad_fields() {
+    let mut payment = epc("DE89370400440532013000");
+    payment.amount = Some(0.0);
+    assert!(payment.build().is_err());
+    let mut payment = epc("DE89370400440532013000");
+    payment.reference = Some("RF18539007547034".to_string());
+    assert!(payment.build().is_err());
+    let mut payment = epc("DE89370400440532013000");
+    payment.bic = Some("COBADE".to_string());
+    assert!(payment.build().is_err());
+  }
+}
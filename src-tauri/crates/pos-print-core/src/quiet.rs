@@ -0,0 +1,249 @@
+//! Quiet hours: windows during which low-priority jobs for a destination
+//! are held instead of printed, then released when the window closes.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::error::PrintError;
+use crate::job::{JobRecord, PrintOptions};
+use crate::target::Target;
+
+/// One recurring quiet window. A window whose `end` is not after `start`
+/// runs past midnight into the next day.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuietWindow {
+  /// Days the window starts on, 0 = Sunday ... 6 = Saturday. Empty means
+  /// every day.
+  #[serde(default)]
+  pub days: Vec<u8>,
+  /// Local time of day, `HH:MM`.
+  pub start: String,
+  pub end: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuietHours {
+  pub windows: Vec<QuietWindow>,
+  /// Jobs with a priority at or above this print even during quiet hours.
+  pub min_priority: u8,
+  /// Offset of local time from UTC, for matching the windows.
+  #[serde(default)]
+  pub utc_offset_minutes: i32,
+}
+
+impl QuietHours {
+  pub fn validate(&self) -> Result<(), PrintError> {
+    for window in &self.windows {
+      if let Some(day) = window.days.iter().find(|d| **d > 6) {
+        return Err(PrintError::InvalidRequest(format!(
+          "Quiet hours day {day} is out of range; use 0 (Sunday) to 6 (Saturday)."
+        )));
+      }
+      parse_time(&window.start)?;
+      parse_time(&window.end)?;
+    }
+    Ok(())
+  }
+
+  /// Whether a job of `priority` should be held at `unix_ms`.
+  pub fn holds(&self, priority: u8, unix_ms: u64) -> bool {
+    priority < self.min_priority && self.is_quiet(unix_ms)
+  }
+
+  pub fn is_quiet(&self, unix_ms: u64) -> bool {
+    let local = (unix_ms / 1000) as i64 + self.utc_offset_minutes as i64 * 60;
+    let days = local.div_euclid(86_400);
+    let minute = (local.rem_euclid(86_400) / 60) as u16;
+    // 1970-01-01 was a Thursday.
+    let today = (days + 4).rem_euclid(7) as u8;
+    let yesterday = (today + 6) % 7;
+    let starts_on = |window: &QuietWindow, day: u8| window.days.is_empty() || window.days.contains(&day);
+
+    self.windows.iter().any(|window| {
+      let (Ok(start), Ok(end)) = (parse_time(&window.start), parse_time(&window.end)) else {
+        return false;
+      };
+      if start < end {
+        starts_on(window, today) && (start..end).contains(&minute)
+      } else {
+        (starts_on(window, today) && minute >= start) || (starts_on(window, yesterday) && minute < end)
+      }
+    })
+  }
+}
+
+/// `HH:MM` to minutes since midnight.
+fn parse_time(text: &str) -> Result<u16, PrintError> {
+  let invalid = || PrintError::InvalidRequest(format!("Quiet hours time \"{text}\" is not HH:MM (00:00-23:59)."));
+  let (h, m) = text.split_once(':').ok_or_else(invalid)?;
+  let (h, m) = (h.parse::<u16>().map_err(|_| invalid())?, m.parse::<u16>().map_err(|_| invalid())?);
+  if h > 23 || m > 59 {
+    return Err(invalid());
+  }
+  Ok(h * 60 + m)
+}
+
+/// A held job as saved to disk.
+#[derive(Serialize, Deserialize)]
+struct SavedJob {
+  job_id: String,
+  target: Target,
+  submitted_at_ms: u64,
+  options: PrintOptions,
+  duplicate_of_ms: Option<u64>,
+  data_base64: String,
+}
+
+impl SavedJob {
+  fn new(record: &JobRecord) -> Self {
+    Self {
+      job_id: record.job_id.clone(),
+      target: record.target.clone(),
+      submitted_at_ms: record.submitted_at_ms,
+      options: record.options.clone(),
+      duplicate_of_ms: record.duplicate_of_ms,
+      data_base64: base64::engine::general_purpose::STANDARD.encode(&record.payload),
+    }
+  }
+
+  fn into_record(self) -> Result<JobRecord, base64::DecodeError> {
+    let data = base64::engine::general_purpose::STANDARD.decode(&self.data_base64)?;
+    let mut record = JobRecord::new(&self.target, &data, self.options);
+    record.job_id = self.job_id;
+    record.submitted_at_ms = self.submitted_at_ms;
+    record.duplicate_of_ms = self.duplicate_of_ms;
+    Ok(record)
+  }
+}
+
+/// Jobs held by quiet hours, waiting to be submitted. Saved to
+/// `<dir>/held.json` on every change so they survive a restart; the default
+/// store keeps them in memory only.
+#[derive(Clone, Default)]
+pub struct HeldJobs {
+  path: Option<PathBuf>,
+  jobs: Arc<Mutex<Vec<JobRecord>>>,
+}
+
+impl HeldJobs {
+  /// Loads the jobs held when the app last exited.
+  pub fn open(dir: PathBuf) -> Self {
+    if let Err(e) = fs::create_dir_all(&dir) {
+      log::warn!("held jobs kept in memory only, unable to create {}: {e}", dir.display());
+      return Self::default();
+    }
+    let path = dir.join("held.json");
+    let saved: Vec<SavedJob> = match fs::read(&path) {
+      Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+        log::warn!("ignoring unreadable held jobs in {}: {e}", path.display());
+        Vec::new()
+      }),
+      Err(_) => Vec::new(),
+    };
+    let jobs: Vec<JobRecord> = saved
+      .into_iter()
+      .filter_map(|job| {
+        let job_id = job.job_id.clone();
+        job
+          .into_record()
+          .map_err(|e| log::warn!("dropping held job {job_id}, its saved payload is unreadable: {e}"))
+          .ok()
+      })
+      .collect();
+    if !jobs.is_empty() {
+      log::info!("{} held jobs restored from {}", jobs.len(), path.display());
+    }
+    Self {
+      path: Some(path),
+      jobs: Arc::new(Mutex::new(jobs)),
+    }
+  }
+
+  pub fn hold(&self, record: JobRecord) {
+    log::info!("Holding job {} for {} until quiet hours end", record.job_id, record.destination);
+    let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+    jobs.push(record);
+    self.save(&jobs);
+  }
+
+  /// Removes and returns the held jobs matching `release`, oldest first.
+  pub fn take_where(&self, mut release: impl FnMut(&JobRecord) -> bool) -> Vec<JobRecord> {
+    let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+    let (out, keep): (Vec<_>, _) = std::mem::take(&mut *jobs).into_iter().partition(|job| release(job));
+    *jobs = keep;
+    if !out.is_empty() {
+      self.save(&jobs);
+    }
+    out
+  }
+
+  /// Held job counts by destination.
+  pub fn counts(&self) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for job in self.jobs.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+      *counts.entry(job.destination.clone()).or_insert(0) += 1;
+    }
+    counts
+  }
+
+  /// Called with the lock held, so saves land in the order of the changes.
+  fn save(&self, jobs: &[JobRecord]) {
+    let Some(path) = &self.path else {
+      return;
+    };
+    let saved: Vec<SavedJob> = jobs.iter().map(SavedJob::new).collect();
+    let tmp = path.with_extension("json.tmp");
+    let result = serde_json::to_vec(&saved)
+      .map_err(|e| e.to_string())
+      .and_then(|json| fs::write(&tmp, json).and_then(|()| fs::rename(&tmp, path)).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+      log::warn!("unable to save held jobs to {}: {e}", path.display());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::job::next_job_id;
+
+  #[test]
+  fn held_jobs_survive_a_reopen() {
+    let dir = std::env::temp_dir().join(format!("pos-held-{}", next_job_id()));
+    let target = Target::Tcp { host: "kitchen".to_string(), port: 9100 };
+    let options = PrintOptions { priority: 1, ..PrintOptions::default() };
+    let record = JobRecord::new(&target, b"\x1b@ticket", options);
+    let job_id = record.job_id.clone();
+    HeldJobs::open(dir.clone()).hold(record);
+
+    let reopened = HeldJobs::open(dir.clone());
+    assert_eq!(reopened.counts().get("tcp://kitchen:9100"), Some(&1));
+    let released = reopened.take_where(|_| true);
+    assert_eq!(released.len(), 1);
+    assert_eq!(released[0].job_id, job_id);
+    assert_eq!(released[0].payload, b"\x1b@ticket");
+    assert_eq!(released[0].options.priority, 1);
+
+    assert!(HeldJobs::open(dir.clone()).counts().is_empty());
+    let _ = fs::remove_dir_all(dir);
+  }
+
+  #[test]
+  fn quiet_window_runs_past_midnight() {
+    let quiet = QuietHours {
+      windows: vec![QuietWindow { days: vec![], start: "22:00".to_string(), end: "06:00".to_string() }],
+      min_priority: 5,
+      utc_offset_minutes: 0,
+    };
+    let at = |h: u64, m: u64| (h * 60 + m) * 60_000;
+    assert!(quiet.holds(1, at(23, 30)));
+    assert!(quiet.holds(1, at(5, 59)));
+    assert!(!quiet.holds(1, at(6, 0)));
+    assert!(!quiet.holds(5, at(23, 30)));
+  }
+}
@@ -0,0 +1,463 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+use qrcode::{Color, EcLevel, QrCode};
+use serde::Deserialize;
+
+use crate::error::PrintError;
+
+/// Dot width of an 80 mm head at 203 dpi.
+pub const DEFAULT_MAX_WIDTH: u32 = 576;
+pub const DEFAULT_THRESHOLD: u8 = 128;
+/// Rows per GS v 0 block; keeps each command within small printer buffers.
+pub(crate) const BAND_HEIGHT: u32 = 256;
+const MAX_IMAGE_FILE_BYTES: u64 = 20 * 1024 * 1024;
+const CACHE_CAPACITY: usize = 32;
+pub const DEFAULT_QR_LOGO_SIZE: u32 = 384;
+/// White modules the spec requires around a QR code.
+const QR_QUIET_ZONE: u32 = 4;
+/// Finder pattern plus separator, in modules, at three of the corners.
+const QR_FINDER: u32 = 8;
+/// Share of the error correction budget the logo may use; the rest is
+/// left for smudges, fading and a curled receipt.
+const LOGO_SHARE: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RasterOptions {
+  pub max_width: u32,
+  pub threshold: u8,
+  pub dither: bool,
+}
+
+impl RasterOptions {
+  pub fn new(max_width: Option<u32>, threshold: Option<u8>, dither: Option<bool>) -> Result<Self, PrintError> {
+    let max_width = max_width.unwrap_or(DEFAULT_MAX_WIDTH);
+    if max_width == 0 || max_width > 65535 {
+      return Err(PrintError::InvalidRequest(format!(
+        "max_width must be between 1 and 65535 dots, got {max_width}."
+      )));
+    }
+    Ok(Self {
+      max_width,
+      threshold: threshold.unwrap_or(DEFAULT_THRESHOLD),
+      dither: dither.unwrap_or(false),
+    })
+  }
+}
+
+/// Decodes an in-memory image (format sniffed from its header) into GS v 0 raster commands.
+pub fn image_to_escpos(bytes: &[u8], opts: RasterOptions) -> Result<Vec<u8>, PrintError> {
+  let img = image::load_from_memory(bytes)
+    .map_err(|e| PrintError::Image(format!("Unable to decode image: {e}. Use PNG, JPEG, or BMP.")))?;
+  Ok(rasterize(&img, opts))
+}
+
+pub fn rasterize(img: &DynamicImage, opts: RasterOptions) -> Vec<u8> {
+  let (bits, width, height) = to_bits(img, opts);
+  encode_gs_v0(&bits, width, height)
+}
+
+/// A 1-bit image, rows packed MSB-first and padded to whole bytes, as the
+/// NV graphics and GS v 0 commands take it.
+#[derive(Clone, Debug)]
+pub struct Bitmap {
+  pub width: u32,
+  pub height: u32,
+  pub data: Vec<u8>,
+}
+
+/// Decodes and thresholds an image like [`image_to_escpos`], without
+/// wrapping it in print commands.
+pub fn image_to_bitmap(bytes: &[u8], opts: RasterOptions) -> Result<Bitmap, PrintError> {
+  let img = image::load_from_memory(bytes)
+    .map_err(|e| PrintError::Image(format!("Unable to decode image: {e}. Use PNG, JPEG, or BMP.")))?;
+  let (bits, width, height) = to_bits(&img, opts);
+  Ok(Bitmap {
+    width,
+    height,
+    data: (0..height).flat_map(|y| pack_row(&bits, width, y)).collect(),
+  })
+}
+
+/// Scales down to `max_width` and thresholds; true is a printed dot.
+fn to_bits(img: &DynamicImage, opts: RasterOptions) -> (Vec<bool>, u32, u32) {
+  let img = if img.width() > opts.max_width {
+    let height = ((img.height() as u64 * opts.max_width as u64) / img.width() as u64).max(1) as u32;
+    img.resize_exact(opts.max_width, height, FilterType::Triangle)
+  } else {
+    img.clone()
+  };
+
+  let gray = flatten_on_white(&img);
+  let bits = if opts.dither {
+    dither_floyd_steinberg(&gray, opts.threshold)
+  } else {
+    threshold(&gray, opts.threshold)
+  };
+  (bits, gray.width(), gray.height())
+}
+
+/// Converts to grayscale with transparent pixels treated as paper (white).
+fn flatten_on_white(img: &DynamicImage) -> GrayImage {
+  let rgba = img.to_rgba8();
+  GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+    let [r, g, b, a] = rgba.get_pixel(x, y).0;
+    let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+    let blended = (luma * a as u32 + 255 * (255 - a as u32)) / 255;
+    image::Luma([blended as u8])
+  })
+}
+
+/// One bool per pixel, true meaning a printed (black) dot.
+fn threshold(gray: &GrayImage, level: u8) -> Vec<bool> {
+  gray.pixels().map(|p| p.0[0] < level).collect()
+}
+
+fn dither_floyd_steinberg(gray: &GrayImage, level: u8) -> Vec<bool> {
+  let (w, h) = (gray.width() as usize, gray.height() as usize);
+  let mut buf: Vec<i32> = gray.pixels().map(|p| p.0[0] as i32).collect();
+  let mut out = vec![false; w * h];
+
+  for y in 0..h {
+    for x in 0..w {
+      let i = y * w + x;
+      let old = buf[i].clamp(0, 255);
+      let black = old < level as i32;
+      out[i] = black;
+      let err = old - if black { 0 } else { 255 };
+
+      let mut spread = |dx: isize, dy: usize, weight: i32| {
+        let nx = x as isize + dx;
+        let ny = y + dy;
+        if nx >= 0 && (nx as usize) < w && ny < h {
+          buf[ny * w + nx as usize] += err * weight / 16;
+        }
+      };
+      spread(1, 0, 7);
+      spread(-1, 1, 3);
+      spread(0, 1, 5);
+      spread(1, 1, 1);
+    }
+  }
+  out
+}
+
+fn encode_gs_v0(bits: &[bool], width: u32, height: u32) -> Vec<u8> {
+  let bytes_per_row = width.div_ceil(8) as usize;
+  let mut out = Vec::with_capacity(bytes_per_row * height as usize + 8 * (height / BAND_HEIGHT + 1) as usize);
+
+  let mut top = 0;
+  while top < height {
+    let band = BAND_HEIGHT.min(height - top);
+    out.extend_from_slice(&[
+      0x1d,
+      0x76,
+      0x30,
+      0x00,
+      (bytes_per_row & 0xff) as u8,
+      (bytes_per_row >> 8) as u8,
+      (band & 0xff) as u8,
+      (band >> 8) as u8,
+    ]);
+    for y in top..top + band {
+      out.extend(pack_row(bits, width, y));
+    }
+    top += band;
+  }
+  out
+}
+
+fn pack_row(bits: &[bool], width: u32, y: u32) -> impl Iterator<Item = u8> + '_ {
+  bits[(y * width) as usize..((y + 1) * width) as usize].chunks(8).map(|byte_bits| {
+    byte_bits
+      .iter()
+      .enumerate()
+      .fold(0u8, |byte, (bit, &black)| if black { byte | 0x80 >> bit } else { byte })
+  })
+}
+
+/// QR error correction level, by the share of the code that can be lost.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum QrEcLevel {
+  /// 7%.
+  L,
+  /// 15%.
+  M,
+  /// 25%.
+  Q,
+  /// 30%, leaving the most room for a logo.
+  #[default]
+  H,
+}
+
+impl QrEcLevel {
+  fn recoverable(self) -> f32 {
+    match self {
+      QrEcLevel::L => 0.07,
+      QrEcLevel::M => 0.15,
+      QrEcLevel::Q => 0.25,
+      QrEcLevel::H => 0.30,
+    }
+  }
+}
+
+impl From<QrEcLevel> for EcLevel {
+  fn from(level: QrEcLevel) -> Self {
+    match level {
+      QrEcLevel::L => EcLevel::L,
+      QrEcLevel::M => EcLevel::M,
+      QrEcLevel::Q => EcLevel::Q,
+      QrEcLevel::H => EcLevel::H,
+    }
+  }
+}
+
+/// Draws a QR code for `data` about `size` dots square with `logo` in its
+/// centre, as GS v 0 raster. The logo area, a white square on the module
+/// grid, is as large as `ec_level` allows with half its correction budget
+/// to spare and never reaches the finder patterns; the logo is scaled
+/// into it keeping its aspect ratio. Alignment patterns inside the area,
+/// at the centre from version 7, are drawn over the logo as generated,
+/// since scanners need them to map the grid. Fails when that area would be
+/// too small to show a logo, rather than printing a code that won't scan.
+pub fn build_qr_with_logo(data: &str, ec_level: QrEcLevel, logo: &[u8], size: u32) -> Result<Vec<u8>, PrintError> {
+  if !(96..=1152).contains(&size) {
+    return Err(PrintError::InvalidRequest(format!(
+      "QR size must be between 96 and 1152 dots, got {size}."
+    )));
+  }
+  let code = QrCode::with_error_correction_level(data.as_bytes(), ec_level.into())
+    .map_err(|e| PrintError::InvalidRequest(format!("QR data is too long to encode: {e}.")))?;
+  let modules = code.width() as u32;
+  let total = modules + 2 * QR_QUIET_ZONE;
+  let module = size / total;
+  if module < 2 {
+    return Err(PrintError::InvalidRequest(format!(
+      "This QR code is {total} modules wide, too many for {size} dots at 2 dots a module; use a size of at least {} or shorter data.",
+      total * 2
+    )));
+  }
+
+  // The logo square, border included, kept centred on the grid.
+  let budget = (modules * modules) as f32 * ec_level.recoverable() * LOGO_SHARE;
+  let mut side = (budget.sqrt() as u32).min(modules.saturating_sub(2 * QR_FINDER));
+  if (modules - side) % 2 != 0 {
+    side = side.saturating_sub(1);
+  }
+  if side < 5 {
+    return Err(PrintError::InvalidRequest(format!(
+      "Error correction level {ec_level:?} leaves no room for a logo in this QR code without risking scans; use level H or shorter data."
+    )));
+  }
+
+  let logo = image::load_from_memory(logo)
+    .map_err(|e| PrintError::Image(format!("Unable to decode logo: {e}. Use PNG, JPEG, or BMP.")))?;
+  let dots = total * module;
+  let mut img = GrayImage::from_pixel(dots, dots, Luma([255]));
+  let start = (modules - side) / 2;
+  let logo_modules = start..start + side;
+  let in_logo = |mx: u32, my: u32| logo_modules.contains(&mx) && logo_modules.contains(&my);
+  let colors = code.to_colors();
+  let paint = |img: &mut GrayImage, i: usize, dark: bool| {
+    let (mx, my) = (i as u32 % modules, i as u32 / modules);
+    let (x0, y0) = ((QR_QUIET_ZONE + mx) * module, (QR_QUIET_ZONE + my) * module);
+    for y in y0..y0 + module {
+      for x in x0..x0 + module {
+        img.put_pixel(x, y, Luma([if dark { 0 } else { 255 }]));
+      }
+    }
+  };
+  for (i, &color) in colors.iter().enumerate() {
+    let (mx, my) = (i as u32 % modules, i as u32 / modules);
+    if color == Color::Dark && !in_logo(mx, my) {
+      paint(&mut img, i, true);
+    }
+  }
+
+  // One module of white around the logo keeps it apart from the code.
+  let room = (side - 2) * module;
+  let logo = flatten_on_white(&logo.resize(room, room, FilterType::Triangle));
+  let x = (QR_QUIET_ZONE + start + 1) * module + (room - logo.width()) / 2;
+  let y = (QR_QUIET_ZONE + start + 1) * module + (room - logo.height()) / 2;
+  imageops::overlay(&mut img, &logo, x as i64, y as i64);
+  for (i, &color) in colors.iter().enumerate() {
+    let (mx, my) = (i as u32 % modules, i as u32 / modules);
+    if in_logo(mx, my) && code.is_functional(mx as usize, my as usize) {
+      paint(&mut img, i, color == Color::Dark);
+    }
+  }
+  Ok(encode_gs_v0(&threshold(&img, DEFAULT_THRESHOLD), dots, dots))
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+  path: PathBuf,
+  modified: SystemTime,
+  opts: RasterOptions,
+}
+
+/// Cached rasters plus their insertion order for eviction.
+type CacheEntries = (HashMap<CacheKey, Arc<Vec<u8>>>, VecDeque<CacheKey>);
+
+/// Rasterized image files keyed by path, mtime, and raster options, so a
+/// logo referenced on every receipt is only decoded once per change.
+#[derive(Clone, Default)]
+pub struct RasterCache {
+  inner: Arc<Mutex<CacheEntries>>,
+}
+
+impl RasterCache {
+  pub fn image_file_to_escpos(&self, path: &Path, opts: RasterOptions) -> Result<Arc<Vec<u8>>, PrintError> {
+    let format = validate_image_path(path)?;
+    let meta = std::fs::metadata(path)
+      .map_err(|e| PrintError::InvalidRequest(format!("Unable to read image file '{}': {e}.", path.display())))?;
+    if meta.len() > MAX_IMAGE_FILE_BYTES {
+      return Err(PrintError::InvalidRequest(format!(
+        "Image file '{}' is {} bytes; the limit is {MAX_IMAGE_FILE_BYTES} bytes.",
+        path.display(),
+        meta.len()
+      )));
+    }
+    let key = CacheKey {
+      path: path.to_path_buf(),
+      modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+      opts,
+    };
+
+    if let Some(hit) = self.lock().0.get(&key) {
+      return Ok(hit.clone());
+    }
+
+    let file = std::fs::File::open(path)
+      .map_err(|e| PrintError::InvalidRequest(format!("Unable to open image file '{}': {e}.", path.display())))?;
+    let img = image::load(BufReader::new(file), format)
+      .map_err(|e| PrintError::Image(format!("Unable to decode image file '{}': {e}.", path.display())))?;
+    let raster = Arc::new(rasterize(&img, opts));
+
+    let mut guard = self.lock();
+    let (entries, order) = &mut *guard;
+    if entries.insert(key.clone(), raster.clone()).is_none() {
+      order.push_back(key);
+      while order.len() > CACHE_CAPACITY {
+        if let Some(old) = order.pop_front() {
+          entries.remove(&old);
+        }
+      }
+    }
+    Ok(raster)
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, CacheEntries> {
+    self.inner.lock().unwrap_or_else(|e| e.into_inner())
+  }
+}
+
+fn validate_image_path(path: &Path) -> Result<ImageFormat, PrintError> {
+  if !path.is_absolute() {
+    return Err(PrintError::InvalidRequest(format!(
+      "Image path '{}' must be absolute.",
+      path.display()
+    )));
+  }
+  if !path.is_file() {
+    return Err(PrintError::InvalidRequest(format!(
+      "Image path '{}' does not exist or is not a file.",
+      path.display()
+    )));
+  }
+  let ext = path
+    .extension()
+    .and_then(|e| e.to_str())
+    .map(|e| e.to_ascii_lowercase())
+    .unwrap_or_default();
+  match ext.as_str() {
+    "png" => Ok(ImageFormat::Png),
+    "jpg" | "jpeg" => Ok(ImageFormat::Jpeg),
+    "bmp" => Ok(ImageFormat::Bmp),
+    _ => Err(PrintError::InvalidRequest(format!(
+      "Unsupported image type '{}'. Use .png, .jpg/.jpeg, or .bmp.",
+      path.display()
+    ))),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+
+  use super::*;
+
+  /// Dots of a GS v 0 raster, band by band, as (width, rows).
+  fn unpack(raster: &[u8]) -> (usize, Vec<Vec<bool>>) {
+    let mut rows = Vec::new();
+    let mut width = 0;
+    let mut rest = raster;
+    while !rest.is_empty() {
+      assert_eq!(&rest[..4], &[0x1d, 0x76, 0x30, 0x00]);
+      let bytes_per_row = u16::from_le_bytes([rest[4], rest[5]]) as usize;
+      let band = u16::from_le_bytes([rest[6], rest[7]]) as usize;
+      width = bytes_per_row * 8;
+      let (data, tail) = rest[8..].split_at(bytes_per_row * band);
+      rows.extend(data.chunks(bytes_per_row).map(|row| (0..width).map(|x| row[x / 8] & 0x80 >> (x % 8) != 0).collect()));
+      rest = tail;
+    }
+    (width, rows)
+  }
+
+  fn decode(raster: &[u8]) -> String {
+    let (width, rows) = unpack(raster);
+    let mut img = rqrr::PreparedImage::prepare_from_greyscale(width, rows.len(), |x, y| if rows[y][x] { 0 } else { 255 });
+    let grids = img.detect_grids();
+    assert_eq!(grids.len(), 1, "expected one QR code");
+    grids[0].decode().expect("QR code decodes").1
+  }
+
+  /// A solid black logo, the worst case for the modules it covers.
+  fn black_logo() -> Vec<u8> {
+    let mut png = Vec::new();
+    DynamicImage::ImageLuma8(GrayImage::from_pixel(64, 64, Luma([0])))
+      .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+      .unwrap();
+    png
+  }
+
+  #[test]
+  fn qr_with_logo_decodes_with_and_without_a_centre_alignment_pattern() {
+    let short = "https://pay.example/t/A-1042";
+    let long = "https://pay.example/checkout?merchant=binancexi-store-0042&receipt=A-1042&amount=18.40&currency=USD";
+    for data in [short, long] {
+      let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::H).unwrap();
+      let raster = build_qr_with_logo(data, QrEcLevel::H, &black_logo(), 576).unwrap();
+      assert_eq!(decode(&raster), data, "version {:?}", code.version());
+    }
+    let long_version = QrCode::with_error_correction_level(long.as_bytes(), EcLevel::H).unwrap().version();
+    assert!(matches!(long_version, qrcode::Version::Normal(v) if v >= 7));
+  }
+
+  #[test]
+  fn logo_leaves_function_patterns_as_generated() {
+    let data = "https://pay.example/checkout?merchant=binancexi-store-0042&receipt=A-1042&amount=18.40&currency=USD";
+    let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::H).unwrap();
+    let (_, rows) = unpack(&build_qr_with_logo(data, QrEcLevel::H, &black_logo(), 576).unwrap());
+    let modules = code.width();
+    let module = 576 / (modules + 2 * QR_QUIET_ZONE as usize);
+    let centre = |m: usize| (QR_QUIET_ZONE as usize + m) * module + module / 2;
+    for (i, color) in code.to_colors().into_iter().enumerate() {
+      let (mx, my) = (i % modules, i / modules);
+      if code.is_functional(mx, my) {
+        assert_eq!(rows[centre(my)][centre(mx)], color == Color::Dark, "function module ({mx}, {my})");
+      }
+    }
+  }
+
+  #[test]
+  fn low_correction_leaves_no_room_for_a_logo() {
+    let err = build_qr_with_logo("https://pay.example/t/A-1042", QrEcLevel::L, &black_logo(), 576).unwrap_err();
+    assert!(matches!(err, PrintError::InvalidRequest(_)));
+  }
+}
@@ -0,0 +1,935 @@
+//! Structured receipt model and its layout.
+//!
+//! [`layout`] turns a receipt into printer lines for a given column count.
+//! The ESC/POS renderer here and the PNG preview both draw from that layout,
+//! so a preview always wraps and aligns the way the paper will.
+//!
+//! One template serves both 58 mm (32 columns) and 80 mm (48 columns)
+//! paper: rows wrap instead of cutting text, tables drop `optional`
+//! columns and shrink the rest in proportion when they don't fit (or stack
+//! below `stack_below`), and a `section` can swap in narrow-paper content.
+//!
+//! Text, row and table cell fields may be [`Field`] values (money, dates)
+//! that are formatted before layout; [`check_fields`] rejects bad ones.
+//!
+//! A section's `lang`, `code_page` or `multibyte` switches the character
+//! set its text is printed in, so one receipt can mix e.g. a Latin header,
+//! a Chinese item name and a Russian note. ESC t or FS & is sent only where
+//! the set actually changes, and the printer is left in its default table
+//! at the end. CJK characters take two columns when lines are wrapped and
+//! padded. Hebrew and Arabic sections are laid out right to left; one that also
+//! holds left-to-right words is rejected, since the printer can't reorder
+//! mixed text.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::PrintError;
+use crate::escpos::{self, Align, CodePage, Cut, DrawerPin, EscPosBuilder, HriFont, HriPosition, MultibyteSystem};
+use crate::format::Field;
+use crate::printer_info;
+
+const BARCODE_HEIGHT: u8 = 80;
+const QR_MODULE_SIZE: u8 = 6;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Receipt {
+  pub elements: Vec<Element>,
+  /// Print density applied before the receipt, -6..=6; the printer's own
+  /// setting is left alone when absent.
+  #[serde(default)]
+  pub density: Option<i8>,
+  /// Print characters outside the code page as their closest ASCII
+  /// spelling (é → e) rather than `?`.
+  #[serde(default)]
+  pub transliterate_fallback: bool,
+  /// The printer's GS I model name, from `query_printer_info`. Full cuts
+  /// are sent as partial cuts on models that can only partial-cut.
+  #[serde(default)]
+  pub printer_model: Option<String>,
+}
+
+/// One piece of a receipt, e.g. `{ "type": "row", "left": "Tea", "right": "2.50" }`.
+///
+/// The drawer never opens unless a `drawer_kick` element is present.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Element {
+  Text {
+    text: Field,
+    #[serde(default = "align_left")]
+    align: Align,
+    #[serde(default)]
+    bold: bool,
+    /// Character width/height multipliers, 1..=8.
+    #[serde(default = "one")]
+    width: u8,
+    #[serde(default = "one")]
+    height: u8,
+  },
+  /// `left` and `right` on one line, e.g. an item and its price. A `left`
+  /// too long for the line wraps, with `right` on its last line.
+  Row {
+    left: Field,
+    right: Field,
+    #[serde(default)]
+    bold: bool,
+  },
+  Divider {
+    #[serde(default = "dash")]
+    ch: char,
+  },
+  Feed {
+    lines: u8,
+  },
+  Barcode {
+    data: String,
+    /// Where the digits print; `none` hides them, e.g. on internal slips.
+    #[serde(default)]
+    hri: HriPosition,
+    #[serde(default)]
+    hri_font: HriFont,
+  },
+  Qr {
+    data: String,
+  },
+  /// Aligned columns, e.g. item / qty / modifiers on a kitchen ticket.
+  Table {
+    columns: Vec<ColumnSpec>,
+    rows: Vec<Vec<Field>>,
+    #[serde(default)]
+    bold: bool,
+    /// On lines narrower than this many characters, print each row's
+    /// cells one per line instead of side by side.
+    #[serde(default)]
+    stack_below: Option<usize>,
+  },
+  /// `elements`, or `narrow` instead on lines narrower than `min_columns`
+  /// characters (nothing, if `narrow` is empty).
+  Section {
+    elements: Vec<Element>,
+    #[serde(default)]
+    min_columns: Option<usize>,
+    #[serde(default)]
+    narrow: Vec<Element>,
+    /// Language of the section's text, e.g. `ru`, `ar` or `zh-TW`,
+    /// selecting its character set and direction.
+    #[serde(default)]
+    lang: Option<String>,
+    /// Overrides the code page `lang` selects.
+    #[serde(default)]
+    code_page: Option<CodePage>,
+    /// Prints the section in this CJK multibyte mode instead; the printer
+    /// needs the matching font.
+    #[serde(default)]
+    multibyte: Option<MultibyteSystem>,
+  },
+  Cut {
+    #[serde(default = "partial_cut")]
+    kind: Cut,
+    /// Send `kind` even where the printer's model says it can't.
+    #[serde(default)]
+    force: bool,
+  },
+  DrawerKick {
+    #[serde(default)]
+    pin: DrawerPin,
+    #[serde(default = "default_pulse_ms")]
+    pulse_ms: u16,
+  },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ColumnSpec {
+  /// Width in characters, not counting the single space between columns.
+  /// Columns shrink in proportion to it when the table is too wide.
+  pub width: usize,
+  #[serde(default = "align_left")]
+  pub align: Align,
+  /// Dropped, rightmost first, before shrinking a table that doesn't fit.
+  #[serde(default)]
+  pub optional: bool,
+}
+
+fn align_left() -> Align {
+  Align::Left
+}
+
+fn one() -> u8 {
+  1
+}
+
+fn dash() -> char {
+  '-'
+}
+
+fn partial_cut() -> Cut {
+  Cut::Partial
+}
+
+fn default_pulse_ms() -> u16 {
+  100
+}
+
+/// Character set a section's text is printed in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Charset {
+  /// An ESC t table.
+  Single(CodePage),
+  /// A CJK multibyte mode, entered with FS &.
+  Multibyte(MultibyteSystem),
+}
+
+/// A laid-out line of text, already wrapped to fit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+  pub text: String,
+  pub align: Align,
+  pub bold: bool,
+  pub width: u8,
+  pub height: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Block {
+  Line(Line),
+  Barcode {
+    data: String,
+    hri: HriPosition,
+    hri_font: HriFont,
+  },
+  Qr(String),
+  Feed(u8),
+  Cut(Cut),
+  DrawerKick { pin: DrawerPin, pulse_ms: u16 },
+  /// Lines from here on are in this character set, or ASCII for `None`.
+  Charset(Option<Charset>),
+  /// A GS v 0 bitmap, MSB-first rows of `width_bytes` bytes. Only produced
+  /// when decoding printed ESC/POS, never by [`layout`].
+  Raster {
+    align: Align,
+    width_bytes: usize,
+    height: usize,
+    bits: Vec<u8>,
+  },
+}
+
+impl Receipt {
+  /// This receipt, designed for `from_columns` characters per line,
+  /// adjusted for `to_columns`, e.g. when an 80 mm printer (48) is swapped
+  /// for a 58 mm one (32). Table columns scale with the line, and enlarged
+  /// text that fit on one line is narrowed so it still does. Everything
+  /// else already lays out to the width it is rendered at.
+  pub fn reflow(&self, from_columns: usize, to_columns: usize) -> Result<Receipt, PrintError> {
+    check_columns(from_columns)?;
+    check_columns(to_columns)?;
+    let mut out = self.clone();
+    reflow_elements(&mut out.elements, from_columns, to_columns);
+    Ok(out)
+  }
+}
+
+fn reflow_elements(elements: &mut [Element], from: usize, to: usize) {
+  for element in elements {
+    match element {
+      Element::Text { text, width, .. } => {
+        let len = text.display(false).chars().count().max(1);
+        let printed = len * (*width).clamp(1, 8) as usize;
+        if printed <= from && printed > to {
+          *width = (to / len).clamp(1, 8) as u8;
+        }
+      }
+      Element::Table { columns, .. } => {
+        for column in columns {
+          column.width = ((column.width * to + from / 2) / from).max(1);
+        }
+      }
+      Element::Section { elements, narrow, .. } => {
+        reflow_elements(elements, from, to);
+        reflow_elements(narrow, from, to);
+      }
+      _ => {}
+    }
+  }
+}
+
+pub fn check_columns(columns: usize) -> Result<(), PrintError> {
+  if !(16..=96).contains(&columns) {
+    return Err(PrintError::InvalidRequest(format!(
+      "Receipt width must be between 16 and 96 characters, got {columns}."
+    )));
+  }
+  Ok(())
+}
+
+pub fn check_density(level: i8) -> Result<(), PrintError> {
+  if !escpos::DENSITY_RANGE.contains(&level) {
+    return Err(PrintError::InvalidRequest(format!(
+      "Print density must be between -6 and 6, got {level}."
+    )));
+  }
+  Ok(())
+}
+
+/// Checks that every formatted field in the receipt renders, naming the
+/// first that doesn't, e.g. `elements[2].rows[1][0]: currency 'EU' is not
+/// a three-letter ISO 4217 code`.
+/// Also rejects unknown section languages and plain text running against
+/// its section's direction.
+pub fn check_fields(receipt: &Receipt) -> Result<(), PrintError> {
+  check_elements(&receipt.elements, "elements", None)
+}
+
+fn check_elements(elements: &[Element], path: &str, rtl: Option<bool>) -> Result<(), PrintError> {
+  let check = |field: &Field, at: String| {
+    let text = field
+      .render(false)
+      .map_err(|e| PrintError::InvalidRequest(format!("{at}: {e}.")))?;
+    // Formatted values are numbers and symbols, whatever their letters.
+    let (Some(rtl), Field::Text(_)) = (rtl, field) else {
+      return Ok(());
+    };
+    match text.chars().find(|&ch| strong_direction(ch).is_some_and(|r| r != rtl)) {
+      Some(ch) => Err(PrintError::InvalidRequest(format!(
+        "{at}: '{ch}' is written {} but the section is {}; put it in a section of its own.",
+        if rtl { "left to right" } else { "right to left" },
+        if rtl { "right-to-left" } else { "left-to-right" }
+      ))),
+      None => Ok(()),
+    }
+  };
+  for (i, element) in elements.iter().enumerate() {
+    let at = format!("{path}[{i}]");
+    match element {
+      Element::Text { text, .. } => check(text, format!("{at}.text"))?,
+      Element::Row { left, right, .. } => {
+        check(left, format!("{at}.left"))?;
+        check(right, format!("{at}.right"))?;
+      }
+      Element::Table { rows, .. } => {
+        for (r, row) in rows.iter().enumerate() {
+          for (c, cell) in row.iter().enumerate() {
+            check(cell, format!("{at}.rows[{r}][{c}]"))?;
+          }
+        }
+      }
+      Element::Section {
+        elements,
+        narrow,
+        lang,
+        code_page,
+        multibyte,
+        ..
+      } => {
+        let script = section_script(lang.as_deref(), *code_page, *multibyte)
+          .map_err(|e| PrintError::InvalidRequest(format!("{at}: {e}.")))?;
+        let rtl = script.map(|s| s.rtl).or(rtl);
+        check_elements(elements, &format!("{at}.elements"), rtl)?;
+        check_elements(narrow, &format!("{at}.narrow"), rtl)?;
+      }
+      _ => {}
+    }
+  }
+  Ok(())
+}
+
+/// Character set and direction of a section's text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Script {
+  charset: Charset,
+  rtl: bool,
+}
+
+fn section_script(
+  lang: Option<&str>,
+  code_page: Option<CodePage>,
+  multibyte: Option<MultibyteSystem>,
+) -> Result<Option<Script>, String> {
+  let charset = match (code_page, multibyte, lang) {
+    (Some(_), Some(_), _) => return Err("set code_page or multibyte, not both".to_string()),
+    (Some(page), None, _) => Charset::Single(page),
+    (None, Some(system), _) => Charset::Multibyte(system),
+    (None, None, Some(lang)) => match MultibyteSystem::for_language(lang) {
+      Some(system) => Charset::Multibyte(system),
+      None => Charset::Single(CodePage::for_language(lang).ok_or_else(|| {
+        format!("no code page is known for language '{lang}'; set code_page or multibyte instead")
+      })?),
+    },
+    (None, None, None) => return Ok(None),
+  };
+  Ok(Some(Script {
+    charset,
+    rtl: matches!(charset, Charset::Single(page) if page.is_rtl()),
+  }))
+}
+
+/// `text` with what `charset` can't print replaced per [`escpos::printable`].
+fn printable_for(text: &str, charset: Option<Charset>, transliterate: bool) -> String {
+  match charset {
+    Some(Charset::Single(page)) => escpos::printable_in(text, Some(page), transliterate),
+    Some(Charset::Multibyte(system)) => escpos::printable_multibyte(text, system, transliterate),
+    None => escpos::printable(text, transliterate),
+  }
+}
+
+/// Font A cells `ch` prints in: two for full-width CJK characters.
+fn char_cols(ch: char, charset: Option<Charset>) -> usize {
+  match charset {
+    Some(Charset::Multibyte(system)) => system.char_bytes(ch).unwrap_or(1),
+    _ => 1,
+  }
+}
+
+fn text_cols(text: &str, charset: Option<Charset>) -> usize {
+  text.chars().map(|ch| char_cols(ch, charset)).sum()
+}
+
+/// `Some(true)` for letters written right to left, `Some(false)` for other
+/// letters, `None` for digits, punctuation and spaces, which take the
+/// direction of the text around them.
+fn strong_direction(ch: char) -> Option<bool> {
+  match ch {
+    '\u{0660}'..='\u{0669}' | '\u{06f0}'..='\u{06f9}' => None,
+    '\u{0590}'..='\u{08ff}' | '\u{fb1d}'..='\u{fdff}' | '\u{fe70}'..='\u{fefc}' => Some(true),
+    _ if ch.is_alphabetic() => Some(false),
+    _ => None,
+  }
+}
+
+/// Reverses a logically ordered right-to-left line, padded to `fit`, into
+/// the left-to-right order the printer draws in. Numbers keep reading left
+/// to right.
+fn visual_rtl(text: &str, fit: usize) -> String {
+  let mut chars = format!("{text:<fit$}").chars().rev().collect::<Vec<_>>();
+  let number_part = |chars: &[char], i: usize| {
+    chars[i].is_ascii_digit() || (matches!(chars[i], '.' | ',') && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+  };
+  let mut i = 0;
+  while i < chars.len() {
+    if !chars[i].is_ascii_digit() {
+      i += 1;
+      continue;
+    }
+    let start = i;
+    while i < chars.len() && number_part(&chars, i) {
+      i += 1;
+    }
+    chars[start..i].reverse();
+  }
+  chars.into_iter().collect()
+}
+
+/// Lays the receipt out for a printer with `columns` Font A characters per line.
+pub fn layout(receipt: &Receipt, columns: usize) -> Vec<Block> {
+  let mut out = Vec::new();
+  let model = receipt.printer_model.as_deref();
+  layout_into(&receipt.elements, columns, receipt.transliterate_fallback, None, model, &mut out);
+  out
+}
+
+fn layout_into(
+  elements: &[Element],
+  columns: usize,
+  transliterate: bool,
+  script: Option<Script>,
+  model: Option<&str>,
+  out: &mut Vec<Block>,
+) {
+  let charset = script.map(|s| s.charset);
+  let printable = |field: &Field| printable_for(&field.display(charset.is_none()), charset, transliterate);
+  for element in elements {
+    let start = out.len();
+    match element {
+      Element::Text {
+        text,
+        align,
+        bold,
+        width,
+        height,
+      } => {
+        let (width, height) = ((*width).clamp(1, 8), (*height).clamp(1, 8));
+        let fit = (columns / width as usize).max(1);
+        for text in wrap_in(&printable(text), fit, charset) {
+          out.push(Block::Line(Line {
+            text,
+            align: *align,
+            bold: *bold,
+            width,
+            height,
+          }));
+        }
+      }
+      Element::Row { left, right, bold } => {
+        let right = truncate(&printable(right), columns, charset);
+        let room = columns.saturating_sub(text_cols(&right, charset) + 1);
+        let mut lefts = if room == 0 { Vec::new() } else { wrap_in(&printable(left), room, charset) };
+        let left = lefts.pop().unwrap_or_default();
+        for text in lefts {
+          out.push(Block::Line(Line {
+            text,
+            align: Align::Left,
+            bold: *bold,
+            width: 1,
+            height: 1,
+          }));
+        }
+        let pad = columns.saturating_sub(text_cols(&left, charset) + text_cols(&right, charset));
+        out.push(Block::Line(Line {
+          text: format!("{left}{}{right}", " ".repeat(pad)),
+          align: Align::Left,
+          bold: *bold,
+          width: 1,
+          height: 1,
+        }));
+      }
+      Element::Divider { ch } => {
+        let ch = if (' '..='~').contains(ch) { *ch } else { '-' };
+        out.push(Block::Line(Line {
+          text: ch.to_string().repeat(columns),
+          align: Align::Left,
+          bold: false,
+          width: 1,
+          height: 1,
+        }));
+      }
+      Element::Table {
+        columns: specs,
+        rows,
+        bold,
+        stack_below,
+      } => {
+        let rows = rows
+          .iter()
+          .map(|row| row.iter().map(printable).collect::<Vec<_>>())
+          .collect::<Vec<_>>();
+        let text = if stack_below.is_some_and(|min| columns < min) {
+          Ok(stack_table(&rows, columns, charset))
+        } else if rows.iter().any(|row| row.len() > specs.len()) {
+          table_text(specs, &rows, columns, charset)
+        } else {
+          let (specs, rows) = fit_table(specs, &rows, columns);
+          table_text(&specs, &rows, columns, charset)
+        };
+        match text {
+          Ok(text) => {
+            for line in text.lines() {
+              out.push(Block::Line(Line {
+                text: line.to_string(),
+                align: Align::Left,
+                bold: *bold,
+                width: 1,
+                height: 1,
+              }));
+            }
+          }
+          Err(e) => log::warn!("skipping table element: {e}"),
+        }
+      }
+      Element::Feed { lines } => out.push(Block::Feed(*lines)),
+      Element::Barcode { data, hri, hri_font } => out.push(Block::Barcode {
+        data: escpos::printable(data, false),
+        hri: *hri,
+        hri_font: *hri_font,
+      }),
+      Element::Qr { data } => out.push(Block::Qr(data.clone())),
+      Element::Cut { kind, force } => out.push(Block::Cut(printer_info::cut_for(*kind, *force, model))),
+      Element::DrawerKick { pin, pulse_ms } => out.push(Block::DrawerKick {
+        pin: *pin,
+        pulse_ms: *pulse_ms,
+      }),
+      Element::Section {
+        elements,
+        min_columns,
+        narrow,
+        lang,
+        code_page,
+        multibyte,
+      } => {
+        let chosen = if min_columns.is_some_and(|min| columns < min) { narrow } else { elements };
+        // check_fields has rejected unknown languages; here they just inherit.
+        let inner = section_script(lang.as_deref(), *code_page, *multibyte).ok().flatten().or(script);
+        if inner != script {
+          out.push(Block::Charset(inner.map(|s| s.charset)));
+        }
+        layout_into(chosen, columns, transliterate, inner, model, out);
+        if inner != script {
+          out.push(Block::Charset(charset));
+        }
+        continue;
+      }
+    }
+    if script.is_some_and(|s| s.rtl) {
+      for block in &mut out[start..] {
+        if let Block::Line(line) = block {
+          let fit = if line.align == Align::Left { columns / line.width as usize } else { 0 };
+          line.text = visual_rtl(&line.text, fit);
+        }
+      }
+    }
+  }
+}
+
+/// Drops optional columns, rightmost first, until the table fits in
+/// `total_width`, then shrinks the remaining widths in proportion if it
+/// still doesn't. Rows keep only the cells of the kept columns.
+fn fit_table(specs: &[ColumnSpec], rows: &[Vec<String>], total_width: usize) -> (Vec<ColumnSpec>, Vec<Vec<String>>) {
+  let needed = |keep: &[usize]| keep.iter().map(|&i| specs[i].width).sum::<usize>() + keep.len().saturating_sub(1);
+  let mut keep = (0..specs.len()).collect::<Vec<_>>();
+  while needed(&keep) > total_width {
+    match keep.iter().rposition(|&i| specs[i].optional) {
+      Some(at) => {
+        keep.remove(at);
+      }
+      None => break,
+    }
+  }
+
+  let mut fitted = keep.iter().map(|&i| specs[i].clone()).collect::<Vec<_>>();
+  let sum = fitted.iter().map(|c| c.width).sum::<usize>();
+  if needed(&keep) > total_width && sum > 0 {
+    let room = total_width.saturating_sub(fitted.len() - 1);
+    for column in &mut fitted {
+      column.width = (column.width * room / sum).max(1);
+    }
+  }
+  let rows = rows
+    .iter()
+    .map(|row| keep.iter().map(|&i| row.get(i).cloned().unwrap_or_default()).collect())
+    .collect();
+  (fitted, rows)
+}
+
+/// Each row's non-empty cells on their own lines, the first at the margin
+/// and the rest indented two spaces.
+fn stack_table(rows: &[Vec<String>], total_width: usize, charset: Option<Charset>) -> String {
+  let mut out = String::new();
+  for row in rows {
+    for (i, cell) in row.iter().filter(|c| !c.trim().is_empty()).enumerate() {
+      let indent = if i == 0 { 0 } else { 2.min(total_width - 1) };
+      for line in wrap_in(cell, total_width - indent, charset) {
+        out.push_str(&" ".repeat(indent));
+        out.push_str(&line);
+        out.push('\n');
+      }
+    }
+  }
+  out
+}
+
+/// Renders the receipt as ESC/POS bytes.
+pub fn render_escpos(receipt: &Receipt, columns: usize) -> Result<Vec<u8>, PrintError> {
+  check_columns(columns)?;
+  check_fields(receipt)?;
+  let mut b = EscPosBuilder::new();
+  b.init();
+  if let Some(level) = receipt.density {
+    check_density(level)?;
+    b.density(level);
+  }
+  // ESC t or FS & goes out only before a line whose section needs another
+  // character set.
+  let (mut wanted, mut current) = (None, None);
+  for block in layout(receipt, columns) {
+    match block {
+      Block::Charset(charset) => wanted = charset,
+      Block::Line(line) => {
+        if wanted != current {
+          switch_charset(&mut b, current, wanted);
+          current = wanted;
+        }
+        b.align(line.align)
+          .bold(line.bold)
+          .size(line.width, line.height)
+          .line(&line.text);
+        if line.bold || line.width > 1 || line.height > 1 {
+          b.bold(false).size(1, 1);
+        }
+      }
+      Block::Barcode { data, hri, hri_font } => {
+        b.align(Align::Center)
+          .barcode_code128(&data, BARCODE_HEIGHT, hri, hri_font)
+          .newline();
+      }
+      Block::Qr(data) => {
+        b.align(Align::Center).qr(&data, QR_MODULE_SIZE).newline();
+      }
+      Block::Feed(lines) => {
+        b.feed(lines);
+      }
+      Block::Cut(cut) => {
+        b.feed(3).cut(cut);
+      }
+      Block::DrawerKick { pin, pulse_ms } => {
+        b.drawer_kick(pin, pulse_ms);
+      }
+      Block::Raster {
+        align,
+        width_bytes,
+        height,
+        bits,
+      } => {
+        let (x, y) = (width_bytes as u16, height as u16);
+        b.align(align)
+          .raw(&[escpos::GS, b'v', b'0', 0])
+          .raw(&x.to_le_bytes())
+          .raw(&y.to_le_bytes())
+          .raw(&bits);
+      }
+    }
+  }
+  if current.is_some() {
+    switch_charset(&mut b, current, None);
+  }
+  b.align(Align::Left);
+  Ok(b.into_bytes())
+}
+
+/// Moves the printer from character set `from` to `to`, `None` being ASCII
+/// in the power-on table.
+fn switch_charset(b: &mut EscPosBuilder, from: Option<Charset>, to: Option<Charset>) {
+  if let Some(Charset::Multibyte(_)) = from {
+    b.disable_multibyte();
+  }
+  match to {
+    Some(Charset::Single(page)) => b.select_code_page(page),
+    Some(Charset::Multibyte(system)) => b.enable_multibyte(system),
+    None => b.code_page(0),
+  };
+}
+
+/// Lays `rows` out in `columns`, one space apart, as monospace text lines
+/// no wider than `total_width`. A cell longer than its column wraps onto
+/// extra lines within that column; the other cells of the row stay on its
+/// first line. Missing cells are blank and extra cells are an error.
+/// Characters outside printable ASCII become `?`.
+pub fn build_table(columns: &[ColumnSpec], rows: &[Vec<String>], total_width: usize) -> Result<String, PrintError> {
+  table_text(columns, rows, total_width, None)
+}
+
+/// [`build_table`] keeping the characters `charset` can print.
+fn table_text(
+  columns: &[ColumnSpec],
+  rows: &[Vec<String>],
+  total_width: usize,
+  charset: Option<Charset>,
+) -> Result<String, PrintError> {
+  if columns.is_empty() || columns.iter().any(|c| c.width == 0) {
+    return Err(PrintError::InvalidRequest(
+      "A table needs at least one column, each at least 1 character wide.".to_string(),
+    ));
+  }
+  let needed = columns.iter().map(|c| c.width).sum::<usize>() + columns.len() - 1;
+  if needed > total_width {
+    return Err(PrintError::InvalidRequest(format!(
+      "Table columns need {needed} characters including separators but the line is {total_width} wide."
+    )));
+  }
+
+  let mut out = String::new();
+  for (r, row) in rows.iter().enumerate() {
+    if row.len() > columns.len() {
+      return Err(PrintError::InvalidRequest(format!(
+        "Table row {} has {} cells but only {} columns are defined.",
+        r + 1,
+        row.len(),
+        columns.len()
+      )));
+    }
+    let cells = columns
+      .iter()
+      .enumerate()
+      .map(|(i, spec)| {
+        let cell = printable_for(row.get(i).map_or("", String::as_str), charset, false);
+        wrap_in(&cell, spec.width, charset)
+      })
+      .collect::<Vec<_>>();
+    let height = cells.iter().map(Vec::len).max().unwrap_or(1);
+    for line in 0..height {
+      let mut text = String::with_capacity(total_width);
+      for (i, (spec, cell)) in columns.iter().zip(&cells).enumerate() {
+        if i > 0 {
+          text.push(' ');
+        }
+        let part = cell.get(line).map_or("", String::as_str);
+        let pad = spec.width.saturating_sub(text_cols(part, charset));
+        let (before, after) = match spec.align {
+          Align::Left => (0, pad),
+          Align::Center => (pad / 2, pad - pad / 2),
+          Align::Right => (pad, 0),
+        };
+        text.push_str(&" ".repeat(before));
+        text.push_str(part);
+        text.push_str(&" ".repeat(after));
+      }
+      out.push_str(text.trim_end());
+      out.push('\n');
+    }
+  }
+  Ok(out)
+}
+
+fn truncate(text: &str, max: usize, charset: Option<Charset>) -> String {
+  let mut used = 0;
+  text
+    .chars()
+    .take_while(|&ch| {
+      used += char_cols(ch, charset);
+      used <= max
+    })
+    .collect()
+}
+
+/// Word-wraps `text` to `width` columns, one per character, splitting
+/// words longer than a line.
+pub(crate) fn wrap(text: &str, width: usize) -> Vec<String> {
+  wrap_in(text, width, None)
+}
+
+/// [`wrap`] counting full-width characters in `charset` as two columns.
+/// CJK text has no spaces, so it breaks wherever the line fills.
+fn wrap_in(text: &str, width: usize, charset: Option<Charset>) -> Vec<String> {
+  let mut lines = Vec::new();
+  let mut current = String::new();
+  for word in text.split_whitespace() {
+    let mut word = word;
+    while text_cols(word, charset) > width {
+      if !current.is_empty() {
+        lines.push(std::mem::take(&mut current));
+      }
+      let mut used = 0;
+      let at = word
+        .char_indices()
+        .find(|&(_, ch)| {
+          used += char_cols(ch, charset);
+          used > width
+        })
+        .map_or(word.len(), |(i, _)| i);
+      // A full-width character on a one-column line still has to go somewhere.
+      let at = if at == 0 { word.chars().next().map_or(0, char::len_utf8) } else { at };
+      let (head, tail) = word.split_at(at);
+      lines.push(head.to_string());
+      word = tail;
+    }
+    if word.is_empty() {
+      continue;
+    }
+    if current.is_empty() {
+      current.push_str(word);
+    } else if text_cols(&current, charset) + 1 + text_cols(word, charset) <= width {
+      current.push(' ');
+      current.push_str(word);
+    } else {
+      lines.push(std::mem::replace(&mut current, word.to_string()));
+    }
+  }
+  if !current.is_empty() || lines.is_empty() {
+    lines.push(current);
+  }
+  lines
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  /// Font A cells are 12 dots wide.
+  const FONT_A_DOTS: usize = 12;
+
+  fn sample() -> Receipt {
+    serde_json::from_value(json!({
+      "elements": [
+        { "type": "text", "text": "CORNER CAFE", "align": "center", "width": 2, "height": 2 },
+        { "type": "section", "min_columns": 40,
+          "elements": [{ "type": "row", "left": "Order 1042 - Table 7 - Server: Alex", "right": "12:41" }],
+          "narrow": [{ "type": "row", "left": "Order 1042", "right": "12:41" }] },
+        { "type": "divider" },
+        { "type": "table",
+          "columns": [
+            { "width": 20 },
+            { "width": 4, "align": "right" },
+            { "width": 13, "optional": true },
+            { "width": 8, "align": "right" }
+          ],
+          "rows": [
+            ["Item", "Qty", "Notes", "Price"],
+            ["Oat milk latte", "2", "extra hot", "9.00"],
+            ["Blueberry muffin", "1", "", "3.50"]
+          ] },
+        { "type": "table", "stack_below": 40,
+          "columns": [{ "width": 16 }, { "width": 14 }, { "width": 14 }],
+          "rows": [["Paid by card", "VISA **** 4242", "Auth 0A1B2C"]] },
+        { "type": "row", "left": "A very long promotional line that cannot fit beside its price", "right": "-1.00" },
+        { "type": "row", "left": "TOTAL", "right": "11.50", "bold": true },
+        { "type": "cut" }
+      ]
+    }))
+    .unwrap()
+  }
+
+  fn lines(receipt: &Receipt, columns: usize) -> Vec<Line> {
+    layout(receipt, columns)
+      .into_iter()
+      .filter_map(|block| match block {
+        Block::Line(line) => Some(line),
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn one_document_fits_58mm_and_80mm_paper() {
+    let receipt = sample();
+    for dots in [384, 576] {
+      let columns = dots / FONT_A_DOTS;
+      for line in lines(&receipt, columns) {
+        let printed = line.text.chars().count() * line.width as usize;
+        assert!(printed <= columns, "{dots} dots: {:?} takes {printed} of {columns} columns", line.text);
+      }
+      render_escpos(&receipt, columns).unwrap();
+      let png = crate::preview::render_png(&receipt, dots as u32).unwrap();
+      assert_eq!(image::load_from_memory(&png).unwrap().width(), dots as u32);
+    }
+  }
+
+  #[test]
+  fn narrow_paper_uses_the_declared_fallbacks() {
+    let receipt = sample();
+    let wide = lines(&receipt, 576 / FONT_A_DOTS);
+    let narrow = lines(&receipt, 384 / FONT_A_DOTS);
+    let has = |lines: &[Line], text: &str| lines.iter().any(|l| l.text.contains(text));
+
+    // Section swaps in its narrow content.
+    assert!(has(&wide, "Server: Alex"));
+    assert!(!has(&narrow, "Server: Alex"));
+    assert!(has(&narrow, "Order 1042"));
+
+    // The optional notes column is dropped, the rest kept.
+    assert!(has(&wide, "Notes") && has(&wide, "extra hot"));
+    assert!(!has(&narrow, "Notes") && !has(&narrow, "extra hot"));
+    assert!(narrow.iter().any(|l| l.text.starts_with("Oat milk") && l.text.trim_end().ends_with("9.00")));
+
+    // The payment table stacks its cells one per line.
+    assert!(wide.iter().any(|l| l.text.contains("Paid by card") && l.text.contains("VISA")));
+    assert!(narrow.iter().any(|l| l.text == "Paid by card"));
+    assert!(narrow.iter().any(|l| l.text == "  VISA **** 4242"));
+
+    // A long row wraps, keeping its price on the last line.
+    let promo = narrow.iter().position(|l| l.text.starts_with("A very long")).unwrap();
+    assert!(!narrow[promo].text.contains("-1.00"));
+    assert!(narrow[promo + 1..].iter().any(|l| l.text.ends_with("-1.00")));
+  }
+
+  #[test]
+  fn reflow_scales_table_columns_and_big_text() {
+    let narrowed = sample().reflow(48, 32).unwrap();
+    let Element::Table { columns, .. } = &narrowed.elements[3] else {
+      panic!("expected the item table");
+    };
+    assert_eq!(columns.iter().map(|c| c.width).collect::<Vec<_>>(), [13, 3, 9, 5]);
+    let Element::Text { width, .. } = &narrowed.elements[0] else {
+      panic!("expected the heading");
+    };
+    assert_eq!(*width, 2);
+  }
+}
@@ -0,0 +1,296 @@
+//! Recovering a printer left mid-command by a truncated job, over a
+//! connection of its own so a wedged worker queue can't hold it up,
+//! resuming one stopped by a recoverable error, and rebooting one that
+//! accepts connections but never prints.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PrintConfig;
+use crate::error::PrintError;
+use crate::escpos::{DLE, ESC};
+use crate::status::{self, FaultKind, FullStatus};
+use crate::target::Target;
+use crate::transport::{self, SerialLink};
+use crate::workers::Duplex;
+
+/// DLE DC4 fn 8 — clear the receive and print buffers. Printers without
+/// the function ignore it.
+const CLEAR_BUFFERS: [u8; 10] = [DLE, 0x14, 8, 1, 3, 20, 1, 6, 2, 8];
+/// Time the printer gets to finish clearing before the reset.
+const SETTLE: Duration = Duration::from_millis(200);
+/// DLE ENQ 1 — recover from a recoverable error and restart printing from
+/// the line where it occurred. Ignored when there is no such error.
+const RECOVER: [u8; 3] = [DLE, 0x05, 1];
+/// Star line mode `ESC ? LF NUL` — restart the printer as if power-cycled.
+const STAR_RESET: [u8; 4] = [ESC, b'?', 0x0a, 0];
+/// A destination is not rebooted again within this long of the last attempt.
+pub const REBOOT_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+const REBOOT_HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RecoverReport {
+  pub destination: String,
+  /// Whether the recovery command was sent; it isn't without a
+  /// recoverable error to clear.
+  pub sent: bool,
+  /// Whether the printer no longer reports a recoverable error.
+  pub cleared: bool,
+  /// What still stops the printer, e.g. paper out, which recovery can't fix.
+  pub fault: Option<FaultKind>,
+  pub status: FullStatus,
+}
+
+/// How to reboot a printer. There is no default; callers name one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RebootMethod {
+  /// The Star line mode reset command, sent over the print connection.
+  /// Epson and most clones ignore it.
+  StarReset,
+  /// A POST to the destination's entry in `PrintConfig::reboot_urls`, for
+  /// print servers with a management page.
+  Http,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RebootReport {
+  pub destination: String,
+  pub method: RebootMethod,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ClearReport {
+  pub destination: String,
+  /// Whether the printer answered the status query after the reset.
+  pub responsive: bool,
+  pub status: Option<FullStatus>,
+  /// Why the status query failed, when it did.
+  pub error: Option<String>,
+}
+
+/// Sends the buffer clear and `ESC @` to `dest`, then checks it answers a
+/// full status query. Opens a fresh connection instead of going through the
+/// destination's worker, so queued jobs don't delay it. Serial ports can't
+/// be opened twice, so for serial printers this fails while a worker still
+/// holds the port.
+pub fn clear_printer(dest: &Target, cfg: &PrintConfig) -> Result<ClearReport, PrintError> {
+  let label = dest.label();
+  let timeout = Duration::from_millis(cfg.status_timeout_ms);
+  let mut link = open_link(dest, &label, cfg, "clear its jobs from the Windows print queue instead")?;
+
+  link
+    .write_all(&CLEAR_BUFFERS)
+    .and_then(|_| link.flush())
+    .map_err(|e| PrintError::Write(format!("Sending the buffer clear to {label} failed: {e}.").into()))?;
+  thread::sleep(SETTLE);
+  link
+    .write_all(&[ESC, b'@'])
+    .and_then(|_| link.flush())
+    .map_err(|e| PrintError::Write(format!("Resetting {label} failed: {e}.").into()))?;
+  log::info!("sent buffer clear and reset to {label}");
+
+  Ok(match status::query_full(&mut *link, &label, timeout) {
+    Ok(status) => ClearReport {
+      destination: label,
+      responsive: true,
+      status: Some(status),
+      error: None,
+    },
+    Err(e) => ClearReport {
+      destination: label,
+      responsive: false,
+      status: None,
+      error: Some(e.to_string()),
+    },
+  })
+}
+
+/// Sends DLE ENQ 1 to `dest` if it reports a recoverable error such as a
+/// cleared cutter jam, then checks the error is gone; printing resumes
+/// from the line the error interrupted. Uses its own connection like
+/// [`clear_printer`], since the destination's worker is likely stuck on
+/// the interrupted job.
+pub fn recover_printer(dest: &Target, cfg: &PrintConfig) -> Result<RecoverReport, PrintError> {
+  let label = dest.label();
+  let timeout = Duration::from_millis(cfg.status_timeout_ms);
+  let mut link = open_link(dest, &label, cfg, "recover it from the printer itself instead")?;
+
+  let before = status::query_full(&mut *link, &label, timeout)?;
+  if !(before.error.recoverable || before.error.cutter_error) {
+    return Ok(RecoverReport {
+      destination: label,
+      sent: false,
+      cleared: before.fault().is_none(),
+      fault: before.fault(),
+      status: before,
+    });
+  }
+  link
+    .write_all(&RECOVER)
+    .and_then(|_| link.flush())
+    .map_err(|e| PrintError::Write(format!("Sending error recovery to {label} failed: {e}.").into()))?;
+  log::info!("sent error recovery to {label}");
+  thread::sleep(SETTLE);
+
+  let after = status::query_full(&mut *link, &label, timeout)?;
+  let cleared = !(after.error.recoverable || after.error.cutter_error);
+  if !cleared {
+    log::warn!("{label} still reports an error after recovery");
+  }
+  Ok(RecoverReport {
+    destination: label,
+    sent: true,
+    cleared,
+    fault: after.fault(),
+    status: after,
+  })
+}
+
+/// A connection of its own to `dest`; `spooler_hint` says what to do
+/// instead for spooler queues, which have no real-time channel.
+pub(crate) fn open_link(dest: &Target, label: &str, cfg: &PrintConfig, spooler_hint: &str) -> Result<Box<dyn Duplex>, PrintError> {
+  Ok(match dest {
+    Target::Tcp { host, port } => {
+      let stream = transport::tcp_connect(host, *port, cfg)?;
+      let _ = stream.set_read_timeout(Some(Duration::from_millis(cfg.status_timeout_ms)));
+      let _ = stream.set_write_timeout(Some(Duration::from_millis(cfg.write_timeout(label))));
+      Box::new(stream)
+    }
+    Target::Serial { port, baud } => Box::new(SerialLink::open(port, *baud, cfg)?),
+    Target::Spooler { .. } => {
+      return Err(PrintError::Unsupported(format!(
+        "{label} is a spooler queue, which has no real-time channel; {spooler_hint}."
+      )))
+    }
+    Target::Port { .. } => {
+      return Err(PrintError::Unsupported(format!(
+        "{label} is written to directly and can't be read from; use a serial target for the printer instead."
+      )))
+    }
+  })
+}
+
+fn last_reboots() -> &'static Mutex<HashMap<String, Instant>> {
+  static LAST: OnceLock<Mutex<HashMap<String, Instant>>> = OnceLock::new();
+  LAST.get_or_init(Mutex::default)
+}
+
+/// Reboots `dest` with `method`. A reboot drops whatever the printer was
+/// doing, so this refuses unless `confirm` is the destination's label, and
+/// refuses a destination attempted within [`REBOOT_COOLDOWN`] so a
+/// watchdog can't reboot-loop a printer. It only sends the command; the
+/// printer is unreachable for a while afterwards.
+pub fn reboot_printer(
+  dest: &Target,
+  method: RebootMethod,
+  confirm: &str,
+  cfg: &PrintConfig,
+) -> Result<RebootReport, PrintError> {
+  let label = dest.label();
+  if confirm != label {
+    return Err(PrintError::InvalidRequest(format!(
+      "Rebooting interrupts anything {label} is printing; pass its label '{label}' as confirm to go ahead."
+    )));
+  }
+  let url = match method {
+    RebootMethod::StarReset => None,
+    RebootMethod::Http => Some(reboot_url(dest, cfg)?),
+  };
+  {
+    let mut last = last_reboots().lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(at) = last.get(&label).filter(|at| at.elapsed() < REBOOT_COOLDOWN) {
+      return Err(PrintError::InvalidRequest(format!(
+        "{label} was already sent a reboot {}s ago; check it by hand before trying again.",
+        at.elapsed().as_secs()
+      )));
+    }
+    last.insert(label.clone(), Instant::now());
+  }
+
+  match url {
+    None => star_reset(dest, &label, cfg)?,
+    Some(url) => {
+      let agent = ureq::AgentBuilder::new().timeout(REBOOT_HTTP_TIMEOUT).build();
+      match agent.post(&url).send_string("") {
+        Ok(_) => {}
+        Err(ureq::Error::Status(code, _)) => {
+          return Err(PrintError::Connect(
+            format!("The management page of {label} refused the reboot with HTTP {code}.").into(),
+          ))
+        }
+        Err(e) => {
+          return Err(PrintError::Connect(
+            format!("Reaching the management page of {label} failed: {e}.").into(),
+          ))
+        }
+      }
+    }
+  }
+  log::warn!("sent {method:?} reboot to {label}");
+  Ok(RebootReport {
+    destination: label,
+    method,
+  })
+}
+
+/// The configured management URL for `dest`, which must point at the same
+/// host so a typo can't reboot some other device.
+fn reboot_url(dest: &Target, cfg: &PrintConfig) -> Result<String, PrintError> {
+  let label = dest.label();
+  let Target::Tcp { host, .. } = dest else {
+    return Err(PrintError::Unsupported(format!(
+      "{label} is not a network printer, so it has no management page to reboot through."
+    )));
+  };
+  let url = cfg.reboot_urls.get(&label).ok_or_else(|| {
+    PrintError::InvalidRequest(format!("No reboot URL is configured for {label}; add one to reboot_urls."))
+  })?;
+  let authority = url
+    .strip_prefix("http://")
+    .or_else(|| url.strip_prefix("https://"))
+    .map(|rest| rest.split(['/', '?', '#']).next().unwrap_or_default())
+    .ok_or_else(|| PrintError::InvalidRequest(format!("The reboot URL for {label} must start with http:// or https://.")))?;
+  let url_host = authority.rsplit('@').next().unwrap_or_default();
+  let url_host = match url_host.strip_prefix('[') {
+    Some(v6) => v6.split(']').next().unwrap_or_default(),
+    None => url_host.split(':').next().unwrap_or_default(),
+  };
+  let host = host.trim().trim_start_matches('[').trim_end_matches(']');
+  if !url_host.eq_ignore_ascii_case(host) {
+    return Err(PrintError::InvalidRequest(format!(
+      "The reboot URL for {label} points at {url_host}, not the printer's host {host}."
+    )));
+  }
+  Ok(url.clone())
+}
+
+fn star_reset(dest: &Target, label: &str, cfg: &PrintConfig) -> Result<(), PrintError> {
+  let mut link: Box<dyn Write> = match dest {
+    Target::Tcp { host, port } => {
+      let stream = transport::tcp_connect(host, *port, cfg)?;
+      let _ = stream.set_write_timeout(Some(Duration::from_millis(cfg.write_timeout(label))));
+      Box::new(stream)
+    }
+    Target::Serial { port, baud } => Box::new(transport::serial_open(port, *baud, cfg)?),
+    Target::Spooler { .. } => {
+      return Err(PrintError::Unsupported(format!(
+        "{label} is a spooler queue, which can't pass a reset through; restart the printer by hand."
+      )))
+    }
+    Target::Port { .. } => {
+      return Err(PrintError::Unsupported(format!(
+        "{label} is written to directly and can't be reset from here; restart the printer by hand."
+      )))
+    }
+  };
+  link
+    .write_all(&STAR_RESET)
+    .and_then(|_| link.flush())
+    .map_err(|e| PrintError::Write(format!("Sending the reset to {label} failed: {e}.").into()))
+}
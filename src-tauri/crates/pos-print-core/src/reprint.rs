@@ -0,0 +1,295 @@
+//! Printed payloads, kept for reprints, and the copy stamp added to them.
+//!
+//! The last few jobs stay in memory. With a retention period configured,
+//! every printed payload is also written gzipped to `<dir>/<job_id>.bin.gz`
+//! next to a `<job_id>.json` description, so a job can be reprinted after a
+//! restart until it ages out. Plain `.bin` files from older versions are
+//! still read.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{now_ms, AuditLog};
+use crate::compress;
+use crate::config::ConfigStore;
+use crate::error::PrintError;
+use crate::escpos::{Align, EscPosBuilder, ESC};
+use crate::rtc::ClockTime;
+use crate::target::Target;
+
+const MAX_JOBS: usize = 32;
+const MAX_TOTAL_BYTES: usize = 32 * 1024 * 1024;
+const PRUNE_INTERVAL_MS: u64 = 3_600_000;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ReprintOptions {
+  /// Stamp the reprint with a `*** COPY ***` banner. Compliance requires
+  /// this for receipts; turn it off only for non-fiscal documents.
+  pub mark_copy: bool,
+  /// Offset of local time from UTC for the stamped reprint time.
+  pub utc_offset_minutes: i32,
+}
+
+impl Default for ReprintOptions {
+  fn default() -> Self {
+    Self {
+      mark_copy: true,
+      utc_offset_minutes: 0,
+    }
+  }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredJob {
+  job_id: String,
+  target: Target,
+  printed_at_ms: u64,
+  copies: u32,
+}
+
+struct Remembered {
+  job: StoredJob,
+  data: Arc<Vec<u8>>,
+}
+
+/// A job picked for reprinting.
+pub struct Reprint {
+  pub job_id: String,
+  pub target: Target,
+  pub data: Arc<Vec<u8>>,
+  /// 1 for the first reprint, 2 for the second, ...
+  pub copy: u32,
+}
+
+#[derive(Clone, Default)]
+pub struct ReprintStore {
+  jobs: Arc<Mutex<VecDeque<Remembered>>>,
+  /// Unset keeps payloads in memory only.
+  dir: Option<PathBuf>,
+  config: ConfigStore,
+  /// Tells a job whose payload aged out from one that never printed.
+  audit: AuditLog,
+  last_prune_ms: Arc<AtomicU64>,
+}
+
+impl ReprintStore {
+  pub fn open(dir: PathBuf, config: ConfigStore, audit: AuditLog) -> Self {
+    let dir = match fs::create_dir_all(&dir) {
+      Ok(()) => Some(dir),
+      Err(e) => {
+        log::warn!("reprints kept in memory only, unable to create {}: {e}", dir.display());
+        None
+      }
+    };
+    Self {
+      dir,
+      config,
+      audit,
+      ..Self::default()
+    }
+  }
+
+  /// Retention in ms, or `None` when payloads are only kept in memory.
+  fn retention_ms(&self) -> Option<u64> {
+    let hours = self.config.snapshot().reprint_retention_hours;
+    (self.dir.is_some() && hours > 0).then_some(hours * 3_600_000)
+  }
+
+  pub fn remember(&self, job_id: &str, target: Target, data: Vec<u8>) {
+    let job = StoredJob {
+      job_id: job_id.to_string(),
+      target,
+      printed_at_ms: now_ms(),
+      copies: 0,
+    };
+    if let (Some(dir), Some(retention)) = (&self.dir, self.retention_ms()) {
+      if let Err(e) = write_job(dir, &job, Some(&data)) {
+        log::warn!("unable to keep job {job_id} for reprints: {e}");
+      }
+      self.prune(dir, retention);
+    }
+
+    if data.len() > MAX_TOTAL_BYTES {
+      return;
+    }
+    let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+    jobs.push_back(Remembered {
+      job,
+      data: Arc::new(data),
+    });
+    let mut total = jobs.iter().map(|j| j.data.len()).sum::<usize>();
+    while jobs.len() > MAX_JOBS || total > MAX_TOTAL_BYTES {
+      match jobs.pop_front() {
+        Some(dropped) => total -= dropped.data.len(),
+        None => break,
+      }
+    }
+  }
+
+  /// The job `job_id`, or the most recent one, with its copy count bumped.
+  pub fn next_copy(&self, job_id: Option<&str>) -> Result<Reprint, PrintError> {
+    let retention = self.retention_ms();
+    let expired = |job: &StoredJob| retention.is_some_and(|r| job.printed_at_ms + r < now_ms());
+
+    let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+    let cached = match job_id {
+      Some(id) => jobs.iter_mut().rev().find(|j| j.job.job_id == id),
+      None => jobs.back_mut(),
+    };
+    let (job, data) = match (cached, job_id) {
+      (Some(cached), _) => {
+        if expired(&cached.job) {
+          return Err(not_retained(&cached.job.job_id));
+        }
+        cached.job.copies += 1;
+        (cached.job.clone(), cached.data.clone())
+      }
+      (None, None) => {
+        return Err(PrintError::InvalidRequest(
+          "Nothing has been printed yet in this session.".to_string(),
+        ))
+      }
+      (None, Some(id)) => {
+        let max_bytes = self.config.snapshot().max_payload_bytes;
+        let stored = self
+          .dir
+          .as_deref()
+          .filter(|_| retention.is_some())
+          .and_then(|dir| read_job(dir, id, max_bytes));
+        match stored {
+          Some((job, _)) if expired(&job) => return Err(not_retained(id)),
+          Some((mut job, data)) => {
+            job.copies += 1;
+            (job, Arc::new(data))
+          }
+          None if self.audit.find_print(id).is_some() => return Err(not_retained(id)),
+          None => {
+            return Err(PrintError::InvalidRequest(format!(
+              "No printed job {id} was found for reprint."
+            )))
+          }
+        }
+      }
+    };
+    drop(jobs);
+
+    if let Some(dir) = self.dir.as_deref().filter(|_| retention.is_some()) {
+      if let Err(e) = write_job(dir, &job, None) {
+        log::warn!("unable to update the copy count of job {}: {e}", job.job_id);
+      }
+    }
+    Ok(Reprint {
+      job_id: job.job_id,
+      target: job.target,
+      data,
+      copy: job.copies,
+    })
+  }
+
+  /// Removes stored jobs past the retention period, at most hourly.
+  fn prune(&self, dir: &Path, retention_ms: u64) {
+    let now = now_ms();
+    let last = self.last_prune_ms.load(Ordering::Relaxed);
+    if now.saturating_sub(last) < PRUNE_INTERVAL_MS
+      || self
+        .last_prune_ms
+        .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+        .is_err()
+    {
+      return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+      return;
+    };
+    for path in entries.flatten().map(|e| e.path()) {
+      if path.extension().and_then(|e| e.to_str()) != Some("json") {
+        continue;
+      }
+      let expired = fs::read(&path)
+        .ok()
+        .and_then(|raw| serde_json::from_slice::<StoredJob>(&raw).ok())
+        .map_or(true, |job| job.printed_at_ms + retention_ms < now);
+      if expired {
+        let _ = fs::remove_file(path.with_extension("bin.gz"));
+        let _ = fs::remove_file(path.with_extension("bin"));
+        let _ = fs::remove_file(&path);
+      }
+    }
+  }
+}
+
+fn not_retained(job_id: &str) -> PrintError {
+  PrintError::NotRetained(format!(
+    "Job {job_id} printed, but its payload is past the reprint retention period and can't be reprinted."
+  ))
+}
+
+/// Job ids come from callers, so only plain ones map to file names.
+fn job_path(dir: &Path, job_id: &str) -> Option<PathBuf> {
+  let plain = !job_id.is_empty() && job_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+  plain.then(|| dir.join(job_id))
+}
+
+/// Writes the description and, when given, the payload of `job`.
+fn write_job(dir: &Path, job: &StoredJob, data: Option<&[u8]>) -> Result<(), String> {
+  let base = job_path(dir, &job.job_id).ok_or("job id is not a plain file name")?;
+  if let Some(data) = data {
+    let packed = compress::gzip(data).map_err(|e| e.to_string())?;
+    fs::write(base.with_extension("bin.gz"), packed).map_err(|e| e.to_string())?;
+  }
+  let json = serde_json::to_vec(job).map_err(|e| e.to_string())?;
+  fs::write(base.with_extension("json"), json).map_err(|e| e.to_string())
+}
+
+fn read_job(dir: &Path, job_id: &str, max_bytes: usize) -> Option<(StoredJob, Vec<u8>)> {
+  let base = job_path(dir, job_id)?;
+  let job = serde_json::from_slice::<StoredJob>(&fs::read(base.with_extension("json")).ok()?).ok()?;
+  let data = match fs::read(base.with_extension("bin.gz")) {
+    Ok(packed) => compress::gunzip(&packed, max_bytes)
+      .map_err(|e| log::warn!("unable to read the stored payload of job {job_id}: {e}"))
+      .ok()?,
+    Err(_) => fs::read(base.with_extension("bin")).ok()?,
+  };
+  Some((job, data))
+}
+
+/// Inserts a `*** COPY ***` banner into `payload`, after any leading `ESC @`
+/// so the reset doesn't clear its styling. Everything else, including a
+/// leading raster image or macro execute, stays byte-for-byte intact.
+pub fn stamp_copy(payload: &[u8], copy: u32, unix_ts: i64) -> Vec<u8> {
+  let mut at = 0;
+  while payload[at..].starts_with(&[ESC, b'@']) {
+    at += 2;
+  }
+
+  let when = ClockTime::from_unix(unix_ts)
+    .map(|t| format!("{:04}-{:02}-{:02} {:02}:{:02}", t.year, t.month, t.day, t.hour, t.minute))
+    .unwrap_or_default();
+  let mut banner = EscPosBuilder::new();
+  banner
+    .align(Align::Center)
+    .inverse(true)
+    .size(2, 1)
+    .line("*** COPY ***")
+    .size(1, 1)
+    .inverse(false)
+    .line(&format!("Reprint #{copy} {when}"))
+    .align(Align::Left);
+
+  let mut out = Vec::with_capacity(payload.len() + 64);
+  out.extend_from_slice(&payload[..at]);
+  out.extend_from_slice(&banner.into_bytes());
+  out.extend_from_slice(&payload[at..]);
+  out
+}
+
+/// Current time shifted by `utc_offset_minutes`, for [`stamp_copy`].
+pub fn local_now(utc_offset_minutes: i32) -> i64 {
+  (now_ms() / 1000) as i64 + utc_offset_minutes as i64 * 60
+}
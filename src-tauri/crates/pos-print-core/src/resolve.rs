@@ -0,0 +1,101 @@
+//! Hostname resolution with a timeout and a short-lived cache.
+//!
+//! The system resolver can block for 15-30 s when the store's DNS server is
+//! down, so lookups run on a helper thread and are abandoned after the
+//! configured timeout. If a lookup fails, the last good address for that
+//! host is used instead, with a warning.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::channel;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{codes, ErrorDetail, PrintError};
+
+/// Cached addresses younger than this are used without a lookup.
+const FRESH_TTL: Duration = Duration::from_secs(300);
+
+pub struct Resolved {
+  pub addr: SocketAddr,
+  /// Set when a failed lookup fell back to a cached address.
+  pub warning: Option<String>,
+}
+
+fn cache() -> &'static Mutex<HashMap<String, (IpAddr, Instant)>> {
+  static CACHE: OnceLock<Mutex<HashMap<String, (IpAddr, Instant)>>> = OnceLock::new();
+  CACHE.get_or_init(Mutex::default)
+}
+
+fn cached(key: &str) -> Option<(IpAddr, Instant)> {
+  cache().lock().unwrap_or_else(|e| e.into_inner()).get(key).copied()
+}
+
+/// Resolves `host`, skipping the lookup entirely for literal IP addresses.
+pub fn resolve(host: &str, port: u16, timeout: Duration) -> Result<Resolved, PrintError> {
+  let literal = host.trim().trim_start_matches('[').trim_end_matches(']');
+  if let Ok(ip) = literal.parse::<IpAddr>() {
+    return Ok(Resolved {
+      addr: SocketAddr::new(ip, port),
+      warning: None,
+    });
+  }
+
+  let key = host.trim().to_ascii_lowercase();
+  if let Some((ip, at)) = cached(&key) {
+    if at.elapsed() < FRESH_TTL {
+      return Ok(Resolved {
+        addr: SocketAddr::new(ip, port),
+        warning: None,
+      });
+    }
+  }
+
+  let (tx, rx) = channel();
+  let name = key.clone();
+  let spawned = thread::Builder::new().name("print-dns".to_string()).spawn(move || {
+    let _ = tx.send((name.as_str(), port).to_socket_addrs().map(|addrs| addrs.collect::<Vec<_>>()));
+  });
+
+  let error = match spawned.map(|_| rx.recv_timeout(timeout)) {
+    Err(e) => format!("unable to start lookup: {e}"),
+    Ok(Err(_)) => format!("lookup timed out after {} ms", timeout.as_millis()),
+    Ok(Ok(Err(e))) => e.to_string(),
+    Ok(Ok(Ok(addrs))) => match addrs.first() {
+      Some(addr) => {
+        cache()
+          .lock()
+          .unwrap_or_else(|e| e.into_inner())
+          .insert(key, (addr.ip(), Instant::now()));
+        return Ok(Resolved {
+          addr: *addr,
+          warning: None,
+        });
+      }
+      None => "no addresses returned".to_string(),
+    },
+  };
+
+  match cached(&key) {
+    Some((ip, at)) => {
+      let warning = format!(
+        "Resolving '{host}' failed ({error}); using cached address {ip} from {} s ago.",
+        at.elapsed().as_secs()
+      );
+      log::warn!("{warning}");
+      Ok(Resolved {
+        addr: SocketAddr::new(ip, port),
+        warning: Some(warning),
+      })
+    }
+    None => Err(PrintError::Resolve(
+      ErrorDetail::new(format!(
+        "Unable to resolve host '{host}:{port}': {error}. Check printer IP/DNS."
+      ))
+      .reason(codes::DNS_LOOKUP_FAILED)
+      .param("host", host)
+      .param("port", port),
+    )),
+  }
+}
@@ -0,0 +1,207 @@
+//! Printer real-time clock access.
+//!
+//! Clock-equipped models accept the vendor function `GS ( T`:
+//! - set: `GS ( T 08 00 30 CC YY MM DD hh mm ss` (fn 48)
+//! - get: `GS ( T 02 00 31 00` (fn 49), answered with `37 54 CC YY MM DD hh mm ss 00`
+//!
+//! Every date/time field is packed BCD (`0x26` for 26). Printers without an
+//! RTC ignore the command, so a read timeout is reported as unsupported.
+
+use std::time::{Duration, Instant};
+
+use crate::error::PrintError;
+use crate::escpos::GS;
+use crate::workers::Duplex;
+
+const FN_SET: u8 = 0x30;
+const FN_GET: u8 = 0x31;
+const REPLY_HEADER: [u8; 2] = [0x37, 0x54];
+const REPLY_LEN: usize = REPLY_HEADER.len() + 7 + 1;
+
+/// Calendar fields as stored by the printer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockTime {
+  pub year: u16,
+  pub month: u8,
+  pub day: u8,
+  pub hour: u8,
+  pub minute: u8,
+  pub second: u8,
+}
+
+impl ClockTime {
+  pub fn from_unix(ts: i64) -> Result<Self, PrintError> {
+    let days = ts.div_euclid(86_400);
+    let secs = ts.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    if !(2000..=2099).contains(&year) {
+      return Err(PrintError::InvalidRequest(format!(
+        "Printer clocks store years 2000-2099; timestamp {ts} is in {year}."
+      )));
+    }
+    Ok(Self {
+      year: year as u16,
+      month,
+      day,
+      hour: (secs / 3600) as u8,
+      minute: (secs / 60 % 60) as u8,
+      second: (secs % 60) as u8,
+    })
+  }
+
+  pub fn to_unix(self) -> i64 {
+    days_from_civil(self.year as i64, self.month, self.day) * 86_400
+      + self.hour as i64 * 3600
+      + self.minute as i64 * 60
+      + self.second as i64
+  }
+
+  fn encode(self) -> [u8; 7] {
+    [
+      to_bcd((self.year / 100) as u8),
+      to_bcd((self.year % 100) as u8),
+      to_bcd(self.month),
+      to_bcd(self.day),
+      to_bcd(self.hour),
+      to_bcd(self.minute),
+      to_bcd(self.second),
+    ]
+  }
+
+  fn decode(fields: &[u8]) -> Result<Self, PrintError> {
+    let mut v = [0u8; 7];
+    for (out, &b) in v.iter_mut().zip(fields) {
+      *out = from_bcd(b).ok_or_else(|| PrintError::Read(format!("Printer clock returned invalid BCD byte 0x{b:02x}.").into()))?;
+    }
+    let t = Self {
+      year: v[0] as u16 * 100 + v[1] as u16,
+      month: v[2],
+      day: v[3],
+      hour: v[4],
+      minute: v[5],
+      second: v[6],
+    };
+    let valid = (1..=12).contains(&t.month)
+      && (1..=days_in_month(t.year as i64, t.month)).contains(&t.day)
+      && t.hour < 24
+      && t.minute < 60
+      && t.second < 60;
+    if !valid {
+      return Err(PrintError::Read(format!("Printer clock returned an impossible date/time: {t:?}.").into()));
+    }
+    Ok(t)
+  }
+}
+
+fn to_bcd(v: u8) -> u8 {
+  ((v / 10) << 4) | (v % 10)
+}
+
+fn from_bcd(b: u8) -> Option<u8> {
+  let (hi, lo) = (b >> 4, b & 0x0f);
+  (hi < 10 && lo < 10).then_some(hi * 10 + lo)
+}
+
+/// Days since 1970-01-01 to (year, month, day), proleptic Gregorian.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u8, u8) {
+  let z = days + 719_468;
+  let era = z.div_euclid(146_097);
+  let doe = z.rem_euclid(146_097);
+  let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+  let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+  let year = yoe + era * 400 + (month <= 2) as i64;
+  (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+  let y = if month <= 2 { year - 1 } else { year };
+  let era = y.div_euclid(400);
+  let yoe = y.rem_euclid(400);
+  let m = month as i64;
+  let mp = if m > 2 { m - 3 } else { m + 9 };
+  let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146_097 + doe - 719_468
+}
+
+fn days_in_month(year: i64, month: u8) -> u8 {
+  match month {
+    2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+    2 => 28,
+    4 | 6 | 9 | 11 => 30,
+    _ => 31,
+  }
+}
+
+pub fn set_command(time: ClockTime) -> Vec<u8> {
+  let mut out = vec![GS, b'(', b'T', 8, 0, FN_SET];
+  out.extend_from_slice(&time.encode());
+  out
+}
+
+pub fn get_command() -> [u8; 7] {
+  [GS, b'(', b'T', 2, 0, FN_GET, 0]
+}
+
+/// Reads the printer clock. `utc_offset_minutes` is the offset of the
+/// printer's wall clock from UTC, used to turn it back into a Unix timestamp.
+pub fn read_clock(io: &mut dyn Duplex, label: &str, utc_offset_minutes: i32, timeout: Duration) -> Result<i64, PrintError> {
+  io.write_all(&get_command())
+    .and_then(|_| io.flush())
+    .map_err(|e| PrintError::Write(format!("Clock request to {label} failed: {e}.").into()))?;
+
+  let reply = read_reply(io, label, timeout)?;
+  let time = ClockTime::decode(&reply[REPLY_HEADER.len()..REPLY_HEADER.len() + 7])?;
+  Ok(time.to_unix() - utc_offset_minutes as i64 * 60)
+}
+
+/// Sets the printer clock to `unix_ts` shifted into the printer's wall-clock offset.
+pub fn write_clock(io: &mut dyn Duplex, label: &str, unix_ts: i64, utc_offset_minutes: i32) -> Result<(), PrintError> {
+  let time = ClockTime::from_unix(unix_ts + utc_offset_minutes as i64 * 60)?;
+  io.write_all(&set_command(time))
+    .and_then(|_| io.flush())
+    .map_err(|e| PrintError::Write(format!("Setting the clock on {label} failed: {e}.").into()))
+}
+
+fn read_reply(io: &mut dyn Duplex, label: &str, timeout: Duration) -> Result<[u8; REPLY_LEN], PrintError> {
+  let deadline = Instant::now() + timeout;
+  let mut reply = [0u8; REPLY_LEN];
+  let mut filled = 0;
+  let mut byte = [0u8; 1];
+
+  while filled < REPLY_LEN {
+    if Instant::now() >= deadline {
+      return Err(PrintError::Unsupported(format!(
+        "{label} did not answer the clock request within {} ms; it likely has no real-time clock.",
+        timeout.as_millis()
+      )));
+    }
+    match io.read(&mut byte) {
+      Ok(0) => return Err(PrintError::Read(format!("{label} closed the connection during the clock request.").into())),
+      Ok(_) => {
+        // Resynchronize on the header so stray status bytes are skipped.
+        if filled < REPLY_HEADER.len() && byte[0] != REPLY_HEADER[filled] {
+          filled = usize::from(byte[0] == REPLY_HEADER[0]);
+          reply[0] = byte[0];
+          continue;
+        }
+        reply[filled] = byte[0];
+        filled += 1;
+      }
+      Err(e)
+        if matches!(
+          e.kind(),
+          std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted
+        ) => {}
+      Err(e) => return Err(PrintError::Read(format!("Clock read from {label} failed: {e}.").into())),
+    }
+  }
+
+  if reply[REPLY_LEN - 1] != 0 {
+    return Err(PrintError::Read(format!("{label} sent a malformed clock reply.").into()));
+  }
+  Ok(reply)
+}
@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::{codes, ErrorDetail, PrintError};
+
+/// Stable ids start with this, which no OS port name does.
+const STABLE_ID_PREFIX: &str = "usb:";
+
+/// Baud rates receipt printers commonly ship with or can be set to.
+pub const STANDARD_BAUD_RATES: [u32; 8] = [1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200];
+
+/// Serial settings the backend accepts, for the configuration UI. Ports are
+/// always opened 8N1 without flow control, so those lists have one entry.
+#[derive(Clone, Debug, Serialize)]
+pub struct SerialConfigOptions {
+  pub baud_rates: Vec<u32>,
+  pub default_baud: u32,
+  pub data_bits: Vec<u8>,
+  pub parity: Vec<&'static str>,
+  pub stop_bits: Vec<u8>,
+  pub flow_control: Vec<&'static str>,
+}
+
+pub fn config_options() -> SerialConfigOptions {
+  SerialConfigOptions {
+    baud_rates: STANDARD_BAUD_RATES.to_vec(),
+    default_baud: 9600,
+    data_bits: vec![8],
+    parity: vec!["none"],
+    stop_bits: vec![1],
+    flow_control: vec!["none"],
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SerialPortKind {
+  Usb,
+  Bluetooth,
+  Pci,
+  Unknown,
+}
+
+impl SerialPortKind {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      SerialPortKind::Usb => "usb",
+      SerialPortKind::Bluetooth => "bluetooth",
+      SerialPortKind::Pci => "pci",
+      SerialPortKind::Unknown => "unknown",
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SerialPortInfo {
+  pub port_name: String,
+  pub kind: SerialPortKind,
+  pub manufacturer: Option<String>,
+  pub product: Option<String>,
+  pub serial_number: Option<String>,
+  pub vid: Option<u16>,
+  pub pid: Option<u16>,
+  /// Where the device sits on the bus, e.g. Windows' location path
+  /// `PCIROOT(0)#PCI(1400)#USBROOT(0)#USB(3)`; the same while it stays
+  /// plugged into the same socket.
+  pub location: Option<String>,
+  /// Names this USB device whichever port name it gets, for
+  /// `Target::Serial`'s `port`; see [`resolve_port`].
+  pub stable_id: Option<String>,
+  /// USB interface number, which tells the ports of a multi-port adapter
+  /// apart.
+  pub interface: Option<u8>,
+  /// The interface's own name, when the device reports one.
+  pub interface_name: Option<String>,
+  /// Kernel or Windows driver serving the port, e.g. `usbser`, `FTSER2K`
+  /// or `cdc_acm`.
+  pub driver: Option<String>,
+}
+
+/// What the OS knows about a port beyond what `serialport` reports.
+#[derive(Default)]
+struct DeviceDetails {
+  location: Option<String>,
+  interface_name: Option<String>,
+  driver: Option<String>,
+}
+
+impl SerialPortInfo {
+  /// Whether `port`, a port name or stable id, refers to this port.
+  pub fn matches(&self, port: &str) -> bool {
+    self.port_name.eq_ignore_ascii_case(port) || self.stable_id.as_deref() == Some(port)
+  }
+}
+
+/// Lists serial ports sorted by name.
+pub fn list_ports() -> Result<Vec<SerialPortInfo>, PrintError> {
+  let mut ports = serialport::available_ports().map_err(|e| {
+    PrintError::Enumerate(format!(
+      "Unable to list serial ports: {e}. Confirm USB/Bluetooth serial drivers are installed."
+    ))
+  })?;
+  ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+  let mut details = device::details();
+
+  let mut out = ports
+    .into_iter()
+    .map(|p| {
+      let mut info = SerialPortInfo {
+        port_name: p.port_name,
+        kind: SerialPortKind::Unknown,
+        manufacturer: None,
+        product: None,
+        serial_number: None,
+        vid: None,
+        pid: None,
+        location: None,
+        stable_id: None,
+        interface: None,
+        interface_name: None,
+        driver: None,
+      };
+      let found = details.remove(&info.port_name).unwrap_or_default();
+      info.location = found.location;
+      info.interface_name = found.interface_name;
+      info.driver = found.driver;
+
+      match p.port_type {
+        serialport::SerialPortType::UsbPort(usb) => {
+          info.kind = SerialPortKind::Usb;
+          info.manufacturer = usb.manufacturer;
+          info.product = usb.product;
+          info.serial_number = usb.serial_number;
+          info.vid = Some(usb.vid);
+          info.pid = Some(usb.pid);
+          info.interface = usb.interface;
+          info.stable_id = stable_id(usb.vid, usb.pid, info.serial_number.as_deref(), info.location.as_deref());
+        }
+        serialport::SerialPortType::BluetoothPort => {
+          info.kind = SerialPortKind::Bluetooth;
+        }
+        serialport::SerialPortType::PciPort => {
+          info.kind = SerialPortKind::Pci;
+        }
+        serialport::SerialPortType::Unknown => {}
+      }
+
+      info
+    })
+    .collect::<Vec<_>>();
+
+  // Some adapters give every port, or every unit, the same serial number;
+  // those are told apart by where they're plugged in instead.
+  let mut seen = HashMap::<String, usize>::new();
+  for id in out.iter().filter_map(|p| p.stable_id.clone()) {
+    *seen.entry(id).or_default() += 1;
+  }
+  for port in &mut out {
+    if port.stable_id.as_ref().is_some_and(|id| seen[id] > 1) {
+      port.stable_id = match (port.vid, port.pid) {
+        (Some(vid), Some(pid)) => stable_id(vid, pid, None, port.location.as_deref()),
+        _ => None,
+      };
+    }
+  }
+
+  Ok(out)
+}
+
+/// `usb:VVVV:PPPP:SERIAL` from the USB serial number, or, without one,
+/// `usb:VVVV:PPPP@HASH` from the bus location, so two identical printers
+/// without serial numbers still get different ids.
+fn stable_id(vid: u16, pid: u16, serial_number: Option<&str>, location: Option<&str>) -> Option<String> {
+  match serial_number.map(str::trim).filter(|s| !s.is_empty()) {
+    Some(serial_number) => Some(format!("{STABLE_ID_PREFIX}{vid:04x}:{pid:04x}:{serial_number}")),
+    None => {
+      let digest = Sha256::digest(location?.as_bytes());
+      let hash = digest[..4].iter().map(|b| format!("{b:02x}")).collect::<String>();
+      Some(format!("{STABLE_ID_PREFIX}{vid:04x}:{pid:04x}@{hash}"))
+    }
+  }
+}
+
+pub fn is_stable_id(port: &str) -> bool {
+  port.starts_with(STABLE_ID_PREFIX)
+}
+
+/// The port name to open for `port`: itself, or for a stable id, the name
+/// the device has right now.
+pub fn resolve_port(port: &str) -> Result<String, PrintError> {
+  if !is_stable_id(port) {
+    return Ok(port.to_string());
+  }
+  list_ports()?
+    .into_iter()
+    .find(|p| p.stable_id.as_deref() == Some(port))
+    .map(|p| p.port_name)
+    .ok_or_else(|| {
+      PrintError::SerialOpen(
+        ErrorDetail::new(format!(
+          "The USB printer {port} is not currently connected. Plug it in, check its cable, and try again."
+        ))
+        .reason(codes::SERIAL_DEVICE_NOT_CONNECTED)
+        .param("port", port),
+      )
+    })
+}
+
+/// Device details by port name.
+#[cfg(target_os = "windows")]
+mod device {
+  use std::collections::HashMap;
+  use std::mem::size_of;
+  use std::ptr::{null, null_mut};
+
+  use windows_sys::Win32::Devices::DeviceAndDriverInstallation::{
+    SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInfo, SetupDiGetClassDevsW, SetupDiGetDevicePropertyW,
+    SetupDiGetDeviceRegistryPropertyW, SetupDiOpenDevRegKey, DICS_FLAG_GLOBAL, DIGCF_PRESENT, DIREG_DEV,
+    GUID_DEVCLASS_PORTS, HDEVINFO, SPDRP_LOCATION_INFORMATION, SPDRP_LOCATION_PATHS, SPDRP_SERVICE, SP_DEVINFO_DATA,
+  };
+  use windows_sys::Win32::Devices::Properties::DEVPKEY_Device_BusReportedDeviceDesc;
+  use windows_sys::Win32::System::Registry::{RegCloseKey, RegQueryValueExW, KEY_READ};
+
+  use super::DeviceDetails;
+
+  /// From the Ports device class, by each device's `PortName`: its location
+  /// path, or the hub port (`Port_#0002.Hub_#0004`) for drivers that don't
+  /// report a path; the interface name the device reports; and the driver
+  /// service.
+  pub fn details() -> HashMap<String, DeviceDetails> {
+    let mut out = HashMap::new();
+    unsafe {
+      let devs = SetupDiGetClassDevsW(&GUID_DEVCLASS_PORTS, null(), null_mut(), DIGCF_PRESENT);
+      if devs as isize == -1 {
+        return out;
+      }
+      for index in 0.. {
+        let mut data: SP_DEVINFO_DATA = std::mem::zeroed();
+        data.cbSize = size_of::<SP_DEVINFO_DATA>() as u32;
+        if SetupDiEnumDeviceInfo(devs, index, &mut data) == 0 {
+          break;
+        }
+        let Some(port) = port_name(devs, &data) else {
+          continue;
+        };
+        let details = DeviceDetails {
+          location: property(devs, &data, SPDRP_LOCATION_PATHS)
+            .or_else(|| property(devs, &data, SPDRP_LOCATION_INFORMATION)),
+          interface_name: bus_description(devs, &data),
+          driver: property(devs, &data, SPDRP_SERVICE),
+        };
+        out.insert(port, details);
+      }
+      SetupDiDestroyDeviceInfoList(devs);
+    }
+    out
+  }
+
+  unsafe fn port_name(devs: HDEVINFO, data: &SP_DEVINFO_DATA) -> Option<String> {
+    let key = SetupDiOpenDevRegKey(devs, data, DICS_FLAG_GLOBAL, 0, DIREG_DEV, KEY_READ);
+    if key as isize == -1 {
+      return None;
+    }
+    let value = "PortName\0".encode_utf16().collect::<Vec<_>>();
+    let mut buf = [0u16; 64];
+    let mut len = (buf.len() * 2) as u32;
+    let status = RegQueryValueExW(key, value.as_ptr(), null(), null_mut(), buf.as_mut_ptr() as *mut u8, &mut len);
+    RegCloseKey(key);
+    if status != 0 {
+      return None;
+    }
+    first_string(&buf[..(len as usize / 2).min(buf.len())])
+  }
+
+  unsafe fn property(devs: HDEVINFO, data: &SP_DEVINFO_DATA, property: u32) -> Option<String> {
+    let mut buf = [0u16; 512];
+    let got = SetupDiGetDeviceRegistryPropertyW(
+      devs,
+      data,
+      property,
+      null_mut(),
+      buf.as_mut_ptr() as *mut u8,
+      (buf.len() * 2) as u32,
+      null_mut(),
+    );
+    if got == 0 {
+      return None;
+    }
+    first_string(&buf)
+  }
+
+  /// The USB string descriptor the device gave for this function.
+  unsafe fn bus_description(devs: HDEVINFO, data: &SP_DEVINFO_DATA) -> Option<String> {
+    let mut buf = [0u16; 256];
+    let mut kind = 0u32;
+    let got = SetupDiGetDevicePropertyW(
+      devs,
+      data,
+      &DEVPKEY_Device_BusReportedDeviceDesc,
+      &mut kind,
+      buf.as_mut_ptr() as *mut u8,
+      (buf.len() * 2) as u32,
+      null_mut(),
+      0,
+    );
+    if got == 0 {
+      return None;
+    }
+    first_string(&buf)
+  }
+
+  /// The first string of a REG_SZ or REG_MULTI_SZ value.
+  fn first_string(buf: &[u16]) -> Option<String> {
+    let first = buf.split(|&c| c == 0).next()?;
+    (!first.is_empty()).then(|| String::from_utf16_lossy(first))
+  }
+}
+
+/// Device details by port name, from sysfs. The location is where the
+/// tty's device sits, e.g. `pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0`.
+#[cfg(target_os = "linux")]
+mod device {
+  use std::collections::HashMap;
+  use std::fs;
+  use std::path::Path;
+
+  use super::DeviceDetails;
+
+  pub fn details() -> HashMap<String, DeviceDetails> {
+    let Ok(entries) = fs::read_dir("/sys/class/tty") else {
+      return HashMap::new();
+    };
+    entries
+      .flatten()
+      .filter_map(|entry| {
+        let device = fs::canonicalize(entry.path().join("device")).ok()?;
+        let details = DeviceDetails {
+          location: Some(device.strip_prefix("/sys/devices").ok()?.to_string_lossy().into_owned()),
+          // USB-serial ttys sit one level below their interface, ACM ones
+          // on it.
+          interface_name: read_trimmed(&device.join("interface"))
+            .or_else(|| read_trimmed(&device.parent()?.join("interface"))),
+          driver: fs::read_link(device.join("driver"))
+            .ok()
+            .and_then(|d| d.file_name().map(|n| n.to_string_lossy().into_owned())),
+        };
+        Some((format!("/dev/{}", entry.file_name().to_string_lossy()), details))
+      })
+      .collect()
+  }
+
+  fn read_trimmed(path: &Path) -> Option<String> {
+    let text = fs::read_to_string(path).ok()?;
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+  }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+mod device {
+  use std::collections::HashMap;
+
+  use super::DeviceDetails;
+
+  pub fn details() -> HashMap<String, DeviceDetails> {
+    HashMap::new()
+  }
+}
+
+/// Whether `port_name` can be opened right now: opens it without raising
+/// DTR and closes it again. A port another program holds fails. Opening
+/// can still disturb some devices, so only do this when asked.
+pub fn probe(port_name: &str) -> bool {
+  serialport::new(port_name, 9600)
+    .timeout(std::time::Duration::from_millis(100))
+    .dtr_on_open(false)
+    .open()
+    .is_ok()
+}
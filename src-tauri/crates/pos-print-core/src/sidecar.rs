@@ -0,0 +1,122 @@
+//! Reconciliation trail linking printed receipts to their sale records.
+//!
+//! When a job carries `record_sidecar`, its metadata is appended once the
+//! job prints to `<dir>/receipts-<YYYY-MM-DD>.jsonl` (UTC day of the
+//! print), one JSON object per line. Files are never rewritten; each day
+//! starts a new one. Write failures are logged and never fail the print.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::audit::now_ms;
+use crate::rtc::civil_from_days;
+
+/// What to record about a receipt, e.g.
+/// `{ "receipt_id": "A-1042", "total": 12.5, "till": 3 }`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReceiptMeta {
+  pub receipt_id: String,
+  /// Any other fields, recorded as given.
+  #[serde(flatten)]
+  pub fields: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+struct SidecarRecord<'a> {
+  receipt_id: &'a str,
+  job_id: &'a str,
+  destination: &'a str,
+  printed_at_ms: u64,
+  #[serde(flatten)]
+  fields: &'a Map<String, Value>,
+}
+
+#[derive(Clone, Default)]
+pub struct SidecarLog {
+  dir: Option<PathBuf>,
+  lock: Arc<Mutex<()>>,
+}
+
+impl SidecarLog {
+  pub fn open(dir: PathBuf) -> Self {
+    if let Err(e) = fs::create_dir_all(&dir) {
+      log::warn!("receipt sidecars disabled, unable to create {}: {e}", dir.display());
+      return Self::default();
+    }
+    Self {
+      dir: Some(dir),
+      lock: Arc::default(),
+    }
+  }
+
+  /// Appends `meta` for job `job_id`, which printed to `destination`.
+  pub fn append(&self, meta: &ReceiptMeta, job_id: &str, destination: &str) {
+    let Some(dir) = &self.dir else {
+      return;
+    };
+    let printed_at_ms = now_ms();
+    // The record's own fields win over same-named ones in `meta`.
+    let mut fields = meta.fields.clone();
+    for key in ["receipt_id", "job_id", "destination", "printed_at_ms"] {
+      fields.remove(key);
+    }
+    let record = SidecarRecord {
+      receipt_id: &meta.receipt_id,
+      job_id,
+      destination,
+      printed_at_ms,
+      fields: &fields,
+    };
+    let mut line = match serde_json::to_vec(&record) {
+      Ok(line) => line,
+      Err(e) => {
+        log::warn!("unable to serialize receipt sidecar for {}: {e}", meta.receipt_id);
+        return;
+      }
+    };
+    line.push(b'\n');
+
+    let (year, month, day) = civil_from_days((printed_at_ms / 86_400_000) as i64);
+    let path = dir.join(format!("receipts-{year:04}-{month:02}-{day:02}.jsonl"));
+    let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+    let written = OpenOptions::new()
+      .create(true)
+      .read(true)
+      .append(true)
+      .open(&path)
+      .and_then(|mut f| {
+        // A crash mid-write can leave a partial last line; start on a fresh
+        // one so this record stays parseable.
+        if !ends_with_newline(&mut f)? {
+          line.insert(0, b'\n');
+        }
+        // One write per record, synced, so a reader never sees half of it
+        // and a printed receipt's record survives a power cut.
+        f.write_all(&line)?;
+        f.sync_data()
+      });
+    if let Err(e) = written {
+      log::warn!(
+        "unable to record receipt sidecar for {} in {}: {e}",
+        meta.receipt_id,
+        path.display()
+      );
+    }
+  }
+}
+
+/// Whether `f` is empty or ends with a newline.
+fn ends_with_newline(f: &mut File) -> std::io::Result<bool> {
+  if f.metadata()?.len() == 0 {
+    return Ok(true);
+  }
+  let mut last = [0u8; 1];
+  f.seek(SeekFrom::End(-1))?;
+  f.read_exact(&mut last)?;
+  Ok(last[0] == b'\n')
+}
@@ -0,0 +1,498 @@
+//! Printer status over SNMP v2c, for managed network printers that publish
+//! the standard Host Resources and Printer MIBs. Unlike DLE EOT this works
+//! the same across firmware and reports consumable levels.
+//!
+//! Only the handful of BER types these MIBs use are understood. Printers
+//! without the Printer MIB report no supplies or inputs rather than failing.
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::audit::now_ms;
+use crate::config::PrintConfig;
+use crate::error::PrintError;
+use crate::resolve;
+
+const SNMP_PORT: u16 = 161;
+const VERSION_2C: i64 = 1;
+/// Requests sent before giving up on a host; UDP may drop one.
+const ATTEMPTS: u32 = 2;
+/// Rows read from a table, so a misbehaving agent can't be walked forever.
+const MAX_ROWS: usize = 64;
+const MAX_DATAGRAM: usize = 65_507;
+
+// BER tags.
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_ID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIME_TICKS: u8 = 0x43;
+const COUNTER64: u8 = 0x46;
+const END_OF_MIB_VIEW: u8 = 0x82;
+const GET_NEXT_REQUEST: u8 = 0xa1;
+const RESPONSE: u8 = 0xa2;
+/// error-status a v1-style agent answers at the end of its MIB.
+const NO_SUCH_NAME: i64 = 2;
+
+// HOST-RESOURCES-MIB hrPrinterStatus and hrPrinterDetectedErrorState.
+const HR_PRINTER_STATUS: &[u32] = &[1, 3, 6, 1, 2, 1, 25, 3, 5, 1, 1];
+const HR_PRINTER_ERRORS: &[u32] = &[1, 3, 6, 1, 2, 1, 25, 3, 5, 1, 2];
+// Printer-MIB prtMarkerSuppliesEntry columns.
+const SUPPLY_DESCRIPTION: &[u32] = &[1, 3, 6, 1, 2, 1, 43, 11, 1, 1, 6];
+const SUPPLY_MAX_CAPACITY: &[u32] = &[1, 3, 6, 1, 2, 1, 43, 11, 1, 1, 8];
+const SUPPLY_LEVEL: &[u32] = &[1, 3, 6, 1, 2, 1, 43, 11, 1, 1, 9];
+// Printer-MIB prtInputEntry columns.
+const INPUT_MAX_CAPACITY: &[u32] = &[1, 3, 6, 1, 2, 1, 43, 8, 2, 1, 9];
+const INPUT_LEVEL: &[u32] = &[1, 3, 6, 1, 2, 1, 43, 8, 2, 1, 10];
+const INPUT_NAME: &[u32] = &[1, 3, 6, 1, 2, 1, 43, 8, 2, 1, 13];
+const INPUT_DESCRIPTION: &[u32] = &[1, 3, 6, 1, 2, 1, 43, 8, 2, 1, 18];
+
+/// hrPrinterDetectedErrorState bits, from the first byte's high bit.
+const ERROR_BITS: &[&str] = &[
+  "low_paper",
+  "no_paper",
+  "low_toner",
+  "no_toner",
+  "door_open",
+  "jammed",
+  "offline",
+  "service_requested",
+  "input_tray_missing",
+  "output_tray_missing",
+  "marker_supply_missing",
+  "output_near_full",
+  "output_full",
+  "input_tray_empty",
+  "overdue_preventive_maintenance",
+];
+
+/// hrPrinterStatus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceStatus {
+  Other,
+  Unknown,
+  Idle,
+  Printing,
+  Warmup,
+}
+
+impl DeviceStatus {
+  fn from_code(code: i64) -> Self {
+    match code {
+      1 => Self::Other,
+      3 => Self::Idle,
+      4 => Self::Printing,
+      5 => Self::Warmup,
+      _ => Self::Unknown,
+    }
+  }
+}
+
+/// A supply or input tray and how much of it is left.
+#[derive(Clone, Debug, Serialize)]
+pub struct Consumable {
+  pub description: String,
+  /// In the printer's own units; `None` when it doesn't say.
+  pub level: Option<i64>,
+  pub max_capacity: Option<i64>,
+  /// Level as a share of capacity, when both are known.
+  pub percent: Option<u8>,
+  /// The printer only reports that some is left, not how much.
+  pub some_remaining: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SnmpStatus {
+  pub host: String,
+  pub status: DeviceStatus,
+  /// Conditions from hrPrinterDetectedErrorState, e.g. `no_paper`.
+  pub errors: Vec<String>,
+  /// Toner, ink, ribbons and other marker supplies.
+  pub supplies: Vec<Consumable>,
+  /// Paper trays and rolls.
+  pub inputs: Vec<Consumable>,
+}
+
+/// Reads `host`'s device status, error conditions and consumable levels
+/// with SNMP community `community`.
+pub fn printer_status(host: &str, community: &str, cfg: &PrintConfig) -> Result<SnmpStatus, PrintError> {
+  let resolved = resolve::resolve(host, SNMP_PORT, Duration::from_millis(cfg.dns_timeout_ms))?;
+  if let Some(warning) = &resolved.warning {
+    log::warn!("{warning}");
+  }
+  let mut session = Session::open(host, resolved.addr, community, Duration::from_millis(cfg.status_timeout_ms))?;
+
+  let mut status = DeviceStatus::Unknown;
+  let mut errors = Vec::new();
+  for (oid, value) in session.get_next(&[HR_PRINTER_STATUS, HR_PRINTER_ERRORS])? {
+    match value {
+      Value::Int(code) if oid.starts_with(HR_PRINTER_STATUS) => status = DeviceStatus::from_code(code),
+      Value::Bytes(bits) if oid.starts_with(HR_PRINTER_ERRORS) => errors = error_names(&bits),
+      _ => {}
+    }
+  }
+
+  let supplies = consumables(&mut session, &[SUPPLY_DESCRIPTION], SUPPLY_MAX_CAPACITY, SUPPLY_LEVEL)?;
+  let inputs = consumables(
+    &mut session,
+    &[INPUT_DESCRIPTION, INPUT_NAME],
+    INPUT_MAX_CAPACITY,
+    INPUT_LEVEL,
+  )?;
+  Ok(SnmpStatus {
+    host: host.to_string(),
+    status,
+    errors,
+    supplies,
+    inputs,
+  })
+}
+
+/// Joins a table's level, capacity and the first non-empty of its
+/// `descriptions` columns by row.
+fn consumables(
+  session: &mut Session,
+  descriptions: &[&[u32]],
+  max_capacity: &[u32],
+  level: &[u32],
+) -> Result<Vec<Consumable>, PrintError> {
+  let levels = session.walk(level)?;
+  if levels.is_empty() {
+    return Ok(Vec::new());
+  }
+  let capacities = session.walk(max_capacity)?;
+  let mut names = BTreeMap::new();
+  for column in descriptions {
+    for (index, value) in session.walk(column)? {
+      if let Value::Bytes(bytes) = value {
+        let text = String::from_utf8_lossy(&bytes).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string();
+        if !text.is_empty() {
+          names.entry(index).or_insert(text);
+        }
+      }
+    }
+  }
+
+  Ok(
+    levels
+      .into_iter()
+      .map(|(index, value)| {
+        let level = value.int();
+        // Printer-MIB: -1 other, -2 unknown, -3 "some remaining".
+        let max_capacity = capacities.get(&index).and_then(Value::int).filter(|&m| m > 0);
+        let known = level.filter(|&l| l >= 0);
+        Consumable {
+          description: names.remove(&index).unwrap_or_else(|| format!("#{}", index_label(&index))),
+          level: known,
+          max_capacity,
+          percent: known.zip(max_capacity).map(|(l, m)| (l * 100 / m).clamp(0, 100) as u8),
+          some_remaining: level == Some(-3),
+        }
+      })
+      .collect(),
+  )
+}
+
+fn error_names(bits: &[u8]) -> Vec<String> {
+  ERROR_BITS
+    .iter()
+    .enumerate()
+    .filter(|(i, _)| bits.get(i / 8).is_some_and(|b| b & (0x80 >> (i % 8)) != 0))
+    .map(|(_, name)| name.to_string())
+    .collect()
+}
+
+fn index_label(index: &[u32]) -> String {
+  index.iter().map(u32::to_string).collect::<Vec<_>>().join(".")
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+  Int(i64),
+  Bytes(Vec<u8>),
+  EndOfMib,
+  /// noSuchObject, noSuchInstance and types this client doesn't read.
+  Other,
+}
+
+impl Value {
+  fn int(&self) -> Option<i64> {
+    match self {
+      Value::Int(n) => Some(*n),
+      _ => None,
+    }
+  }
+}
+
+struct Session<'a> {
+  socket: UdpSocket,
+  host: &'a str,
+  community: &'a str,
+  next_id: i32,
+  timeout: Duration,
+}
+
+impl<'a> Session<'a> {
+  fn open(host: &'a str, addr: SocketAddr, community: &'a str, timeout: Duration) -> Result<Self, PrintError> {
+    let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(local)
+      .and_then(|s| s.connect(addr).map(|()| s))
+      .map_err(|e| PrintError::Connect(format!("Unable to open an SNMP socket to {host}: {e}.").into()))?;
+    Ok(Self {
+      socket,
+      host,
+      community,
+      next_id: (now_ms() & 0x7fff) as i32,
+      timeout,
+    })
+  }
+
+  /// The variables following each of `oids`, resending once on silence.
+  fn get_next(&mut self, oids: &[&[u32]]) -> Result<Vec<(Vec<u32>, Value)>, PrintError> {
+    self.next_id = self.next_id.wrapping_add(1) & 0x7fff_ffff;
+    let id = i64::from(self.next_id);
+    let request = encode_request(GET_NEXT_REQUEST, id, self.community, oids);
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    for _ in 0..ATTEMPTS {
+      self
+        .socket
+        .send(&request)
+        .map_err(|e| PrintError::Write(format!("SNMP request to {} failed: {e}.", self.host).into()))?;
+      let deadline = Instant::now() + self.timeout;
+      loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+          break;
+        }
+        let _ = self.socket.set_read_timeout(Some(remaining));
+        let n = match self.socket.recv(&mut buf) {
+          Ok(n) => n,
+          Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+          Err(e) => {
+            return Err(PrintError::Read(
+              format!("Reading the SNMP reply from {} failed: {e}. Check SNMP is enabled on the printer.", self.host)
+                .into(),
+            ))
+          }
+        };
+        let Some(response) = decode_response(&buf[..n]) else {
+          return Err(PrintError::Read(format!("{} sent a malformed SNMP reply.", self.host).into()));
+        };
+        if response.id != id {
+          // A late reply to an earlier attempt.
+          continue;
+        }
+        return match response.error_status {
+          0 => Ok(response.bindings),
+          NO_SUCH_NAME => Ok(Vec::new()),
+          status => Err(PrintError::Read(
+            format!("{} refused the SNMP request (error-status {status}).", self.host).into(),
+          )),
+        };
+      }
+    }
+    Err(PrintError::Timeout(
+      format!(
+        "No SNMP reply from {} within {} ms. Check SNMP is enabled on the printer and the community name is right.",
+        self.host,
+        self.timeout.as_millis()
+      )
+      .into(),
+    ))
+  }
+
+  /// The rows of table column `column`, by index.
+  fn walk(&mut self, column: &[u32]) -> Result<BTreeMap<Vec<u32>, Value>, PrintError> {
+    let mut rows = BTreeMap::new();
+    let mut at = column.to_vec();
+    while rows.len() < MAX_ROWS {
+      let Some((oid, value)) = self.get_next(&[&at])?.into_iter().next() else {
+        break;
+      };
+      let Some(index) = oid.strip_prefix(column).filter(|i| !i.is_empty()) else {
+        break;
+      };
+      if matches!(value, Value::EndOfMib) {
+        break;
+      }
+      rows.insert(index.to_vec(), value);
+      at = oid;
+    }
+    Ok(rows)
+  }
+}
+
+fn push_tlv(tag: u8, value: &[u8], out: &mut Vec<u8>) {
+  out.push(tag);
+  if value.len() < 0x80 {
+    out.push(value.len() as u8);
+  } else {
+    let len = value.len().to_be_bytes();
+    let skip = len.iter().take_while(|&&b| b == 0).count();
+    out.push(0x80 | (len.len() - skip) as u8);
+    out.extend_from_slice(&len[skip..]);
+  }
+  out.extend_from_slice(value);
+}
+
+fn encode_int(n: i64) -> Vec<u8> {
+  let bytes = n.to_be_bytes();
+  let mut skip = 0;
+  // Drop leading bytes that only repeat the sign.
+  while skip < 7
+    && ((bytes[skip] == 0 && bytes[skip + 1] & 0x80 == 0) || (bytes[skip] == 0xff && bytes[skip + 1] & 0x80 != 0))
+  {
+    skip += 1;
+  }
+  bytes[skip..].to_vec()
+}
+
+fn encode_oid(arcs: &[u32]) -> Vec<u8> {
+  let mut out = Vec::new();
+  let first = arcs.first().copied().unwrap_or(0) * 40 + arcs.get(1).copied().unwrap_or(0);
+  for arc in std::iter::once(first).chain(arcs.iter().skip(2).copied()) {
+    let mut chunk = vec![(arc & 0x7f) as u8];
+    let mut rest = arc >> 7;
+    while rest > 0 {
+      chunk.push(0x80 | (rest & 0x7f) as u8);
+      rest >>= 7;
+    }
+    out.extend(chunk.iter().rev());
+  }
+  out
+}
+
+fn encode_request(pdu: u8, id: i64, community: &str, oids: &[&[u32]]) -> Vec<u8> {
+  let mut bindings = Vec::new();
+  for oid in oids {
+    let mut binding = Vec::new();
+    push_tlv(OBJECT_ID, &encode_oid(oid), &mut binding);
+    push_tlv(NULL, &[], &mut binding);
+    push_tlv(SEQUENCE, &binding, &mut bindings);
+  }
+  let mut body = Vec::new();
+  push_tlv(INTEGER, &encode_int(id), &mut body);
+  push_tlv(INTEGER, &[0], &mut body);
+  push_tlv(INTEGER, &[0], &mut body);
+  push_tlv(SEQUENCE, &bindings, &mut body);
+
+  let mut message = Vec::new();
+  push_tlv(INTEGER, &encode_int(VERSION_2C), &mut message);
+  push_tlv(OCTET_STRING, community.as_bytes(), &mut message);
+  push_tlv(pdu, &body, &mut message);
+  let mut out = Vec::new();
+  push_tlv(SEQUENCE, &message, &mut out);
+  out
+}
+
+struct Reader<'a> {
+  data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  fn tlv(&mut self) -> Option<(u8, &'a [u8])> {
+    let (&tag, rest) = self.data.split_first()?;
+    let (&first, mut rest) = rest.split_first()?;
+    let len = if first < 0x80 {
+      first as usize
+    } else {
+      let n = (first & 0x7f) as usize;
+      if n == 0 || n > 4 || rest.len() < n {
+        return None;
+      }
+      let len = rest[..n].iter().fold(0usize, |len, &b| len << 8 | b as usize);
+      rest = &rest[n..];
+      len
+    };
+    if rest.len() < len {
+      return None;
+    }
+    let (value, rest) = rest.split_at(len);
+    self.data = rest;
+    Some((tag, value))
+  }
+
+  fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+    let (found, value) = self.tlv()?;
+    (found == tag).then_some(value)
+  }
+}
+
+fn decode_int(bytes: &[u8], signed: bool) -> Option<i64> {
+  if bytes.is_empty() || bytes.len() > 9 {
+    return None;
+  }
+  let negative = signed && bytes[0] & 0x80 != 0;
+  Some(bytes.iter().fold(if negative { -1 } else { 0 }, |n: i64, &b| n << 8 | i64::from(b)))
+}
+
+fn decode_oid(bytes: &[u8]) -> Option<Vec<u32>> {
+  let mut subids = Vec::new();
+  let mut value: u32 = 0;
+  for &b in bytes {
+    value = value.checked_mul(128)? | u32::from(b & 0x7f);
+    if b & 0x80 == 0 {
+      subids.push(value);
+      value = 0;
+    }
+  }
+  let (&first, rest) = subids.split_first()?;
+  let (a, b) = match first {
+    0..=39 => (0, first),
+    40..=79 => (1, first - 40),
+    _ => (2, first - 80),
+  };
+  Some([a, b].into_iter().chain(rest.iter().copied()).collect())
+}
+
+struct Response {
+  id: i64,
+  error_status: i64,
+  bindings: Vec<(Vec<u32>, Value)>,
+}
+
+fn decode_response(datagram: &[u8]) -> Option<Response> {
+  let mut message = Reader {
+    data: Reader { data: datagram }.expect(SEQUENCE)?,
+  };
+  message.expect(INTEGER)?;
+  message.expect(OCTET_STRING)?;
+  let mut pdu = Reader {
+    data: message.expect(RESPONSE)?,
+  };
+  let id = decode_int(pdu.expect(INTEGER)?, true)?;
+  let error_status = decode_int(pdu.expect(INTEGER)?, true)?;
+  pdu.expect(INTEGER)?;
+  let mut list = Reader {
+    data: pdu.expect(SEQUENCE)?,
+  };
+  let mut bindings = Vec::new();
+  while !list.data.is_empty() {
+    let mut binding = Reader {
+      data: list.expect(SEQUENCE)?,
+    };
+    let oid = decode_oid(binding.expect(OBJECT_ID)?)?;
+    let (tag, raw) = binding.tlv()?;
+    let value = match tag {
+      INTEGER => Value::Int(decode_int(raw, true)?),
+      COUNTER32 | GAUGE32 | TIME_TICKS | COUNTER64 => Value::Int(decode_int(raw, false)?),
+      OCTET_STRING => Value::Bytes(raw.to_vec()),
+      END_OF_MIB_VIEW => Value::EndOfMib,
+      _ => Value::Other,
+    };
+    bindings.push((oid, value));
+  }
+  Some(Response {
+    id,
+    error_status,
+    bindings,
+  })
+}
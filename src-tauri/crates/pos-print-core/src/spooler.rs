@@ -0,0 +1,1030 @@
+//! Raw (RAW datatype) printing through the Windows print spooler.
+//!
+//! Shared printers are named by UNC path, `\\server\share`. Windows only
+//! opens one once the current user has a connection to it, so printing to
+//! a UNC name that isn't connected yet connects it first.
+//!
+//! Non-Windows builds get stubs: listing returns no printers and printing
+//! fails with `PrintError::Unsupported`.
+
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::config::PrintConfig;
+use crate::error::PrintError;
+
+/// `DEVMODEW` offsets: 32 UTF-16 device-name chars, then dmSpecVersion,
+/// dmDriverVersion, dmSize and dmDriverExtra as little-endian u16s.
+const DM_SIZE_OFFSET: usize = 68;
+const DM_DRIVER_EXTRA_OFFSET: usize = 70;
+/// Smallest public part any driver reports (the pre-Windows 2000 layout).
+const DM_MIN_SIZE: usize = 156;
+
+/// Checks that `devmode` is a whole `DEVMODEW` as exported from a driver:
+/// its length must equal `dmSize + dmDriverExtra`.
+pub fn check_devmode(devmode: &[u8]) -> Result<(), PrintError> {
+  if devmode.len() < DM_DRIVER_EXTRA_OFFSET + 2 {
+    return Err(PrintError::InvalidRequest(format!(
+      "DEVMODE is {} bytes, too short to be a DEVMODEW structure.",
+      devmode.len()
+    )));
+  }
+  let field = |at: usize| u16::from_le_bytes([devmode[at], devmode[at + 1]]) as usize;
+  let (size, extra) = (field(DM_SIZE_OFFSET), field(DM_DRIVER_EXTRA_OFFSET));
+  if size < DM_MIN_SIZE || size + extra != devmode.len() {
+    return Err(PrintError::InvalidRequest(format!(
+      "DEVMODE has dmSize {size} and dmDriverExtra {extra} but is {} bytes. Export it as a wide (DEVMODEW) structure including the driver-private part.",
+      devmode.len()
+    )));
+  }
+  Ok(())
+}
+
+/// Whether `name` is a shared printer's UNC path, `\\server\share`.
+pub fn is_unc(name: &str) -> bool {
+  name
+    .strip_prefix(r"\\")
+    .and_then(|rest| rest.split_once('\\'))
+    .is_some_and(|(server, share)| !server.is_empty() && !share.is_empty() && !share.contains('\\'))
+}
+
+fn check_unc(unc_path: &str) -> Result<(), PrintError> {
+  if is_unc(unc_path) {
+    Ok(())
+  } else {
+    Err(PrintError::InvalidRequest(format!(
+      "'{unc_path}' is not a shared printer path; use the form \\\\server\\printer."
+    )))
+  }
+}
+
+/// A local or connected Windows queue.
+#[derive(Clone, Debug, Serialize)]
+pub struct SpoolerPrinter {
+  pub name: String,
+  pub driver_name: String,
+  pub port_name: String,
+  /// Prints to a file or virtual device (PDF, XPS, OneNote, fax) by the
+  /// `virtual_drivers` and `virtual_ports` settings.
+  pub is_virtual: bool,
+  /// Set queue status flags, e.g. `paused`, `offline`, `paper_out`. Many
+  /// port monitors never set any, so empty doesn't prove the printer is up.
+  pub status: Vec<String>,
+  /// Jobs waiting in the queue.
+  pub jobs: u32,
+}
+
+/// One queue as the platform reports it.
+struct Queue {
+  name: String,
+  driver_name: String,
+  port_name: String,
+  status: u32,
+  jobs: u32,
+}
+
+/// PRINTER_INFO_2 status flags, by bit.
+const PRINTER_STATUS_NAMES: &[(u32, &str)] = &[
+  (0x0000_0001, "paused"),
+  (0x0000_0002, "error"),
+  (0x0000_0004, "pending_deletion"),
+  (0x0000_0008, "paper_jam"),
+  (0x0000_0010, "paper_out"),
+  (0x0000_0020, "manual_feed"),
+  (0x0000_0040, "paper_problem"),
+  (0x0000_0080, "offline"),
+  (0x0000_0100, "io_active"),
+  (0x0000_0200, "busy"),
+  (0x0000_0400, "printing"),
+  (0x0000_0800, "output_bin_full"),
+  (0x0000_1000, "not_available"),
+  (0x0000_2000, "waiting"),
+  (0x0000_4000, "processing"),
+  (0x0000_8000, "initializing"),
+  (0x0001_0000, "warming_up"),
+  (0x0010_0000, "user_intervention"),
+  (0x0040_0000, "door_open"),
+  (0x0100_0000, "power_save"),
+];
+
+/// Local and connected queues with their driver, port and status, sorted
+/// by name.
+pub fn list_printers_detailed(cfg: &PrintConfig) -> Result<Vec<SpoolerPrinter>, PrintError> {
+  Ok(
+    imp::list_queues()?
+      .into_iter()
+      .map(|q| SpoolerPrinter {
+        is_virtual: cfg.is_virtual_printer(&q.driver_name, &q.port_name),
+        name: q.name,
+        driver_name: q.driver_name,
+        port_name: q.port_name,
+        status: status_names(PRINTER_STATUS_NAMES, q.status),
+        jobs: q.jobs,
+      })
+      .collect(),
+  )
+}
+
+/// A job in a Windows print queue.
+#[derive(Clone, Debug, Serialize)]
+pub struct SpoolJob {
+  pub job_id: u32,
+  pub document: String,
+  /// Set status flags, e.g. `error`, `printing`, `restart`.
+  pub status: Vec<String>,
+  /// The port monitor's own status text, when it sets one.
+  pub status_text: Option<String>,
+  pub pages_printed: u32,
+  pub total_pages: u32,
+}
+
+/// JOB_INFO_1 status flags, by bit.
+#[cfg(target_os = "windows")]
+const JOB_STATUS_NAMES: &[(u32, &str)] = &[
+  (0x0001, "paused"),
+  (0x0002, "error"),
+  (0x0004, "deleting"),
+  (0x0008, "spooling"),
+  (0x0010, "printing"),
+  (0x0020, "offline"),
+  (0x0040, "paper_out"),
+  (0x0080, "printed"),
+  (0x0100, "deleted"),
+  (0x0200, "blocked"),
+  (0x0400, "user_intervention"),
+  (0x0800, "restart"),
+  (0x1000, "complete"),
+];
+
+fn status_names(table: &[(u32, &str)], status: u32) -> Vec<String> {
+  table
+    .iter()
+    .filter(|(bit, _)| status & bit != 0)
+    .map(|(_, name)| name.to_string())
+    .collect()
+}
+
+impl SpoolJob {
+  /// Whether any of it may already be on paper, so a restart could print
+  /// it twice.
+  pub fn started_printing(&self) -> bool {
+    self.pages_printed > 0 || self.status.iter().any(|s| s == "printing" || s == "printed")
+  }
+}
+
+/// Where a remote printer was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoteOrigin {
+  /// Shared by the print server that was asked.
+  Server,
+  /// Browsed from the network or the directory.
+  Network,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RemotePrinter {
+  /// UNC path, `\\server\printer`, as used to print and to connect.
+  pub name: String,
+  /// Usually `name,driver,location`.
+  pub description: Option<String>,
+  pub comment: Option<String>,
+  pub origin: RemoteOrigin,
+}
+
+/// Printers shared by print server `server` (`printserver` or
+/// `\\printserver`), or browsable on the network when `None`. Enumeration
+/// runs on a helper thread and is abandoned after `timeout`, since Windows
+/// can take a minute to give up on a server that doesn't answer.
+pub fn list_remote_printers(server: Option<&str>, timeout: Duration) -> Result<Vec<RemotePrinter>, PrintError> {
+  let server = match server.map(str::trim) {
+    Some(server) => {
+      let name = server.trim_start_matches('\\');
+      if name.is_empty() || name.contains('\\') {
+        return Err(PrintError::InvalidRequest(format!(
+          "'{server}' is not a print server name; give just the server, e.g. printserver."
+        )));
+      }
+      Some(format!("\\\\{name}"))
+    }
+    None => None,
+  };
+  let what = server.clone().unwrap_or_else(|| "the network".to_string());
+  let (tx, rx) = channel();
+  thread::Builder::new()
+    .name("print-enum-remote".to_string())
+    .spawn(move || {
+      let _ = tx.send(imp::list_remote(server.as_deref()));
+    })
+    .map_err(|e| PrintError::Task(format!("Unable to start printer enumeration: {e}.")))?;
+  rx.recv_timeout(timeout).unwrap_or_else(|_| {
+    Err(PrintError::Timeout(
+      format!(
+        "Listing printers on {what} took longer than {} s. Check the server name and that it is reachable.",
+        timeout.as_secs()
+      )
+      .into(),
+    ))
+  })
+}
+
+/// One printer's result when printing to several at once.
+#[derive(Clone, Debug, Serialize)]
+pub struct MultiPrintResult {
+  pub printer_name: String,
+  pub ok: bool,
+  pub error: Option<PrintError>,
+}
+
+/// Checks a multi-printer request: at least one printer, none listed
+/// twice, and a whole DEVMODE if one is given.
+pub fn check_multi(printer_names: &[String], devmode: Option<&[u8]>) -> Result<(), PrintError> {
+  if printer_names.is_empty() {
+    return Err(PrintError::InvalidRequest("Name at least one printer.".to_string()));
+  }
+  for (i, name) in printer_names.iter().enumerate() {
+    if printer_names[..i].iter().any(|n| n.eq_ignore_ascii_case(name)) {
+      return Err(PrintError::InvalidRequest(format!(
+        "'{name}' is listed twice; list each printer once and print again for more copies."
+      )));
+    }
+  }
+  if let Some(devmode) = devmode {
+    check_devmode(devmode)?;
+  }
+  Ok(())
+}
+
+/// What a Windows queue's driver is and can do, to tell receipt printers
+/// apart from PDF, fax and office printers.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DriverInfo {
+  pub printer_name: String,
+  pub driver_name: String,
+  /// `a.b.c.d` from the driver package.
+  pub driver_version: Option<String>,
+  pub manufacturer: Option<String>,
+  pub port_name: String,
+  pub default_datatype: Option<String>,
+  /// Driver model: 3 for classic drivers, 4 for v4 (XPS-based) drivers.
+  pub driver_model: u32,
+  /// A v4 class driver (e.g. Microsoft's in-box drivers), which usually
+  /// mangles raw ESC/POS.
+  pub v4_class_driver: bool,
+  /// The "Generic / Text Only" driver, which passes text straight through.
+  pub generic_text: bool,
+  /// Prints to a file or virtual device (PDF, XPS, OneNote, fax).
+  pub virtual_printer: bool,
+  pub paper_names: Vec<String>,
+  /// Whether raw ESC/POS through this queue is likely to reach the printer
+  /// unchanged.
+  pub raw_capable: bool,
+}
+
+#[cfg(target_os = "windows")]
+mod imp {
+  use std::ffi::c_void;
+  use std::ffi::OsStr;
+  use std::iter::once;
+  use std::os::windows::ffi::OsStrExt;
+  use std::ptr::null_mut;
+
+  use windows_sys::Win32::Foundation::{GetLastError, HANDLE};
+  use windows_sys::Win32::Graphics::Gdi::DEVMODEW;
+
+  use super::{DriverInfo, Queue, RemoteOrigin, RemotePrinter, SpoolJob};
+  use crate::cancel::CancelToken;
+  use crate::error::{codes, ErrorDetail, PrintError};
+  use windows_sys::Win32::Graphics::Printing::{
+    AddPrinterConnectionW, ClosePrinter, DeletePrinterConnectionW, DOC_INFO_1W, DRIVER_INFO_8W, EndDocPrinter,
+    EndPagePrinter, EnumPrintersW, GetJobW, GetPrinterDriverW, GetPrinterW, OpenPrinterW, SetJobW, JOB_CONTROL_DELETE,
+    JOB_CONTROL_RESTART, JOB_INFO_1W, PRINTER_ACCESS_USE, PRINTER_DEFAULTSW, PRINTER_ENUM_CONNECTIONS,
+    PRINTER_ENUM_LOCAL, PRINTER_ENUM_NAME, PRINTER_ENUM_NETWORK, PRINTER_ENUM_REMOTE, PRINTER_INFO_1W,
+    PRINTER_INFO_2W, PRINTER_INFO_4W, StartDocPrinterW, StartPagePrinter, WritePrinter,
+  };
+  use windows_sys::Win32::Storage::Xps::{DeviceCapabilitiesW, DC_PAPERNAMES};
+
+  // dwPrinterDriverAttributes flags.
+  const PRINTER_DRIVER_CLASS: u32 = 0x0000_0008;
+  const PRINTER_DRIVER_CATEGORY_FAX: u32 = 0x0000_0040;
+  const PRINTER_DRIVER_CATEGORY_FILE: u32 = 0x0000_0080;
+  const PRINTER_DRIVER_CATEGORY_VIRTUAL: u32 = 0x0000_0100;
+  /// PRINTER_INFO_1 flag of browse-list entries that hold printers.
+  const PRINTER_ENUM_CONTAINER: u32 = 0x0000_8000;
+  /// DC_PAPERNAMES entries are fixed 64-character slots.
+  const PAPER_NAME_CHARS: usize = 64;
+  /// Largest single WritePrinter call.
+  const WRITE_CHUNK: usize = 64 * 1024;
+  /// Consecutive WritePrinter calls that may accept nothing before a
+  /// short write is treated as a failure.
+  const SHORT_WRITE_RETRIES: u32 = 3;
+
+  const ERROR_ACCESS_DENIED: u32 = 5;
+  const ERROR_NOT_READY: u32 = 21;
+  const ERROR_BAD_NETPATH: u32 = 53;
+  const ERROR_BAD_NET_NAME: u32 = 67;
+  const ERROR_INVALID_PARAMETER: u32 = 87;
+  const ERROR_INVALID_PASSWORD: u32 = 86;
+  const ERROR_SESSION_CREDENTIAL_CONFLICT: u32 = 1219;
+  const ERROR_NOT_AUTHENTICATED: u32 = 1244;
+  const ERROR_LOGON_FAILURE: u32 = 1326;
+  const ERROR_PASSWORD_EXPIRED: u32 = 1330;
+  const ERROR_INVALID_PRINTER_NAME: u32 = 1801;
+  const ERROR_INVALID_DATATYPE: u32 = 1804;
+
+  /// A spooler error carrying the Win32 code, which must be read right
+  /// after the failing call, before any cleanup overwrites it.
+  fn spooler_error(code: u32, message: &str) -> PrintError {
+    let detail = ErrorDetail::new(format!("{message} [Win32 error {code}]")).param("os_error", code);
+    PrintError::Spooler(match code {
+      ERROR_ACCESS_DENIED => detail.reason(codes::SPOOLER_ACCESS_DENIED),
+      ERROR_NOT_READY => detail.reason(codes::SPOOLER_PRINTER_OFFLINE),
+      ERROR_INVALID_PRINTER_NAME | ERROR_BAD_NETPATH | ERROR_BAD_NET_NAME => {
+        detail.reason(codes::SPOOLER_PRINTER_NOT_FOUND)
+      }
+      ERROR_INVALID_DATATYPE => detail.reason(codes::SPOOLER_INVALID_DATATYPE),
+      code if needs_credentials(code) => detail.reason(codes::SPOOLER_CREDENTIALS_REQUIRED),
+      _ => detail,
+    })
+  }
+
+  fn with_written(error: PrintError, written: usize) -> PrintError {
+    match error {
+      PrintError::Spooler(detail) => PrintError::Spooler(detail.param("written", written)),
+      other => other,
+    }
+  }
+
+  fn needs_credentials(code: u32) -> bool {
+    matches!(
+      code,
+      ERROR_INVALID_PASSWORD
+        | ERROR_SESSION_CREDENTIAL_CONFLICT
+        | ERROR_NOT_AUTHENTICATED
+        | ERROR_LOGON_FAILURE
+        | ERROR_PASSWORD_EXPIRED
+    )
+  }
+
+  /// A failed `OpenPrinterW`, with advice for the likely cause.
+  fn open_error(code: u32, printer_name: &str) -> PrintError {
+    let message = if code == ERROR_ACCESS_DENIED {
+      format!("Access to printer '{printer_name}' was denied. Ask its administrator to allow this Windows user to print to it.")
+    } else if needs_credentials(code) {
+      format!("Printer '{printer_name}' needs a sign-in this Windows user doesn't have. Open the print server in Explorer once to sign in, then retry.")
+    } else {
+      format!("Failed to open printer '{printer_name}'. Verify exact printer name and driver installation.")
+    };
+    spooler_error(code, &message)
+  }
+
+  /// Connects the current user to shared printer `unc_path`, installing
+  /// its driver from the server if needed.
+  pub fn add_connection(unc_path: &str) -> Result<(), PrintError> {
+    super::check_unc(unc_path)?;
+    let mut name_w = to_wide(unc_path);
+    if unsafe { AddPrinterConnectionW(name_w.as_mut_ptr()) } == 0 {
+      let code = unsafe { GetLastError() };
+      let message = match code {
+        ERROR_ACCESS_DENIED => format!(
+          "Access to shared printer '{unc_path}' was denied. Ask the print server's administrator to allow this Windows user to use it."
+        ),
+        code if needs_credentials(code) => format!(
+          "Shared printer '{unc_path}' needs a sign-in this Windows user doesn't have. Open the print server in Explorer once to sign in, then retry."
+        ),
+        ERROR_INVALID_PRINTER_NAME | ERROR_BAD_NETPATH | ERROR_BAD_NET_NAME => format!(
+          "Shared printer '{unc_path}' was not found. Check the server and printer share names and that the server is reachable."
+        ),
+        _ => format!("Unable to connect to shared printer '{unc_path}'. Check the print server is reachable."),
+      };
+      return Err(spooler_error(code, &message));
+    }
+    log::info!("connected to shared printer '{unc_path}'");
+    Ok(())
+  }
+
+  /// Removes the current user's connection to shared printer `unc_path`.
+  pub fn remove_connection(unc_path: &str) -> Result<(), PrintError> {
+    super::check_unc(unc_path)?;
+    let mut name_w = to_wide(unc_path);
+    if unsafe { DeletePrinterConnectionW(name_w.as_mut_ptr()) } == 0 {
+      return Err(spooler_error(
+        unsafe { GetLastError() },
+        &format!("Unable to remove the connection to shared printer '{unc_path}'. It may not be connected."),
+      ));
+    }
+    log::info!("removed connection to shared printer '{unc_path}'");
+    Ok(())
+  }
+
+  fn to_wide(input: &str) -> Vec<u16> {
+    OsStr::new(input).encode_wide().chain(once(0)).collect()
+  }
+
+  fn from_wide_ptr(ptr: *const u16) -> String {
+    if ptr.is_null() {
+      return String::new();
+    }
+    let mut len = 0usize;
+    unsafe {
+      while *ptr.add(len) != 0 {
+        len += 1;
+      }
+      String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len))
+    }
+  }
+
+  pub fn list_printers() -> Result<Vec<String>, PrintError> {
+    unsafe {
+      let flags = PRINTER_ENUM_LOCAL | PRINTER_ENUM_CONNECTIONS;
+      let mut needed = 0u32;
+      let mut returned = 0u32;
+
+      EnumPrintersW(
+        flags,
+        null_mut(),
+        4,
+        null_mut(),
+        0,
+        &mut needed,
+        &mut returned,
+      );
+
+      if needed == 0 {
+        return Ok(vec![]);
+      }
+
+      let mut buffer = vec![0u8; needed as usize];
+      let ok = EnumPrintersW(
+        flags,
+        null_mut(),
+        4,
+        buffer.as_mut_ptr(),
+        needed,
+        &mut needed,
+        &mut returned,
+      );
+      if ok == 0 {
+        return Err(spooler_error(
+          GetLastError(),
+          "Failed to enumerate Windows printers. Verify print spooler service is running.",
+        ));
+      }
+
+      let ptr = buffer.as_ptr() as *const PRINTER_INFO_4W;
+      let mut out: Vec<String> = Vec::new();
+      for i in 0..returned as usize {
+        let item = *ptr.add(i);
+        let name = from_wide_ptr(item.pPrinterName);
+        if !name.trim().is_empty() {
+          out.push(name);
+        }
+      }
+      out.sort();
+      out.dedup();
+      Ok(out)
+    }
+  }
+
+  /// Name, driver, port and status of each local and connected queue.
+  pub fn list_queues() -> Result<Vec<Queue>, PrintError> {
+    unsafe {
+      let flags = PRINTER_ENUM_LOCAL | PRINTER_ENUM_CONNECTIONS;
+      let mut returned = 0u32;
+      let mut needed = 0u32;
+      EnumPrintersW(flags, null_mut(), 2, null_mut(), 0, &mut needed, &mut returned);
+      if needed == 0 {
+        return Ok(vec![]);
+      }
+
+      let mut buffer = vec![0u64; (needed as usize).div_ceil(8)];
+      if EnumPrintersW(flags, null_mut(), 2, buffer.as_mut_ptr() as *mut u8, needed, &mut needed, &mut returned) == 0 {
+        return Err(spooler_error(
+          GetLastError(),
+          "Failed to enumerate Windows printers. Verify print spooler service is running.",
+        ));
+      }
+
+      let ptr = buffer.as_ptr() as *const PRINTER_INFO_2W;
+      let mut out = Vec::new();
+      for i in 0..returned as usize {
+        let item = &*ptr.add(i);
+        let name = from_wide_ptr(item.pPrinterName);
+        if !name.trim().is_empty() {
+          out.push(Queue {
+            name,
+            driver_name: from_wide_ptr(item.pDriverName),
+            port_name: from_wide_ptr(item.pPortName),
+            status: item.Status,
+            jobs: item.cJobs,
+          });
+        }
+      }
+      out.sort_by(|a, b| a.name.cmp(&b.name));
+      out.dedup_by(|a, b| a.name == b.name);
+      Ok(out)
+    }
+  }
+
+  /// Printers shared by `server` (`\\name`), or browsable on the network.
+  pub fn list_remote(server: Option<&str>) -> Result<Vec<RemotePrinter>, PrintError> {
+    let (flags, origin) = match server {
+      Some(_) => (PRINTER_ENUM_NAME, RemoteOrigin::Server),
+      None => (PRINTER_ENUM_REMOTE | PRINTER_ENUM_NETWORK, RemoteOrigin::Network),
+    };
+    let mut name_w = server.map(to_wide);
+    let name_ptr = name_w.as_mut().map_or(null_mut(), |n| n.as_mut_ptr());
+    let what = server.unwrap_or("the network");
+    unsafe {
+      let mut needed = 0u32;
+      let mut returned = 0u32;
+      let sized = EnumPrintersW(flags, name_ptr, 1, null_mut(), 0, &mut needed, &mut returned);
+      if needed == 0 {
+        if sized != 0 {
+          return Ok(vec![]);
+        }
+        return Err(spooler_error(
+          GetLastError(),
+          &format!("Unable to list printers on {what}. Check the server name and that it is reachable."),
+        ));
+      }
+
+      // u64s keep the PRINTER_INFO_1W array aligned.
+      let mut buffer = vec![0u64; (needed as usize).div_ceil(8)];
+      if EnumPrintersW(flags, name_ptr, 1, buffer.as_mut_ptr() as *mut u8, needed, &mut needed, &mut returned) == 0 {
+        return Err(spooler_error(
+          GetLastError(),
+          &format!("Unable to list printers on {what}. Check the server name and that it is reachable."),
+        ));
+      }
+
+      let ptr = buffer.as_ptr() as *const PRINTER_INFO_1W;
+      let mut out = Vec::new();
+      for i in 0..returned as usize {
+        let item = *ptr.add(i);
+        // Domains and servers in a browse list, not printers.
+        if item.Flags & PRINTER_ENUM_CONTAINER != 0 {
+          continue;
+        }
+        let name = from_wide_ptr(item.pName);
+        if name.trim().is_empty() {
+          continue;
+        }
+        out.push(RemotePrinter {
+          name,
+          description: Some(from_wide_ptr(item.pDescription)).filter(|d| !d.is_empty()),
+          comment: Some(from_wide_ptr(item.pComment)).filter(|c| !c.is_empty()),
+          origin,
+        });
+      }
+      out.sort_by(|a, b| a.name.cmp(&b.name));
+      out.dedup_by(|a, b| a.name == b.name);
+      Ok(out)
+    }
+  }
+
+  pub fn driver_info(printer_name: &str) -> Result<DriverInfo, PrintError> {
+    if printer_name.trim().is_empty() {
+      return Err(PrintError::InvalidRequest("Printer name is required".to_string()));
+    }
+    unsafe {
+      let mut handle: HANDLE = std::ptr::null_mut();
+      let mut printer_name_w = to_wide(printer_name);
+      if OpenPrinterW(printer_name_w.as_mut_ptr(), &mut handle, null_mut()) == 0 || handle.is_null() {
+        return Err(spooler_error(
+          GetLastError(),
+          &format!("Failed to open printer '{printer_name}'. Verify exact printer name and driver installation."),
+        ));
+      }
+
+      let mut info = DriverInfo {
+        printer_name: printer_name.to_string(),
+        ..DriverInfo::default()
+      };
+      let mut port_w = to_wide("");
+      if let Some(buffer) = query(|buf, len, needed| GetPrinterW(handle, 2, buf, len, needed)) {
+        let p = &*(buffer.as_ptr() as *const PRINTER_INFO_2W);
+        info.driver_name = from_wide_ptr(p.pDriverName);
+        info.port_name = from_wide_ptr(p.pPortName);
+        info.default_datatype = Some(from_wide_ptr(p.pDatatype)).filter(|d| !d.is_empty());
+        port_w = to_wide(&info.port_name);
+      }
+      let driver = query(|buf, len, needed| GetPrinterDriverW(handle, null_mut(), 8, buf, len, needed));
+      let code = GetLastError();
+      ClosePrinter(handle);
+      let Some(driver) = driver else {
+        return Err(spooler_error(
+          code,
+          &format!("Unable to read the driver details of '{printer_name}'. Check the driver installation."),
+        ));
+      };
+
+      let d = &*(driver.as_ptr() as *const DRIVER_INFO_8W);
+      let v = d.dwlDriverVersion;
+      info.driver_model = d.cVersion;
+      info.driver_version = (v != 0).then(|| format!("{}.{}.{}.{}", v >> 48, (v >> 32) & 0xffff, (v >> 16) & 0xffff, v & 0xffff));
+      info.manufacturer = Some(from_wide_ptr(d.pszMfgName)).filter(|m| !m.is_empty());
+      info.v4_class_driver = d.cVersion == 4 && d.dwPrinterDriverAttributes & PRINTER_DRIVER_CLASS != 0;
+      info.virtual_printer = d.dwPrinterDriverAttributes
+        & (PRINTER_DRIVER_CATEGORY_FAX | PRINTER_DRIVER_CATEGORY_FILE | PRINTER_DRIVER_CATEGORY_VIRTUAL)
+        != 0;
+      info.generic_text = info.driver_name.eq_ignore_ascii_case("Generic / Text Only");
+      if info.driver_name.is_empty() {
+        info.driver_name = from_wide_ptr(d.pName);
+      }
+
+      let count = DeviceCapabilitiesW(printer_name_w.as_ptr(), port_w.as_ptr(), DC_PAPERNAMES, null_mut(), null_mut());
+      if count > 0 {
+        let mut names = vec![0u16; count as usize * PAPER_NAME_CHARS];
+        let got = DeviceCapabilitiesW(
+          printer_name_w.as_ptr(),
+          port_w.as_ptr(),
+          DC_PAPERNAMES,
+          names.as_mut_ptr(),
+          null_mut(),
+        );
+        for slot in names.chunks(PAPER_NAME_CHARS).take(got.max(0) as usize) {
+          let len = slot.iter().position(|&c| c == 0).unwrap_or(slot.len());
+          info.paper_names.push(String::from_utf16_lossy(&slot[..len]));
+        }
+      }
+
+      info.raw_capable = !info.virtual_printer
+        && !info.v4_class_driver
+        && info
+          .default_datatype
+          .as_deref()
+          .map_or(true, |d| d.to_ascii_uppercase().starts_with("RAW"));
+      Ok(info)
+    }
+  }
+
+  /// Runs a size-query-then-fill Win32 call, returning an 8-byte aligned
+  /// buffer with the result.
+  unsafe fn query(mut call: impl FnMut(*mut u8, u32, &mut u32) -> i32) -> Option<Vec<u64>> {
+    let mut needed = 0u32;
+    call(null_mut(), 0, &mut needed);
+    if needed == 0 {
+      return None;
+    }
+    let mut buffer = vec![0u64; (needed as usize).div_ceil(8)];
+    (call(buffer.as_mut_ptr() as *mut u8, needed, &mut needed) != 0).then_some(buffer)
+  }
+
+  /// Opens a document of `datatype`; returns the spooler job id, 0 on failure.
+  unsafe fn start_doc(handle: HANDLE, datatype: &str) -> u32 {
+    let doc_name = to_wide("BinanceXI Receipt");
+    let data_type = to_wide(datatype);
+    let doc_info = DOC_INFO_1W {
+      pDocName: doc_name.as_ptr() as *mut u16,
+      pOutputFile: null_mut(),
+      pDatatype: data_type.as_ptr() as *mut u16,
+    };
+    StartDocPrinterW(handle, 1, &doc_info as *const DOC_INFO_1W)
+  }
+
+  /// The queue's default datatype from `PRINTER_INFO_2`, e.g. `RAW [FF auto]`.
+  unsafe fn default_datatype(handle: HANDLE) -> Option<String> {
+    let buffer = query(|buf, len, needed| GetPrinterW(handle, 2, buf, len, needed))?;
+    let info = &*(buffer.as_ptr() as *const PRINTER_INFO_2W);
+    Some(from_wide_ptr(info.pDatatype)).filter(|d| !d.is_empty())
+  }
+
+  pub fn print_raw(
+    printer_name: &str,
+    data: &[u8],
+    devmode: Option<&[u8]>,
+    cancel: Option<&CancelToken>,
+  ) -> Result<(), PrintError> {
+    if printer_name.trim().is_empty() {
+      return Err(PrintError::InvalidRequest("Printer name is required".to_string()));
+    }
+    if let Some(devmode) = devmode {
+      super::check_devmode(devmode)?;
+    }
+
+    unsafe {
+      let mut handle: HANDLE = std::ptr::null_mut();
+      let mut printer_name_w = to_wide(printer_name);
+      // Copied into a u64 buffer so the DEVMODEW the spooler reads is aligned.
+      let mut devmode_buf = devmode.map(|dm| {
+        let mut buf = vec![0u64; dm.len().div_ceil(8)];
+        std::ptr::copy_nonoverlapping(dm.as_ptr(), buf.as_mut_ptr() as *mut u8, dm.len());
+        buf
+      });
+      let defaults = devmode_buf.as_mut().map(|buf| PRINTER_DEFAULTSW {
+        pDatatype: null_mut(),
+        pDevMode: buf.as_mut_ptr() as *mut DEVMODEW,
+        DesiredAccess: PRINTER_ACCESS_USE,
+      });
+      let defaults_ptr = defaults
+        .as_ref()
+        .map_or(null_mut(), |d| d as *const PRINTER_DEFAULTSW as *mut PRINTER_DEFAULTSW);
+      let open_ok = OpenPrinterW(printer_name_w.as_mut_ptr(), &mut handle, defaults_ptr);
+      if open_ok == 0 || handle.is_null() {
+        let code = GetLastError();
+        if code != ERROR_INVALID_PRINTER_NAME || !super::is_unc(printer_name) {
+          return Err(open_error(code, printer_name));
+        }
+        // Not connected for this user yet; connect once and retry.
+        log::info!("'{printer_name}' is not connected for this user, connecting");
+        add_connection(printer_name)?;
+        let open_ok = OpenPrinterW(printer_name_w.as_mut_ptr(), &mut handle, defaults_ptr);
+        if open_ok == 0 || handle.is_null() {
+          return Err(open_error(GetLastError(), printer_name));
+        }
+      }
+
+      let mut job_id = start_doc(handle, "RAW");
+      if job_id == 0 {
+        let code = GetLastError();
+        if code != ERROR_INVALID_DATATYPE {
+          ClosePrinter(handle);
+          return Err(spooler_error(code, "StartDocPrinter failed. Printer driver/spooler rejected RAW job."));
+        }
+        // Some drivers only take a RAW variant such as "RAW [FF auto]";
+        // anything else (EMF, XPS) can't carry ESC/POS bytes.
+        let default = default_datatype(handle);
+        match default.as_deref().filter(|d| d.to_ascii_uppercase().starts_with("RAW")) {
+          Some(datatype) => {
+            log::warn!("'{printer_name}' rejected RAW, retrying with its default datatype '{datatype}'");
+            job_id = start_doc(handle, datatype);
+            if job_id == 0 {
+              let code = GetLastError();
+              ClosePrinter(handle);
+              return Err(spooler_error(
+                code,
+                &format!("StartDocPrinter failed with datatype '{datatype}' after the driver rejected RAW."),
+              ));
+            }
+          }
+          None => {
+            ClosePrinter(handle);
+            return Err(spooler_error(
+              code,
+              &format!(
+                "Printer '{printer_name}' can't accept raw ESC/POS: its driver rejects the RAW datatype (default datatype: {}). Print through the driver's TEXT path or send directly to the printer over TCP instead.",
+                default.as_deref().unwrap_or("unknown")
+              ),
+            ));
+          }
+        }
+      }
+      if let Some(cancel) = cancel {
+        cancel.set_spooler_job(job_id);
+        if let Err(e) = cancel.check(printer_name, 0, data.len()) {
+          SetJobW(handle, job_id, 0, null_mut(), JOB_CONTROL_DELETE);
+          EndDocPrinter(handle);
+          ClosePrinter(handle);
+          return Err(e);
+        }
+      }
+
+      if StartPagePrinter(handle) == 0 {
+        let code = GetLastError();
+        EndDocPrinter(handle);
+        ClosePrinter(handle);
+        return Err(spooler_error(code, "StartPagePrinter failed. Printer may be offline or out of paper."));
+      }
+
+      let written = write_chunks(handle, data, cancel, printer_name);
+      if let Err(WriteFailure::Aborted(e)) = &written {
+        SetJobW(handle, job_id, 0, null_mut(), JOB_CONTROL_DELETE);
+        EndDocPrinter(handle);
+        ClosePrinter(handle);
+        return Err(e.clone());
+      }
+      let page_ok = EndPagePrinter(handle);
+      let page_error = GetLastError();
+      let doc_ok = EndDocPrinter(handle);
+      let doc_error = GetLastError();
+      ClosePrinter(handle);
+
+      // Errors from here on carry `written`: part or all of the job may
+      // still print, so failover must not resend it elsewhere.
+      if let Err(WriteFailure::Spooler { code, written }) = written {
+        return Err(with_written(
+          spooler_error(
+            code,
+            &format!(
+              "WritePrinter failed (written {written}/{} bytes). RAW printing may not be supported by this driver.",
+              data.len()
+            ),
+          ),
+          written,
+        ));
+      }
+      if page_ok == 0 || doc_ok == 0 {
+        return Err(with_written(
+          spooler_error(
+            if page_ok == 0 { page_error } else { doc_error },
+            "Failed to finalize print job. Check printer spooler status and driver health.",
+          ),
+          data.len(),
+        ));
+      }
+
+      Ok(())
+    }
+  }
+
+  enum WriteFailure {
+    Aborted(PrintError),
+    Spooler { code: u32, written: usize },
+  }
+
+  /// Feeds `data` to WritePrinter in chunks of at most [`WRITE_CHUNK`],
+  /// resending the rest of a chunk the spooler only partly took. Checks
+  /// `cancel` between chunks.
+  unsafe fn write_chunks(
+    handle: HANDLE,
+    data: &[u8],
+    cancel: Option<&CancelToken>,
+    printer_name: &str,
+  ) -> Result<(), WriteFailure> {
+    let mut sent = 0;
+    for chunk in data.chunks(WRITE_CHUNK) {
+      if let Some(cancel) = cancel {
+        cancel.check(printer_name, sent, data.len()).map_err(WriteFailure::Aborted)?;
+      }
+      let mut offset = 0;
+      let mut stalled = 0;
+      while offset < chunk.len() {
+        let rest = &chunk[offset..];
+        let mut written = 0u32;
+        if WritePrinter(handle, rest.as_ptr() as *const c_void, rest.len() as u32, &mut written) == 0 {
+          return Err(WriteFailure::Spooler {
+            code: GetLastError(),
+            written: sent + offset,
+          });
+        }
+        if written == 0 {
+          stalled += 1;
+          if stalled >= SHORT_WRITE_RETRIES {
+            return Err(WriteFailure::Spooler {
+              code: GetLastError(),
+              written: sent + offset,
+            });
+          }
+          continue;
+        }
+        if (written as usize) < rest.len() {
+          log::debug!("WritePrinter to '{printer_name}' took {written} of {} bytes; resending the rest", rest.len());
+        }
+        stalled = 0;
+        offset += written as usize;
+      }
+      sent += chunk.len();
+    }
+    Ok(())
+  }
+
+  /// Reads job `job_id` through an open printer handle.
+  unsafe fn get_job(handle: HANDLE, printer_name: &str, job_id: u32) -> Result<SpoolJob, PrintError> {
+    let Some(buffer) = query(|buf, len, needed| GetJobW(handle, job_id, 1, buf, len, needed)) else {
+      let code = GetLastError();
+      let message = if code == ERROR_INVALID_PARAMETER {
+        format!("Job {job_id} is not in the queue of '{printer_name}'. It may have finished or been deleted.")
+      } else {
+        format!("Unable to read job {job_id} on '{printer_name}'.")
+      };
+      return Err(spooler_error(code, &message));
+    };
+    let info = &*(buffer.as_ptr() as *const JOB_INFO_1W);
+    Ok(SpoolJob {
+      job_id: info.JobId,
+      document: from_wide_ptr(info.pDocument),
+      status: super::status_names(super::JOB_STATUS_NAMES, info.Status),
+      status_text: Some(from_wide_ptr(info.pStatus)).filter(|s| !s.is_empty()),
+      pages_printed: info.PagesPrinted,
+      total_pages: info.TotalPages,
+    })
+  }
+
+  /// Restarts spooler job `job_id` on `printer_name` from its beginning.
+  /// Refuses a job that may already be partly on paper unless `force`.
+  pub fn restart_job(printer_name: &str, job_id: u32, force: bool) -> Result<SpoolJob, PrintError> {
+    unsafe {
+      let mut handle: HANDLE = std::ptr::null_mut();
+      let mut printer_name_w = to_wide(printer_name);
+      if OpenPrinterW(printer_name_w.as_mut_ptr(), &mut handle, null_mut()) == 0 || handle.is_null() {
+        return Err(open_error(GetLastError(), printer_name));
+      }
+      let result = (|| {
+        let job = get_job(handle, printer_name, job_id)?;
+        if job.started_printing() && !force {
+          return Err(PrintError::InvalidRequest(format!(
+            "Job {job_id} on '{printer_name}' has already started printing ({} of {} pages), so restarting prints it again from the start. Pass force to restart anyway, or delete the job if the receipt came out.",
+            job.pages_printed, job.total_pages
+          )));
+        }
+        if SetJobW(handle, job_id, 0, null_mut(), JOB_CONTROL_RESTART) == 0 {
+          return Err(spooler_error(
+            GetLastError(),
+            &format!("Unable to restart job {job_id} on '{printer_name}'. Check the printer is connected, then try again."),
+          ));
+        }
+        log::info!("restarted spooler job {job_id} on '{printer_name}'");
+        get_job(handle, printer_name, job_id)
+      })();
+      ClosePrinter(handle);
+      result
+    }
+  }
+
+  /// Deletes spooler job `job_id` on `printer_name`, whether it is still
+  /// spooling or already printing.
+  pub fn delete_job(printer_name: &str, job_id: u32) -> Result<(), PrintError> {
+    unsafe {
+      let mut handle: HANDLE = std::ptr::null_mut();
+      let mut printer_name_w = to_wide(printer_name);
+      if OpenPrinterW(printer_name_w.as_mut_ptr(), &mut handle, null_mut()) == 0 || handle.is_null() {
+        return Err(spooler_error(
+          GetLastError(),
+          &format!("Failed to open printer '{printer_name}' to delete job {job_id}."),
+        ));
+      }
+      let ok = SetJobW(handle, job_id, 0, null_mut(), JOB_CONTROL_DELETE);
+      let code = GetLastError();
+      ClosePrinter(handle);
+      if ok == 0 {
+        return Err(spooler_error(
+          code,
+          &format!(
+            "Unable to delete job {job_id} on '{printer_name}'. It may have finished already; otherwise remove it from the Windows print queue."
+          ),
+        ));
+      }
+      Ok(())
+    }
+  }
+}
+
+#[cfg(not(target_os = "windows"))]
+mod imp {
+  use super::{DriverInfo, Queue, RemotePrinter, SpoolJob};
+  use crate::cancel::CancelToken;
+  use crate::error::PrintError;
+
+  pub fn driver_info(_printer_name: &str) -> Result<DriverInfo, PrintError> {
+    Err(PrintError::Unsupported(
+      "Windows spooler transport is only available on Windows builds".to_string(),
+    ))
+  }
+
+  pub fn list_printers() -> Result<Vec<String>, PrintError> {
+    Ok(vec![])
+  }
+
+  pub fn list_queues() -> Result<Vec<Queue>, PrintError> {
+    Ok(vec![])
+  }
+
+  pub fn list_remote(_server: Option<&str>) -> Result<Vec<RemotePrinter>, PrintError> {
+    Err(PrintError::Unsupported(
+      "Windows spooler transport is only available on Windows builds".to_string(),
+    ))
+  }
+
+  pub fn print_raw(
+    _printer_name: &str,
+    _data: &[u8],
+    _devmode: Option<&[u8]>,
+    _cancel: Option<&CancelToken>,
+  ) -> Result<(), PrintError> {
+    Err(PrintError::Unsupported(
+      "Windows spooler transport is only available on Windows builds".to_string(),
+    ))
+  }
+
+  pub fn delete_job(_printer_name: &str, _job_id: u32) -> Result<(), PrintError> {
+    Err(PrintError::Unsupported(
+      "Windows spooler transport is only available on Windows builds".to_string(),
+    ))
+  }
+
+  pub fn restart_job(_printer_name: &str, _job_id: u32, _force: bool) -> Result<SpoolJob, PrintError> {
+    Err(PrintError::Unsupported(
+      "Windows spooler transport is only available on Windows builds".to_string(),
+    ))
+  }
+
+  pub fn add_connection(unc_path: &str) -> Result<(), PrintError> {
+    super::check_unc(unc_path)?;
+    Err(PrintError::Unsupported(
+      "Shared printer connections are only available on Windows builds".to_string(),
+    ))
+  }
+
+  pub fn remove_connection(unc_path: &str) -> Result<(), PrintError> {
+    super::check_unc(unc_path)?;
+    Err(PrintError::Unsupported(
+      "Shared printer connections are only available on Windows builds".to_string(),
+    ))
+  }
+}
+
+pub use imp::{add_connection, delete_job, driver_info, list_printers, print_raw, remove_connection, restart_job};
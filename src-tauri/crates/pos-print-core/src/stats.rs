@@ -0,0 +1,240 @@
+//! Print statistics aggregated from the audit log.
+//!
+//! Entries are folded into hourly buckets per destination as they're read,
+//! and the index remembers how far into the log it got, so each call only
+//! parses lines appended since the last one.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{now_ms, AuditEntry};
+
+const HOUR_MS: u64 = 3_600_000;
+const TOP_ERRORS: usize = 5;
+/// Error messages longer than this are grouped by their start.
+const ERROR_KEY_CHARS: usize = 160;
+
+/// Window to summarize. Defaults to the current local day.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct StatsRange {
+  pub from_ms: Option<u64>,
+  pub to_ms: Option<u64>,
+  /// Offset of local time from UTC, for "today" and the busiest hour.
+  pub utc_offset_minutes: i32,
+  /// Only jobs sent with this `PrintOptions::register`; all jobs when absent.
+  pub register: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ErrorCount {
+  pub message: String,
+  pub count: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DestinationStats {
+  pub destination: String,
+  pub jobs: u64,
+  pub failed: u64,
+  pub failure_rate: f64,
+  pub avg_latency_ms: Option<u64>,
+  /// Bytes of jobs that printed.
+  pub bytes: u64,
+  pub top_errors: Vec<ErrorCount>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BusiestHour {
+  /// Local hour of day, 0-23.
+  pub hour: u8,
+  pub jobs: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PrintStatistics {
+  pub from_ms: u64,
+  pub to_ms: u64,
+  pub jobs: u64,
+  pub failed: u64,
+  pub failure_rate: f64,
+  pub avg_latency_ms: Option<u64>,
+  pub bytes: u64,
+  pub busiest_hour: Option<BusiestHour>,
+  /// Busiest first.
+  pub destinations: Vec<DestinationStats>,
+}
+
+#[derive(Clone, Default)]
+struct Bucket {
+  jobs: u64,
+  failed: u64,
+  bytes: u64,
+  latency_total_ms: u64,
+  latency_samples: u64,
+  errors: BTreeMap<String, u64>,
+}
+
+impl Bucket {
+  fn add(&mut self, other: &Bucket) {
+    self.jobs += other.jobs;
+    self.failed += other.failed;
+    self.bytes += other.bytes;
+    self.latency_total_ms += other.latency_total_ms;
+    self.latency_samples += other.latency_samples;
+    for (message, count) in &other.errors {
+      *self.errors.entry(message.clone()).or_insert(0) += count;
+    }
+  }
+
+  fn failure_rate(&self) -> f64 {
+    if self.jobs == 0 {
+      0.0
+    } else {
+      self.failed as f64 / self.jobs as f64
+    }
+  }
+
+  fn avg_latency_ms(&self) -> Option<u64> {
+    (self.latency_samples > 0).then(|| self.latency_total_ms / self.latency_samples)
+  }
+}
+
+/// Hourly buckets by `(hour since the epoch, destination, register)`; jobs
+/// sent without a register have an empty one.
+#[derive(Default)]
+pub(crate) struct StatsIndex {
+  /// Bytes of the log already folded in.
+  offset: u64,
+  buckets: BTreeMap<(u64, String, String), Bucket>,
+}
+
+impl StatsIndex {
+  /// Folds in entries appended to `path` since the last call. Starts over
+  /// if the log got shorter, e.g. after it was deleted.
+  pub(crate) fn catch_up(&mut self, path: &Path) {
+    let Ok(mut file) = File::open(path) else {
+      return;
+    };
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    if len < self.offset {
+      *self = Self::default();
+    }
+    if file.seek(SeekFrom::Start(self.offset)).is_err() {
+      return;
+    }
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    loop {
+      line.clear();
+      match reader.read_until(b'\n', &mut line) {
+        Ok(0) | Err(_) => break,
+        // A line still being written; pick it up next time.
+        Ok(_) if !line.ends_with(b"\n") => break,
+        Ok(n) => {
+          self.offset += n as u64;
+          if let Ok(entry) = serde_json::from_slice::<AuditEntry>(&line) {
+            self.fold(&entry);
+          }
+        }
+      }
+    }
+  }
+
+  fn fold(&mut self, entry: &AuditEntry) {
+    let suppressed = entry.detail.as_deref().is_some_and(|d| d.contains("duplicate_suppressed=true"));
+    if entry.action != "print" || suppressed {
+      return;
+    }
+    let destination = entry.destination.clone().unwrap_or_default();
+    let register = entry.register.clone().unwrap_or_default();
+    let bucket = self.buckets.entry((entry.ts_ms / HOUR_MS, destination, register)).or_default();
+    bucket.jobs += 1;
+    if entry.ok {
+      bucket.bytes += entry.bytes.unwrap_or(0) as u64;
+    } else {
+      bucket.failed += 1;
+      let message = entry.error.as_deref().unwrap_or("unknown error");
+      *bucket.errors.entry(message.chars().take(ERROR_KEY_CHARS).collect()).or_insert(0) += 1;
+    }
+    if let Some(ms) = entry.duration_ms {
+      bucket.latency_total_ms += ms;
+      bucket.latency_samples += 1;
+    }
+  }
+
+  /// Summarizes the hours overlapping `range`.
+  pub(crate) fn summarize(&self, range: &StatsRange) -> PrintStatistics {
+    let offset_ms = range.utc_offset_minutes as i64 * 60_000;
+    let now = now_ms();
+    let today_start = ((now as i64 + offset_ms).div_euclid(86_400_000) * 86_400_000 - offset_ms).max(0) as u64;
+    let from_ms = range.from_ms.unwrap_or(today_start);
+    let to_ms = range.to_ms.unwrap_or(now);
+
+    let mut total = Bucket::default();
+    let mut by_destination = BTreeMap::<&str, Bucket>::new();
+    let mut by_hour = [0u64; 24];
+    let start = (from_ms / HOUR_MS, String::new(), String::new());
+    for ((hour, destination, register), bucket) in self.buckets.range(start..) {
+      if hour * HOUR_MS > to_ms {
+        break;
+      }
+      if range.register.as_ref().is_some_and(|r| r != register) {
+        continue;
+      }
+      total.add(bucket);
+      by_destination.entry(destination).or_default().add(bucket);
+      let local_hour = ((*hour * HOUR_MS) as i64 + offset_ms).rem_euclid(86_400_000) / HOUR_MS as i64;
+      by_hour[local_hour as usize] += bucket.jobs;
+    }
+
+    let busiest_hour = (0..24)
+      .max_by_key(|h| (by_hour[*h], std::cmp::Reverse(*h)))
+      .filter(|h| by_hour[*h] > 0)
+      .map(|h| BusiestHour {
+        hour: h as u8,
+        jobs: by_hour[h],
+      });
+    let mut destinations = by_destination
+      .into_iter()
+      .map(|(destination, bucket)| {
+        let mut top_errors = bucket
+          .errors
+          .iter()
+          .map(|(message, count)| ErrorCount {
+            message: message.clone(),
+            count: *count,
+          })
+          .collect::<Vec<_>>();
+        top_errors.sort_by_key(|e| std::cmp::Reverse(e.count));
+        top_errors.truncate(TOP_ERRORS);
+        DestinationStats {
+          destination: destination.to_string(),
+          jobs: bucket.jobs,
+          failed: bucket.failed,
+          failure_rate: bucket.failure_rate(),
+          avg_latency_ms: bucket.avg_latency_ms(),
+          bytes: bucket.bytes,
+          top_errors,
+        }
+      })
+      .collect::<Vec<_>>();
+    destinations.sort_by_key(|d| std::cmp::Reverse(d.jobs));
+
+    PrintStatistics {
+      from_ms,
+      to_ms,
+      jobs: total.jobs,
+      failed: total.failed,
+      failure_rate: total.failure_rate(),
+      avg_latency_ms: total.avg_latency_ms(),
+      bytes: total.bytes,
+      busiest_hour,
+      destinations,
+    }
+  }
+}
@@ -0,0 +1,506 @@
+//! Real-time status (DLE EOT n) requests and decoding, and waiting for the
+//! answer to a GS ( H process ID marker.
+
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{codes, ErrorDetail, PrintError};
+use crate::escpos::DLE;
+
+const EOT: u8 = 0x04;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusKind {
+  Printer = 1,
+  OfflineCause = 2,
+  ErrorCause = 3,
+  PaperSensor = 4,
+}
+
+impl StatusKind {
+  pub fn request(self) -> [u8; 3] {
+    [DLE, EOT, self as u8]
+  }
+}
+
+/// Every status byte has bit 1 and bit 4 set and bits 0 and 7 clear, which is
+/// how a status reply is told apart from unrelated bytes on the line.
+pub fn is_status_byte(b: u8) -> bool {
+  b & 0b1001_0011 == 0b0001_0010
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PrinterStatus {
+  pub online: bool,
+  /// Drawer kick connector pin 3 is high.
+  pub drawer_open: bool,
+  pub waiting_for_recovery: bool,
+  pub feed_button_pressed: bool,
+}
+
+impl PrinterStatus {
+  pub fn decode(b: u8) -> Self {
+    Self {
+      online: b & 0x08 == 0,
+      drawer_open: b & 0x04 != 0,
+      waiting_for_recovery: b & 0x20 != 0,
+      feed_button_pressed: b & 0x40 != 0,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OfflineCause {
+  pub cover_open: bool,
+  pub feeding_by_button: bool,
+  pub paper_end_stop: bool,
+  pub error: bool,
+}
+
+impl OfflineCause {
+  pub fn decode(b: u8) -> Self {
+    Self {
+      cover_open: b & 0x04 != 0,
+      feeding_by_button: b & 0x08 != 0,
+      paper_end_stop: b & 0x20 != 0,
+      error: b & 0x40 != 0,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ErrorCause {
+  pub recoverable: bool,
+  pub cutter_error: bool,
+  pub unrecoverable: bool,
+  /// Auto-recoverable error, typically print head over temperature.
+  pub auto_recoverable: bool,
+}
+
+impl ErrorCause {
+  pub fn decode(b: u8) -> Self {
+    Self {
+      recoverable: b & 0x04 != 0,
+      cutter_error: b & 0x08 != 0,
+      unrecoverable: b & 0x20 != 0,
+      auto_recoverable: b & 0x40 != 0,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PaperStatus {
+  pub near_end: bool,
+  pub out: bool,
+}
+
+impl PaperStatus {
+  pub fn decode(b: u8) -> Self {
+    Self {
+      near_end: b & 0x0c != 0,
+      out: b & 0x60 != 0,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StatusReport {
+  pub printer: PrinterStatus,
+  pub paper: PaperStatus,
+}
+
+/// Sends one DLE EOT request and returns the matching status byte.
+///
+/// Bytes that can't be status replies (e.g. leftovers from earlier traffic)
+/// are skipped until `timeout` elapses.
+pub fn request_byte<T: Read + Write + ?Sized>(
+  io: &mut T,
+  label: &str,
+  kind: StatusKind,
+  timeout: Duration,
+) -> Result<u8, PrintError> {
+  io.write_all(&kind.request())
+    .and_then(|_| io.flush())
+    .map_err(|e| PrintError::Write(format!("Status request to {label} failed: {e}.").into()))?;
+
+  let deadline = Instant::now() + timeout;
+  let mut byte = [0u8; 1];
+  while Instant::now() < deadline {
+    match io.read(&mut byte) {
+      Ok(0) => {
+        return Err(PrintError::Read(format!(
+          "{label} closed the connection before answering the status request."
+        ).into()))
+      }
+      Ok(_) if is_status_byte(byte[0]) => return Ok(byte[0]),
+      Ok(_) => continue,
+      Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => continue,
+      Err(e) => return Err(PrintError::Read(format!("Status read from {label} failed: {e}.").into())),
+    }
+  }
+  Err(PrintError::Timeout(
+    ErrorDetail::new(format!(
+      "{label} did not answer the status request within {} ms. The printer may not support DLE EOT.",
+      timeout.as_millis()
+    ))
+    .reason(codes::STATUS_TIMEOUT)
+    .param("printer", label)
+    .param("timeout_ms", timeout.as_millis()),
+  ))
+}
+
+pub fn query<T: Read + Write + ?Sized>(io: &mut T, label: &str, timeout: Duration) -> Result<StatusReport, PrintError> {
+  let printer = request_byte(io, label, StatusKind::Printer, timeout)?;
+  let paper = request_byte(io, label, StatusKind::PaperSensor, timeout)?;
+  Ok(StatusReport {
+    printer: PrinterStatus::decode(printer),
+    paper: PaperStatus::decode(paper),
+  })
+}
+
+/// All four DLE EOT status types, read in one round trip.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FullStatus {
+  pub printer: PrinterStatus,
+  pub offline: OfflineCause,
+  pub error: ErrorCause,
+  pub paper: PaperStatus,
+}
+
+/// A condition that stops the printer from printing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultKind {
+  PaperOut,
+  CoverOpen,
+  CutterJam,
+  HeadOverheat,
+  Unrecoverable,
+  Offline,
+}
+
+impl FaultKind {
+  pub fn describe(self) -> &'static str {
+    match self {
+      FaultKind::PaperOut => "ran out of paper",
+      FaultKind::CoverOpen => "has its cover open",
+      FaultKind::CutterJam => "has a cutter jam",
+      FaultKind::HeadOverheat => "stopped with an overheated print head",
+      FaultKind::Unrecoverable => "reported an unrecoverable error",
+      FaultKind::Offline => "went offline",
+    }
+  }
+}
+
+impl FullStatus {
+  /// The most specific reason the printer can't print, if any.
+  pub fn fault(&self) -> Option<FaultKind> {
+    if self.paper.out || self.offline.paper_end_stop {
+      Some(FaultKind::PaperOut)
+    } else if self.offline.cover_open {
+      Some(FaultKind::CoverOpen)
+    } else if self.error.cutter_error {
+      Some(FaultKind::CutterJam)
+    } else if self.error.auto_recoverable {
+      Some(FaultKind::HeadOverheat)
+    } else if self.error.unrecoverable || self.error.recoverable || self.offline.error {
+      Some(FaultKind::Unrecoverable)
+    } else if !self.printer.online {
+      Some(FaultKind::Offline)
+    } else {
+      None
+    }
+  }
+
+  /// The status as one sentence for reading aloud, e.g. "Online, paper OK,
+  /// cover closed, drawer open." or "Offline: cover open and paper out;
+  /// drawer closed."
+  pub fn summary(&self) -> String {
+    let paper_out = self.paper.out || self.offline.paper_end_stop;
+    let mut problems = Vec::new();
+    if self.offline.cover_open {
+      problems.push("cover open");
+    }
+    if paper_out {
+      problems.push("paper out");
+    }
+    if self.error.cutter_error {
+      problems.push("cutter jammed");
+    }
+    if self.error.auto_recoverable {
+      problems.push("print head overheated");
+    }
+    if self.error.unrecoverable {
+      problems.push("unrecoverable error");
+    } else if self.error.recoverable || (self.offline.error && problems.is_empty()) {
+      problems.push("recoverable error");
+    }
+    if self.offline.feeding_by_button {
+      problems.push("feeding paper by button");
+    }
+    let drawer = if self.printer.drawer_open { "drawer open" } else { "drawer closed" };
+
+    if self.printer.online && problems.is_empty() {
+      let paper = if self.paper.near_end { "paper low" } else { "paper OK" };
+      return format!("Online, {paper}, cover closed, {drawer}.");
+    }
+    let state = if self.printer.online { "Online but not ready" } else { "Offline" };
+    let mut out = if problems.is_empty() {
+      state.to_string()
+    } else {
+      format!("{state}: {}", join_and(&problems))
+    };
+    if self.paper.near_end && !paper_out {
+      out.push_str("; paper low");
+    }
+    if self.printer.waiting_for_recovery {
+      out.push_str("; waiting for recovery");
+    }
+    format!("{out}; {drawer}.")
+  }
+}
+
+/// "a", "a and b", "a, b and c".
+fn join_and(items: &[&str]) -> String {
+  match items {
+    [] => String::new(),
+    [only] => only.to_string(),
+    [init @ .., last] => format!("{} and {last}", init.join(", ")),
+  }
+}
+
+/// Sends the four DLE EOT requests back-to-back and decodes the replies.
+///
+/// Status bytes all share the same fixed bits, so a reply can't be told
+/// apart by its value; the printer answers real-time requests strictly in
+/// the order received, and the n-th status byte read belongs to the n-th
+/// request. Non-status bytes in between are skipped as in [`request_byte`].
+pub fn query_full<T: Read + Write + ?Sized>(io: &mut T, label: &str, timeout: Duration) -> Result<FullStatus, PrintError> {
+  const KINDS: [StatusKind; 4] = [
+    StatusKind::Printer,
+    StatusKind::OfflineCause,
+    StatusKind::ErrorCause,
+    StatusKind::PaperSensor,
+  ];
+  let request = KINDS.iter().flat_map(|k| k.request()).collect::<Vec<_>>();
+  io.write_all(&request)
+    .and_then(|_| io.flush())
+    .map_err(|e| PrintError::Write(format!("Status request to {label} failed: {e}.").into()))?;
+
+  let deadline = Instant::now() + timeout;
+  let mut replies = [0u8; 4];
+  let mut filled = 0;
+  let mut buf = [0u8; 16];
+  while filled < replies.len() {
+    if Instant::now() >= deadline {
+      return Err(PrintError::Timeout(
+        ErrorDetail::new(format!(
+          "{label} answered {filled} of 4 status requests within {} ms. The printer may not support DLE EOT n=1..4.",
+          timeout.as_millis()
+        ))
+        .reason(codes::STATUS_TIMEOUT)
+        .param("printer", label)
+        .param("timeout_ms", timeout.as_millis()),
+      ));
+    }
+    match io.read(&mut buf) {
+      Ok(0) => {
+        return Err(PrintError::Read(format!(
+          "{label} closed the connection before answering the status requests."
+        ).into()))
+      }
+      Ok(n) => {
+        for &b in buf[..n].iter().filter(|&&b| is_status_byte(b)) {
+          if filled < replies.len() {
+            replies[filled] = b;
+            filled += 1;
+          }
+        }
+      }
+      Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => continue,
+      Err(e) => return Err(PrintError::Read(format!("Status read from {label} failed: {e}.").into())),
+    }
+  }
+
+  Ok(FullStatus {
+    printer: PrinterStatus::decode(replies[0]),
+    offline: OfflineCause::decode(replies[1]),
+    error: ErrorCause::decode(replies[2]),
+    paper: PaperStatus::decode(replies[3]),
+  })
+}
+
+/// Waits for the printer's answer to the GS ( H process ID request `id`
+/// (`37 22 id 00`), which it sends only once everything before the request
+/// has been processed, i.e. printed. Other bytes, such as automatic status
+/// replies, are skipped. `sent` is the payload offset of the marker, for
+/// the error.
+pub fn await_process_id<T: Read + ?Sized>(
+  io: &mut T,
+  label: &str,
+  id: [u8; 4],
+  sent: usize,
+  timeout: Duration,
+) -> Result<(), PrintError> {
+  let expected = [0x37, 0x22, id[0], id[1], id[2], id[3], 0x00];
+  let marker = String::from_utf8_lossy(&id).into_owned();
+  let deadline = Instant::now() + timeout;
+  let mut matched = 0;
+  let mut byte = [0u8; 1];
+  while Instant::now() < deadline {
+    match io.read(&mut byte) {
+      Ok(0) => {
+        return Err(PrintError::Read(format!(
+          "{label} closed the connection before confirming marker '{marker}'; the receipt may be incomplete."
+        ).into()))
+      }
+      Ok(_) => {
+        matched = match byte[0] {
+          b if b == expected[matched] => matched + 1,
+          b if b == expected[0] => 1,
+          _ => 0,
+        };
+        if matched == expected.len() {
+          return Ok(());
+        }
+      }
+      Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => continue,
+      Err(e) => return Err(PrintError::Read(format!("Reading marker confirmation from {label} failed: {e}.").into())),
+    }
+  }
+  Err(PrintError::Timeout(
+    ErrorDetail::new(format!(
+      "{label} did not confirm reaching marker '{marker}' within {} s. It may be out of paper or not support GS ( H; check the receipt before reprinting.",
+      timeout.as_secs()
+    ))
+    .reason(codes::MARKER_TIMEOUT)
+    .param("printer", label)
+    .param("marker", marker)
+    .param("sent_bytes", sent)
+    .param("timeout_ms", timeout.as_millis()),
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::VecDeque;
+  use std::io;
+
+  use super::*;
+
+  /// A printer that answers with `replies`, then goes quiet.
+  #[derive(Default)]
+  struct Scripted {
+    replies: VecDeque<u8>,
+    written: Vec<u8>,
+  }
+
+  impl Scripted {
+    fn new(replies: &[u8]) -> Self {
+      Self {
+        replies: replies.iter().copied().collect(),
+        written: Vec::new(),
+      }
+    }
+  }
+
+  impl Read for Scripted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      match self.replies.pop_front() {
+        Some(b) => {
+          buf[0] = b;
+          Ok(1)
+        }
+        None => Err(ErrorKind::TimedOut.into()),
+      }
+    }
+  }
+
+  impl Write for Scripted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.written.extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  const TIMEOUT: Duration = Duration::from_millis(50);
+
+  #[test]
+  fn status_bytes_have_the_fixed_bits() {
+    assert!(is_status_byte(0x12));
+    assert!(is_status_byte(0x1e));
+    assert!(!is_status_byte(0x00));
+    assert!(!is_status_byte(0x13));
+    assert!(!is_status_byte(0x92));
+  }
+
+  #[test]
+  fn decodes_each_status_type() {
+    let printer = PrinterStatus::decode(0x1e);
+    assert!(!printer.online && printer.drawer_open && !printer.waiting_for_recovery);
+    assert!(PrinterStatus::decode(0x12).online);
+
+    let offline = OfflineCause::decode(0x12 | 0x04 | 0x20);
+    assert!(offline.cover_open && offline.paper_end_stop && !offline.feeding_by_button && !offline.error);
+
+    let error = ErrorCause::decode(0x12 | 0x08 | 0x40);
+    assert!(error.cutter_error && error.auto_recoverable && !error.recoverable && !error.unrecoverable);
+
+    let paper = PaperStatus::decode(0x12 | 0x0c);
+    assert!(paper.near_end && !paper.out);
+    assert!(PaperStatus::decode(0x12 | 0x60).out);
+  }
+
+  #[test]
+  fn request_skips_stray_bytes() {
+    let mut io = Scripted::new(&[b'x', 0x00, 0x16]);
+    assert_eq!(request_byte(&mut io, "test", StatusKind::Printer, TIMEOUT).unwrap(), 0x16);
+    assert_eq!(io.written, [DLE, EOT, 1]);
+  }
+
+  #[test]
+  fn request_times_out_without_a_reply() {
+    let mut io = Scripted::new(b"x");
+    let err = request_byte(&mut io, "test", StatusKind::PaperSensor, TIMEOUT).unwrap_err();
+    assert_eq!(err.reason(), codes::STATUS_TIMEOUT);
+  }
+
+  #[test]
+  fn full_status_reads_replies_in_request_order() {
+    let mut io = Scripted::new(&[0x12, b'?', 0x16, 0x12, 0x72]);
+    let status = query_full(&mut io, "test", TIMEOUT).unwrap();
+    assert_eq!(io.written, [DLE, EOT, 1, DLE, EOT, 2, DLE, EOT, 3, DLE, EOT, 4]);
+    assert!(status.printer.online);
+    assert!(status.offline.cover_open);
+    assert!(status.paper.out);
+    assert_eq!(status.fault(), Some(FaultKind::PaperOut));
+    assert_eq!(status.summary(), "Online but not ready: cover open and paper out; drawer closed.");
+  }
+
+  #[test]
+  fn healthy_status_has_no_fault() {
+    let status = FullStatus {
+      printer: PrinterStatus::decode(0x16),
+      offline: OfflineCause::decode(0x12),
+      error: ErrorCause::decode(0x12),
+      paper: PaperStatus::decode(0x1e),
+    };
+    assert_eq!(status.fault(), None);
+    assert_eq!(status.summary(), "Online, paper low, cover closed, drawer open.");
+  }
+
+  #[test]
+  fn process_id_reply_is_found_among_other_bytes() {
+    let mut io = Scripted::new(&[0x12, 0x37, 0x37, 0x22, b'A', b'0', b'0', b'1', 0x00]);
+    await_process_id(&mut io, "test", *b"A001", 10, TIMEOUT).unwrap();
+    let mut io = Scripted::new(&[0x37, 0x22, b'A', b'0', b'0', b'2', 0x00]);
+    let err = await_process_id(&mut io, "test", *b"A001", 10, TIMEOUT).unwrap_err();
+    assert_eq!(err.reason(), codes::MARKER_TIMEOUT);
+  }
+}
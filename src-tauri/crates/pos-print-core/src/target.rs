@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+/// A printer destination as sent by the frontend, e.g.
+/// `{ "transport": "tcp", "host": "10.0.0.20", "port": 9100 }`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "transport", rename_all = "snake_case")]
+pub enum Target {
+  Tcp { host: String, port: u16 },
+  /// `port` is a port name like `COM3`, or a USB printer's stable id from
+  /// `list_serial_ports`, which survives Windows renumbering the port.
+  Serial { port: String, baud: u32 },
+  Spooler {
+    printer_name: String,
+    /// A driver-exported DEVMODEW (duplex, paper, ...) applied to this job
+    /// instead of the printer's defaults.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    devmode: Option<Vec<u8>>,
+  },
+  /// A Windows port device such as `COM3` or a mapped `LPT1`, written to
+  /// directly; see [`winport`](crate::winport).
+  Port { port_name: String },
+}
+
+impl Target {
+  pub fn label(&self) -> String {
+    match self {
+      Target::Tcp { host, port } => format!("tcp://{host}:{port}"),
+      Target::Serial { port, baud } => format!("serial://{port}@{baud}"),
+      Target::Spooler { printer_name, .. } => format!("spooler://{printer_name}"),
+      Target::Port { port_name } => format!("port://{port_name}"),
+    }
+  }
+
+  /// Transport name, also used as the key for per-transport limits.
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Target::Tcp { .. } => "tcp",
+      Target::Serial { .. } => "serial",
+      Target::Spooler { .. } => "spooler",
+      Target::Port { .. } => "port",
+    }
+  }
+
+  /// The target without its DEVMODE, and the DEVMODE. Workers are keyed by
+  /// the former, so every job for one printer shares its queue.
+  pub fn split_devmode(self) -> (Target, Option<Vec<u8>>) {
+    match self {
+      Target::Spooler { printer_name, devmode } => (Target::Spooler { printer_name, devmode: None }, devmode),
+      other => (other, None),
+    }
+  }
+
+  /// Whether this build can reach the target's transport at all.
+  pub fn is_supported(&self) -> bool {
+    let transports = crate::capabilities::capabilities().transports;
+    match self {
+      Target::Tcp { .. } => transports.tcp,
+      Target::Serial { .. } => transports.serial,
+      Target::Spooler { .. } => transports.windows_spooler,
+      Target::Port { .. } => transports.windows_port,
+    }
+  }
+}
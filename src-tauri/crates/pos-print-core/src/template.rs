@@ -0,0 +1,318 @@
+//! Conditions, loops and placeholders in receipt templates, evaluated
+//! against the receipt's `data` before it is parsed.
+//!
+//! An element (usually a section) may carry `when`, and is dropped unless
+//! it holds:
+//! `"when": "tax"` (truthy), `"when": "!tax"` (falsy), or
+//! `"when": { "field": "totals.tax", "op": "gt", "value": 0 }` with `op`
+//! one of `eq ne gt gte lt lte exists`. An element with
+//! `"repeat": "tenders", "as": "tender"` is printed once per array item;
+//! repeats nest up to [`MAX_REPEAT_DEPTH`] deep.
+//! With `data` present, `{{tender.amount}}` in any string is replaced by
+//! the value; a string that is only a placeholder takes the value as is,
+//! so `"amount": "{{total}}"` stays a number.
+//!
+//! Paths are dotted with `[n]` indexes; the first name is a repeat's item
+//! when it matches one, otherwise a key of `data`. A missing field or a
+//! value of the wrong type fails with the template path and the field.
+
+use serde_json::{Map, Value};
+
+use crate::error::PrintError;
+
+/// Repeats nested deeper than this are refused.
+pub const MAX_REPEAT_DEPTH: usize = 4;
+/// Elements a receipt may expand to, so a stray large array can't produce
+/// a roll of paper.
+pub const MAX_EXPANDED_ELEMENTS: usize = 2000;
+
+/// Evaluates `receipt`'s template logic in place and removes its `data`.
+pub fn apply(receipt: &mut Value) -> Result<(), PrintError> {
+  let Some(object) = receipt.as_object_mut() else {
+    return Ok(());
+  };
+  let data = object.remove("data");
+  let Some(elements) = object.get_mut("elements") else {
+    return Ok(());
+  };
+  let mut eval = Eval {
+    data: data.as_ref(),
+    vars: Vec::new(),
+    expanded: 0,
+  };
+  eval.list(elements, "elements", 0)
+}
+
+struct Eval<'a> {
+  /// `None` leaves placeholders alone, for receipts without templating.
+  data: Option<&'a Value>,
+  /// Repeat items in scope, innermost last.
+  vars: Vec<(String, Value)>,
+  expanded: usize,
+}
+
+impl Eval<'_> {
+  fn list(&mut self, elements: &mut Value, path: &str, depth: usize) -> Result<(), PrintError> {
+    let Value::Array(items) = elements else {
+      return Ok(());
+    };
+    let mut out = Vec::with_capacity(items.len());
+    for (i, mut item) in std::mem::take(items).into_iter().enumerate() {
+      let at = format!("{path}[{i}]");
+      let Some(object) = item.as_object_mut() else {
+        out.push(item);
+        continue;
+      };
+      if let Some(when) = object.remove("when") {
+        if !self.condition(&when, &format!("{at}.when"))? {
+          continue;
+        }
+      }
+      let Some(over) = object.remove("repeat") else {
+        self.element(object, &at, depth)?;
+        self.count(&at)?;
+        out.push(item);
+        continue;
+      };
+      let name = match object.remove("as") {
+        Some(Value::String(name)) => name,
+        None => "item".to_string(),
+        Some(_) => return Err(invalid(&format!("{at}.as"), "must be a name")),
+      };
+      let Value::String(field) = over else {
+        return Err(invalid(&format!("{at}.repeat"), "must name an array field"));
+      };
+      if depth >= MAX_REPEAT_DEPTH {
+        return Err(invalid(
+          &format!("{at}.repeat"),
+          &format!("repeats are nested more than {MAX_REPEAT_DEPTH} deep"),
+        ));
+      }
+      let array = match self.lookup(&field, &format!("{at}.repeat"))? {
+        Value::Array(array) => array,
+        other => {
+          return Err(invalid(
+            &format!("{at}.repeat"),
+            &format!("'{field}' is {}, not an array", kind(&other)),
+          ))
+        }
+      };
+      for (n, value) in array.into_iter().enumerate() {
+        let mut copy = Value::Object(object.clone());
+        self.vars.push((name.clone(), value));
+        let copied = copy.as_object_mut().expect("copied from an object");
+        let result = self.element(copied, &format!("{at}#{n}"), depth + 1);
+        self.vars.pop();
+        result?;
+        self.count(&at)?;
+        out.push(copy);
+      }
+    }
+    *items = out;
+    Ok(())
+  }
+
+  /// Expands a section's lists, or fills placeholders in any other element.
+  fn element(&mut self, object: &mut Map<String, Value>, at: &str, depth: usize) -> Result<(), PrintError> {
+    let is_section = object.get("type").and_then(Value::as_str) == Some("section");
+    for (key, value) in object.iter_mut() {
+      let at = format!("{at}.{key}");
+      match key.as_str() {
+        "elements" | "narrow" if is_section => self.list(value, &at, depth)?,
+        _ => self.fill(value, &at)?,
+      }
+    }
+    Ok(())
+  }
+
+  fn fill(&self, value: &mut Value, at: &str) -> Result<(), PrintError> {
+    if self.data.is_none() {
+      return Ok(());
+    }
+    match value {
+      Value::String(text) if text.contains("{{") => *value = self.interpolate(text, at)?,
+      Value::Array(items) => {
+        for (i, item) in items.iter_mut().enumerate() {
+          self.fill(item, &format!("{at}[{i}]"))?;
+        }
+      }
+      Value::Object(object) => {
+        for (key, item) in object.iter_mut() {
+          self.fill(item, &format!("{at}.{key}"))?;
+        }
+      }
+      _ => {}
+    }
+    Ok(())
+  }
+
+  fn interpolate(&self, text: &str, at: &str) -> Result<Value, PrintError> {
+    let trimmed = text.trim();
+    if let Some(field) = trimmed.strip_prefix("{{").and_then(|t| t.strip_suffix("}}")) {
+      if !field.contains("{{") && !field.contains("}}") {
+        return self.lookup(field.trim(), at);
+      }
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+      out.push_str(&rest[..start]);
+      let Some(len) = rest[start..].find("}}") else {
+        return Err(invalid(at, "has a '{{' without a closing '}}'"));
+      };
+      let field = rest[start + 2..start + len].trim();
+      match self.lookup(field, at)? {
+        Value::String(s) => out.push_str(&s),
+        Value::Null => {}
+        v @ (Value::Number(_) | Value::Bool(_)) => out.push_str(&v.to_string()),
+        other => {
+          return Err(invalid(
+            at,
+            &format!("'{field}' is {}, which can't be printed as text", kind(&other)),
+          ))
+        }
+      }
+      rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+    Ok(Value::String(out))
+  }
+
+  fn condition(&self, when: &Value, at: &str) -> Result<bool, PrintError> {
+    let (field, op, expected) = match when {
+      Value::String(field) => match field.trim().strip_prefix('!') {
+        Some(field) => return Ok(!truthy(&self.lookup(field.trim(), at)?)),
+        None => return Ok(truthy(&self.lookup(field.trim(), at)?)),
+      },
+      Value::Object(object) => (
+        object.get("field").and_then(Value::as_str),
+        object.get("op").and_then(Value::as_str).unwrap_or("eq"),
+        object.get("value"),
+      ),
+      _ => return Err(invalid(at, "must be a field name or { field, op, value }")),
+    };
+    let Some(field) = field else {
+      return Err(invalid(at, "needs a field"));
+    };
+    if op == "exists" {
+      return Ok(self.resolve(field).is_some());
+    }
+    let actual = self.lookup(field, at)?;
+    let Some(expected) = expected else {
+      return Err(invalid(at, &format!("'{op}' needs a value to compare with")));
+    };
+    match op {
+      "eq" => Ok(actual == *expected),
+      "ne" => Ok(actual != *expected),
+      "gt" | "gte" | "lt" | "lte" => {
+        let (Some(a), Some(b)) = (actual.as_f64(), expected.as_f64()) else {
+          return Err(invalid(
+            at,
+            &format!(
+              "'{op}' compares numbers, but '{field}' is {} and the value is {}",
+              kind(&actual),
+              kind(expected)
+            ),
+          ));
+        };
+        Ok(match op {
+          "gt" => a > b,
+          "gte" => a >= b,
+          "lt" => a < b,
+          _ => a <= b,
+        })
+      }
+      _ => Err(invalid(at, &format!("unknown op '{op}'; use eq, ne, gt, gte, lt, lte or exists"))),
+    }
+  }
+
+  fn lookup(&self, field: &str, at: &str) -> Result<Value, PrintError> {
+    self
+      .resolve(field)
+      .ok_or_else(|| invalid(at, &format!("field '{field}' is missing from the data")))
+  }
+
+  fn resolve(&self, field: &str) -> Option<Value> {
+    let mut steps = steps(field)?.into_iter();
+    let first = steps.next()?;
+    let mut value = match &first {
+      Step::Key(name) => match self.vars.iter().rev().find(|(var, _)| var == name) {
+        Some((_, item)) => item,
+        None => self.data?.get(name)?,
+      },
+      Step::Index(i) => self.data?.get(*i)?,
+    };
+    for step in steps {
+      value = match step {
+        Step::Key(name) => value.get(&name)?,
+        Step::Index(i) => value.get(i)?,
+      };
+    }
+    Some(value.clone())
+  }
+
+  fn count(&mut self, at: &str) -> Result<(), PrintError> {
+    self.expanded += 1;
+    if self.expanded > MAX_EXPANDED_ELEMENTS {
+      return Err(invalid(
+        at,
+        &format!("the receipt expands to more than {MAX_EXPANDED_ELEMENTS} elements"),
+      ));
+    }
+    Ok(())
+  }
+}
+
+enum Step {
+  Key(String),
+  Index(usize),
+}
+
+/// `a.b[2].c` as steps; `None` if malformed.
+fn steps(field: &str) -> Option<Vec<Step>> {
+  let mut out = Vec::new();
+  for part in field.split('.') {
+    let (key, mut indexes) = part.split_once('[').map_or((part, ""), |(k, rest)| (k, rest));
+    if key.is_empty() && out.is_empty() {
+      return None;
+    }
+    if !key.is_empty() {
+      out.push(Step::Key(key.to_string()));
+    }
+    while !indexes.is_empty() {
+      let (n, rest) = indexes.split_once(']')?;
+      out.push(Step::Index(n.trim().parse().ok()?));
+      indexes = rest.strip_prefix('[').unwrap_or(rest);
+      if !rest.is_empty() && !rest.starts_with('[') {
+        return None;
+      }
+    }
+  }
+  Some(out)
+}
+
+fn truthy(value: &Value) -> bool {
+  match value {
+    Value::Null => false,
+    Value::Bool(b) => *b,
+    Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+    Value::String(s) => !s.is_empty(),
+    Value::Array(a) => !a.is_empty(),
+    Value::Object(o) => !o.is_empty(),
+  }
+}
+
+fn kind(value: &Value) -> &'static str {
+  match value {
+    Value::Null => "null",
+    Value::Bool(_) => "a boolean",
+    Value::Number(_) => "a number",
+    Value::String(_) => "a string",
+    Value::Array(_) => "an array",
+    Value::Object(_) => "an object",
+  }
+}
+
+fn invalid(at: &str, reason: &str) -> PrintError {
+  PrintError::InvalidRequest(format!("{at}: {reason}."))
+}
@@ -0,0 +1,675 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use serialport::SerialPort;
+
+use crate::config::PrintConfig;
+use crate::error::{codes, ErrorDetail, PrintError};
+use crate::resolve;
+use crate::serial;
+use crate::status::{self, PrinterStatus, StatusKind, StatusReport};
+use crate::target::Target;
+
+/// TCP writes are split into slices of this size so progress can be reported
+/// and an abort takes effect between slices.
+const TCP_PROGRESS_SLICE: usize = 4096;
+
+/// Software flow control bytes (DC1/DC3).
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+/// Largest write between XOFF checks, well inside the headroom printers
+/// leave when they send XOFF.
+const XON_XOFF_CHUNK: usize = 64;
+const XOFF_POLL: Duration = Duration::from_millis(10);
+
+/// Bytes a UART's FIFO and shift register can still hold once the driver
+/// reports its queue empty.
+const UART_FIFO_BYTES: u32 = 16;
+const DRAIN_POLL_MAX: Duration = Duration::from_millis(50);
+
+const SERIAL_BUSY_BACKOFF_MIN: Duration = Duration::from_millis(50);
+const SERIAL_BUSY_BACKOFF_MAX: Duration = Duration::from_millis(400);
+
+pub fn tcp_connect(host: &str, port: u16, cfg: &PrintConfig) -> Result<TcpStream, PrintError> {
+  let started = Instant::now();
+  let resolved = tracing::trace_span!("dns", host).in_scope(|| resolve::resolve(host, port, Duration::from_millis(cfg.dns_timeout_ms)));
+  tracing::trace!(dns_ms = started.elapsed().as_millis() as u64, ok = resolved.is_ok(), "dns lookup done");
+  let addr = resolved?.addr;
+
+  let timeout = Duration::from_millis(cfg.connect_timeout_ms);
+  let started = Instant::now();
+  let connected = tracing::trace_span!("tcp_connect", %addr).in_scope(|| TcpStream::connect_timeout(&addr, timeout));
+  tracing::trace!(tcp_connect_ms = started.elapsed().as_millis() as u64, ok = connected.is_ok(), "tcp connect done");
+  let stream = connected.map_err(|e| {
+    let detail = ErrorDetail::new(format!(
+      "TCP connect failed to '{host}:{port}': {e}. Verify printer is online and port 9100 is reachable."
+    ))
+    .param("host", host)
+    .param("port", port)
+    .os_error(&e);
+    PrintError::Connect(match e.kind() {
+      _ if is_timeout(&e) => detail.reason(codes::TCP_CONNECT_TIMEOUT),
+      io::ErrorKind::ConnectionRefused => detail.reason(codes::TCP_CONNECTION_REFUSED),
+      _ => detail,
+    })
+  })?;
+  let _ = stream.set_nodelay(true);
+  Ok(stream)
+}
+
+pub fn tcp_write(
+  stream: &mut TcpStream,
+  host: &str,
+  port: u16,
+  data: &[u8],
+  cfg: &PrintConfig,
+  progress: &mut dyn FnMut(usize) -> Result<(), PrintError>,
+) -> Result<(), PrintError> {
+  let label = Target::Tcp { host: host.to_string(), port }.label();
+  log::debug!(
+    "tcp write to {host}:{port}: {} bytes, up to {} ms per {TCP_PROGRESS_SLICE}-byte slice",
+    data.len(),
+    cfg.chunk_write_timeout(&label, TCP_PROGRESS_SLICE, None).as_millis()
+  );
+  let pacing = cfg.pacing(&label);
+  let burst = pacing.map_or(data.len(), |p| p.max_burst_bytes.max(1));
+  let slice_len = TCP_PROGRESS_SLICE.min(burst.max(1));
+  let mut sent = 0;
+  for slice in data.chunks(burst.max(1)).flat_map(|b| b.chunks(slice_len)) {
+    if let Some(pacing) = pacing.filter(|_| sent > 0 && sent % burst == 0) {
+      let _ = stream.flush();
+      if pacing.wait_for_ready {
+        wait_until_ready(stream, &label, cfg)?;
+      }
+      std::thread::sleep(Duration::from_millis(pacing.inter_burst_delay_ms));
+    }
+    let timeout = cfg.chunk_write_timeout(&label, slice.len(), None);
+    let _ = stream.set_write_timeout(Some(timeout));
+    stream.write_all(slice).map_err(|e| {
+      if is_timeout(&e) {
+        PrintError::Timeout(
+          ErrorDetail::new(format!(
+            "TCP write to '{host}:{port}' timed out after {} ms on a {}-byte chunk ({} ms base + {} B/s) with {sent} of {} bytes sent. The printer may be stalled; raise the write timeout for slow links.",
+            timeout.as_millis(),
+            slice.len(),
+            cfg.write_timeout(&label),
+            cfg.write_throughput(&label, None),
+            data.len()
+          ))
+          .reason(codes::TCP_WRITE_TIMEOUT)
+          .param("host", host)
+          .param("port", port)
+          .param("timeout_ms", timeout.as_millis()),
+        )
+      } else {
+        PrintError::Write(
+          ErrorDetail::new(format!(
+            "TCP write failed to '{host}:{port}': {e}. Check network stability and printer state."
+          ))
+          .param("host", host)
+          .param("port", port)
+          .os_error(&e),
+        )
+      }
+    })?;
+    sent += slice.len();
+    progress(sent)?;
+  }
+  let _ = stream.flush();
+  Ok(())
+}
+
+/// Polls DLE EOT 1 until the printer reports itself online, for up to
+/// the destination's write timeout.
+fn wait_until_ready(stream: &mut TcpStream, label: &str, cfg: &PrintConfig) -> Result<(), PrintError> {
+  let timeout = Duration::from_millis(cfg.status_timeout_ms);
+  let write_timeout_ms = cfg.write_timeout(label);
+  let deadline = Instant::now() + Duration::from_millis(write_timeout_ms);
+  let _ = stream.set_read_timeout(Some(timeout));
+  loop {
+    let byte = status::request_byte(stream, label, StatusKind::Printer, timeout)?;
+    if PrinterStatus::decode(byte).online {
+      return Ok(());
+    }
+    if Instant::now() >= deadline {
+      return Err(PrintError::Timeout(
+        ErrorDetail::new(format!(
+          "{label} stayed busy for {write_timeout_ms} ms between bursts. Lower max_burst_bytes or raise inter_burst_delay_ms in its tcp_pacing."
+        ))
+        .reason(codes::TCP_WRITE_TIMEOUT)
+        .param("printer", label)
+        .param("timeout_ms", write_timeout_ms),
+      ));
+    }
+    std::thread::sleep(Duration::from_millis(50));
+  }
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+  matches!(e.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock)
+}
+
+pub fn tcp_is_stale(stream: &TcpStream) -> bool {
+  if stream.set_nonblocking(true).is_err() {
+    return true;
+  }
+  let mut probe = [0u8; 1];
+  let stale = match stream.peek(&mut probe) {
+    Ok(0) => true,
+    Ok(_) => false,
+    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => false,
+    Err(_) => true,
+  };
+  if stream.set_nonblocking(false).is_err() {
+    return true;
+  }
+  stale
+}
+
+pub fn tcp_send(host: &str, port: u16, data: &[u8], cfg: &PrintConfig) -> Result<(), PrintError> {
+  let mut stream = tcp_connect(host, port, cfg)?;
+  tcp_write(&mut stream, host, port, data, cfg, &mut |_| Ok(()))?;
+  let label = Target::Tcp { host: host.to_string(), port }.label();
+  std::thread::sleep(cfg.post_write_delay(&label));
+  Ok(())
+}
+
+pub fn tcp_status(host: &str, port: u16, cfg: &PrintConfig) -> Result<StatusReport, PrintError> {
+  let mut stream = tcp_connect(host, port, cfg)?;
+  let timeout = Duration::from_millis(cfg.status_timeout_ms);
+  let label = Target::Tcp { host: host.to_string(), port }.label();
+  let _ = stream.set_write_timeout(Some(Duration::from_millis(cfg.write_timeout(&label))));
+  let _ = stream.set_read_timeout(Some(timeout));
+  status::query(&mut stream, &format!("'{host}:{port}'"), timeout)
+}
+
+/// Opens `port`, retrying for up to `serial_busy_retry_ms` while it reports
+/// busy. Windows refuses to reopen a COM port for a moment after it was
+/// closed, which is what two back-to-back receipts run into.
+///
+/// `port` may be a stable id (see [`serial::resolve_port`]), looked up again
+/// on every open so a printer that moved to another COM number is found.
+pub fn serial_open(port: &str, baud: u32, cfg: &PrintConfig) -> Result<Box<dyn SerialPort>, PrintError> {
+  let write_timeout = Duration::from_millis(cfg.write_timeout(&Target::Serial { port: port.to_string(), baud }.label()));
+  let resolved = serial::resolve_port(port)?;
+  if resolved != port {
+    log::debug!("{port} is currently {resolved}");
+  }
+  let port = resolved.as_str();
+  let deadline = Instant::now() + Duration::from_millis(cfg.serial_busy_retry_ms);
+  let mut backoff = SERIAL_BUSY_BACKOFF_MIN;
+  loop {
+    match serialport::new(port, baud)
+      .timeout(write_timeout)
+      .open()
+    {
+      Ok(sp) => return Ok(sp),
+      Err(e) if is_port_busy(&e) && Instant::now() + backoff < deadline => {
+        log::debug!("serial port {port} busy ({e}), retrying in {} ms", backoff.as_millis());
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(SERIAL_BUSY_BACKOFF_MAX);
+      }
+      Err(e) if is_port_busy(&e) => {
+        return Err(PrintError::SerialOpen(
+          ErrorDetail::new(format!(
+            "Unable to open serial port {port}: {e}. The port appears to be held by another process (still busy after {} ms); close other apps using it or reconnect the printer.",
+            cfg.serial_busy_retry_ms
+          ))
+          .reason(codes::SERIAL_PORT_BUSY)
+          .param("port", port)
+          .param("waited_ms", cfg.serial_busy_retry_ms),
+        ))
+      }
+      Err(e) => {
+        let detail = ErrorDetail::new(format!(
+          "Unable to open serial port {port} at {baud} baud: {e}. Check COM port, pairing, and driver."
+        ))
+        .param("port", port)
+        .param("baud", baud);
+        return Err(PrintError::SerialOpen(match e.kind() {
+          serialport::ErrorKind::NoDevice | serialport::ErrorKind::Io(io::ErrorKind::NotFound) => {
+            detail.reason(codes::SERIAL_PORT_NOT_FOUND)
+          }
+          _ => detail,
+        }));
+      }
+    }
+  }
+}
+
+/// Access denied (Windows) or busy (Unix lock) on open; both clear once the
+/// previous holder lets go.
+fn is_port_busy(e: &serialport::Error) -> bool {
+  if let serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied) = e.kind() {
+    return true;
+  }
+  let description = e.description.to_ascii_lowercase();
+  description.contains("access is denied") || description.contains("busy")
+}
+
+/// An open serial port. With `xon_xoff`, writes through [`serial_write`]
+/// pause while the printer has sent XOFF, and reads never return XON or
+/// XOFF, so status replies and identity strings arrive clean.
+pub struct SerialLink {
+  pub port: Box<dyn SerialPort>,
+  /// `Target::label`, for per-destination settings.
+  label: String,
+  xon_xoff: bool,
+  paused: bool,
+  /// Bytes read while watching for XON/XOFF, held for the next read.
+  pending: VecDeque<u8>,
+}
+
+impl SerialLink {
+  pub fn new(port: Box<dyn SerialPort>, label: String, xon_xoff: bool) -> Self {
+    Self {
+      port,
+      label,
+      xon_xoff,
+      paused: false,
+      pending: VecDeque::new(),
+    }
+  }
+
+  /// Opens `port` with XON/XOFF if `cfg` lists it.
+  pub fn open(port: &str, baud: u32, cfg: &PrintConfig) -> Result<Self, PrintError> {
+    let label = Target::Serial { port: port.to_string(), baud }.label();
+    let xon_xoff = cfg.xon_xoff.contains(&label);
+    Ok(Self::new(serial_open(port, baud, cfg)?, label, xon_xoff))
+  }
+
+  fn take(&mut self, bytes: &[u8]) {
+    for &b in bytes {
+      match b {
+        XON => self.paused = false,
+        XOFF => self.paused = true,
+        _ => self.pending.push_back(b),
+      }
+    }
+  }
+
+  /// Reads whatever has arrived, without waiting.
+  fn poll(&mut self) -> io::Result<()> {
+    let available = self.port.bytes_to_read()? as usize;
+    if available > 0 {
+      let mut buf = vec![0; available];
+      let n = self.port.read(&mut buf)?;
+      self.take(&buf[..n]);
+    }
+    Ok(())
+  }
+
+  /// Returns once the printer isn't holding us off with XOFF, failing if
+  /// it stays that way for `stall`.
+  fn wait_for_xon(&mut self, port: &str, stall: Duration, sent: usize, total: usize) -> Result<(), PrintError> {
+    let read_failed = |e: io::Error| PrintError::Read(format!("Reading flow control from {port} failed: {e}.").into());
+    self.poll().map_err(read_failed)?;
+    if !self.paused {
+      return Ok(());
+    }
+    log::debug!("{port} sent XOFF after {sent} of {total} bytes, waiting for XON");
+    let deadline = Instant::now() + stall;
+    while self.paused {
+      if Instant::now() >= deadline {
+        return Err(PrintError::Timeout(
+          ErrorDetail::new(format!(
+            "{port} sent XOFF and no XON within {} ms, with {sent} of {total} bytes sent. Check the printer for paper or errors.",
+            stall.as_millis()
+          ))
+          .reason(codes::SERIAL_WRITE_TIMEOUT)
+          .param("port", port)
+          .param("timeout_ms", stall.as_millis()),
+        ));
+      }
+      std::thread::sleep(XOFF_POLL);
+      self.poll().map_err(read_failed)?;
+    }
+    Ok(())
+  }
+}
+
+impl Read for SerialLink {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if !self.xon_xoff {
+      return self.port.read(buf);
+    }
+    loop {
+      if !self.pending.is_empty() {
+        let n = buf.len().min(self.pending.len());
+        for (slot, b) in buf.iter_mut().zip(self.pending.drain(..n)) {
+          *slot = b;
+        }
+        return Ok(n);
+      }
+      let mut raw = vec![0; buf.len().max(1)];
+      let n = self.port.read(&mut raw)?;
+      if n == 0 {
+        return Ok(0);
+      }
+      // Flow control bytes alone aren't data; read again.
+      self.take(&raw[..n]);
+    }
+  }
+}
+
+impl Write for SerialLink {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.port.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.port.flush()
+  }
+}
+
+pub fn serial_write(
+  link: &mut SerialLink,
+  port: &str,
+  data: &[u8],
+  cfg: &PrintConfig,
+  progress: &mut dyn FnMut(usize) -> Result<(), PrintError>,
+) -> Result<(), PrintError> {
+  // Roughly 10 bits per byte on the wire (start + 8 data + stop).
+  let line_bps = link.port.baud_rate().ok().map(|baud| u64::from(baud) / 10);
+  let chunk_size = if link.xon_xoff {
+    cfg.chunk_size.clamp(1, XON_XOFF_CHUNK)
+  } else {
+    cfg.chunk_size.max(1)
+  };
+  log::debug!(
+    "serial write to {port}: {} bytes, up to {} ms per {chunk_size}-byte chunk",
+    data.len(),
+    cfg.chunk_write_timeout(&link.label, chunk_size, line_bps).as_millis()
+  );
+  let mut sent = 0;
+  let mut last_chunk = 0;
+  for chunk in data.chunks(chunk_size) {
+    last_chunk = chunk.len();
+    if link.xon_xoff {
+      link.wait_for_xon(port, Duration::from_millis(cfg.xoff_stall_ms), sent, data.len())?;
+    }
+    let timeout = cfg.chunk_write_timeout(&link.label, chunk.len(), line_bps);
+    let _ = link.port.set_timeout(timeout);
+    link.port.write_all(chunk).map_err(|e| {
+      if is_timeout(&e) {
+        PrintError::Timeout(
+          ErrorDetail::new(format!(
+            "Serial write on {port} timed out after {} ms on a {}-byte chunk ({} ms base + {} B/s) with {sent} of {} bytes sent. Check flow control and printer readiness.",
+            timeout.as_millis(),
+            chunk.len(),
+            cfg.write_timeout(&link.label),
+            cfg.write_throughput(&link.label, line_bps),
+            data.len()
+          ))
+          .reason(codes::SERIAL_WRITE_TIMEOUT)
+          .param("port", port)
+          .param("timeout_ms", timeout.as_millis()),
+        )
+      } else {
+        PrintError::Write(
+          ErrorDetail::new(format!(
+            "Serial write failed on {port}: {e}. Check cable/pairing and printer readiness."
+          ))
+          .param("port", port)
+          .os_error(&e),
+        )
+      }
+    })?;
+    sent += chunk.len();
+    progress(sent)?;
+    std::thread::sleep(Duration::from_millis(cfg.chunk_delay_ms));
+  }
+
+  link
+    .port
+    .flush()
+    .map_err(|e| PrintError::Write(format!("Serial flush failed on {port}: {e}. Printer may be offline or busy.").into()))?;
+  serial_drain(link.port.as_ref(), port, &link.label, line_bps, last_chunk, cfg)
+}
+
+/// Waits until the bytes written are on the wire. `flush` only empties the
+/// library's buffer; a port closed with bytes still queued in the driver or
+/// UART can lose the end of a receipt, usually its cut. Drivers that can't
+/// report their queue get a wait long enough for `last_chunk` instead.
+fn serial_drain(
+  sp: &dyn SerialPort,
+  port: &str,
+  label: &str,
+  line_bps: Option<u64>,
+  last_chunk: usize,
+  cfg: &PrintConfig,
+) -> Result<(), PrintError> {
+  // 9600 baud when the port won't say, slower than any receipt printer.
+  let byte_time = Duration::from_micros(1_000_000 / line_bps.unwrap_or(960).max(1));
+  let started = Instant::now();
+  let mut timeout = None;
+  loop {
+    let queued = match sp.bytes_to_write() {
+      Ok(0) => break,
+      Ok(queued) => queued,
+      Err(e) => {
+        log::debug!("{port} can't report its transmit queue ({e}), waiting out the last chunk");
+        std::thread::sleep(byte_time * last_chunk as u32);
+        break;
+      }
+    };
+    let timeout = *timeout.get_or_insert_with(|| cfg.chunk_write_timeout(label, queued as usize, line_bps));
+    if started.elapsed() >= timeout {
+      return Err(PrintError::Timeout(
+        ErrorDetail::new(format!(
+          "{port} still had {queued} bytes waiting to be sent at the end of the job. Check flow control and printer readiness."
+        ))
+        .reason(codes::SERIAL_WRITE_TIMEOUT)
+        .param("port", port)
+        .param("timeout_ms", timeout.as_millis()),
+      ));
+    }
+    std::thread::sleep((byte_time * queued).min(DRAIN_POLL_MAX));
+  }
+  std::thread::sleep(byte_time * UART_FIFO_BYTES);
+  Ok(())
+}
+
+/// Holds the line in a BREAK condition for `duration`, then releases it.
+/// Printers that ignore data while wedged often still reset on a break.
+pub fn serial_break(sp: &dyn SerialPort, port: &str, duration: Duration) -> Result<(), PrintError> {
+  sp.set_break()
+    .map_err(|e| PrintError::Write(format!("Unable to assert BREAK on {port}: {e}.").into()))?;
+  std::thread::sleep(duration);
+  sp.clear_break().map_err(|e| {
+    PrintError::Write(format!(
+      "Unable to clear BREAK on {port}: {e}. Reconnect the printer if it stays unresponsive."
+    ).into())
+  })
+}
+
+pub fn serial_send(port: &str, baud: u32, data: &[u8], cfg: &PrintConfig) -> Result<(), PrintError> {
+  let mut link = SerialLink::open(port, baud, cfg)?;
+  serial_write(&mut link, port, data, cfg, &mut |_| Ok(()))?;
+  let label = Target::Serial { port: port.to_string(), baud }.label();
+  std::thread::sleep(cfg.post_write_delay(&label));
+  Ok(())
+}
+
+pub fn serial_status(port: &str, baud: u32, cfg: &PrintConfig) -> Result<StatusReport, PrintError> {
+  let mut link = SerialLink::open(port, baud, cfg)?;
+  let timeout = Duration::from_millis(cfg.status_timeout_ms);
+  let _ = link.port.set_timeout(timeout);
+  status::query(&mut link, port, timeout)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::Cell;
+  use std::sync::{Arc, Mutex};
+
+  use serialport::{ClearBuffer, DataBits, FlowControl, Parity, StopBits};
+
+  use super::*;
+
+  type Events = Arc<Mutex<Vec<String>>>;
+
+  /// Records writes, flushes, transmit queue polls and the close (drop).
+  /// Written bytes count as sent once a poll has seen them queued. Without
+  /// `reports_queue`, polls fail like drivers that can't report the queue.
+  struct MockPort {
+    events: Events,
+    queued: Cell<u32>,
+    reports_queue: bool,
+  }
+
+  impl MockPort {
+    fn boxed(events: &Events, reports_queue: bool) -> Box<dyn SerialPort> {
+      Box::new(Self {
+        events: events.clone(),
+        queued: Cell::new(0),
+        reports_queue,
+      })
+    }
+
+    fn log(&self, event: String) {
+      self.events.lock().unwrap().push(event);
+    }
+  }
+
+  impl Drop for MockPort {
+    fn drop(&mut self) {
+      self.log("close".to_string());
+    }
+  }
+
+  impl Read for MockPort {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+      Err(io::ErrorKind::TimedOut.into())
+    }
+  }
+
+  impl Write for MockPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.log(format!("write {}", buf.len()));
+      self.queued.set(self.queued.get() + buf.len() as u32);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      self.log("flush".to_string());
+      Ok(())
+    }
+  }
+
+  impl SerialPort for MockPort {
+    fn name(&self) -> Option<String> {
+      Some("COM9".to_string())
+    }
+    fn baud_rate(&self) -> serialport::Result<u32> {
+      Ok(115_200)
+    }
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+      Ok(DataBits::Eight)
+    }
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+      Ok(FlowControl::None)
+    }
+    fn parity(&self) -> serialport::Result<Parity> {
+      Ok(Parity::None)
+    }
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+      Ok(StopBits::One)
+    }
+    fn timeout(&self) -> Duration {
+      Duration::from_secs(1)
+    }
+    fn set_baud_rate(&mut self, _baud_rate: u32) -> serialport::Result<()> {
+      Ok(())
+    }
+    fn set_data_bits(&mut self, _data_bits: DataBits) -> serialport::Result<()> {
+      Ok(())
+    }
+    fn set_flow_control(&mut self, _flow_control: FlowControl) -> serialport::Result<()> {
+      Ok(())
+    }
+    fn set_parity(&mut self, _parity: Parity) -> serialport::Result<()> {
+      Ok(())
+    }
+    fn set_stop_bits(&mut self, _stop_bits: StopBits) -> serialport::Result<()> {
+      Ok(())
+    }
+    fn set_timeout(&mut self, _timeout: Duration) -> serialport::Result<()> {
+      Ok(())
+    }
+    fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+      Ok(())
+    }
+    fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+      Ok(())
+    }
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+      Ok(true)
+    }
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+      Ok(true)
+    }
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+      Ok(false)
+    }
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+      Ok(false)
+    }
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+      Ok(0)
+    }
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+      if !self.reports_queue {
+        self.log("queue unknown".to_string());
+        return Err(serialport::Error::new(serialport::ErrorKind::Unknown, "not supported"));
+      }
+      let queued = self.queued.replace(0);
+      self.log(format!("queue {queued}"));
+      Ok(queued)
+    }
+    fn clear(&self, _buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+      Ok(())
+    }
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+      Err(serialport::Error::new(serialport::ErrorKind::Unknown, "not supported"))
+    }
+    fn set_break(&self) -> serialport::Result<()> {
+      Ok(())
+    }
+    fn clear_break(&self) -> serialport::Result<()> {
+      Ok(())
+    }
+  }
+
+  /// Writes `data` through a mock port, closes it and returns its events.
+  fn write_then_close(reports_queue: bool, data: &[u8]) -> Vec<String> {
+    let events = Events::default();
+    let cfg = PrintConfig {
+      chunk_size: 4,
+      chunk_delay_ms: 0,
+      ..PrintConfig::default()
+    };
+    let mut link = SerialLink::new(MockPort::boxed(&events, reports_queue), "serial://COM9@115200".to_string(), false);
+    let result = serial_write(&mut link, "COM9", data, &cfg, &mut |_| Ok(()));
+    drop(link);
+    let events = events.lock().unwrap().clone();
+    if let Err(e) = result {
+      panic!("{e}; events: {events:?}");
+    }
+    events
+  }
+
+  #[test]
+  fn serial_write_drains_before_the_port_closes() {
+    let events = write_then_close(true, b"receipt!");
+    assert_eq!(events, ["write 4", "write 4", "flush", "queue 8", "queue 0", "close"]);
+  }
+
+  #[test]
+  fn serial_write_waits_out_an_unreported_queue_before_closing() {
+    let events = write_then_close(false, b"receipt!");
+    assert_eq!(events, ["write 4", "write 4", "flush", "queue unknown", "close"]);
+  }
+}
@@ -0,0 +1,350 @@
+//! Receipts drawn entirely in a TrueType/OpenType font and printed as raster.
+//!
+//! For printers whose built-in fonts are poor or lack glyphs, and for shops
+//! that want the receipt in their own typeface. Lines break on real glyph
+//! advances, are drawn 1-bit at the head's dot width, and leave as GS v 0
+//! bands while the rest is still being drawn, so memory stays at one band
+//! plus one line however long the receipt is. Barcodes, QR codes, cuts and
+//! drawer kicks are still sent as native commands between bands.
+
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+
+use crate::error::PrintError;
+use crate::escpos::{self, Align, EscPosBuilder};
+use crate::format::Field;
+use crate::printer_info;
+use crate::raster::{self, BAND_HEIGHT};
+use crate::receipt::{ColumnSpec, Element, Receipt};
+
+pub const DEFAULT_FONT_SIZE_PX: f32 = 24.0;
+const BARCODE_HEIGHT: u8 = 80;
+const QR_MODULE_SIZE: u8 = 6;
+/// Glyph coverage at or above this prints as a dot.
+const COVERAGE_THRESHOLD: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TypesetOptions {
+  /// Printable width of the head; the raster is exactly this wide.
+  pub dot_width: u32,
+  /// Em size in dots for text at width and height 1.
+  pub font_size_px: f32,
+}
+
+impl TypesetOptions {
+  pub fn new(dot_width: Option<u32>, font_size_px: Option<f32>) -> Result<Self, PrintError> {
+    let dot_width = dot_width.unwrap_or(raster::DEFAULT_MAX_WIDTH);
+    if !(192..=1152).contains(&dot_width) || dot_width % 8 != 0 {
+      return Err(PrintError::InvalidRequest(format!(
+        "dot_width must be a multiple of 8 between 192 and 1152, got {dot_width}."
+      )));
+    }
+    let font_size_px = font_size_px.unwrap_or(DEFAULT_FONT_SIZE_PX);
+    if !(8.0..=128.0).contains(&font_size_px) {
+      return Err(PrintError::InvalidRequest(format!(
+        "font_size_px must be between 8 and 128, got {font_size_px}."
+      )));
+    }
+    Ok(Self { dot_width, font_size_px })
+  }
+}
+
+/// A parsed font file, reusable across receipts.
+pub struct TtfFont(FontVec);
+
+impl TtfFont {
+  pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, PrintError> {
+    FontVec::try_from_vec(bytes)
+      .map(Self)
+      .map_err(|e| PrintError::InvalidRequest(format!("The font is not a usable TrueType or OpenType file: {e}.")))
+  }
+}
+
+/// Renders `receipt` in `font` as ESC/POS, text as banded GS v 0 raster.
+pub fn render_escpos(receipt: &Receipt, font: &TtfFont, opts: TypesetOptions) -> Result<Vec<u8>, PrintError> {
+  crate::receipt::check_fields(receipt)?;
+  let mut b = EscPosBuilder::new();
+  b.init();
+  if let Some(level) = receipt.density {
+    crate::receipt::check_density(level)?;
+    b.density(level);
+  }
+  let mut t = Typesetter {
+    font: &font.0,
+    size: opts.font_size_px,
+    width: opts.dot_width,
+    width_bytes: (opts.dot_width / 8) as usize,
+    band: Vec::new(),
+    band_rows: 0,
+    out: b,
+    model: receipt.printer_model.as_deref(),
+  };
+  for element in &receipt.elements {
+    t.element(element);
+  }
+  t.flush();
+  t.out.align(Align::Left);
+  Ok(t.out.into_bytes())
+}
+
+struct Typesetter<'a> {
+  font: &'a FontVec,
+  size: f32,
+  width: u32,
+  width_bytes: usize,
+  /// Rows drawn but not yet sent, at most `BAND_HEIGHT`.
+  band: Vec<u8>,
+  band_rows: u32,
+  out: EscPosBuilder,
+  /// `Receipt::printer_model`, for choosing cuts.
+  model: Option<&'a str>,
+}
+
+impl Typesetter<'_> {
+  fn element(&mut self, element: &Element) {
+    let full = self.width as f32;
+    match element {
+      Element::Text {
+        text,
+        align,
+        bold,
+        width,
+        height,
+      } => {
+        let scale = self.scale((*width).clamp(1, 8), (*height).clamp(1, 8));
+        for line in self.wrap(&text.display(false), full, scale) {
+          let x = aligned_x(*align, self.measure(&line, scale), full);
+          self.draw_line(&[(x, line.as_str())], scale, *bold);
+        }
+      }
+      Element::Row { left, right, bold } => {
+        let scale = self.scale(1, 1);
+        let right = self.truncate(&right.display(false), full, scale);
+        let right_w = self.measure(&right, scale);
+        let room = full - right_w - self.measure(" ", scale);
+        let left = self.truncate(&left.display(false), room.max(0.0), scale);
+        self.draw_line(&[(0.0, left.as_str()), (full - right_w, right.as_str())], scale, *bold);
+      }
+      Element::Divider { ch } => {
+        let scale = self.scale(1, 1);
+        let ch = if ch.is_whitespace() || ch.is_control() { '-' } else { *ch };
+        let advance = self.measure(&ch.to_string(), scale);
+        let count = if advance > 0.0 { (full / advance) as usize } else { 0 };
+        self.draw_line(&[(0.0, ch.to_string().repeat(count).as_str())], scale, false);
+      }
+      Element::Table { columns, rows, bold, .. } => self.table(columns, rows, *bold),
+      Element::Section {
+        elements,
+        min_columns,
+        narrow,
+        ..
+      } => {
+        // Measured in this font's digits, the closest to a character column.
+        let columns = (full / self.measure("0", self.scale(1, 1)).max(1.0)) as usize;
+        let chosen = if min_columns.is_some_and(|min| columns < min) { narrow } else { elements };
+        for element in chosen {
+          self.element(element);
+        }
+      }
+      Element::Feed { lines } => {
+        let rows = self.line_height(self.scale(1, 1)) * *lines as u32;
+        self.push_rows(&vec![0; self.width_bytes * rows as usize]);
+      }
+      Element::Barcode { data, hri, hri_font } => {
+        self.flush();
+        let data = escpos::printable(data, false);
+        self
+          .out
+          .align(Align::Center)
+          .barcode_code128(&data, BARCODE_HEIGHT, *hri, *hri_font)
+          .newline();
+      }
+      Element::Qr { data } => {
+        self.flush();
+        self.out.align(Align::Center).qr(data, QR_MODULE_SIZE).newline();
+      }
+      Element::Cut { kind, force } => {
+        self.flush();
+        self.out.feed(3).cut(printer_info::cut_for(*kind, *force, self.model));
+      }
+      Element::DrawerKick { pin, pulse_ms } => {
+        self.flush();
+        self.out.drawer_kick(*pin, *pulse_ms);
+      }
+    }
+  }
+
+  /// Columns keep their character widths as shares of the line, one
+  /// character's share apart; cells wrap within their column.
+  fn table(&mut self, specs: &[ColumnSpec], rows: &[Vec<Field>], bold: bool) {
+    let chars = specs.iter().map(|c| c.width).sum::<usize>() + specs.len().saturating_sub(1);
+    if specs.is_empty() || specs.iter().any(|c| c.width == 0) || rows.iter().any(|r| r.len() > specs.len()) {
+      log::warn!("skipping table element: columns are empty or a row has more cells than columns");
+      return;
+    }
+    let unit = self.width as f32 / chars as f32;
+    let scale = self.scale(1, 1);
+    for row in rows {
+      let mut x = 0.0;
+      let mut cells = Vec::with_capacity(specs.len());
+      for (i, spec) in specs.iter().enumerate() {
+        let col_w = spec.width as f32 * unit;
+        let text = row.get(i).map(|cell| cell.display(false)).unwrap_or_default();
+        let lines = self.wrap(&text, col_w, scale);
+        cells.push((x, col_w, spec.align, lines));
+        x += col_w + unit;
+      }
+      let height = cells.iter().map(|c| c.3.len()).max().unwrap_or(1);
+      for line in 0..height {
+        let runs = cells
+          .iter()
+          .filter_map(|(x, col_w, align, lines)| {
+            let text = lines.get(line)?;
+            Some((x + aligned_x(*align, self.measure(text, scale), *col_w), text.as_str()))
+          })
+          .collect::<Vec<_>>();
+        self.draw_line(&runs, scale, bold);
+      }
+    }
+  }
+
+  fn scale(&self, width: u8, height: u8) -> PxScale {
+    PxScale {
+      x: self.size * width as f32,
+      y: self.size * height as f32,
+    }
+  }
+
+  fn line_height(&self, scale: PxScale) -> u32 {
+    let font = self.font.as_scaled(scale);
+    (font.ascent() - font.descent() + font.line_gap()).ceil().max(1.0) as u32
+  }
+
+  /// Advance width of `text` in dots, kerning included.
+  fn measure(&self, text: &str, scale: PxScale) -> f32 {
+    let font = self.font.as_scaled(scale);
+    let mut width = 0.0;
+    let mut prev = None;
+    for ch in text.chars() {
+      let id = font.glyph_id(ch);
+      if let Some(prev) = prev {
+        width += font.kern(prev, id);
+      }
+      width += font.h_advance(id);
+      prev = Some(id);
+    }
+    width
+  }
+
+  /// Word-wraps `text` to `max` dots, splitting words wider than a line.
+  fn wrap(&self, text: &str, max: f32, scale: PxScale) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+      let candidate = if current.is_empty() { word.to_string() } else { format!("{current} {word}") };
+      if self.measure(&candidate, scale) <= max {
+        current = candidate;
+        continue;
+      }
+      if !current.is_empty() {
+        lines.push(std::mem::take(&mut current));
+      }
+      for ch in word.chars() {
+        current.push(ch);
+        if current.chars().count() > 1 && self.measure(&current, scale) > max {
+          current.pop();
+          lines.push(std::mem::replace(&mut current, ch.to_string()));
+        }
+      }
+    }
+    if !current.is_empty() || lines.is_empty() {
+      lines.push(current);
+    }
+    lines
+  }
+
+  fn truncate(&self, text: &str, max: f32, scale: PxScale) -> String {
+    let mut text = text.to_string();
+    while !text.is_empty() && self.measure(&text, scale) > max {
+      text.pop();
+    }
+    text
+  }
+
+  /// Draws one line holding `runs` of text at their x offsets.
+  fn draw_line(&mut self, runs: &[(f32, &str)], scale: PxScale, bold: bool) {
+    let rows = self.line_height(scale);
+    let width_bytes = self.width_bytes;
+    let mut bits = vec![0u8; self.width_bytes * rows as usize];
+    let font = self.font.as_scaled(scale);
+    let baseline = font.ascent() + font.line_gap() / 2.0;
+    for &(x, text) in runs {
+      let mut caret = x;
+      let mut prev = None;
+      for ch in text.chars() {
+        let id = font.glyph_id(ch);
+        if let Some(prev) = prev {
+          caret += font.kern(prev, id);
+        }
+        let glyph = id.with_scale_and_position(scale, point(caret, baseline));
+        caret += font.h_advance(id);
+        prev = Some(id);
+        let Some(outlined) = self.font.outline_glyph(glyph) else {
+          continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|gx, gy, coverage| {
+          if coverage < COVERAGE_THRESHOLD {
+            return;
+          }
+          let (px, py) = (bounds.min.x as i64 + gx as i64, bounds.min.y as i64 + gy as i64);
+          for dx in 0..=i64::from(bold) {
+            set_dot(&mut bits, width_bytes, rows, px + dx, py);
+          }
+        });
+      }
+    }
+    self.push_rows(&bits);
+  }
+
+  /// Appends whole rows to the band, sending each band as soon as it fills.
+  fn push_rows(&mut self, bits: &[u8]) {
+    for row in bits.chunks(self.width_bytes) {
+      self.band.extend_from_slice(row);
+      self.band_rows += 1;
+      if self.band_rows == BAND_HEIGHT {
+        self.flush();
+      }
+    }
+  }
+
+  fn flush(&mut self) {
+    if self.band_rows == 0 {
+      return;
+    }
+    let (x, y) = (self.width_bytes as u16, self.band_rows as u16);
+    self
+      .out
+      .align(Align::Left)
+      .raw(&[escpos::GS, b'v', b'0', 0])
+      .raw(&x.to_le_bytes())
+      .raw(&y.to_le_bytes())
+      .raw(&self.band);
+    self.band.clear();
+    self.band_rows = 0;
+  }
+}
+
+fn aligned_x(align: Align, content: f32, room: f32) -> f32 {
+  match align {
+    Align::Left => 0.0,
+    Align::Center => ((room - content) / 2.0).max(0.0),
+    Align::Right => (room - content).max(0.0),
+  }
+}
+
+fn set_dot(bits: &mut [u8], width_bytes: usize, rows: u32, x: i64, y: i64) {
+  if x < 0 || y < 0 || x as usize >= width_bytes * 8 || y >= rows as i64 {
+    return;
+  }
+  bits[y as usize * width_bytes + x as usize / 8] |= 0x80 >> (x % 8);
+}
@@ -0,0 +1,233 @@
+//! Step-by-step check of a printer setup before it is saved, so an
+//! installer can see which step fails: resolve the target, connect, read
+//! status, read the model, and print a small alignment mark.
+//!
+//! A failed step skips the steps that depend on it. Spooler queues have no
+//! real-time channel, so their status and model steps are skipped and the
+//! mark goes through the spooler.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PrintConfig;
+use crate::error::{PrintError, PrinterFault};
+use crate::escpos;
+use crate::printer_info;
+use crate::recovery;
+use crate::resolve;
+use crate::serial;
+use crate::spooler;
+use crate::status;
+use crate::target::Target;
+use crate::winport;
+use crate::workers::Duplex;
+
+/// Rows of the alignment mark; enough to see centering and the cut.
+const MARK_LINES: usize = 2;
+
+/// The printer setup being checked.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PrinterProfile {
+  pub target: Target,
+  /// Characters per line, for the alignment mark.
+  #[serde(default = "default_columns")]
+  pub columns: usize,
+  /// Print the alignment mark; off checks without using paper.
+  #[serde(default = "default_test_print")]
+  pub test_print: bool,
+}
+
+fn default_columns() -> usize {
+  48
+}
+
+fn default_test_print() -> bool {
+  true
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationStep {
+  Resolve,
+  Connect,
+  Status,
+  Model,
+  TestPrint,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StepResult {
+  pub step: ValidationStep,
+  pub ok: bool,
+  /// Not run, because an earlier step failed or it doesn't apply.
+  pub skipped: bool,
+  pub detail: String,
+  pub duration_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ValidationReport {
+  pub destination: String,
+  /// Every step that ran succeeded.
+  pub ok: bool,
+  pub steps: Vec<StepResult>,
+}
+
+/// Runs every step against `profile`. Failures are reported in the steps,
+/// never as an error.
+pub fn validate_profile(profile: &PrinterProfile, cfg: &PrintConfig) -> ValidationReport {
+  let target = &profile.target;
+  let label = target.label();
+  let timeout = Duration::from_millis(cfg.status_timeout_ms);
+  let mut steps = Vec::new();
+
+  let resolved = run(&mut steps, ValidationStep::Resolve, || resolve_target(target, cfg));
+  let mut link = if !resolved {
+    skip(&mut steps, ValidationStep::Connect, "Skipped because the resolve step failed.");
+    None
+  } else if let Target::Spooler { .. } = target {
+    skip(&mut steps, ValidationStep::Connect, "Spooler queues are opened when printing.");
+    None
+  } else if let Target::Port { .. } = target {
+    skip(&mut steps, ValidationStep::Connect, "Port devices are opened when printing.");
+    None
+  } else {
+    let mut link = None;
+    run(&mut steps, ValidationStep::Connect, || {
+      link = Some(recovery::open_link(target, &label, cfg, "")?);
+      Ok("Connected.".to_string())
+    });
+    link
+  };
+
+  let mut healthy = resolved;
+  match link.as_deref_mut() {
+    Some(io) => {
+      healthy = run(&mut steps, ValidationStep::Status, || {
+        let status = status::query_full(&mut *io, &label, timeout)?;
+        match status.fault() {
+          None => Ok("Online and ready.".to_string()),
+          Some(kind) => Err(PrintError::PrinterFault(PrinterFault {
+            kind,
+            confirmed_bytes: 0,
+            sent_bytes: 0,
+            total_bytes: 0,
+            message: format!("The printer {}. Fix it and validate again.", kind.describe()),
+          })),
+        }
+      });
+      run(&mut steps, ValidationStep::Model, || model(io, &label, timeout));
+    }
+    None => {
+      let why = if matches!(target, Target::Spooler { .. }) && resolved {
+        "Spooler queues have no real-time status channel."
+      } else if matches!(target, Target::Port { .. }) && resolved {
+        "Port devices are only written to."
+      } else {
+        "Skipped because the printer could not be reached."
+      };
+      skip(&mut steps, ValidationStep::Status, why);
+      skip(&mut steps, ValidationStep::Model, why);
+      healthy &= matches!(target, Target::Spooler { .. } | Target::Port { .. });
+    }
+  }
+
+  if !profile.test_print {
+    skip(&mut steps, ValidationStep::TestPrint, "Test print not requested.");
+  } else if !healthy {
+    skip(&mut steps, ValidationStep::TestPrint, "Skipped because an earlier step failed.");
+  } else {
+    let mark = escpos::build_alignment_grid(profile.columns, MARK_LINES);
+    run(&mut steps, ValidationStep::TestPrint, || {
+      match (target, link.as_deref_mut()) {
+        (Target::Spooler { printer_name, .. }, _) => spooler::print_raw(printer_name, &mark, None, None)?,
+        (Target::Port { port_name }, _) => winport::print_to_port(port_name, &mark, cfg)?,
+        (_, Some(io)) => io
+          .write_all(&mark)
+          .and_then(|()| io.flush())
+          .map_err(|e| PrintError::Write(format!("Sending the test print to {label} failed: {e}.").into()))?,
+        (_, None) => return Err(PrintError::Task(format!("{label} is not connected."))),
+      }
+      Ok(format!("Printed a {}-column alignment mark; check it is centered and cut cleanly.", profile.columns))
+    });
+  }
+
+  ValidationReport {
+    destination: label,
+    ok: steps.iter().all(|s| s.ok || s.skipped),
+    steps,
+  }
+}
+
+/// Records `step`'s outcome and returns whether it succeeded.
+fn run(
+  steps: &mut Vec<StepResult>,
+  step: ValidationStep,
+  check: impl FnOnce() -> Result<String, PrintError>,
+) -> bool {
+  let started = Instant::now();
+  let result = check();
+  let ok = result.is_ok();
+  steps.push(StepResult {
+    step,
+    ok,
+    skipped: false,
+    detail: result.unwrap_or_else(|e| e.to_string()),
+    duration_ms: started.elapsed().as_millis() as u64,
+  });
+  ok
+}
+
+fn skip(steps: &mut Vec<StepResult>, step: ValidationStep, why: &str) {
+  steps.push(StepResult {
+    step,
+    ok: false,
+    skipped: true,
+    detail: why.to_string(),
+    duration_ms: 0,
+  });
+}
+
+fn resolve_target(target: &Target, cfg: &PrintConfig) -> Result<String, PrintError> {
+  match target {
+    Target::Tcp { host, port } => {
+      let resolved = resolve::resolve(host, *port, Duration::from_millis(cfg.dns_timeout_ms))?;
+      Ok(match resolved.warning {
+        Some(warning) => format!("{host} is {}, from the cache: {warning}", resolved.addr),
+        None => format!("{host} is {}.", resolved.addr),
+      })
+    }
+    Target::Serial { port, .. } => {
+      let ports = serial::list_ports()?;
+      if ports.iter().any(|p| p.matches(port)) {
+        Ok(format!("{port} is present."))
+      } else {
+        Err(PrintError::InvalidRequest(format!(
+          "{port} is not among this computer's serial ports. Check the cable or Bluetooth pairing."
+        )))
+      }
+    }
+    Target::Spooler { printer_name, .. } => {
+      let info = spooler::driver_info(printer_name)?;
+      if info.raw_capable {
+        Ok(format!("'{printer_name}' uses driver '{}'.", info.driver_name))
+      } else {
+        Err(PrintError::Unsupported(format!(
+          "'{printer_name}' uses driver '{}', which is unlikely to pass ESC/POS through unchanged. Use a receipt printer driver or Generic / Text Only.",
+          info.driver_name
+        )))
+      }
+    }
+    Target::Port { port_name } => winport::device_path(port_name).map(|path| format!("{port_name} opens as {path}.")),
+  }
+}
+
+fn model(io: &mut dyn Duplex, label: &str, timeout: Duration) -> Result<String, PrintError> {
+  let info = printer_info::query_info(io, label, timeout)?;
+  Ok(match (info.manufacturer, info.model) {
+    (Some(maker), Some(model)) => format!("{maker} {model}."),
+    (None, Some(model)) => format!("{model}."),
+    _ => "The printer does not report its model; that's fine for printing.".to_string(),
+  })
+}
@@ -0,0 +1,193 @@
+//! Job-completion webhooks.
+//!
+//! After a job finishes, its payload is POSTed as JSON to the job's
+//! configured URL from a background thread, so delivery never delays or
+//! fails the print. When a secret is set the body is signed with
+//! HMAC-SHA256 and sent as `X-Pos-Signature: sha256=<hex>`.
+//!
+//! Failed deliveries are retried with exponential backoff. At most
+//! `RETRY_QUEUE_CAP` deliveries wait for a retry; beyond that the oldest is
+//! dropped. Pending retries do not survive an app restart.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::error::PrintError;
+
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_QUEUE_CAP: usize = 64;
+const BASE_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WebhookConfig {
+  pub url: String,
+  /// Shared secret for the `X-Pos-Signature` header; unsigned when absent.
+  #[serde(default)]
+  pub secret: Option<String>,
+  /// Passed through untouched in the payload, e.g. a receipt number.
+  #[serde(default)]
+  pub metadata: Option<Value>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobResult {
+  Printed,
+  Skipped,
+  Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WebhookPayload {
+  pub job_id: String,
+  pub destination: String,
+  pub outcome: JobResult,
+  pub bytes: usize,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<PrintError>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub metadata: Option<Value>,
+  pub submitted_at_ms: u64,
+  pub finished_at_ms: u64,
+}
+
+struct Delivery {
+  url: String,
+  secret: Option<String>,
+  body: String,
+  job_id: String,
+  attempts: u32,
+  next_at: Instant,
+}
+
+/// Handle to the webhook delivery thread.
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+  tx: Sender<Delivery>,
+}
+
+impl Default for WebhookDispatcher {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl WebhookDispatcher {
+  pub fn new() -> Self {
+    let (tx, rx) = channel::<Delivery>();
+    let spawned = thread::Builder::new().name("print-webhooks".to_string()).spawn(move || {
+      let agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build();
+      let mut pending = VecDeque::<Delivery>::new();
+      loop {
+        let wait = pending
+          .iter()
+          .map(|d| d.next_at.saturating_duration_since(Instant::now()))
+          .min()
+          .unwrap_or(Duration::from_secs(3600));
+        match rx.recv_timeout(wait) {
+          Ok(delivery) => pending.push_back(delivery),
+          Err(RecvTimeoutError::Timeout) => {}
+          Err(RecvTimeoutError::Disconnected) if pending.is_empty() => break,
+          Err(RecvTimeoutError::Disconnected) => thread::sleep(wait),
+        }
+
+        let now = Instant::now();
+        let (due, waiting): (Vec<_>, Vec<_>) = pending.drain(..).partition(|d| d.next_at <= now);
+        pending.extend(waiting);
+        for mut delivery in due {
+          match deliver(&agent, &delivery) {
+            Ok(()) => log::info!("webhook for job {} delivered", delivery.job_id),
+            Err(e) => {
+              delivery.attempts += 1;
+              if delivery.attempts >= MAX_ATTEMPTS {
+                log::warn!(
+                  "giving up on webhook for job {} after {} attempts: {e}",
+                  delivery.job_id,
+                  delivery.attempts
+                );
+                continue;
+              }
+              log::warn!("webhook for job {} failed (attempt {}): {e}", delivery.job_id, delivery.attempts);
+              let backoff = BASE_BACKOFF.saturating_mul(1 << (delivery.attempts - 1)).min(MAX_BACKOFF);
+              delivery.next_at = Instant::now() + backoff;
+              if pending.len() >= RETRY_QUEUE_CAP {
+                if let Some(dropped) = pending.pop_front() {
+                  log::warn!("webhook retry queue full, dropping job {}", dropped.job_id);
+                }
+              }
+              pending.push_back(delivery);
+            }
+          }
+        }
+      }
+    });
+    if let Err(e) = spawned {
+      log::error!("failed to spawn webhook thread: {e}");
+    }
+    Self { tx }
+  }
+
+  /// Queues `payload` for delivery to `config.url`. Never blocks; problems
+  /// are logged rather than returned.
+  pub fn dispatch(&self, config: &WebhookConfig, mut payload: WebhookPayload) {
+    let url = config.url.trim();
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+      log::warn!("ignoring webhook for job {}: '{url}' is not an http(s) URL", payload.job_id);
+      return;
+    }
+    payload.metadata = config.metadata.clone();
+    let body = match serde_json::to_string(&payload) {
+      Ok(body) => body,
+      Err(e) => {
+        log::warn!("unable to serialize webhook for job {}: {e}", payload.job_id);
+        return;
+      }
+    };
+    let delivery = Delivery {
+      url: url.to_string(),
+      secret: config.secret.clone().filter(|s| !s.is_empty()),
+      body,
+      job_id: payload.job_id,
+      attempts: 0,
+      next_at: Instant::now(),
+    };
+    if self.tx.send(delivery).is_err() {
+      log::warn!("webhook thread is not running; delivery dropped");
+    }
+  }
+}
+
+fn deliver(agent: &ureq::Agent, delivery: &Delivery) -> Result<(), String> {
+  let mut request = agent
+    .post(&delivery.url)
+    .set("Content-Type", "application/json")
+    .set("X-Pos-Job-Id", &delivery.job_id);
+  if let Some(secret) = &delivery.secret {
+    request = request.set("X-Pos-Signature", &format!("sha256={}", sign(secret, &delivery.body)));
+  }
+  match request.send_string(&delivery.body) {
+    Ok(_) => Ok(()),
+    Err(ureq::Error::Status(code, _)) => Err(format!("endpoint returned HTTP {code}")),
+    Err(e) => Err(e.to_string()),
+  }
+}
+
+fn sign(secret: &str, body: &str) -> String {
+  let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+  mac.update(body.as_bytes());
+  mac
+    .finalize()
+    .into_bytes()
+    .iter()
+    .map(|b| format!("{b:02x}"))
+    .collect()
+}
@@ -0,0 +1,150 @@
+//! Raw writes straight to a Windows port device such as `COM3` or a mapped
+//! `LPT1`, bypassing both the spooler and the serialport crate. Some legacy
+//! installs only have a port mapping whose spooler driver mangles ESC/POS,
+//! and the serialport crate can't open spooler-mapped ports.
+
+use crate::config::PrintConfig;
+use crate::error::PrintError;
+
+/// `\\.\NAME` for a `COMn`/`LPTn` name, with or without the trailing colon
+/// or the device prefix.
+pub fn device_path(port_name: &str) -> Result<String, PrintError> {
+  let name = port_name.trim();
+  let name = name.strip_prefix(r"\\.\").unwrap_or(name);
+  let name = name.strip_suffix(':').unwrap_or(name).to_ascii_uppercase();
+  let valid = ["COM", "LPT"].iter().any(|prefix| {
+    name
+      .strip_prefix(prefix)
+      .is_some_and(|n| !n.is_empty() && n.len() <= 3 && n.bytes().all(|b| b.is_ascii_digit()) && n != "0")
+  });
+  if !valid {
+    return Err(PrintError::InvalidRequest(format!(
+      "'{port_name}' is not a Windows port name. Expected e.g. COM3 or LPT1."
+    )));
+  }
+  Ok(format!(r"\\.\{name}"))
+}
+
+#[cfg(target_os = "windows")]
+mod imp {
+  use std::ffi::OsStr;
+  use std::iter::once;
+  use std::os::windows::ffi::OsStrExt;
+  use std::ptr::{null, null_mut};
+
+  use windows_sys::Win32::Devices::Communication::{SetCommTimeouts, COMMTIMEOUTS};
+  use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, GENERIC_WRITE, HANDLE, INVALID_HANDLE_VALUE};
+  use windows_sys::Win32::Storage::FileSystem::{CreateFileW, FlushFileBuffers, WriteFile, OPEN_EXISTING};
+
+  use crate::config::PrintConfig;
+  use crate::error::{codes, ErrorDetail, PrintError};
+
+  const ERROR_FILE_NOT_FOUND: u32 = 2;
+  const ERROR_ACCESS_DENIED: u32 = 5;
+  /// Largest single WriteFile call; keeps each call well inside the
+  /// write timeout at legacy baud rates.
+  const WRITE_CHUNK: usize = 4 * 1024;
+
+  /// Closes the port handle on every return path.
+  struct Port(HANDLE);
+
+  impl Drop for Port {
+    fn drop(&mut self) {
+      unsafe {
+        CloseHandle(self.0);
+      }
+    }
+  }
+
+  pub fn print_to_port(port_name: &str, data: &[u8], cfg: &PrintConfig) -> Result<(), PrintError> {
+    let path = super::device_path(port_name)?;
+    let path_w: Vec<u16> = OsStr::new(&path).encode_wide().chain(once(0)).collect();
+
+    unsafe {
+      let handle = CreateFileW(path_w.as_ptr(), GENERIC_WRITE, 0, null(), OPEN_EXISTING, 0, null_mut());
+      if handle == INVALID_HANDLE_VALUE {
+        return Err(open_error(GetLastError(), port_name));
+      }
+      let port = Port(handle);
+
+      // Without a timeout a COM port held by flow control blocks forever.
+      // LPT devices reject this and keep their driver's own timeout.
+      let timeouts = COMMTIMEOUTS {
+        ReadIntervalTimeout: 0,
+        ReadTotalTimeoutMultiplier: 0,
+        ReadTotalTimeoutConstant: 0,
+        WriteTotalTimeoutMultiplier: 0,
+        WriteTotalTimeoutConstant: cfg.write_timeout_ms.min(u32::MAX as u64) as u32,
+      };
+      SetCommTimeouts(port.0, &timeouts);
+
+      let mut sent = 0usize;
+      while sent < data.len() {
+        let chunk = &data[sent..(sent + WRITE_CHUNK).min(data.len())];
+        let mut written = 0u32;
+        if WriteFile(port.0, chunk.as_ptr(), chunk.len() as u32, &mut written, null_mut()) == 0 {
+          let code = GetLastError();
+          return Err(PrintError::Write(
+            ErrorDetail::new(format!(
+              "Writing to {port_name} failed after {sent} of {} bytes [Win32 error {code}]. Check the cable and that the printer is on.",
+              data.len()
+            ))
+            .param("os_error", code),
+          ));
+        }
+        if written == 0 {
+          return Err(PrintError::Timeout(
+            ErrorDetail::new(format!(
+              "{port_name} accepted nothing for {} ms after {sent} of {} bytes. Check that the printer is on, online and has paper.",
+              cfg.write_timeout_ms,
+              data.len()
+            ))
+            .reason(codes::SERIAL_WRITE_TIMEOUT)
+            .param("port", port_name)
+            .param("timeout_ms", cfg.write_timeout_ms),
+          ));
+        }
+        sent += written as usize;
+      }
+      FlushFileBuffers(port.0);
+      Ok(())
+    }
+  }
+
+  fn open_error(code: u32, port_name: &str) -> PrintError {
+    let message = match code {
+      ERROR_FILE_NOT_FOUND => format!(
+        "{port_name} does not exist on this computer. Check the port in Device Manager or the LPT mapping (net use)."
+      ),
+      ERROR_ACCESS_DENIED => format!(
+        "{port_name} is in use by another program or the spooler. Pause the printer queue that uses it and retry."
+      ),
+      _ => format!("Failed to open {port_name}. Verify the port name."),
+    };
+    let detail = ErrorDetail::new(format!("{message} [Win32 error {code}]")).param("os_error", code);
+    PrintError::SerialOpen(if code == ERROR_FILE_NOT_FOUND {
+      detail.reason(codes::SERIAL_PORT_NOT_FOUND).param("port", port_name)
+    } else {
+      detail
+    })
+  }
+}
+
+#[cfg(not(target_os = "windows"))]
+mod imp {
+  use crate::config::PrintConfig;
+  use crate::error::PrintError;
+
+  pub fn print_to_port(port_name: &str, _data: &[u8], _cfg: &PrintConfig) -> Result<(), PrintError> {
+    super::device_path(port_name)?;
+    Err(PrintError::Unsupported(
+      "Direct port printing is only available on Windows builds".to_string(),
+    ))
+  }
+}
+
+/// Writes `data` to `port_name` (e.g. `COM3` or `LPT1`) through its device
+/// handle. COM writes give up after `write_timeout_ms` without progress.
+pub fn print_to_port(port_name: &str, data: &[u8], cfg: &PrintConfig) -> Result<(), PrintError> {
+  imp::print_to_port(port_name, data, cfg)
+}
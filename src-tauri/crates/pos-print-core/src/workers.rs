@@ -0,0 +1,1193 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, RecvTimeoutError, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+
+use crate::benchmark::{self, BenchmarkReport};
+use crate::breaker::{CircuitBreakers, CircuitStatus};
+use crate::budget::{RetryBudgetStatus, RetryBudgets};
+use crate::cancel::CancelToken;
+use crate::config::{ConfigStore, PrintConfig, RetryClass, RetryPolicy, RetrySource};
+use crate::error::{PrintError, PrinterFault};
+use crate::events::{JobEvent, JobEvents, JobObserver, JobPhase, PhaseTimings};
+use crate::job::{next_job_id, FailoverAttempt, FailoverOutcome};
+use crate::limiter::{ConcurrencyStats, Limiter};
+use crate::target::Target;
+use crate::transport::SerialLink;
+use crate::{decode, spooler, status, transport, winport};
+
+/// Jobs a single destination may have waiting before submissions are refused.
+const QUEUE_CAPACITY: usize = 16;
+/// A worker with no jobs for this long closes its connection and exits.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often a job held by the offline grace period tries to reconnect.
+const OFFLINE_POLL: Duration = Duration::from_millis(500);
+
+/// Line speed used when opening a port only to send BREAK; a break does
+/// not depend on the baud rate.
+const BREAK_OPEN_BAUD: u32 = 9600;
+
+/// A bidirectional printer link (TCP socket or serial port).
+pub trait Duplex: Read + Write {}
+
+impl<T: Read + Write + ?Sized> Duplex for T {}
+
+/// Runs on the worker thread; returns whether the job succeeded.
+type Task = Box<dyn FnOnce(&mut Worker, &PrintConfig) -> bool + Send>;
+
+struct Job {
+  task: Task,
+  /// Set for print jobs; a cancelled job skips the concurrency limit and
+  /// fails as soon as it runs.
+  cancel: Option<CancelToken>,
+}
+
+#[derive(Default)]
+struct WorkerMetrics {
+  queued: AtomicUsize,
+  completed: AtomicU64,
+  failed: AtomicU64,
+  /// Milliseconds since `started` at which the current job began, or 0 when idle.
+  busy_since_ms: AtomicU64,
+}
+
+struct WorkerHandle {
+  id: u64,
+  tx: SyncSender<Job>,
+  started: Instant,
+  metrics: Arc<WorkerMetrics>,
+}
+
+#[derive(serde::Serialize)]
+pub struct WorkerStats {
+  destination: String,
+  queue_depth: usize,
+  queue_capacity: usize,
+  completed: u64,
+  failed: u64,
+  /// How long the current job has been running; a large value means the worker is wedged.
+  busy_ms: Option<u64>,
+  /// Jobs held by quiet hours; see [`with_held`].
+  held: usize,
+}
+
+/// Fills in held-job counts, adding rows for destinations that have held
+/// jobs but no worker yet.
+pub fn with_held(mut stats: Vec<WorkerStats>, held: BTreeMap<String, usize>) -> Vec<WorkerStats> {
+  for (destination, count) in held {
+    match stats.iter_mut().find(|s| s.destination == destination) {
+      Some(s) => s.held = count,
+      None => stats.push(WorkerStats {
+        destination,
+        queue_depth: 0,
+        queue_capacity: QUEUE_CAPACITY,
+        completed: 0,
+        failed: 0,
+        busy_ms: None,
+        held: count,
+      }),
+    }
+  }
+  stats.sort_by(|a, b| a.destination.cmp(&b.destination));
+  stats
+}
+
+type WorkerMap = Arc<Mutex<HashMap<Target, WorkerHandle>>>;
+
+/// Print jobs that are queued or running, by job id.
+type ActiveJobs = Arc<Mutex<HashMap<String, (Target, CancelToken)>>>;
+
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct AbortSummary {
+  /// Jobs stopped while sending; their printouts are incomplete.
+  pub aborted_in_flight: usize,
+  /// Jobs removed from the queues before anything was sent.
+  pub discarded_queued: usize,
+}
+
+/// A GS ( H process ID marker the printer confirmed reaching; see
+/// [`EscPosBuilder::process_id`](crate::escpos::EscPosBuilder::process_id).
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct MarkerConfirmation {
+  pub id: String,
+  /// Payload bytes up to the end of the marker.
+  pub offset: usize,
+  /// From sending the marker to the printer's answer.
+  pub wait_ms: u64,
+}
+
+/// What a worker reports about a job it ran, besides the result.
+#[derive(Clone, Debug, Default)]
+pub struct JobReport {
+  pub timings: PhaseTimings,
+  /// Markers in the payload the printer confirmed, in order. Spooler jobs
+  /// confirm none.
+  pub markers: Vec<MarkerConfirmation>,
+}
+
+/// A print accepted onto a worker queue. `job_id` matches the lifecycle
+/// events, so callers can hand it out before the job completes.
+pub struct Submitted {
+  pub job_id: String,
+  done: oneshot::Receiver<Result<(), PrintError>>,
+  report: Arc<OnceLock<JobReport>>,
+}
+
+impl Submitted {
+  pub async fn wait(self) -> Result<(), PrintError> {
+    self.done.await.map_err(|_| dropped())?
+  }
+
+  /// Like [`wait`](Self::wait), also returning the job's [`JobReport`]:
+  /// its timings, whether its connection was reused, how it retried and
+  /// which markers were confirmed. `None` if the job never ran.
+  pub async fn wait_report(self) -> (Result<(), PrintError>, Option<JobReport>) {
+    let report = self.report.clone();
+    let result = self.wait().await;
+    (result, report.get().cloned())
+  }
+
+  /// Must not be called from inside an async runtime.
+  pub fn wait_blocking(self) -> Result<(), PrintError> {
+    self.done.blocking_recv().map_err(|_| dropped())?
+  }
+
+  /// [`wait_report`](Self::wait_report) for callers on plain threads. Must
+  /// not be called from inside an async runtime.
+  pub fn wait_report_blocking(self) -> (Result<(), PrintError>, Option<JobReport>) {
+    let report = self.report.clone();
+    let result = self.wait_blocking();
+    (result, report.get().cloned())
+  }
+}
+
+fn dropped() -> PrintError {
+  PrintError::Task("Print worker dropped the job before completing it.".to_string())
+}
+
+/// Long-lived worker threads, one per active destination, each owning its
+/// connection or port and draining a bounded job queue. Spooler queues get a
+/// worker too, which serializes jobs per printer without holding a handle.
+#[derive(Clone)]
+pub struct WorkerPool {
+  workers: WorkerMap,
+  active: ActiveJobs,
+  next_id: Arc<AtomicU64>,
+  config: ConfigStore,
+  events: JobEvents,
+  limiter: Arc<Limiter>,
+  breakers: CircuitBreakers,
+  budgets: RetryBudgets,
+}
+
+impl WorkerPool {
+  pub fn new(config: ConfigStore) -> Self {
+    Self {
+      workers: WorkerMap::default(),
+      active: ActiveJobs::default(),
+      next_id: Arc::default(),
+      limiter: Arc::new(Limiter::new(config.clone())),
+      config,
+      events: JobEvents::default(),
+      breakers: CircuitBreakers::default(),
+      budgets: RetryBudgets::default(),
+    }
+  }
+
+  /// Installs the receiver for job lifecycle events.
+  pub fn set_observer(&self, observer: Arc<dyn JobObserver>) {
+    self.events.set_observer(observer);
+  }
+
+  pub async fn submit(&self, dest: Target, data: Vec<u8>) -> Result<(), PrintError> {
+    self.submit_print(&next_job_id(), dest, data)?.wait().await
+  }
+
+  /// Like [`submit`](Self::submit) for callers on plain threads. Must not be
+  /// called from inside an async runtime.
+  pub fn submit_blocking(&self, dest: Target, data: Vec<u8>) -> Result<(), PrintError> {
+    self.submit_print(&next_job_id(), dest, data)?.wait_blocking()
+  }
+
+  /// Prints to the first of `targets` that can be reached. Only failures
+  /// that happen before any data is sent (resolve, connect, open, spooler
+  /// OpenPrinter/StartDoc/StartPage, queue full) move on to the next target;
+  /// a failed write stops the attempt so a partly printed receipt is never
+  /// duplicated on the backup.
+  pub async fn submit_failover(&self, targets: Vec<Target>, data: Vec<u8>) -> Result<FailoverOutcome, PrintError> {
+    if targets.is_empty() {
+      return Err(PrintError::InvalidRequest("Failover needs at least one target.".to_string()));
+    }
+
+    let mut failed = Vec::new();
+    for target in targets {
+      let destination = target.label();
+      match self.submit(target.clone(), data.clone()).await {
+        Ok(()) => {
+          if !failed.is_empty() {
+            log::warn!("printed to fallback {destination} after {} failed target(s)", failed.len());
+          }
+          return Ok(FailoverOutcome {
+            target,
+            destination,
+            failed,
+          });
+        }
+        Err(
+          e @ (PrintError::Resolve(_)
+          | PrintError::Connect(_)
+          | PrintError::SerialOpen(_)
+          | PrintError::Spooler(_)
+          | PrintError::Unsupported(_)
+          | PrintError::QueueFull(_)
+          | PrintError::CircuitOpen(_)),
+        ) if !e.after_send() => {
+          log::warn!("failover: {destination} unavailable: {e}");
+          failed.push(FailoverAttempt { destination, error: e });
+        }
+        Err(e) => return Err(e),
+      }
+    }
+
+    let summary = failed
+      .iter()
+      .map(|a| format!("{}: {}", a.destination, a.error))
+      .collect::<Vec<_>>()
+      .join(" | ");
+    Err(PrintError::AllTargetsFailed(format!(
+      "None of the {} printers could be reached. {summary}",
+      failed.len()
+    )))
+  }
+
+  /// Queues a print under `job_id` without waiting for it. Every job emits
+  /// `Queued`, then `Started`, `Progress` and `Finished` or `Failed`; a job
+  /// rejected here, e.g. by the destination's circuit breaker, only emits
+  /// `Failed`.
+  pub fn submit_print(&self, job_id: &str, dest: Target, data: Vec<u8>) -> Result<Submitted, PrintError> {
+    self.submit_print_with(job_id, dest, data, None)
+  }
+
+  /// [`submit_print`](Self::submit_print) retrying under `retry` instead of
+  /// the destination's configured policy.
+  pub fn submit_print_with(
+    &self,
+    job_id: &str,
+    dest: Target,
+    data: Vec<u8>,
+    retry: Option<RetryPolicy>,
+  ) -> Result<Submitted, PrintError> {
+    let (dest, devmode) = dest.split_devmode();
+    let mut event = JobEvent::new(job_id, &dest.label(), data.len());
+    let job_report = Arc::new(OnceLock::new());
+    let accepted = self
+      .check_payload(&data)
+      .and_then(|()| {
+        if dest.is_supported() {
+          Ok(())
+        } else {
+          Err(PrintError::Unsupported(format!("{} is not available on this platform.", event.destination)))
+        }
+      })
+      .and_then(|()| self.breakers.admit(&event.destination, &self.config.snapshot()))
+      .and_then(|()| {
+        self.events.emit(JobPhase::Queued, &event);
+        let events = self.events.clone();
+        let mut event = event.clone();
+        let cancel = CancelToken::default();
+        let token = cancel.clone();
+        let active = self.active.clone();
+        let id = job_id.to_string();
+        let queued_at = Instant::now();
+        active
+          .lock()
+          .unwrap_or_else(|e| e.into_inner())
+          .insert(id.clone(), (dest.clone(), cancel.clone()));
+        let transport = dest.kind();
+        let finished = job_report.clone();
+        let breakers = self.breakers.clone();
+        let queued = self.enqueue(dest, Some(cancel.clone()), move |worker, cfg| {
+          let span = tracing::info_span!("print_job", job_id = %id, destination = %event.destination, transport);
+          let _entered = span.enter();
+          let queue_ms = queued_at.elapsed().as_millis() as u64;
+          tracing::debug!(queue_ms, "dequeued");
+          worker.timings = PhaseTimings {
+            queue_ms,
+            ..PhaseTimings::default()
+          };
+          worker.retry = retry;
+          worker.devmode = devmode;
+          worker.markers.clear();
+          let result = token.check(&event.destination, 0, data.len()).and_then(|()| {
+            if let Some(ttl) = cfg.queued_ttl(&event.destination) {
+              let waited = queued_at.elapsed();
+              if waited > ttl {
+                return Err(PrintError::Expired(format!(
+                  "Job waited {} s for {}, past its {} s limit; it was not printed.",
+                  waited.as_secs(),
+                  event.destination,
+                  ttl.as_secs()
+                )));
+              }
+            }
+            token.mark_started();
+            events.emit(JobPhase::Started, &event);
+            worker.print(&data, cfg, &token, &mut |sent| {
+              event.bytes_sent = sent;
+              events.emit(JobPhase::Progress, &event);
+              token.check(&event.destination, sent, data.len())
+            })
+          });
+          active.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+          breakers.record(&event.destination, &result, cfg);
+          worker.retry = None;
+          worker.devmode = None;
+          let timings = std::mem::take(&mut worker.timings);
+          let _ = finished.set(JobReport {
+            timings,
+            markers: std::mem::take(&mut worker.markers),
+          });
+          tracing::info!(
+            queue_ms = timings.queue_ms,
+            connect_ms = timings.connect_ms,
+            write_ms = timings.write_ms,
+            confirm_ms = timings.confirm_ms,
+            ok = result.is_ok(),
+            "job done"
+          );
+          event.timings = Some(timings);
+          match &result {
+            Ok(()) => events.emit(JobPhase::Finished, &event),
+            Err(e) => {
+              event.error = Some(e.clone());
+              events.emit(JobPhase::Failed, &event);
+            }
+          }
+          result
+        });
+        if queued.is_err() {
+          self.active.lock().unwrap_or_else(|e| e.into_inner()).remove(job_id);
+        }
+        queued
+      });
+
+    match accepted {
+      Ok(done) => Ok(Submitted {
+        job_id: job_id.to_string(),
+        done,
+        report: job_report,
+      }),
+      Err(e) => {
+        // A job the breaker let through but the queue refused frees its
+        // half-open probe slot.
+        if matches!(e, PrintError::QueueFull(_) | PrintError::Task(_)) {
+          self.breakers.record(&event.destination, &Err(e.clone()), &self.config.snapshot());
+        }
+        event.error = Some(e.clone());
+        self.events.emit(JobPhase::Failed, &event);
+        Err(e)
+      }
+    }
+  }
+
+  fn check_payload(&self, data: &[u8]) -> Result<(), PrintError> {
+    let max_payload = self.config.snapshot().max_payload_bytes;
+    if data.len() > max_payload {
+      return Err(PrintError::InvalidRequest(format!(
+        "Print payload is {} bytes, above the configured limit of {max_payload} bytes.",
+        data.len()
+      )));
+    }
+    Ok(())
+  }
+
+  /// Runs `f` against the destination's open link on its worker thread, in
+  /// order with queued print jobs. Used for requests that read a reply.
+  pub async fn exchange<R, F>(&self, dest: Target, f: F) -> Result<R, PrintError>
+  where
+    R: Send + 'static,
+    F: FnOnce(&mut dyn Duplex, &PrintConfig) -> Result<R, PrintError> + Send + 'static,
+  {
+    self
+      .run(dest, move |worker, cfg| {
+        let label = worker.dest.label();
+        let link = worker.link(cfg)?;
+        f(link, cfg).map_err(|e| {
+          log::warn!("exchange with {label} failed: {e}");
+          e
+        })
+      })
+      .await
+  }
+
+  async fn run<R, F>(&self, dest: Target, f: F) -> Result<R, PrintError>
+  where
+    R: Send + 'static,
+    F: FnOnce(&mut Worker, &PrintConfig) -> Result<R, PrintError> + Send + 'static,
+  {
+    self.enqueue(dest, None, f)?.await.map_err(|_| dropped())?
+  }
+
+  fn enqueue<R, F>(
+    &self,
+    dest: Target,
+    cancel: Option<CancelToken>,
+    f: F,
+  ) -> Result<oneshot::Receiver<Result<R, PrintError>>, PrintError>
+  where
+    R: Send + 'static,
+    F: FnOnce(&mut Worker, &PrintConfig) -> Result<R, PrintError> + Send + 'static,
+  {
+    // One worker per printer, whatever DEVMODE a job carries.
+    let (dest, _) = dest.split_devmode();
+    let (done, rx) = oneshot::channel();
+    let task: Task = Box::new(move |worker, cfg| {
+      let result = f(worker, cfg);
+      let ok = result.is_ok();
+      let _ = done.send(result);
+      ok
+    });
+
+    {
+      let mut workers = self.workers.lock().unwrap_or_else(|e| e.into_inner());
+      let handle = workers
+        .entry(dest.clone())
+        .or_insert_with(|| self.spawn_worker(dest.clone()));
+
+      handle.metrics.queued.fetch_add(1, Ordering::SeqCst);
+      if let Err(e) = handle.tx.try_send(Job { task, cancel }) {
+        handle.metrics.queued.fetch_sub(1, Ordering::SeqCst);
+        return Err(match e {
+          TrySendError::Full(_) => PrintError::QueueFull(format!(
+            "Print queue for {} is full ({QUEUE_CAPACITY} jobs). The printer may be stalled; check it before retrying.",
+            dest.label()
+          )),
+          TrySendError::Disconnected(_) => {
+            workers.remove(&dest);
+            PrintError::Task(format!("Print worker for {} stopped unexpectedly. Retry the print.", dest.label()))
+          }
+        });
+      }
+    }
+    Ok(rx)
+  }
+
+  pub fn stats(&self) -> Vec<WorkerStats> {
+    let workers = self.workers.lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = workers
+      .iter()
+      .map(|(dest, handle)| {
+        let busy_since = handle.metrics.busy_since_ms.load(Ordering::SeqCst);
+        let now = handle.started.elapsed().as_millis() as u64;
+        WorkerStats {
+          destination: dest.label(),
+          queue_depth: handle.metrics.queued.load(Ordering::SeqCst),
+          queue_capacity: QUEUE_CAPACITY,
+          completed: handle.metrics.completed.load(Ordering::SeqCst),
+          failed: handle.metrics.failed.load(Ordering::SeqCst),
+          busy_ms: (busy_since != 0).then(|| now.saturating_sub(busy_since)),
+          held: 0,
+        }
+      })
+      .collect::<Vec<_>>();
+    out.sort_by(|a, b| a.destination.cmp(&b.destination));
+    out
+  }
+
+  /// Ports of serial destinations that have a worker, as given in their
+  /// targets (port names or stable ids). The worker owns its port, so
+  /// nothing else should open it.
+  pub fn serial_ports_in_use(&self) -> Vec<String> {
+    self
+      .workers
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .keys()
+      .filter_map(|t| match t {
+        Target::Serial { port, .. } => Some(port.clone()),
+        _ => None,
+      })
+      .collect()
+  }
+
+  /// Asserts a serial BREAK on `port`. Runs on the port's worker when one
+  /// has it open (whatever its baud), otherwise opens the port briefly.
+  pub async fn serial_break(&self, port: String, duration: Duration) -> Result<(), PrintError> {
+    let existing = self
+      .workers
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .keys()
+      .find(|t| matches!(t, Target::Serial { port: p, .. } if *p == port))
+      .cloned();
+    if let Some(dest) = existing {
+      return self.run(dest, move |worker, cfg| worker.send_break(duration, cfg)).await;
+    }
+
+    let cfg = self.config.snapshot();
+    let (done, rx) = oneshot::channel();
+    thread::Builder::new()
+      .name(format!("serial-break-{port}"))
+      .spawn(move || {
+        let result = transport::serial_open(&port, BREAK_OPEN_BAUD, &cfg)
+          .and_then(|sp| transport::serial_break(sp.as_ref(), &port, duration));
+        let _ = done.send(result);
+      })
+      .map_err(|e| PrintError::Task(format!("Unable to start serial break task: {e}.")))?;
+    rx.await.map_err(|_| dropped())?
+  }
+
+  /// Circuit breaker state of destinations with recent failures.
+  pub fn circuits(&self) -> Vec<CircuitStatus> {
+    self.breakers.status(&self.config.snapshot())
+  }
+
+  /// Retry budgets of destinations that have been retrying.
+  pub fn retry_budgets(&self) -> Vec<RetryBudgetStatus> {
+    self.budgets.status(&self.config.snapshot())
+  }
+
+  /// Closes `dest`'s circuit breaker; see [`CircuitBreakers::reset`].
+  pub fn reset_circuit(&self, dest: &Target) -> bool {
+    self.breakers.reset(&dest.label())
+  }
+
+  /// Jobs in flight and waiting, globally and per transport.
+  pub fn concurrency(&self) -> ConcurrencyStats {
+    let queued = self
+      .workers
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .values()
+      .map(|h| h.metrics.queued.load(Ordering::SeqCst))
+      .sum();
+    self.limiter.stats(queued)
+  }
+
+  /// Sends a generated payload of about `payload_bytes` to `dest`
+  /// `iterations` times. Each sample is the time the worker spends writing
+  /// the payload, after the connection is open, so it measures how fast the
+  /// printer ingests data rather than connect or queueing overhead.
+  pub async fn benchmark(&self, dest: Target, payload_bytes: usize, iterations: u32) -> Result<BenchmarkReport, PrintError> {
+    benchmark::check(payload_bytes, iterations)?;
+    let (data, lines) = benchmark::payload(payload_bytes);
+    let data = Arc::new(data);
+    let mut samples = Vec::with_capacity(iterations as usize);
+    for _ in 0..iterations {
+      let data = data.clone();
+      let elapsed = self
+        .run(dest.clone(), move |worker, cfg| {
+          if !matches!(worker.dest, Target::Spooler { .. } | Target::Port { .. }) {
+            worker.connect(cfg)?;
+          }
+          let started = Instant::now();
+          worker.print(&data, cfg, &CancelToken::default(), &mut |_| Ok(()))?;
+          Ok(started.elapsed())
+        })
+        .await?;
+      samples.push(elapsed);
+    }
+    Ok(benchmark::summarize(dest.label(), &samples, data.len(), lines))
+  }
+
+  /// Cancels every queued and running print job. Running TCP and serial
+  /// writes stop at the next chunk boundary; running spooler jobs are
+  /// deleted from the Windows queue. Queued jobs fail with `Aborted`
+  /// without sending anything.
+  pub fn abort_all(&self) -> AbortSummary {
+    let active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+    let mut summary = AbortSummary::default();
+    for (job_id, (dest, cancel)) in active.iter() {
+      if cancel.is_cancelled() {
+        continue;
+      }
+      cancel.cancel();
+      if !cancel.is_started() {
+        summary.discarded_queued += 1;
+        continue;
+      }
+      summary.aborted_in_flight += 1;
+      if let (Target::Spooler { printer_name, .. }, Some(spooler_job)) = (dest, cancel.spooler_job()) {
+        if let Err(e) = spooler::delete_job(printer_name, spooler_job) {
+          log::warn!("abort: unable to delete spooler job for {job_id}: {e}");
+        }
+      }
+    }
+    log::warn!(
+      "aborted {} running and {} queued print job(s)",
+      summary.aborted_in_flight,
+      summary.discarded_queued
+    );
+    summary
+  }
+
+  /// Drops every job sender so workers exit once their current job finishes.
+  /// Threads are not joined, so a wedged printer can't hold up app exit.
+  pub fn shutdown(&self) {
+    self.workers.lock().unwrap_or_else(|e| e.into_inner()).clear();
+  }
+
+  fn spawn_worker(&self, dest: Target) -> WorkerHandle {
+    let (tx, rx) = sync_channel(QUEUE_CAPACITY);
+    let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+    let started = Instant::now();
+    let metrics = Arc::new(WorkerMetrics::default());
+
+    let worker = Worker {
+      id,
+      dest: dest.clone(),
+      rx,
+      started,
+      metrics: metrics.clone(),
+      workers: self.workers.clone(),
+      config: self.config.clone(),
+      limiter: self.limiter.clone(),
+      budgets: self.budgets.clone(),
+      conn: None,
+      timings: PhaseTimings::default(),
+      retry: None,
+      devmode: None,
+      markers: Vec::new(),
+    };
+    let spawned = thread::Builder::new()
+      .name(format!("print-worker-{}", dest.label()))
+      .spawn(move || worker.run());
+    if let Err(e) = spawned {
+      log::error!("failed to spawn print worker for {}: {e}", dest.label());
+    }
+
+    WorkerHandle {
+      id,
+      tx,
+      started,
+      metrics,
+    }
+  }
+}
+
+enum Connection {
+  Tcp(TcpStream),
+  Serial(SerialLink),
+}
+
+struct Worker {
+  id: u64,
+  dest: Target,
+  rx: Receiver<Job>,
+  started: Instant,
+  metrics: Arc<WorkerMetrics>,
+  workers: WorkerMap,
+  config: ConfigStore,
+  limiter: Arc<Limiter>,
+  budgets: RetryBudgets,
+  conn: Option<Connection>,
+  /// Phase timings for the job in hand; print tasks reset and take it.
+  timings: PhaseTimings,
+  /// The job in hand's own retry policy, overriding the configured one.
+  retry: Option<RetryPolicy>,
+  /// The job in hand's DEVMODEW, for spooler jobs.
+  devmode: Option<Vec<u8>>,
+  /// Markers the job in hand's printer has confirmed so far.
+  markers: Vec<MarkerConfirmation>,
+}
+
+impl Worker {
+  fn run(mut self) {
+    while let Some(job) = self.next_job() {
+      self.metrics.queued.fetch_sub(1, Ordering::SeqCst);
+      let aborted = job.cancel.as_ref().is_some_and(|c| c.is_cancelled());
+      let permit = (!aborted).then(|| self.limiter.acquire(self.dest.kind()));
+      self
+        .metrics
+        .busy_since_ms
+        .store(self.started.elapsed().as_millis().max(1) as u64, Ordering::SeqCst);
+
+      let cfg = self.config.snapshot();
+      if (job.task)(&mut self, &cfg) {
+        self.metrics.completed.fetch_add(1, Ordering::SeqCst);
+      } else {
+        // Never reuse a connection that just failed.
+        self.conn = None;
+        self.metrics.failed.fetch_add(1, Ordering::SeqCst);
+      }
+      self.metrics.busy_since_ms.store(0, Ordering::SeqCst);
+      drop(permit);
+    }
+    log::info!("print worker for {} stopped", self.dest.label());
+  }
+
+  /// Waits for the next job, nudging an idle TCP connection every
+  /// `nudge_interval_ms` meanwhile. `None` once the pool is gone or the
+  /// worker has sat idle for `IDLE_TIMEOUT` and deregistered.
+  fn next_job(&mut self) -> Option<Job> {
+    let idle_since = Instant::now();
+    let mut nudged_at = idle_since;
+    loop {
+      let idle_left = IDLE_TIMEOUT.saturating_sub(idle_since.elapsed());
+      let interval = Duration::from_millis(self.config.snapshot().nudge_interval_ms);
+      let wait = if interval.is_zero() || !matches!(self.conn, Some(Connection::Tcp(_))) {
+        idle_left
+      } else {
+        (nudged_at + interval).saturating_duration_since(Instant::now()).min(idle_left)
+      };
+      match self.rx.recv_timeout(wait) {
+        Ok(job) => return Some(job),
+        Err(RecvTimeoutError::Disconnected) => return None,
+        Err(RecvTimeoutError::Timeout) if idle_since.elapsed() < IDLE_TIMEOUT => {
+          self.nudge(&self.config.snapshot());
+          nudged_at = Instant::now();
+        }
+        Err(RecvTimeoutError::Timeout) => {
+          // Deregister under the pool lock so no job can slip in between the
+          // final check and the thread exiting.
+          let mut workers = self.workers.lock().unwrap_or_else(|e| e.into_inner());
+          return match self.rx.try_recv() {
+            Ok(job) => Some(job),
+            Err(_) => {
+              if workers.get(&self.dest).is_some_and(|h| h.id == self.id) {
+                workers.remove(&self.dest);
+              }
+              None
+            }
+          };
+        }
+      }
+    }
+  }
+
+  /// Asks the printer on an idle TCP connection for its status, closing
+  /// the connection if it doesn't answer so the next job reconnects rather
+  /// than writing into a socket a router has silently dropped.
+  fn nudge(&mut self, cfg: &PrintConfig) {
+    let label = self.dest.label();
+    let Some(Connection::Tcp(stream)) = self.conn.as_mut() else {
+      return;
+    };
+    let result = if transport::tcp_is_stale(stream) {
+      Err(PrintError::Read("the printer closed the connection.".to_string().into()))
+    } else {
+      let _ = stream.set_read_timeout(Some(Duration::from_millis(cfg.status_timeout_ms)));
+      let _ = stream.set_write_timeout(Some(Duration::from_millis(cfg.write_timeout(&label))));
+      status::request_byte(
+        stream,
+        &label,
+        status::StatusKind::Printer,
+        Duration::from_millis(cfg.status_timeout_ms),
+      )
+    };
+    match result {
+      Ok(_) => tracing::debug!(destination = %label, "idle connection nudged"),
+      Err(e) => {
+        log::info!("closing idle connection to {label}, it didn't answer a keep-alive status request: {e}");
+        self.conn = None;
+      }
+    }
+  }
+
+  fn print(
+    &mut self,
+    data: &[u8],
+    cfg: &PrintConfig,
+    cancel: &CancelToken,
+    progress: &mut dyn FnMut(usize) -> Result<(), PrintError>,
+  ) -> Result<(), PrintError> {
+    if let Target::Spooler { printer_name, .. } = &self.dest {
+      let started = Instant::now();
+      let result = tracing::debug_span!("write", bytes = data.len())
+        .in_scope(|| spooler::print_raw(printer_name, data, self.devmode.as_deref(), Some(cancel)));
+      self.timings.write_ms += elapsed_ms(started);
+      tracing::debug!(write_ms = self.timings.write_ms, "spooler write done");
+      result?;
+      return progress(data.len());
+    }
+    if let Target::Port { port_name } = &self.dest {
+      let started = Instant::now();
+      let result = tracing::debug_span!("write", bytes = data.len()).in_scope(|| winport::print_to_port(port_name, data, cfg));
+      self.timings.write_ms += elapsed_ms(started);
+      tracing::debug!(write_ms = self.timings.write_ms, "port write done");
+      result?;
+      return progress(data.len());
+    }
+
+    let markers = decode::process_ids(data);
+    if !cfg.confirm_status && markers.is_empty() {
+      self.write(data, cfg, progress)?;
+      thread::sleep(cfg.post_write_delay(&self.dest.label()));
+      return Ok(());
+    }
+
+    // Write up to each checkpoint, then ask the printer whether it is still
+    // printing, or wait for it to reach the marker there, before sending
+    // more.
+    let mut checkpoints = Vec::new();
+    if cfg.confirm_status {
+      let mut last = 0;
+      for end in decode::line_ends(data) {
+        if end - last >= cfg.confirm_interval_bytes.max(1) {
+          checkpoints.push((end, None));
+          last = end;
+        }
+      }
+    }
+    checkpoints.extend(markers.into_iter().map(|(end, id)| (end, Some(id))));
+    checkpoints.sort_by_key(|&(end, _)| end);
+    if checkpoints.last().map_or(true, |&(end, _)| end < data.len()) {
+      checkpoints.push((data.len(), None));
+    }
+
+    let label = self.dest.label();
+    let mut confirmed = 0;
+    for (end, marker) in checkpoints {
+      if end > confirmed {
+        self.write(&data[confirmed..end], cfg, &mut |sent| progress(confirmed + sent))?;
+      }
+      if let Some(id) = marker {
+        let link = self.link(cfg)?;
+        let started = Instant::now();
+        let timeout = Duration::from_millis(cfg.marker_timeout_ms);
+        tracing::debug_span!("marker", sent = end)
+          .in_scope(|| status::await_process_id(link, &label, id, end, timeout))?;
+        let wait_ms = elapsed_ms(started);
+        self.timings.confirm_ms += wait_ms;
+        tracing::debug!(wait_ms, sent = end, "marker confirmed");
+        self.markers.push(MarkerConfirmation {
+          id: String::from_utf8_lossy(&id).into_owned(),
+          offset: end,
+          wait_ms,
+        });
+        confirmed = end;
+        continue;
+      }
+      if !cfg.confirm_status {
+        confirmed = end;
+        continue;
+      }
+      let link = self.link(cfg)?;
+      let started = Instant::now();
+      let status = tracing::debug_span!("confirm", sent = end)
+        .in_scope(|| status::query_full(link, &label, Duration::from_millis(cfg.status_timeout_ms)));
+      let confirm_ms = elapsed_ms(started);
+      self.timings.confirm_ms += confirm_ms;
+      tracing::debug!(confirm_ms, sent = end, "status confirmed");
+      let status = status?;
+      if let Some(kind) = status.fault() {
+        return Err(PrintError::PrinterFault(PrinterFault {
+          kind,
+          confirmed_bytes: confirmed,
+          sent_bytes: end,
+          total_bytes: data.len(),
+          message: format!(
+            "{label} {} after {end} of {} bytes were sent; the receipt is incomplete. Fix the printer, then reprint the receipt.",
+            kind.describe(),
+            data.len()
+          ),
+        }));
+      }
+      confirmed = end;
+    }
+    thread::sleep(cfg.post_write_delay(&label));
+    Ok(())
+  }
+
+  fn write(
+    &mut self,
+    data: &[u8],
+    cfg: &PrintConfig,
+    progress: &mut dyn FnMut(usize) -> Result<(), PrintError>,
+  ) -> Result<(), PrintError> {
+    let dest = self.dest.clone();
+    self.connect(cfg)?;
+    let started = Instant::now();
+    let span = tracing::debug_span!("write", bytes = data.len()).entered();
+    let result = match (&dest, self.conn.as_mut()) {
+      (Target::Tcp { host, port }, Some(Connection::Tcp(stream))) => transport::tcp_write(stream, host, *port, data, cfg, progress),
+      (Target::Serial { port, .. }, Some(Connection::Serial(link))) => transport::serial_write(link, port, data, cfg, progress),
+      _ => unreachable!("connection kind always matches the destination"),
+    };
+    let write_ms = elapsed_ms(started);
+    self.timings.write_ms += write_ms;
+    tracing::debug!(write_ms, bytes = data.len(), ok = result.is_ok(), "write done");
+    drop(span);
+    result
+  }
+
+  /// Returns the open connection, (re)connecting as needed. Connect failures
+  /// are retried per the job's or destination's retry policy since no bytes
+  /// have been sent yet, then for the destination's offline grace period,
+  /// as long as the destination's retry budget lasts.
+  fn connect(&mut self, cfg: &PrintConfig) -> Result<&mut Connection, PrintError> {
+    if let Some(Connection::Tcp(stream)) = &self.conn {
+      if transport::tcp_is_stale(stream) {
+        self.conn = None;
+      }
+    }
+
+    if self.timings.connection_reused.is_none() {
+      self.timings.connection_reused = Some(self.conn.is_some());
+    }
+    if self.conn.is_none() {
+      let span = tracing::debug_span!("connect").entered();
+      let started = Instant::now();
+      let mut attempt = 1;
+      let label = self.dest.label();
+      let grace = cfg.offline_grace(&label);
+      let (policy, source) = match &self.retry {
+        Some(policy) => (policy, RetrySource::Call),
+        None => cfg.retry_policy(&label),
+      };
+      let deadline = Duration::from_millis(policy.deadline_ms);
+      let first_failure = Instant::now();
+      let mut holding = false;
+      self.timings.retry_policy = Some(source);
+      let conn = loop {
+        self.timings.connect_attempts += 1;
+        let opened = match &self.dest {
+          Target::Tcp { host, port } => transport::tcp_connect(host, *port, cfg).map(Connection::Tcp),
+          Target::Serial { port, baud } => SerialLink::open(port, *baud, cfg).map(Connection::Serial),
+          Target::Spooler { printer_name, .. } => {
+            return Err(PrintError::Unsupported(format!(
+              "Spooler printer '{printer_name}' cannot be read from; use a TCP or serial connection."
+            )))
+          }
+          Target::Port { port_name } => {
+            return Err(PrintError::Unsupported(format!(
+              "{port_name} is written to directly and cannot be read from; use a serial connection."
+            )))
+          }
+        };
+        let unreachable = opened.as_ref().err().and_then(RetryClass::of).is_some();
+        let retry = match &opened {
+          Err(e) if policy.retries(e) && attempt < policy.max_attempts => Some(policy.delay(attempt)),
+          _ => None,
+        }
+        .filter(|delay| deadline.is_zero() || started.elapsed() + *delay <= deadline);
+        match (opened, retry) {
+          (Err(_), Some(delay)) if self.budgets.spend(&label, cfg) => {
+            attempt += 1;
+            thread::sleep(delay);
+          }
+          // A held job spends one retry for the whole hold, not one per poll.
+          (Err(e), _)
+            if unreachable
+              && first_failure.elapsed() + OFFLINE_POLL <= grace
+              && (holding || self.budgets.spend(&label, cfg)) =>
+          {
+            if !holding {
+              log::warn!("{label} is offline, holding the job for up to {} ms: {e}", grace.as_millis());
+              holding = true;
+            }
+            thread::sleep(OFFLINE_POLL);
+          }
+          (result, _) => {
+            if holding {
+              match &result {
+                Ok(_) => log::info!("{label} is back after {} ms", first_failure.elapsed().as_millis()),
+                Err(_) => log::warn!("{label} stayed offline through its {} ms grace period", grace.as_millis()),
+              }
+            }
+            let connect_ms = elapsed_ms(started);
+            self.timings.connect_ms += connect_ms;
+            tracing::debug!(
+              connect_ms,
+              attempts = self.timings.connect_attempts,
+              retry_policy = source.as_str(),
+              ok = result.is_ok(),
+              "connect done"
+            );
+            break result?;
+          }
+        }
+      };
+      drop(span);
+      self.conn = Some(conn);
+    }
+
+    Ok(self.conn.as_mut().expect("connection was just established"))
+  }
+
+  fn send_break(&mut self, duration: Duration, cfg: &PrintConfig) -> Result<(), PrintError> {
+    let dest = self.dest.clone();
+    match (&dest, self.connect(cfg)?) {
+      (Target::Serial { port, .. }, Connection::Serial(link)) => transport::serial_break(link.port.as_ref(), port, duration),
+      _ => Err(PrintError::Unsupported(format!("{} is not a serial port.", dest.label()))),
+    }
+  }
+
+  /// The open link with read timeouts set for request/reply exchanges.
+  fn link(&mut self, cfg: &PrintConfig) -> Result<&mut dyn Duplex, PrintError> {
+    let read_timeout = Duration::from_millis(cfg.status_timeout_ms);
+    let write_timeout = Duration::from_millis(cfg.write_timeout(&self.dest.label()));
+    let link: &mut dyn Duplex = match self.connect(cfg)? {
+      Connection::Tcp(stream) => {
+        let _ = stream.set_read_timeout(Some(read_timeout));
+        let _ = stream.set_write_timeout(Some(write_timeout));
+        stream
+      }
+      Connection::Serial(link) => {
+        let _ = link.port.set_timeout(read_timeout);
+        link
+      }
+    };
+    Ok(link)
+  }
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+  started.elapsed().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+  use std::future::Future;
+  use std::net::TcpListener;
+  use std::pin::pin;
+  use std::task::{Context, Poll, Wake, Waker};
+
+  use super::*;
+  use crate::config::PrintConfigPatch;
+
+  /// Accepts one connection and returns the first `len` bytes it receives.
+  fn fake_printer(len: usize) -> (Target, thread::JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let printer = thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut received = vec![0u8; len];
+      stream.read_exact(&mut received).unwrap();
+      received
+    });
+    (Target::Tcp { host: "127.0.0.1".to_string(), port }, printer)
+  }
+
+  /// A port nothing listens on.
+  fn refused_target() -> Target {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    Target::Tcp { host: "127.0.0.1".to_string(), port }
+  }
+
+  struct Unpark(thread::Thread);
+
+  impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+      self.0.unpark();
+    }
+  }
+
+  fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+      match future.as_mut().poll(&mut cx) {
+        Poll::Ready(out) => return out,
+        Poll::Pending => thread::park(),
+      }
+    }
+  }
+
+  #[test]
+  fn jobs_for_one_destination_print_in_order() {
+    let (dest, printer) = fake_printer(10);
+    let pool = WorkerPool::new(ConfigStore::default());
+    let first = pool.submit_print("job-1", dest.clone(), b"first".to_vec()).unwrap();
+    let second = pool.submit_print("job-2", dest.clone(), b"later".to_vec()).unwrap();
+    assert_eq!(first.job_id, "job-1");
+    first.wait_blocking().unwrap();
+    second.wait_blocking().unwrap();
+    assert_eq!(printer.join().unwrap(), b"firstlater");
+
+    // Both jobs went through the destination's one worker.
+    let stats = pool.stats();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].destination, dest.label());
+    pool.shutdown();
+  }
+
+  #[test]
+  fn oversized_payloads_are_refused_before_queueing() {
+    let config = ConfigStore::default();
+    config
+      .apply(PrintConfigPatch {
+        max_payload_bytes: Some(4),
+        ..PrintConfigPatch::default()
+      })
+      .unwrap();
+    let pool = WorkerPool::new(config);
+    let err = pool.submit_blocking(refused_target(), b"12345".to_vec()).unwrap_err();
+    assert!(matches!(err, PrintError::InvalidRequest(_)));
+    assert!(pool.stats().is_empty());
+  }
+
+  #[test]
+  fn failover_moves_past_an_unreachable_printer() {
+    let (backup, printer) = fake_printer(7);
+    let down = refused_target();
+    let pool = WorkerPool::new(ConfigStore::default());
+    let outcome = block_on(pool.submit_failover(vec![down.clone(), backup.clone()], b"receipt".to_vec())).unwrap();
+    assert_eq!(outcome.destination, backup.label());
+    assert_eq!(outcome.failed.len(), 1);
+    assert_eq!(outcome.failed[0].destination, down.label());
+    assert!(matches!(outcome.failed[0].error, PrintError::Connect(_)));
+    assert_eq!(printer.join().unwrap(), b"receipt");
+
+    let err = block_on(pool.submit_failover(Vec::new(), b"receipt".to_vec())).unwrap_err();
+    assert!(matches!(err, PrintError::InvalidRequest(_)));
+    pool.shutdown();
+  }
+
+  #[test]
+  fn abort_all_discards_jobs_waiting_behind_a_busy_worker() {
+    let dest = refused_target();
+    let pool = WorkerPool::new(ConfigStore::default());
+    let (release, wait) = std::sync::mpsc::channel::<()>();
+    let (running, started) = std::sync::mpsc::channel::<()>();
+    let busy = {
+      let pool = pool.clone();
+      let dest = dest.clone();
+      thread::spawn(move || {
+        block_on(pool.run(dest, move |_, _| {
+          let _ = running.send(());
+          let _ = wait.recv();
+          Ok(())
+        }))
+      })
+    };
+    started.recv().unwrap();
+    let queued = ["job-1", "job-2"].map(|id| pool.submit_print(id, dest.clone(), b"receipt".to_vec()).unwrap());
+
+    let summary = pool.abort_all();
+    assert_eq!((summary.aborted_in_flight, summary.discarded_queued), (0, 2));
+    release.send(()).unwrap();
+    busy.join().unwrap().unwrap();
+    for submitted in queued {
+      assert!(matches!(submitted.wait_blocking(), Err(PrintError::Aborted(_))));
+    }
+    // Nothing is left to abort.
+    assert_eq!(pool.abort_all().discarded_queued, 0);
+    pool.shutdown();
+  }
+
+  #[test]
+  fn held_counts_fill_existing_rows_and_add_missing_ones() {
+    let stats = vec![WorkerStats {
+      destination: "tcp://b:9100".to_string(),
+      queue_depth: 2,
+      queue_capacity: QUEUE_CAPACITY,
+      completed: 5,
+      failed: 0,
+      busy_ms: None,
+      held: 0,
+    }];
+    let held = BTreeMap::from([("tcp://b:9100".to_string(), 1), ("tcp://a:9100".to_string(), 3)]);
+    let rows = with_held(stats, held);
+    let summary: Vec<_> = rows.iter().map(|s| (s.destination.as_str(), s.queue_depth, s.held)).collect();
+    assert_eq!(summary, [("tcp://a:9100", 0, 3), ("tcp://b:9100", 2, 1)]);
+  }
+}
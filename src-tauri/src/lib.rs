@@ -1,7 +1,69 @@
-use std::io::Write;
-use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
 use std::time::Duration;
 
+use pos_print_core::alerts::FailureAlerts;
+use pos_print_core::archive::{ArchiveFilter, ArchiveSettings, ArchivedReceipt, ReceiptArchive};
+use pos_print_core::audit::{now_ms, AuditEntry, AuditLog};
+use pos_print_core::benchmark::BenchmarkReport;
+use pos_print_core::bluetooth::{BluetoothDevice, BluetoothScanner};
+use pos_print_core::breaker::CircuitStatus;
+use pos_print_core::bridge::{BridgeConfig, BridgeInfo, PrintBridge};
+use pos_print_core::budget::RetryBudgetStatus;
+use pos_print_core::capabilities::{self, Capabilities};
+use pos_print_core::compress::{self, PayloadEncoding};
+use pos_print_core::config::{ConfigStore, PrintConfigPatch, PrintConfigView};
+use pos_print_core::deadletter::{DeadLetter, DeadLetters};
+use pos_print_core::diagnostics::{self, Diagnostics};
+use pos_print_core::discovery::{PrinterDiff, PrinterSnapshot};
+use pos_print_core::drawer::{DrawerEvent, DrawerLog, DrawerObserver, DrawerRange, DrawerReason};
+use pos_print_core::duplicates::RecentPayloads;
+use pos_print_core::error::PrintError;
+use pos_print_core::escpos::{self, BuzzerModel, DrawerPin, EscPosBuilder};
+use pos_print_core::events::{JobEvent, JobObserver, JobPhase};
+use pos_print_core::job::{
+  self, FailoverOutcome, FanoutResult, JobRecord, JobSinks, PrintOptions, PrintOutcome, PrintStatus,
+};
+use pos_print_core::kitchen::{self, KitchenTicket};
+use pos_print_core::limiter::ConcurrencyStats;
+use pos_print_core::netif::{self, NetworkInterface};
+use pos_print_core::nvlogo::{self, ProvisionReport};
+use pos_print_core::partials::PartialStore;
+use pos_print_core::quiet::HeldJobs;
+use pos_print_core::printer_info::{self, PrinterInfo};
+use pos_print_core::qr_payload::QrPayload;
+use pos_print_core::raster::{self, QrEcLevel, RasterCache, RasterOptions};
+use pos_print_core::receipt::{self, Receipt};
+use pos_print_core::recovery::{self, ClearReport, RebootMethod, RebootReport, RecoverReport};
+use pos_print_core::reprint::{self, ReprintOptions, ReprintStore};
+use pos_print_core::sidecar::SidecarLog;
+use pos_print_core::snmp::{self, SnmpStatus};
+use pos_print_core::spooler::{DriverInfo, MultiPrintResult, RemotePrinter, SpoolJob, SpoolerPrinter};
+use pos_print_core::stats::{PrintStatistics, StatsRange};
+use pos_print_core::status::{self, FullStatus};
+use pos_print_core::target::Target;
+use pos_print_core::typeset::{self, TtfFont, TypesetOptions};
+use pos_print_core::validate::{self, PrinterProfile, ValidationReport};
+use pos_print_core::webhook::WebhookDispatcher;
+use pos_print_core::winport;
+use pos_print_core::workers::{self, AbortSummary, WorkerPool, WorkerStats};
+use pos_print_core::{preview, rtc, serial, spooler};
+use serde_json::Value;
+use tauri::{Emitter, Manager};
+use tauri_plugin_notification::NotificationExt;
+
+/// How often jobs held by quiet hours are checked for release and newly
+/// expired jobs are reported.
+const HOUSEKEEPING_POLL: Duration = Duration::from_secs(15);
+
+/// Payload of the `print-jobs-expired` event.
+#[derive(Clone, serde::Serialize)]
+struct ExpiredJobs {
+  /// Jobs that expired since the last event.
+  expired: usize,
+  /// Entries now in the dead-letter list.
+  dead_letters: usize,
+}
+
 #[derive(serde::Serialize)]
 struct SerialPortDto {
   port_name: String,
@@ -11,303 +73,1630 @@ struct SerialPortDto {
   serial_number: Option<String>,
   vid: Option<u16>,
   pid: Option<u16>,
+  /// Use as `port` in a serial target to find this USB printer whatever
+  /// COM number it gets; absent for non-USB ports.
+  stable_id: Option<String>,
+  /// Bus location, which tells two identical printers apart.
+  location: Option<String>,
+  interface: Option<u8>,
+  interface_name: Option<String>,
+  driver: Option<String>,
+  /// Whether the port could be opened, when `probe` was asked for; false
+  /// usually means another program holds it.
+  can_open: Option<bool>,
+}
+
+/// Forwards job lifecycle events to the frontend as `print-job-*` events,
+/// and raises an OS notification for failures when they're turned on.
+struct TauriJobEvents(tauri::AppHandle);
+
+impl JobObserver for TauriJobEvents {
+  fn notify(&self, phase: JobPhase, event: &JobEvent) {
+    if let Err(e) = self.0.emit(phase.event_name(), event) {
+      log::warn!("failed to emit {}: {e}", phase.event_name());
+    }
+    if phase == JobPhase::Failed {
+      let cfg = self.0.state::<ConfigStore>().snapshot();
+      if let Some(alert) = self.0.state::<FailureAlerts>().on_failure(event, &cfg) {
+        let shown = self.0.notification().builder().title(alert.title).body(alert.body).show();
+        if let Err(e) = shown {
+          log::warn!("unable to show failure notification for {}: {e}", event.destination);
+        }
+      }
+    }
+  }
+}
+
+impl DrawerObserver for TauriJobEvents {
+  fn drawer_opened(&self, event: &DrawerEvent) {
+    if let Err(e) = self.0.emit("drawer-opened", event) {
+      log::warn!("failed to emit drawer-opened: {e}");
+    }
+  }
 }
 
 #[tauri::command]
-async fn tcp_print_escpos(host: String, port: u16, data: Vec<u8>) -> Result<(), String> {
-  tauri::async_runtime::spawn_blocking(move || {
-    let host_label = host.clone();
-    let addr = (host.as_str(), port)
-      .to_socket_addrs()
-      .map_err(|e| format!("Unable to resolve host '{host_label}:{port}': {e}. Check printer IP/DNS."))?
-      .next()
-      .ok_or_else(|| format!("Unable to resolve host '{host_label}:{port}'. Check printer IP/DNS."))?;
-
-    let timeout = Duration::from_secs(3);
-    let mut stream =
-      TcpStream::connect_timeout(&addr, timeout).map_err(|e| {
-        format!(
-          "TCP connect failed to '{host_label}:{port}': {e}. Verify printer is online and port 9100 is reachable."
-        )
-      })?;
-    let _ = stream.set_write_timeout(Some(Duration::from_secs(3)));
-    let _ = stream.set_nodelay(true);
-
-    stream
-      .write_all(&data)
-      .map_err(|e| format!("TCP write failed to '{host_label}:{port}': {e}. Check network stability and printer state."))?;
-    let _ = stream.flush();
-
-    Ok(())
-  })
-  .await
-  .map_err(|e| format!("Print task failed: {e}"))?
+async fn tcp_print_escpos(
+  workers: tauri::State<'_, WorkerPool>,
+  config: tauri::State<'_, ConfigStore>,
+  sinks: tauri::State<'_, JobSinks>,
+  host: String,
+  port: u16,
+  data: Vec<u8>,
+  expected_len: Option<usize>,
+) -> Result<(), String> {
+  PrintError::check_len(&data, expected_len)?;
+  let target = Target::Tcp { host, port };
+  let record = JobRecord::new(&target, &data, PrintOptions::default());
+  record
+    .print(&workers, &sinks, &config.snapshot(), "app", data)
+    .await
+    .map(|_| ())
+    .map_err(String::from)
+}
+
+/// Baud rates and framing for the serial settings dropdowns.
+#[tauri::command]
+fn serial_config_options() -> serial::SerialConfigOptions {
+  serial::config_options()
 }
 
+/// Serial ports with their USB details. With `probe`, each port is also
+/// briefly opened to see whether another program holds it; ports this
+/// app's workers own are reported openable without touching them. Opening
+/// can disturb some devices, so probing is off by default.
 #[tauri::command]
-async fn list_serial_ports() -> Result<Vec<SerialPortDto>, String> {
+async fn list_serial_ports(
+  workers: tauri::State<'_, WorkerPool>,
+  probe: Option<bool>,
+) -> Result<Vec<SerialPortDto>, String> {
+  let probe = probe.unwrap_or(false);
+  let in_use = workers.serial_ports_in_use();
   tauri::async_runtime::spawn_blocking(move || {
-    let mut ports =
-      serialport::available_ports().map_err(|e| {
-        format!(
-          "Unable to list serial ports: {e}. Confirm USB/Bluetooth serial drivers are installed."
-        )
-      })?;
-    ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
-
-    let out = ports
-      .into_iter()
-      .map(|p| {
-        let mut dto = SerialPortDto {
+    serial::list_ports().map(|ports| {
+      ports
+        .into_iter()
+        .map(|p| SerialPortDto {
+          can_open: probe.then(|| in_use.iter().any(|port| p.matches(port)) || serial::probe(&p.port_name)),
           port_name: p.port_name,
-          port_type: "unknown".to_string(),
-          manufacturer: None,
-          product: None,
-          serial_number: None,
-          vid: None,
-          pid: None,
-        };
-
-        match p.port_type {
-          serialport::SerialPortType::UsbPort(info) => {
-            dto.port_type = "usb".to_string();
-            dto.manufacturer = info.manufacturer;
-            dto.product = info.product;
-            dto.serial_number = info.serial_number;
-            dto.vid = Some(info.vid);
-            dto.pid = Some(info.pid);
-          }
-          serialport::SerialPortType::BluetoothPort => {
-            dto.port_type = "bluetooth".to_string();
-          }
-          serialport::SerialPortType::PciPort => {
-            dto.port_type = "pci".to_string();
-          }
-          serialport::SerialPortType::Unknown => {}
-        }
-
-        dto
-      })
-      .collect::<Vec<_>>();
-
-    Ok(out)
+          port_type: p.kind.as_str().to_string(),
+          manufacturer: p.manufacturer,
+          product: p.product,
+          serial_number: p.serial_number,
+          vid: p.vid,
+          pid: p.pid,
+          stable_id: p.stable_id,
+          location: p.location,
+          interface: p.interface,
+          interface_name: p.interface_name,
+          driver: p.driver,
+        })
+        .collect::<Vec<_>>()
+    })
   })
   .await
   .map_err(|e| format!("List ports task failed: {e}"))?
+  .map_err(String::from)
 }
 
+/// Scans for classic Bluetooth devices for `scan_seconds` (default 10,
+/// 1-30) and lists them with their pairing state. Windows, Linux and
+/// macOS; Android is not supported yet.
 #[tauri::command]
-async fn serial_print_escpos(port: String, baud: u32, data: Vec<u8>) -> Result<(), String> {
-  tauri::async_runtime::spawn_blocking(move || {
-    let port_name = port.clone();
-    let mut sp = serialport::new(port, baud)
-      .timeout(Duration::from_secs(3))
-      .open()
-      .map_err(|e| {
-        format!(
-          "Unable to open serial port {port_name} at {baud} baud: {e}. Check COM port, pairing, and driver."
-        )
-      })?;
-
-    for chunk in data.chunks(512) {
-      sp.write_all(chunk)
-        .map_err(|e| format!("Serial write failed on {port_name}: {e}. Check cable/pairing and printer readiness."))?;
-      std::thread::sleep(Duration::from_millis(20));
-    }
+async fn discover_bluetooth_devices(
+  scanner: tauri::State<'_, BluetoothScanner>,
+  scan_seconds: Option<u64>,
+) -> Result<Vec<BluetoothDevice>, PrintError> {
+  let seconds = scan_seconds.unwrap_or(10).clamp(1, 30);
+  scanner.scan(Duration::from_secs(seconds)).await
+}
+
+/// Stops a running Bluetooth scan; it resolves with what it found so far.
+#[tauri::command]
+fn cancel_bluetooth_scan(scanner: tauri::State<'_, BluetoothScanner>) -> bool {
+  scanner.cancel()
+}
 
-    sp.flush()
-      .map_err(|e| format!("Serial flush failed on {port_name}: {e}. Printer may be offline or busy."))?;
-    Ok(())
+/// Re-enumerates serial ports and spooler printers and emits
+/// `printer://added`, `printer://removed` and `printer://changed` for each
+/// difference from the previous refresh. The first call reports everything
+/// as added. `exclude_virtual` leaves PDF, XPS, OneNote and fax queues out.
+#[tauri::command]
+async fn refresh_printers(app: tauri::AppHandle, exclude_virtual: Option<bool>) -> Result<PrinterDiff, PrintError> {
+  let handle = app.clone();
+  let cfg = app.state::<ConfigStore>().snapshot();
+  let exclude_virtual = exclude_virtual.unwrap_or(false);
+  let diff = tauri::async_runtime::spawn_blocking(move || {
+    handle.state::<PrinterSnapshot>().refresh(&cfg, exclude_virtual)
   })
-  .await
-  .map_err(|e| format!("Print task failed: {e}"))?
-}
-
-#[cfg(target_os = "windows")]
-mod windows_printing {
-  use std::ffi::c_void;
-  use std::ffi::OsStr;
-  use std::iter::once;
-  use std::os::windows::ffi::OsStrExt;
-  use std::ptr::null_mut;
-
-  use windows_sys::Win32::Foundation::HANDLE;
-  use windows_sys::Win32::Graphics::Printing::{
-    ClosePrinter, DOC_INFO_1W, EndDocPrinter, EndPagePrinter, EnumPrintersW, OpenPrinterW,
-    PRINTER_ENUM_CONNECTIONS, PRINTER_ENUM_LOCAL, PRINTER_INFO_4W, StartDocPrinterW,
-    StartPagePrinter, WritePrinter,
+    .await
+    .map_err(|e| PrintError::Task(format!("Printer refresh task failed: {e}")))?;
+
+  for (event, entries) in [
+    ("printer://added", &diff.added),
+    ("printer://removed", &diff.removed),
+    ("printer://changed", &diff.changed),
+  ] {
+    for entry in entries {
+      if let Err(e) = app.emit(event, entry) {
+        log::warn!("failed to emit {event}: {e}");
+      }
+    }
+  }
+  Ok(diff)
+}
+
+#[tauri::command]
+async fn serial_print_escpos(
+  workers: tauri::State<'_, WorkerPool>,
+  config: tauri::State<'_, ConfigStore>,
+  sinks: tauri::State<'_, JobSinks>,
+  port: String,
+  baud: u32,
+  data: Vec<u8>,
+  expected_len: Option<usize>,
+) -> Result<(), String> {
+  PrintError::check_len(&data, expected_len)?;
+  let target = Target::Serial { port, baud };
+  let record = JobRecord::new(&target, &data, PrintOptions::default());
+  record
+    .print(&workers, &sinks, &config.snapshot(), "app", data)
+    .await
+    .map(|_| ())
+    .map_err(String::from)
+}
+
+/// Unified print entry point for every transport. Waits for the job to
+/// finish; lifecycle events are emitted as it runs. With `encoding` of
+/// `gzip`, `data` is decompressed first; `expected_len` is the length as
+/// sent.
+#[tauri::command]
+async fn print_job(
+  workers: tauri::State<'_, WorkerPool>,
+  config: tauri::State<'_, ConfigStore>,
+  sinks: tauri::State<'_, JobSinks>,
+  target: Target,
+  data: Vec<u8>,
+  options: Option<PrintOptions>,
+  expected_len: Option<usize>,
+  encoding: Option<PayloadEncoding>,
+) -> Result<PrintOutcome, PrintError> {
+  PrintError::check_len(&data, expected_len)?;
+  let data = compress::decode(data, encoding.unwrap_or_default(), config.snapshot().max_payload_bytes)?;
+  let record = JobRecord::new(&target, &data, options.unwrap_or_default());
+  record.print(&workers, &sinks, &config.snapshot(), "app", data).await
+}
+
+/// Queues a print and returns its job id immediately. Progress and the
+/// result arrive as `print-job-*` events. During the destination's quiet
+/// hours, jobs below its priority threshold are held until the hours end.
+/// `encoding` is as for `print_job`.
+#[tauri::command]
+fn queue_print_job(
+  workers: tauri::State<'_, WorkerPool>,
+  config: tauri::State<'_, ConfigStore>,
+  sinks: tauri::State<'_, JobSinks>,
+  target: Target,
+  data: Vec<u8>,
+  options: Option<PrintOptions>,
+  expected_len: Option<usize>,
+  encoding: Option<PayloadEncoding>,
+) -> Result<String, PrintError> {
+  PrintError::check_len(&data, expected_len)?;
+  let cfg = config.snapshot();
+  let data = compress::decode(data, encoding.unwrap_or_default(), cfg.max_payload_bytes)?;
+  let mut record = JobRecord::new(&target, &data, options.unwrap_or_default());
+  if record.check_duplicate(&cfg, &sinks) {
+    return record.settle("app", Ok(()), &sinks).map(|o| o.job_id);
+  }
+  if cfg.holds(&record.destination, record.options.priority, now_ms()) {
+    let job_id = record.job_id.clone();
+    sinks.held.hold(record);
+    return Ok(job_id);
+  }
+  submit_queued(&workers, &sinks, record)
+}
+
+/// Submits a queued job and settles it in the background once it finishes.
+fn submit_queued(workers: &WorkerPool, sinks: &JobSinks, mut record: JobRecord) -> Result<String, PrintError> {
+  let submitted = match record.queue(workers, record.payload.clone()) {
+    Ok(submitted) => submitted,
+    Err(e) => return record.settle("app", Err(e), sinks).map(|o| o.job_id),
   };
 
-  fn to_wide(input: &str) -> Vec<u16> {
-    OsStr::new(input).encode_wide().chain(once(0)).collect()
+  let job_id = submitted.job_id.clone();
+  let sinks = sinks.clone();
+  tauri::async_runtime::spawn(async move {
+    let (result, report) = submitted.wait_report().await;
+    record.set_report(report);
+    let _ = record.settle("app", result, &sinks);
+  });
+  Ok(job_id)
+}
+
+/// Prints jobs held by quiet hours now, for `destination` or for every
+/// destination when omitted. Returns how many were released.
+#[tauri::command]
+fn release_held_jobs(
+  workers: tauri::State<'_, WorkerPool>,
+  sinks: tauri::State<'_, JobSinks>,
+  destination: Option<String>,
+) -> usize {
+  let released = sinks
+    .held
+    .take_where(|r| destination.as_ref().map_or(true, |d| *d == r.destination));
+  let mut entry = AuditEntry::new("app", "release_held_jobs").detail(format!("released={}", released.len()));
+  if let Some(destination) = destination {
+    entry = entry.destination(destination);
   }
+  sinks.audit.record(entry.outcome(&Ok::<(), PrintError>(())));
 
-  fn from_wide_ptr(ptr: *const u16) -> String {
-    if ptr.is_null() {
-      return String::new();
-    }
-    let mut len = 0usize;
-    unsafe {
-      while *ptr.add(len) != 0 {
-        len += 1;
-      }
-      String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len))
-    }
+  let count = released.len();
+  for record in released {
+    let _ = submit_queued(&workers, &sinks, record);
   }
+  count
+}
 
-  pub fn list_windows_printers() -> Result<Vec<String>, String> {
-    unsafe {
-      let flags = PRINTER_ENUM_LOCAL | PRINTER_ENUM_CONNECTIONS;
-      let mut needed = 0u32;
-      let mut returned = 0u32;
-
-      EnumPrintersW(
-        flags,
-        null_mut(),
-        4,
-        null_mut(),
-        0,
-        &mut needed,
-        &mut returned,
+/// Jobs that expired in their queue instead of printing, oldest first.
+#[tauri::command]
+fn list_dead_letters(sinks: tauri::State<'_, JobSinks>) -> Vec<DeadLetter> {
+  sinks.dead_letters.list()
+}
+
+/// Queues expired jobs `job_ids`, or all of them, again as new jobs.
+/// Returns the new ids of the jobs the queues accepted; rejected ones are
+/// audited as failed prints.
+#[tauri::command]
+fn retry_dead_letters(
+  workers: tauri::State<'_, WorkerPool>,
+  sinks: tauri::State<'_, JobSinks>,
+  job_ids: Option<Vec<String>>,
+) -> Vec<String> {
+  sinks
+    .dead_letters
+    .take(job_ids.as_deref())
+    .into_iter()
+    .filter_map(|letter| {
+      let record = JobRecord::new(&letter.target, &letter.payload, PrintOptions::default());
+      sinks.audit.record(
+        AuditEntry::new("app", "retry_dead_letter")
+          .destination(letter.destination)
+          .bytes(letter.bytes)
+          .detail(format!("job_id={} expired_job_id={}", record.job_id, letter.job_id))
+          .outcome(&Ok::<(), PrintError>(())),
       );
+      submit_queued(&workers, &sinks, record).ok()
+    })
+    .collect()
+}
 
-      if needed == 0 {
-        return Ok(vec![]);
-      }
+/// Drops expired jobs `job_ids`, or all of them. Returns how many were dropped.
+#[tauri::command]
+fn discard_dead_letters(sinks: tauri::State<'_, JobSinks>, job_ids: Option<Vec<String>>) -> usize {
+  let discarded = sinks.dead_letters.take(job_ids.as_deref());
+  sinks.audit.record(
+    AuditEntry::new("app", "discard_dead_letters")
+      .detail(format!(
+        "job_ids={}",
+        discarded.iter().map(|l| l.job_id.as_str()).collect::<Vec<_>>().join(",")
+      ))
+      .outcome(&Ok::<(), PrintError>(())),
+  );
+  discarded.len()
+}
 
-      let mut buffer = vec![0u8; needed as usize];
-      let ok = EnumPrintersW(
-        flags,
-        null_mut(),
-        4,
-        buffer.as_mut_ptr(),
-        needed,
-        &mut needed,
-        &mut returned,
-      );
-      if ok == 0 {
-        return Err("Failed to enumerate Windows printers. Verify print spooler service is running.".to_string());
-      }
+/// Prints job `job_id` again, or the last printed job when omitted, on
+/// `destination_override` or the printer it originally went to. Stamped as
+/// a copy unless `options.mark_copy` is off. Payloads are kept for
+/// `reprint_retention_hours`; older jobs fail with `payload_not_retained`.
+#[tauri::command]
+async fn reprint_job(
+  workers: tauri::State<'_, WorkerPool>,
+  sinks: tauri::State<'_, JobSinks>,
+  job_id: Option<String>,
+  destination_override: Option<Target>,
+  options: Option<ReprintOptions>,
+) -> Result<PrintOutcome, PrintError> {
+  let options = options.unwrap_or_default();
+  let original = sinks.reprints.next_copy(job_id.as_deref())?;
+  let data = if options.mark_copy {
+    reprint::stamp_copy(&original.data, original.copy, reprint::local_now(options.utc_offset_minutes))
+  } else {
+    original.data.to_vec()
+  };
+  let target = destination_override.unwrap_or_else(|| original.target.clone());
+  let destination = target.label();
+  let bytes = data.len();
+  let reprint_id = job::next_job_id();
 
-      let ptr = buffer.as_ptr() as *const PRINTER_INFO_4W;
-      let mut out: Vec<String> = Vec::new();
-      for i in 0..returned as usize {
-        let item = *ptr.add(i);
-        let name = from_wide_ptr(item.pPrinterName);
-        if !name.trim().is_empty() {
-          out.push(name);
-        }
-      }
-      out.sort();
-      out.dedup();
-      Ok(out)
-    }
+  let (result, report) = match workers.submit_print(&reprint_id, target, data) {
+    Ok(submitted) => submitted.wait_report().await,
+    Err(e) => (Err(e), None),
+  };
+  let report = report.unwrap_or_default();
+  sinks.audit.record(
+    AuditEntry::new("app", "reprint")
+      .destination(destination.clone())
+      .bytes(bytes)
+      .detail(format!(
+        "job_id={reprint_id} original={} original_destination={} copy={} stamped={}",
+        original.job_id,
+        original.target.label(),
+        original.copy,
+        options.mark_copy
+      ))
+      .outcome(&result),
+  );
+  result.map(|()| PrintOutcome {
+    job_id: reprint_id,
+    status: PrintStatus::Printed,
+    destination,
+    bytes,
+    reason: None,
+    connection_reused: report.timings.connection_reused,
+    markers: report.markers,
+  })
+}
+
+/// Measures how fast `target` takes data by printing a generated payload
+/// `iterations` times. Uses real paper on real printers.
+#[tauri::command]
+async fn benchmark_printer(
+  workers: tauri::State<'_, WorkerPool>,
+  audit: tauri::State<'_, AuditLog>,
+  target: Target,
+  payload_bytes: usize,
+  iterations: u32,
+) -> Result<BenchmarkReport, PrintError> {
+  let destination = target.label();
+  let result = workers.benchmark(target, payload_bytes, iterations).await;
+  audit.record(
+    AuditEntry::new("app", "benchmark_printer")
+      .destination(destination)
+      .bytes(payload_bytes)
+      .detail(format!("iterations={iterations}"))
+      .outcome(&result),
+  );
+  result
+}
+
+/// Prints an alignment grid `width_chars` wide (default 48) with `lines`
+/// numbered rows (default 10), for centering paper and checking the cutter.
+#[tauri::command]
+async fn print_alignment_grid(
+  workers: tauri::State<'_, WorkerPool>,
+  audit: tauri::State<'_, AuditLog>,
+  target: Target,
+  width_chars: Option<usize>,
+  lines: Option<usize>,
+) -> Result<(), PrintError> {
+  let width_chars = width_chars.unwrap_or(48);
+  receipt::check_columns(width_chars)?;
+  let lines = lines.unwrap_or(10);
+  if lines > 99 {
+    return Err(PrintError::InvalidRequest(format!(
+      "An alignment grid can have at most 99 lines, got {lines}."
+    )));
   }
+  let destination = target.label();
+  let data = escpos::build_alignment_grid(width_chars, lines);
+  let bytes = data.len();
+  let result = workers.submit(target, data).await;
+  audit.record(
+    AuditEntry::new("app", "print_alignment_grid")
+      .destination(destination)
+      .bytes(bytes)
+      .outcome(&result),
+  );
+  result
+}
 
-  pub fn spooler_print_raw(printer_name: String, data: Vec<u8>) -> Result<(), String> {
-    if printer_name.trim().is_empty() {
-      return Err("Printer name is required".to_string());
-    }
+/// Emergency stop: cancels every queued and running print job, including
+/// those of the direct print commands, which all run on the worker pool.
+#[tauri::command]
+async fn abort_all_jobs(
+  workers: tauri::State<'_, WorkerPool>,
+  audit: tauri::State<'_, AuditLog>,
+) -> Result<AbortSummary, PrintError> {
+  let summary = workers.abort_all();
+  audit.record(
+    AuditEntry::new("app", "abort_all_jobs")
+      .detail(format!(
+        "aborted_in_flight={} discarded_queued={}",
+        summary.aborted_in_flight, summary.discarded_queued
+      ))
+      .outcome(&Ok::<(), PrintError>(())),
+  );
+  Ok(summary)
+}
 
-    unsafe {
-      let mut handle: HANDLE = std::ptr::null_mut();
-      let mut printer_name_w = to_wide(&printer_name);
-      let open_ok = OpenPrinterW(printer_name_w.as_mut_ptr(), &mut handle, null_mut());
-      if open_ok == 0 || handle.is_null() {
-        return Err(format!("Failed to open printer '{printer_name}'. Verify exact printer name and driver installation."));
-      }
+/// Prints to the first reachable printer in `targets` (e.g. primary, then
+/// backup) and reports which one produced the receipt.
+#[tauri::command]
+async fn print_failover(
+  workers: tauri::State<'_, WorkerPool>,
+  audit: tauri::State<'_, AuditLog>,
+  targets: Vec<Target>,
+  data: Vec<u8>,
+  expected_len: Option<usize>,
+) -> Result<FailoverOutcome, PrintError> {
+  PrintError::check_len(&data, expected_len)?;
+  let bytes = data.len();
+  let result = workers.submit_failover(targets, data).await;
+  let mut entry = AuditEntry::new("app", "print_failover").bytes(bytes);
+  if let Ok(outcome) = &result {
+    entry = entry.destination(outcome.destination.clone());
+    if !outcome.failed.is_empty() {
+      let skipped = outcome.failed.iter().map(|a| a.destination.as_str()).collect::<Vec<_>>();
+      entry = entry.detail(format!("skipped={}", skipped.join(",")));
+    }
+  }
+  audit.record(entry.outcome(&result));
+  result
+}
 
-      let doc_name = to_wide("BinanceXI Receipt");
-      let data_type = to_wide("RAW");
-      let doc_info = DOC_INFO_1W {
-        pDocName: doc_name.as_ptr() as *mut u16,
-        pOutputFile: null_mut(),
-        pDatatype: data_type.as_ptr() as *mut u16,
+/// Prints each of `jobs` to its own printer at the same time, e.g. an
+/// order's kitchen, bar and customer tickets. Unlike failover every job
+/// prints; one printer failing doesn't stop the others, and each job's
+/// outcome or error is returned in the order given.
+#[tauri::command]
+async fn print_fanout(
+  workers: tauri::State<'_, WorkerPool>,
+  config: tauri::State<'_, ConfigStore>,
+  sinks: tauri::State<'_, JobSinks>,
+  jobs: Vec<(Target, Vec<u8>)>,
+  options: Option<PrintOptions>,
+) -> Result<Vec<FanoutResult>, PrintError> {
+  if jobs.is_empty() {
+    return Err(PrintError::InvalidRequest("Fan-out needs at least one job.".to_string()));
+  }
+  let cfg = config.snapshot();
+  let options = options.unwrap_or_default();
+  // Queue every job before waiting on any, so the destinations' workers
+  // print them concurrently.
+  let pending = jobs
+    .into_iter()
+    .map(|(target, data)| {
+      let mut record = JobRecord::new(&target, &data, options.clone());
+      let submitted = if record.check_duplicate(&cfg, &sinks) {
+        None
+      } else {
+        Some(record.queue(&workers, data))
       };
+      (record, submitted)
+    })
+    .collect::<Vec<_>>();
 
-      let job_id = StartDocPrinterW(handle, 1, &doc_info as *const DOC_INFO_1W);
-      if job_id == 0 {
-        ClosePrinter(handle);
-        return Err("StartDocPrinter failed. Printer driver/spooler rejected RAW job.".to_string());
+  let mut results = Vec::with_capacity(pending.len());
+  for (mut record, submitted) in pending {
+    let destination = record.destination.clone();
+    let result = match submitted {
+      None => Ok(()),
+      Some(Ok(submitted)) => {
+        let (result, report) = submitted.wait_report().await;
+        record.set_report(report);
+        result
       }
+      Some(Err(e)) => Err(e),
+    };
+    results.push(FanoutResult::new(destination, record.settle("app", result, &sinks)));
+  }
+  Ok(results)
+}
 
-      if StartPagePrinter(handle) == 0 {
-        EndDocPrinter(handle);
-        ClosePrinter(handle);
-        return Err("StartPagePrinter failed. Printer may be offline or out of paper.".to_string());
-      }
+/// Pulses the cash drawer attached to `target`. Receipts never open the
+/// drawer on their own, so cash sales must call this explicitly. Every call
+/// is kept in the drawer log with its reason and operator and announced as
+/// `drawer-opened`.
+#[tauri::command]
+async fn open_drawer(
+  workers: tauri::State<'_, WorkerPool>,
+  sinks: tauri::State<'_, JobSinks>,
+  target: Target,
+  pin: Option<DrawerPin>,
+  pulse_ms: Option<u16>,
+  reason: DrawerReason,
+  operator: String,
+) -> Result<String, PrintError> {
+  reason.validate()?;
+  let operator = operator.trim().to_string();
+  if operator.is_empty() {
+    return Err(PrintError::InvalidRequest(
+      "Opening the drawer needs the operator's identifier.".to_string(),
+    ));
+  }
+  let destination = target.label();
+  let job_id = job::next_job_id();
+  let mut kick = EscPosBuilder::new();
+  kick.drawer_kick(pin.unwrap_or_default(), pulse_ms.unwrap_or(100));
 
-      let mut written = 0u32;
-      let write_ok = WritePrinter(
-        handle,
-        data.as_ptr() as *const c_void,
-        data.len() as u32,
-        &mut written,
-      );
-      let page_ok = EndPagePrinter(handle);
-      let doc_ok = EndDocPrinter(handle);
-      ClosePrinter(handle);
-
-      if write_ok == 0 || written != data.len() as u32 {
-        return Err(format!(
-          "WritePrinter failed (written {written}/{} bytes). RAW printing may not be supported by this driver.",
-          data.len()
-        ));
-      }
-      if page_ok == 0 || doc_ok == 0 {
-        return Err("Failed to finalize print job. Check printer spooler status and driver health.".to_string());
-      }
+  let result = match workers.submit_print(&job_id, target, kick.into_bytes()) {
+    Ok(submitted) => submitted.wait().await,
+    Err(e) => Err(e),
+  };
+  sinks.audit.record(
+    AuditEntry::new("app", "open_drawer")
+      .destination(destination.clone())
+      .detail(format!("job_id={job_id} operator={operator}"))
+      .outcome(&result),
+  );
+  sinks.drawer.record(DrawerEvent {
+    reason: Some(reason),
+    operator: Some(operator),
+    ok: result.is_ok(),
+    error: result.as_ref().err().map(|e| e.to_string()),
+    ..DrawerEvent::new(&destination, &job_id)
+  });
+  result.map(|()| job_id)
+}
 
-      Ok(())
-    }
+/// Drawer openings within `range` (all of them when omitted), oldest first,
+/// including kicks embedded in printed receipts.
+#[tauri::command]
+async fn query_drawer_events(
+  sinks: tauri::State<'_, JobSinks>,
+  range: Option<DrawerRange>,
+) -> Result<Vec<DrawerEvent>, PrintError> {
+  let drawer = sinks.drawer.clone();
+  let range = range.unwrap_or_default();
+  tauri::async_runtime::spawn_blocking(move || drawer.query(&range))
+    .await
+    .map_err(|e| PrintError::Task(format!("Drawer event query failed: {e}.")))
+}
+
+/// Sounds the buzzer on `target` (typically a kitchen printer) `times` times,
+/// using the destination's configured buzzer model unless `model` is given.
+#[tauri::command]
+async fn sound_buzzer(
+  workers: tauri::State<'_, WorkerPool>,
+  config: tauri::State<'_, ConfigStore>,
+  audit: tauri::State<'_, AuditLog>,
+  target: Target,
+  times: u8,
+  model: Option<BuzzerModel>,
+  on_ms: Option<u16>,
+) -> Result<String, PrintError> {
+  if !(1..=escpos::MAX_BUZZER_TIMES).contains(&times) {
+    return Err(PrintError::InvalidRequest(format!(
+      "times must be between 1 and {}.",
+      escpos::MAX_BUZZER_TIMES
+    )));
   }
+  let destination = target.label();
+  let model = model.unwrap_or_else(|| config.snapshot().buzzer_model(&destination));
+  let job_id = job::next_job_id();
+  let data = escpos::build_buzzer(model, times, on_ms.unwrap_or(200), 200);
+
+  let result = match workers.submit_print(&job_id, target, data) {
+    Ok(submitted) => submitted.wait().await,
+    Err(e) => Err(e),
+  };
+  audit.record(
+    AuditEntry::new("app", "sound_buzzer")
+      .destination(destination)
+      .detail(format!("job_id={job_id} model={model:?} times={times}"))
+      .outcome(&result),
+  );
+  result.map(|()| job_id)
 }
 
-#[cfg(not(target_os = "windows"))]
-mod windows_printing {
-  pub fn list_windows_printers() -> Result<Vec<String>, String> {
-    Ok(vec![])
+/// Holds a serial BREAK for `duration_ms` to recover a wedged printer that
+/// no longer reacts to data (including ESC @).
+#[tauri::command]
+async fn serial_send_break(
+  workers: tauri::State<'_, WorkerPool>,
+  audit: tauri::State<'_, AuditLog>,
+  port: String,
+  duration_ms: u64,
+) -> Result<(), PrintError> {
+  if !(1..=5000).contains(&duration_ms) {
+    return Err(PrintError::InvalidRequest(
+      "duration_ms must be between 1 and 5000.".to_string(),
+    ));
   }
+  let result = workers
+    .serial_break(port.clone(), Duration::from_millis(duration_ms))
+    .await;
+  audit.record(
+    AuditEntry::new("app", "serial_break")
+      .destination(port)
+      .detail(format!("duration_ms={duration_ms}"))
+      .outcome(&result),
+  );
+  result
+}
+
+/// Sets print density (-6 lightest to 6 darkest, 0 standard). On some
+/// models this is written to NV memory, so call it when the setting changes,
+/// not before every receipt.
+#[tauri::command]
+async fn set_print_density(
+  workers: tauri::State<'_, WorkerPool>,
+  sinks: tauri::State<'_, JobSinks>,
+  target: Target,
+  level: i8,
+) -> Result<(), PrintError> {
+  receipt::check_density(level)?;
+  let mut b = EscPosBuilder::new();
+  b.density(level);
+  let data = b.into_bytes();
+  let record = JobRecord::new(&target, &data, PrintOptions::default());
+  record.submit(&workers, &sinks, "app", data).await.map(|_| ())
+}
+
+/// Rasterizes `image_bytes` and stores it in the printer's NV memory under
+/// the two-character `key`, then checks the printer lists the key. NV
+/// memory has limited write cycles; run this at setup, not per receipt.
+#[tauri::command]
+async fn provision_logo(
+  workers: tauri::State<'_, WorkerPool>,
+  target: Target,
+  image_bytes: Vec<u8>,
+  key: String,
+  max_width: Option<u32>,
+  threshold: Option<u8>,
+  dither: Option<bool>,
+) -> Result<ProvisionReport, PrintError> {
+  nvlogo::parse_key(&key)?;
+  let opts = RasterOptions::new(max_width, threshold, dither)?;
+  let bitmap = tauri::async_runtime::spawn_blocking(move || raster::image_to_bitmap(&image_bytes, opts))
+    .await
+    .map_err(|e| PrintError::Task(format!("Image conversion task failed: {e}")))??;
+  let label = target.label();
+  workers
+    .exchange(target, move |io, cfg| {
+      nvlogo::provision(io, &label, &key, &bitmap, Duration::from_millis(cfg.status_timeout_ms))
+    })
+    .await
+}
+
+/// Keys of the logos stored in the printer's NV memory.
+#[tauri::command]
+async fn list_nv_logos(workers: tauri::State<'_, WorkerPool>, target: Target) -> Result<Vec<String>, PrintError> {
+  let label = target.label();
+  workers
+    .exchange(target, move |io, cfg| {
+      nvlogo::list_keys(io, &label, Duration::from_millis(cfg.status_timeout_ms))
+    })
+    .await
+}
+
+/// Prints the logo stored under `key` by [`provision_logo`].
+#[tauri::command]
+async fn print_nv_logo(
+  workers: tauri::State<'_, WorkerPool>,
+  sinks: tauri::State<'_, JobSinks>,
+  target: Target,
+  key: String,
+) -> Result<(), PrintError> {
+  let data = nvlogo::print_command(nvlogo::parse_key(&key)?).to_vec();
+  let record = JobRecord::new(&target, &data, PrintOptions::default());
+  record.submit(&workers, &sinks, "app", data).await.map(|_| ())
+}
+
+/// Reads the printer's real-time clock as a Unix timestamp.
+#[tauri::command]
+async fn get_printer_time(
+  workers: tauri::State<'_, WorkerPool>,
+  target: Target,
+  utc_offset_minutes: Option<i32>,
+) -> Result<i64, PrintError> {
+  let label = target.label();
+  let offset = utc_offset_minutes.unwrap_or(0);
+  workers
+    .exchange(target, move |io, cfg| {
+      rtc::read_clock(io, &label, offset, Duration::from_millis(cfg.status_timeout_ms))
+    })
+    .await
+}
 
-  pub fn spooler_print_raw(_printer_name: String, _data: Vec<u8>) -> Result<(), String> {
-    Err("Windows spooler transport is only available on Windows builds".to_string())
+#[tauri::command]
+async fn set_printer_time(
+  workers: tauri::State<'_, WorkerPool>,
+  target: Target,
+  unix_ts: i64,
+  utc_offset_minutes: Option<i32>,
+) -> Result<(), PrintError> {
+  let label = target.label();
+  let offset = utc_offset_minutes.unwrap_or(0);
+  workers
+    .exchange(target, move |io, _| rtc::write_clock(io, &label, unix_ts, offset))
+    .await
+}
+
+/// Reads printer, offline-cause, error-cause and paper-sensor status in one
+/// round trip, for the health dashboard and pre-flight checks.
+#[tauri::command]
+async fn query_full_status(workers: tauri::State<'_, WorkerPool>, target: Target) -> Result<FullStatus, PrintError> {
+  let label = target.label();
+  workers
+    .exchange(target, move |io, cfg| {
+      status::query_full(io, &label, Duration::from_millis(cfg.status_timeout_ms))
+    })
+    .await
+}
+
+/// `status` from `query_full_status` as one sentence for reading aloud,
+/// e.g. "Offline: cover open and paper out; drawer closed."
+#[tauri::command]
+fn describe_status(status: FullStatus) -> String {
+  status.summary()
+}
+
+/// Maker, model, firmware and unit serial number as reported by the printer
+/// (GS I). Fields the firmware doesn't support are `null`. Spooler queues
+/// can't be queried. Store the model in `printer_models` to get its default
+/// TCP pacing.
+#[tauri::command]
+async fn query_printer_info(workers: tauri::State<'_, WorkerPool>, target: Target) -> Result<PrinterInfo, PrintError> {
+  let label = target.label();
+  workers
+    .exchange(target, move |io, cfg| {
+      printer_info::query_info(io, &label, Duration::from_millis(cfg.status_timeout_ms))
+    })
+    .await
+}
+
+/// Device status, error conditions and paper/toner levels of a managed
+/// network printer over SNMP v2c, for printers with SNMP enabled.
+/// `community` defaults to `public`.
+#[tauri::command]
+async fn snmp_printer_status(
+  config: tauri::State<'_, ConfigStore>,
+  host: String,
+  community: Option<String>,
+) -> Result<SnmpStatus, PrintError> {
+  let cfg = config.snapshot();
+  let community = community.unwrap_or_else(|| "public".to_string());
+  tauri::async_runtime::spawn_blocking(move || snmp::printer_status(&host, &community, &cfg))
+    .await
+    .map_err(|e| PrintError::Task(format!("SNMP status task failed: {e}.")))?
+}
+
+/// Checks a printer setup step by step before it is saved: resolve,
+/// connect, status, model and a small alignment print, each with its own
+/// result. Uses its own connection, like `clear_printer`.
+#[tauri::command]
+async fn validate_profile(
+  config: tauri::State<'_, ConfigStore>,
+  audit: tauri::State<'_, AuditLog>,
+  profile: PrinterProfile,
+) -> Result<ValidationReport, PrintError> {
+  let cfg = config.snapshot();
+  let report = tauri::async_runtime::spawn_blocking(move || validate::validate_profile(&profile, &cfg))
+    .await
+    .map_err(|e| PrintError::Task(format!("Validate profile task failed: {e}.")))?;
+  let failed = report.steps.iter().filter(|s| !s.ok && !s.skipped).map(|s| format!("{:?}", s.step));
+  audit.record(
+    AuditEntry::new("app", "validate_profile")
+      .destination(report.destination.clone())
+      .detail(format!("ok={} failed={}", report.ok, failed.collect::<Vec<_>>().join(",")))
+      .outcome(&Ok::<(), PrintError>(())),
+  );
+  Ok(report)
+}
+
+/// Clears a printer garbled by a truncated job and reports whether it
+/// answers again. Skips the destination's queue; see
+/// [`recovery::clear_printer`].
+#[tauri::command]
+async fn clear_printer(
+  config: tauri::State<'_, ConfigStore>,
+  audit: tauri::State<'_, AuditLog>,
+  target: Target,
+) -> Result<ClearReport, PrintError> {
+  let cfg = config.snapshot();
+  let destination = target.label();
+  let result = tauri::async_runtime::spawn_blocking(move || recovery::clear_printer(&target, &cfg))
+    .await
+    .map_err(|e| PrintError::Task(format!("Clear printer task failed: {e}.")))?;
+  audit.record(
+    AuditEntry::new("app", "clear_printer")
+      .destination(destination)
+      .detail(match &result {
+        Ok(report) => format!("responsive={}", report.responsive),
+        Err(_) => "responsive=false".to_string(),
+      })
+      .outcome(&result),
+  );
+  result
+}
+
+/// Resumes a printer stopped by a recoverable error, e.g. once a cutter jam
+/// has been cleared, and reports its status afterwards. Skips the
+/// destination's queue; see [`recovery::recover_printer`].
+#[tauri::command]
+async fn recover_printer(
+  config: tauri::State<'_, ConfigStore>,
+  audit: tauri::State<'_, AuditLog>,
+  target: Target,
+) -> Result<RecoverReport, PrintError> {
+  let cfg = config.snapshot();
+  let destination = target.label();
+  let result = tauri::async_runtime::spawn_blocking(move || recovery::recover_printer(&target, &cfg))
+    .await
+    .map_err(|e| PrintError::Task(format!("Recover printer task failed: {e}.")))?;
+  audit.record(
+    AuditEntry::new("app", "recover_printer")
+      .destination(destination)
+      .detail(match &result {
+        Ok(report) => format!("sent={} cleared={}", report.sent, report.cleared),
+        Err(_) => "cleared=false".to_string(),
+      })
+      .outcome(&result),
+  );
+  result
+}
+
+/// [`recovery::reboot_printer`]; `confirm` must repeat the destination's label.
+#[tauri::command]
+async fn reboot_printer(
+  config: tauri::State<'_, ConfigStore>,
+  audit: tauri::State<'_, AuditLog>,
+  target: Target,
+  method: RebootMethod,
+  confirm: String,
+) -> Result<RebootReport, PrintError> {
+  let cfg = config.snapshot();
+  let destination = target.label();
+  let result = tauri::async_runtime::spawn_blocking(move || recovery::reboot_printer(&target, method, &confirm, &cfg))
+    .await
+    .map_err(|e| PrintError::Task(format!("Reboot printer task failed: {e}.")))?;
+  audit.record(
+    AuditEntry::new("app", "reboot_printer")
+      .destination(destination)
+      .detail(format!("method={method:?}"))
+      .outcome(&result),
+  );
+  result
+}
+
+/// Starts the local HTTP print bridge for browser-based clients.
+#[tauri::command]
+fn start_print_bridge(
+  bridge: tauri::State<'_, PrintBridge>,
+  workers: tauri::State<'_, WorkerPool>,
+  config: tauri::State<'_, ConfigStore>,
+  audit: tauri::State<'_, AuditLog>,
+  sinks: tauri::State<'_, JobSinks>,
+  bridge_config: BridgeConfig,
+) -> Result<BridgeInfo, PrintError> {
+  bridge.start(
+    bridge_config,
+    workers.inner().clone(),
+    config.inner().clone(),
+    audit.inner().clone(),
+    sinks.inner().clone(),
+  )
+}
+
+#[tauri::command]
+fn stop_print_bridge(bridge: tauri::State<'_, PrintBridge>) -> BridgeInfo {
+  bridge.stop()
+}
+
+#[tauri::command]
+fn print_bridge_status(bridge: tauri::State<'_, PrintBridge>) -> BridgeInfo {
+  bridge.info()
+}
+
+/// Job counts, failure rate, latency and top errors per destination over
+/// `range` (default: today), from the audit log. `range.register` narrows
+/// it to jobs whose print options named that register.
+#[tauri::command]
+async fn get_print_statistics(
+  audit: tauri::State<'_, AuditLog>,
+  range: Option<StatsRange>,
+) -> Result<PrintStatistics, PrintError> {
+  let audit = audit.inner().clone();
+  let range = range.unwrap_or_default();
+  tauri::async_runtime::spawn_blocking(move || audit.statistics(&range))
+    .await
+    .map_err(|e| PrintError::Task(format!("Statistics task failed: {e}.")))
+}
+
+/// One report for support tickets: versions, serial ports, printer queues,
+/// the config without credentials, the last audit entries, queue depths,
+/// and a quick check of each of `profiles`. Takes a few seconds at most,
+/// even with printers offline.
+#[tauri::command]
+async fn collect_diagnostics(
+  app: tauri::AppHandle,
+  config: tauri::State<'_, ConfigStore>,
+  audit: tauri::State<'_, AuditLog>,
+  workers: tauri::State<'_, WorkerPool>,
+  sinks: tauri::State<'_, JobSinks>,
+  profiles: Option<Vec<Target>>,
+) -> Result<Diagnostics, PrintError> {
+  let version = app.package_info().version.to_string();
+  let cfg = config.snapshot();
+  let audit = audit.inner().clone();
+  let queues = workers::with_held(workers.stats(), sinks.held.counts());
+  tauri::async_runtime::spawn_blocking(move || {
+    diagnostics::collect(&version, profiles.unwrap_or_default(), &cfg, &audit, queues)
+  })
+  .await
+  .map_err(|e| PrintError::Task(format!("Diagnostics task failed: {e}.")))
+}
+
+#[tauri::command]
+fn print_worker_stats(workers: tauri::State<'_, WorkerPool>, sinks: tauri::State<'_, JobSinks>) -> Vec<WorkerStats> {
+  workers::with_held(workers.stats(), sinks.held.counts())
+}
+
+#[tauri::command]
+fn print_concurrency_stats(workers: tauri::State<'_, WorkerPool>) -> ConcurrencyStats {
+  workers.concurrency()
+}
+
+/// Circuit breaker state of printers that have been failing.
+#[tauri::command]
+fn print_circuit_breakers(workers: tauri::State<'_, WorkerPool>) -> Vec<CircuitStatus> {
+  workers.circuits()
+}
+
+/// Retry budgets of printers that have been retrying; unlisted ones are full.
+#[tauri::command]
+fn print_retry_budgets(workers: tauri::State<'_, WorkerPool>) -> Vec<RetryBudgetStatus> {
+  workers.retry_budgets()
+}
+
+/// Lets jobs through to `target` again without waiting out its cooldown.
+#[tauri::command]
+fn reset_circuit_breaker(
+  workers: tauri::State<'_, WorkerPool>,
+  audit: tauri::State<'_, AuditLog>,
+  target: Target,
+) -> bool {
+  let had_failures = workers.reset_circuit(&target);
+  audit.record(
+    AuditEntry::new("app", "reset_circuit_breaker")
+      .destination(target.label())
+      .detail(format!("had_failures={had_failures}"))
+      .outcome(&Ok::<(), PrintError>(())),
+  );
+  had_failures
+}
+
+#[tauri::command]
+fn get_receipt_archive_settings(archive: tauri::State<'_, ReceiptArchive>) -> ArchiveSettings {
+  archive.settings()
+}
+
+/// Turns receipt archiving on or off and sets its retention limits.
+#[tauri::command]
+fn set_receipt_archive_settings(
+  archive: tauri::State<'_, ReceiptArchive>,
+  settings: ArchiveSettings,
+) -> Result<ArchiveSettings, PrintError> {
+  archive.set_settings(settings)
+}
+
+#[tauri::command]
+async fn find_archived_receipt(
+  archive: tauri::State<'_, ReceiptArchive>,
+  filter: ArchiveFilter,
+) -> Result<Vec<ArchivedReceipt>, PrintError> {
+  let archive = archive.inner().clone();
+  tauri::async_runtime::spawn_blocking(move || archive.find(&filter))
+    .await
+    .map_err(|e| PrintError::Task(format!("Archive lookup task failed: {e}")))?
+}
+
+#[tauri::command]
+fn get_print_config(config: tauri::State<'_, ConfigStore>) -> PrintConfigView {
+  config.view()
+}
+
+#[tauri::command]
+fn set_print_config(config: tauri::State<'_, ConfigStore>, patch: PrintConfigPatch) -> Result<PrintConfigView, PrintError> {
+  config.apply(patch)
+}
+
+#[tauri::command]
+async fn image_to_escpos(
+  data: Vec<u8>,
+  max_width: Option<u32>,
+  threshold: Option<u8>,
+  dither: Option<bool>,
+) -> Result<Vec<u8>, PrintError> {
+  let opts = RasterOptions::new(max_width, threshold, dither)?;
+  tauri::async_runtime::spawn_blocking(move || raster::image_to_escpos(&data, opts))
+    .await
+    .map_err(|e| PrintError::Task(format!("Image conversion task failed: {e}")))?
+}
+
+/// The text for a Wi-Fi, vCard, URL or EPC payment QR code, escaped for
+/// its format; pass it as a QR element's `data`.
+#[tauri::command]
+fn build_qr_payload(payload: QrPayload) -> Result<String, PrintError> {
+  payload.build()
+}
+
+/// [`raster::build_qr_with_logo`]; `ec_level` defaults to H and `size` to
+/// 384 dots.
+#[tauri::command]
+async fn build_qr_with_logo(
+  data: String,
+  logo: Vec<u8>,
+  ec_level: Option<QrEcLevel>,
+  size: Option<u32>,
+) -> Result<Vec<u8>, PrintError> {
+  let size = size.unwrap_or(raster::DEFAULT_QR_LOGO_SIZE);
+  tauri::async_runtime::spawn_blocking(move || {
+    raster::build_qr_with_logo(&data, ec_level.unwrap_or_default(), &logo, size)
+  })
+  .await
+  .map_err(|e| PrintError::Task(format!("QR rendering task failed: {e}.")))?
+}
+
+/// Rasterizes a PNG/JPEG/BMP file from disk, scaled down to `max_width` dots.
+/// Results are cached per path and modification time.
+#[tauri::command]
+async fn image_file_to_escpos(
+  cache: tauri::State<'_, RasterCache>,
+  path: String,
+  max_width: Option<u32>,
+  threshold: Option<u8>,
+  dither: Option<bool>,
+) -> Result<Vec<u8>, PrintError> {
+  let opts = RasterOptions::new(max_width, threshold, dither)?;
+  let cache = cache.inner().clone();
+  tauri::async_runtime::spawn_blocking(move || {
+    cache
+      .image_file_to_escpos(std::path::Path::new(&path), opts)
+      .map(|raster| raster.as_ref().clone())
+  })
+  .await
+  .map_err(|e| PrintError::Task(format!("Image conversion task failed: {e}")))?
+}
+
+/// Renders a receipt model to PNG bytes for an on-screen preview.
+/// `dot_width` defaults to an 80 mm head (576 dots).
+#[tauri::command]
+async fn render_receipt_preview(
+  partials: tauri::State<'_, PartialStore>,
+  receipt: Value,
+  dot_width: Option<u32>,
+) -> Result<Vec<u8>, PrintError> {
+  let receipt = partials.receipt(receipt)?;
+  let dot_width = dot_width.unwrap_or(raster::DEFAULT_MAX_WIDTH);
+  tauri::async_runtime::spawn_blocking(move || preview::render_png(&receipt, dot_width))
+    .await
+    .map_err(|e| PrintError::Task(format!("Receipt preview task failed: {e}")))?
+}
+
+/// Renders a receipt model to ESC/POS bytes for `width_chars` columns (default 48).
+#[tauri::command]
+fn render_receipt_escpos(
+  partials: tauri::State<'_, PartialStore>,
+  receipt: Value,
+  width_chars: Option<usize>,
+) -> Result<Vec<u8>, PrintError> {
+  receipt::render_escpos(&partials.receipt(receipt)?, width_chars.unwrap_or(48))
+}
+
+/// A receipt laid out for `from_width` characters per line, adjusted for
+/// `to_width`; see [`Receipt::reflow`]. Partials and template logic are
+/// expanded in the result.
+#[tauri::command]
+fn reflow_receipt(
+  partials: tauri::State<'_, PartialStore>,
+  receipt: Value,
+  from_width: usize,
+  to_width: usize,
+) -> Result<Receipt, PrintError> {
+  partials.receipt(receipt)?.reflow(from_width, to_width)
+}
+
+/// Renders a receipt model entirely in the TrueType/OpenType font at
+/// `font_path`, as GS v 0 raster bands `dot_width` dots wide (default 576).
+#[tauri::command]
+async fn render_receipt_ttf(
+  partials: tauri::State<'_, PartialStore>,
+  receipt: Value,
+  font_path: String,
+  dot_width: Option<u32>,
+  font_size_px: Option<f32>,
+) -> Result<Vec<u8>, PrintError> {
+  let receipt = partials.receipt(receipt)?;
+  let opts = TypesetOptions::new(dot_width, font_size_px)?;
+  tauri::async_runtime::spawn_blocking(move || {
+    let bytes = std::fs::read(&font_path)
+      .map_err(|e| PrintError::InvalidRequest(format!("Unable to read font file '{font_path}': {e}.")))?;
+    typeset::render_escpos(&receipt, &TtfFont::from_bytes(bytes)?, opts)
+  })
+  .await
+  .map_err(|e| PrintError::Task(format!("Receipt typesetting task failed: {e}")))?
+}
+
+/// Saves `fragment` (a receipt element or an array of them) as the
+/// partial `name`, used in receipts as `{ "partial": name }`.
+#[tauri::command]
+fn register_template_partial(
+  partials: tauri::State<'_, PartialStore>,
+  audit: tauri::State<'_, AuditLog>,
+  name: String,
+  fragment: Value,
+) -> Result<(), PrintError> {
+  let result = partials.register(&name, fragment);
+  audit.record(
+    AuditEntry::new("app", "register_template_partial")
+      .detail(format!("name={name}"))
+      .outcome(&result),
+  );
+  result
+}
+
+#[tauri::command]
+fn remove_template_partial(partials: tauri::State<'_, PartialStore>, name: String) -> Result<bool, PrintError> {
+  partials.remove(&name)
+}
+
+#[tauri::command]
+fn list_template_partials(partials: tauri::State<'_, PartialStore>) -> Vec<String> {
+  partials.names()
+}
+
+/// Renders a kitchen order chit to ESC/POS bytes for `width_chars` columns (default 48).
+#[tauri::command]
+fn render_kitchen_ticket(ticket: KitchenTicket, width_chars: Option<usize>) -> Result<Vec<u8>, PrintError> {
+  kitchen::render_escpos(&ticket, width_chars.unwrap_or(48))
+}
+
+fn header_str<'a>(headers: &'a tauri::http::HeaderMap, name: &str) -> Result<&'a str, PrintError> {
+  headers
+    .get(name)
+    .ok_or_else(|| PrintError::InvalidRequest(format!("Missing '{name}' header.")))?
+    .to_str()
+    .map_err(|_| PrintError::InvalidRequest(format!("Header '{name}' is not valid ASCII.")))
+}
+
+fn header_num<T: std::str::FromStr>(headers: &tauri::http::HeaderMap, name: &str) -> Result<T, PrintError> {
+  let raw = header_str(headers, name)?;
+  raw
+    .trim()
+    .parse::<T>()
+    .map_err(|_| PrintError::InvalidRequest(format!("Header '{name}' has invalid value '{raw}'.")))
+}
+
+/// Prints ESC/POS bytes sent as a raw (ArrayBuffer) IPC body.
+///
+/// Transport parameters travel in headers:
+/// - `x-print-transport`: `tcp` or `serial`
+/// - `x-print-host` / `x-print-port` for TCP
+/// - `x-print-serial-port` / `x-print-baud` for serial
+/// - `x-print-expected-len` (optional): the body's length, checked before printing
+/// - `x-print-encoding` (optional): `gzip` for a gzip-compressed body
+#[tauri::command]
+async fn print_raw_ipc(
+  workers: tauri::State<'_, WorkerPool>,
+  config: tauri::State<'_, ConfigStore>,
+  sinks: tauri::State<'_, JobSinks>,
+  request: tauri::ipc::Request<'_>,
+) -> Result<(), PrintError> {
+  let tauri::ipc::InvokeBody::Raw(body) = request.body() else {
+    return Err(PrintError::InvalidRequest(
+      "print_raw_ipc expects a raw binary body (ArrayBuffer/Uint8Array), not JSON.".to_string(),
+    ));
+  };
+  let headers = request.headers();
+
+  let dest = match header_str(headers, "x-print-transport")?.trim().to_ascii_lowercase().as_str() {
+    "tcp" => Target::Tcp {
+      host: header_str(headers, "x-print-host")?.trim().to_string(),
+      port: header_num(headers, "x-print-port")?,
+    },
+    "serial" => Target::Serial {
+      port: header_str(headers, "x-print-serial-port")?.trim().to_string(),
+      baud: header_num(headers, "x-print-baud")?,
+    },
+    other => {
+      return Err(PrintError::InvalidRequest(format!(
+        "Unsupported transport '{other}'. Expected 'tcp' or 'serial'."
+      )))
+    }
+  };
+
+  if headers.contains_key("x-print-expected-len") {
+    PrintError::check_len(body, Some(header_num(headers, "x-print-expected-len")?))?;
   }
+
+  let encoding = if headers.contains_key("x-print-encoding") {
+    PayloadEncoding::parse(header_str(headers, "x-print-encoding")?)?
+  } else {
+    PayloadEncoding::Raw
+  };
+
+  // The body is borrowed from the request, so it has to be copied once to
+  // hand it to the worker thread.
+  let data = compress::decode(body.clone(), encoding, config.snapshot().max_payload_bytes)?;
+  let record = JobRecord::new(&dest, &data, PrintOptions::default());
+  record.print(&workers, &sinks, &config.snapshot(), "app", data).await.map(|_| ())
+}
+
+#[tauri::command]
+async fn list_windows_printers(
+  config: tauri::State<'_, ConfigStore>,
+  exclude_virtual: Option<bool>,
+) -> Result<Vec<String>, String> {
+  if !exclude_virtual.unwrap_or(false) {
+    return tauri::async_runtime::spawn_blocking(|| spooler::list_printers().map_err(String::from))
+      .await
+      .map_err(|e| format!("List printers task failed: {e}"))?;
+  }
+  let cfg = config.snapshot();
+  let printers = tauri::async_runtime::spawn_blocking(move || spooler::list_printers_detailed(&cfg))
+    .await
+    .map_err(|e| format!("List printers task failed: {e}"))??;
+  Ok(printers.into_iter().filter(|p| !p.is_virtual).map(|p| p.name).collect())
+}
+
+/// Local and connected Windows queues with their driver, port and whether
+/// they are virtual (PDF, XPS, OneNote, fax; see the `virtual_drivers` and
+/// `virtual_ports` settings), for the printer picker.
+#[tauri::command]
+async fn list_windows_printers_detailed(
+  config: tauri::State<'_, ConfigStore>,
+  exclude_virtual: Option<bool>,
+) -> Result<Vec<SpoolerPrinter>, PrintError> {
+  let cfg = config.snapshot();
+  let printers = tauri::async_runtime::spawn_blocking(move || spooler::list_printers_detailed(&cfg))
+    .await
+    .map_err(|e| PrintError::Task(format!("List printers task failed: {e}.")))??;
+  Ok(if exclude_virtual.unwrap_or(false) {
+    printers.into_iter().filter(|p| !p.is_virtual).collect()
+  } else {
+    printers
+  })
+}
+
+/// This computer's network interfaces and their addresses, for choosing
+/// which network to look for printers on.
+#[tauri::command]
+fn list_network_interfaces(include_loopback: Option<bool>) -> Result<Vec<NetworkInterface>, PrintError> {
+  netif::list_interfaces(include_loopback.unwrap_or(false))
+}
+
+/// Printers shared by print server `server`, or browsable on the network
+/// when omitted, e.g. to find a `\\server\printer` path for
+/// `add_printer_connection`. Gives up after `timeout_ms` (default 10 s)
+/// on a server that doesn't answer.
+#[tauri::command]
+async fn list_remote_printers(
+  server: Option<String>,
+  timeout_ms: Option<u64>,
+) -> Result<Vec<RemotePrinter>, PrintError> {
+  let timeout = Duration::from_millis(timeout_ms.unwrap_or(10_000));
+  tauri::async_runtime::spawn_blocking(move || spooler::list_remote_printers(server.as_deref(), timeout))
+    .await
+    .map_err(|e| PrintError::Task(format!("List remote printers task failed: {e}.")))?
+}
+
+/// Transports and features compiled into this build, plus its version.
+#[tauri::command]
+fn get_capabilities() -> Capabilities {
+  capabilities::capabilities()
 }
 
+/// Driver details for a Windows queue, so the UI can warn when a PDF or
+/// office printer is picked for receipts.
 #[tauri::command]
-async fn list_windows_printers() -> Result<Vec<String>, String> {
-  tauri::async_runtime::spawn_blocking(windows_printing::list_windows_printers)
+async fn get_printer_driver_info(printer_name: String) -> Result<DriverInfo, PrintError> {
+  tauri::async_runtime::spawn_blocking(move || spooler::driver_info(&printer_name))
     .await
-    .map_err(|e| format!("List printers task failed: {e}"))?
+    .map_err(|e| PrintError::Task(format!("Driver info task failed: {e}.")))?
 }
 
+/// Connects this Windows user to shared printer `unc_path`
+/// (`\\server\printer`), so it can be printed to by that name.
+/// Fails with `spooler_credentials_required` when the server wants a
+/// sign-in first.
 #[tauri::command]
-async fn spooler_print_raw(printer_name: String, data: Vec<u8>) -> Result<(), String> {
-  tauri::async_runtime::spawn_blocking(move || windows_printing::spooler_print_raw(printer_name, data))
+async fn add_printer_connection(audit: tauri::State<'_, AuditLog>, unc_path: String) -> Result<(), PrintError> {
+  let path = unc_path.clone();
+  let result = tauri::async_runtime::spawn_blocking(move || spooler::add_connection(&path))
     .await
-    .map_err(|e| format!("Spooler print task failed: {e}"))?
+    .map_err(|e| PrintError::Task(format!("Printer connection task failed: {e}.")))?;
+  audit.record(AuditEntry::new("app", "add_printer_connection").destination(unc_path).outcome(&result));
+  result
+}
+
+/// Removes this Windows user's connection to shared printer `unc_path`.
+#[tauri::command]
+async fn remove_printer_connection(audit: tauri::State<'_, AuditLog>, unc_path: String) -> Result<(), PrintError> {
+  let path = unc_path.clone();
+  let result = tauri::async_runtime::spawn_blocking(move || spooler::remove_connection(&path))
+    .await
+    .map_err(|e| PrintError::Task(format!("Printer connection task failed: {e}.")))?;
+  audit.record(AuditEntry::new("app", "remove_printer_connection").destination(unc_path).outcome(&result));
+  result
+}
+
+/// Restarts a stuck Windows spool job from its first byte and returns its
+/// new state. A job that already started printing is refused unless
+/// `force`, since restarting reprints it; delete it instead if the receipt
+/// came out.
+#[tauri::command]
+async fn restart_spool_job(
+  audit: tauri::State<'_, AuditLog>,
+  printer_name: String,
+  job_id: u32,
+  force: Option<bool>,
+) -> Result<SpoolJob, PrintError> {
+  let force = force.unwrap_or(false);
+  let name = printer_name.clone();
+  let result = tauri::async_runtime::spawn_blocking(move || spooler::restart_job(&name, job_id, force))
+    .await
+    .map_err(|e| PrintError::Task(format!("Spool job restart task failed: {e}.")))?;
+  audit.record(
+    AuditEntry::new("app", "restart_spool_job")
+      .destination(printer_name)
+      .detail(format!("spool_job_id={job_id} force={force}"))
+      .outcome(&result),
+  );
+  result
+}
+
+/// `devmode` is an optional driver-exported DEVMODEW (duplex, paper, ...)
+/// applied instead of the printer's defaults.
+#[tauri::command]
+async fn spooler_print_raw(
+  workers: tauri::State<'_, WorkerPool>,
+  config: tauri::State<'_, ConfigStore>,
+  sinks: tauri::State<'_, JobSinks>,
+  printer_name: String,
+  data: Vec<u8>,
+  devmode: Option<Vec<u8>>,
+  expected_len: Option<usize>,
+) -> Result<(), String> {
+  PrintError::check_len(&data, expected_len)?;
+  if let Some(devmode) = &devmode {
+    spooler::check_devmode(devmode)?;
+  }
+  let target = Target::Spooler { printer_name, devmode };
+  let record = JobRecord::new(&target, &data, PrintOptions::default());
+  record
+    .print(&workers, &sinks, &config.snapshot(), "app", data)
+    .await
+    .map(|_| ())
+    .map_err(String::from)
+}
+
+/// Writes `data` straight to a Windows port device such as `COM3` or a
+/// mapped `LPT1`, bypassing the spooler, for legacy setups whose port
+/// driver corrupts raw ESC/POS.
+#[tauri::command]
+async fn windows_print_to_port(
+  workers: tauri::State<'_, WorkerPool>,
+  config: tauri::State<'_, ConfigStore>,
+  sinks: tauri::State<'_, JobSinks>,
+  port_name: String,
+  data: Vec<u8>,
+  expected_len: Option<usize>,
+) -> Result<(), PrintError> {
+  PrintError::check_len(&data, expected_len)?;
+  winport::device_path(&port_name)?;
+  let target = Target::Port { port_name };
+  let record = JobRecord::new(&target, &data, PrintOptions::default());
+  record.print(&workers, &sinks, &config.snapshot(), "app", data).await.map(|_| ())
+}
+
+/// Prints the same document to each of `printer_names`, e.g. an
+/// end-of-day report to the filing and the manager's printer. A printer
+/// that fails doesn't stop the others; each one's result is returned in
+/// the order given. `devmode` applies to all of them. Each printer's copy
+/// is its own job, settled like any other print.
+#[tauri::command]
+async fn spooler_print_multi(
+  workers: tauri::State<'_, WorkerPool>,
+  config: tauri::State<'_, ConfigStore>,
+  sinks: tauri::State<'_, JobSinks>,
+  printer_names: Vec<String>,
+  data: Vec<u8>,
+  devmode: Option<Vec<u8>>,
+  expected_len: Option<usize>,
+) -> Result<Vec<MultiPrintResult>, PrintError> {
+  PrintError::check_len(&data, expected_len)?;
+  spooler::check_multi(&printer_names, devmode.as_deref())?;
+  let cfg = config.snapshot();
+  // Queue every printer's job before waiting on any, so the printers'
+  // workers print them concurrently.
+  let pending = printer_names
+    .into_iter()
+    .map(|printer_name| {
+      let target = Target::Spooler {
+        printer_name: printer_name.clone(),
+        devmode: devmode.clone(),
+      };
+      let mut record = JobRecord::new(&target, &data, PrintOptions::default());
+      let submitted = if record.check_duplicate(&cfg, &sinks) {
+        None
+      } else {
+        Some(record.queue(&workers, data.clone()))
+      };
+      (printer_name, record, submitted)
+    })
+    .collect::<Vec<_>>();
+
+  let mut results = Vec::with_capacity(pending.len());
+  for (printer_name, mut record, submitted) in pending {
+    let result = match submitted {
+      None => Ok(()),
+      Some(Ok(submitted)) => {
+        let (result, report) = submitted.wait_report().await;
+        record.set_report(report);
+        result
+      }
+      Some(Err(e)) => Err(e),
+    };
+    let result = record.settle("app", result, &sinks);
+    results.push(MultiPrintResult {
+      printer_name,
+      ok: result.is_ok(),
+      error: result.err(),
+    });
+  }
+  Ok(results)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+  let config = ConfigStore::default();
+
   tauri::Builder::default()
+    .manage(config.clone())
+    .manage(RasterCache::default())
+    .manage(WorkerPool::new(config))
+    .manage(PrintBridge::default())
+    .manage(PrinterSnapshot::default())
+    .manage(FailureAlerts::default())
+    .manage(BluetoothScanner::default())
+    .plugin(tauri_plugin_notification::init())
     .invoke_handler(tauri::generate_handler![
       tcp_print_escpos,
       list_serial_ports,
+      serial_config_options,
+      discover_bluetooth_devices,
+      cancel_bluetooth_scan,
+      refresh_printers,
       serial_print_escpos,
+      print_raw_ipc,
+      print_job,
+      queue_print_job,
+      release_held_jobs,
+      list_dead_letters,
+      retry_dead_letters,
+      discard_dead_letters,
+      reprint_job,
+      abort_all_jobs,
+      benchmark_printer,
+      print_alignment_grid,
+      print_failover,
+      print_fanout,
+      open_drawer,
+      query_drawer_events,
+      sound_buzzer,
+      serial_send_break,
+      set_print_density,
+      get_printer_time,
+      set_printer_time,
+      query_full_status,
+      describe_status,
+      query_printer_info,
+      snmp_printer_status,
+      validate_profile,
+      clear_printer,
+      recover_printer,
+      reboot_printer,
+      start_print_bridge,
+      stop_print_bridge,
+      print_bridge_status,
+      print_worker_stats,
+      get_print_statistics,
+      collect_diagnostics,
+      print_concurrency_stats,
+      print_circuit_breakers,
+      reset_circuit_breaker,
+      print_retry_budgets,
+      get_receipt_archive_settings,
+      set_receipt_archive_settings,
+      find_archived_receipt,
+      get_print_config,
+      set_print_config,
+      image_to_escpos,
+      image_file_to_escpos,
+      provision_logo,
+      list_nv_logos,
+      print_nv_logo,
+      build_qr_payload,
+      build_qr_with_logo,
+      render_receipt_preview,
+      render_receipt_escpos,
+      reflow_receipt,
+      render_receipt_ttf,
+      register_template_partial,
+      remove_template_partial,
+      list_template_partials,
+      render_kitchen_ticket,
       list_windows_printers,
-      spooler_print_raw
+      list_windows_printers_detailed,
+      get_printer_driver_info,
+      get_capabilities,
+      spooler_print_raw,
+      spooler_print_multi,
+      windows_print_to_port,
+      list_remote_printers,
+      list_network_interfaces,
+      add_printer_connection,
+      remove_printer_connection,
+      restart_spool_job
     ])
     .setup(|app| {
-      if cfg!(debug_assertions) {
-        app.handle().plugin(
-          tauri_plugin_log::Builder::default()
-            .level(log::LevelFilter::Info)
-            .build(),
-        )?;
+      // Setting POS_PRINT_TRACE logs the print pipeline's spans and phase
+      // timings at TRACE, in release builds too, without raising other modules.
+      let trace_printing = std::env::var_os("POS_PRINT_TRACE").is_some_and(|v| !v.is_empty() && v != "0");
+      if cfg!(debug_assertions) || trace_printing {
+        let mut logger = tauri_plugin_log::Builder::default().level(log::LevelFilter::Info);
+        if trace_printing {
+          // Span open/close records carry the job_id/destination fields.
+          logger = logger
+            .level_for("pos_print_core", log::LevelFilter::Trace)
+            .level_for("tracing::span", log::LevelFilter::Trace);
+        }
+        app.handle().plugin(logger.build())?;
       }
+      let data_dir = app.path().app_data_dir()?;
+      let audit = AuditLog::open(data_dir.join("audit"));
+      let archive = ReceiptArchive::open(data_dir.join("receipts"));
+      let drawer = DrawerLog::open(data_dir.join("audit"));
+      drawer.set_observer(Arc::new(TauriJobEvents(app.handle().clone())));
+      app.manage(JobSinks {
+        audit: audit.clone(),
+        webhooks: WebhookDispatcher::new(),
+        archive: archive.clone(),
+        reprints: ReprintStore::open(
+          data_dir.join("reprints"),
+          app.state::<ConfigStore>().inner().clone(),
+          audit.clone(),
+        ),
+        recent: RecentPayloads::default(),
+        held: HeldJobs::open(data_dir.join("held")),
+        dead_letters: DeadLetters::default(),
+        drawer,
+        sidecars: SidecarLog::open(data_dir.join("sidecars")),
+      });
+      app.manage(audit);
+      app.manage(archive);
+      app.manage(PartialStore::open(data_dir.join("templates")));
+      app
+        .state::<WorkerPool>()
+        .set_observer(Arc::new(TauriJobEvents(app.handle().clone())));
+      let handle = app.handle().clone();
+      std::thread::spawn(move || loop {
+        std::thread::sleep(HOUSEKEEPING_POLL);
+        let cfg = handle.state::<ConfigStore>().snapshot();
+        let sinks = handle.state::<JobSinks>();
+        let now = now_ms();
+        for record in sinks.held.take_where(|r| !cfg.holds(&r.destination, r.options.priority, now)) {
+          let _ = submit_queued(&handle.state::<WorkerPool>(), &sinks, record);
+        }
+        let (expired, dead_letters) = sinks.dead_letters.take_unreported();
+        if expired > 0 {
+          if let Err(e) = handle.emit("print-jobs-expired", ExpiredJobs { expired, dead_letters }) {
+            log::warn!("failed to emit print-jobs-expired: {e}");
+          }
+        }
+      });
       Ok(())
     })
-    .run(tauri::generate_context!())
-    .expect("error while running tauri application");
+    .build(tauri::generate_context!())
+    .expect("error while building tauri application")
+    .run(|app, event| {
+      if let tauri::RunEvent::Exit = event {
+        app.state::<PrintBridge>().stop();
+        app.state::<WorkerPool>().shutdown();
+      }
+    });
 }
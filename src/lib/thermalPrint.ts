@@ -188,6 +188,9 @@ export type ThermalReceiptData = {
   activeDiscountName?: string | null;
   taxRatePct?: number | null;
   settings?: ReceiptStoreSettings | null;
+  // Receipts never pulse the cash drawer unless this is set explicitly.
+  // Prefer the standalone `open_drawer` command for cash sales.
+  drawerKick?: { pin?: 2 | 5; pulseMs?: number } | null;
 };
 
 function buildCanonicalReceiptModel(d: ThermalReceiptData) {
@@ -640,6 +643,11 @@ async function buildEscPos(d: ThermalReceiptData, paper: ReceiptPaperMm) {
   parts.push(bytes(ESC, 0x64, 0x05));
   parts.push(bytes(GS, 0x56, 0x00)); // cut (some printers ignore; safe)
 
+  if (d.drawerKick) {
+    const on = Math.min(255, Math.max(1, Math.round(Number(d.drawerKick.pulseMs ?? 100) / 2)));
+    parts.push(bytes(ESC, 0x70, d.drawerKick.pin === 5 ? 0x01 : 0x00, on, on)); // drawer kick
+  }
+
   return concat(parts);
 }
 
@@ -765,7 +773,7 @@ async function sendTcp(ip: string, port: number, data: Uint8Array) {
 async function sendTcpDesktopViaTauri(ip: string, port: number, data: Uint8Array) {
   try {
     const { invoke } = await import("@tauri-apps/api/core");
-    await invoke("tcp_print_escpos", { host: ip, port, data: Array.from(data) });
+    await invoke("tcp_print_escpos", { host: ip, port, data: Array.from(data), expectedLen: data.length });
   } catch (e: any) {
     throw new Error(e?.message || "Tauri TCP print failed");
   }
@@ -774,7 +782,7 @@ async function sendTcpDesktopViaTauri(ip: string, port: number, data: Uint8Array
 async function sendSerialDesktopViaTauri(port: string, baud: number, data: Uint8Array) {
   try {
     const { invoke } = await import("@tauri-apps/api/core");
-    await invoke("serial_print_escpos", { port, baud, data: Array.from(data) });
+    await invoke("serial_print_escpos", { port, baud, data: Array.from(data), expectedLen: data.length });
   } catch (e: any) {
     throw new Error(e?.message || "Tauri serial print failed");
   }
@@ -783,7 +791,7 @@ async function sendSerialDesktopViaTauri(port: string, baud: number, data: Uint8
 async function sendSpoolerDesktopViaTauri(printer_name: string, data: Uint8Array) {
   try {
     const { invoke } = await import("@tauri-apps/api/core");
-    await invoke("spooler_print_raw", { printer_name, data: Array.from(data) });
+    await invoke("spooler_print_raw", { printer_name, data: Array.from(data), expectedLen: data.length });
   } catch (e: any) {
     throw new Error(e?.message || "Tauri spooler print failed");
   }